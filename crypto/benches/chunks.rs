@@ -0,0 +1,69 @@
+//! Benchmark of the chunk encryption and decryption, for several `BUFFER_SIZE` values.
+//!
+//! The small buffer sizes are dominated by the per-chunk overhead (allocations, copies), so they
+//! show the effect of reusing the buffers. Run with `cargo bench -p crypto --bench chunks`, and
+//! compare against a saved baseline with `--save-baseline` / `--baseline`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crypto::{CryptoReader, CryptoWriter, RsaKeys, WriterOptions};
+use rand::{rngs::StdRng, SeedableRng as _};
+use std::{
+    hint::black_box,
+    io::{Read as _, Write as _},
+};
+
+/// The length of the benchmarked streams.
+const STREAM_LEN: usize = 256 * 1024;
+const AES_KEY: [u8; 32] = [0x42; 32];
+const NONCE: [u8; 12] = [0x24; 12];
+
+fn encrypt<const BUFFER_SIZE: usize>(keys: &RsaKeys, data: &[u8]) -> Vec<u8> {
+    let mut encrypted = Vec::with_capacity(data.len() * 2);
+    let mut writer = CryptoWriter::<_, BUFFER_SIZE>::new_with_key_and_nonce(
+        &mut encrypted,
+        keys.public_key().unwrap().clone(),
+        &AES_KEY,
+        &NONCE,
+        &WriterOptions::default(),
+        StdRng::seed_from_u64(0),
+    )
+    .unwrap();
+    writer.write_all(data).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+    encrypted
+}
+
+fn decrypt<const BUFFER_SIZE: usize>(encrypted: &[u8], decrypted: &mut Vec<u8>) {
+    decrypted.clear();
+    CryptoReader::<_, BUFFER_SIZE>::from_raw_key(encrypted, &AES_KEY)
+        .unwrap()
+        .read_to_end(decrypted)
+        .unwrap();
+}
+
+fn bench_buffer_size<const BUFFER_SIZE: usize>(c: &mut Criterion, keys: &RsaKeys) {
+    let data = vec![0x5a; STREAM_LEN];
+    let encrypted = encrypt::<BUFFER_SIZE>(keys, &data);
+    let mut decrypted = Vec::with_capacity(STREAM_LEN);
+
+    let mut group = c.benchmark_group("chunks");
+    group.throughput(Throughput::Bytes(STREAM_LEN as u64));
+    group.bench_function(BenchmarkId::new("encrypt", BUFFER_SIZE), |b| {
+        b.iter(|| encrypt::<BUFFER_SIZE>(keys, black_box(&data)))
+    });
+    group.bench_function(BenchmarkId::new("decrypt", BUFFER_SIZE), |b| {
+        b.iter(|| decrypt::<BUFFER_SIZE>(black_box(&encrypted), &mut decrypted))
+    });
+    group.finish();
+}
+
+fn chunks(c: &mut Criterion) {
+    let keys = RsaKeys::generate().unwrap();
+    bench_buffer_size::<16>(c, &keys);
+    bench_buffer_size::<64>(c, &keys);
+    bench_buffer_size::<256>(c, &keys);
+    bench_buffer_size::<4096>(c, &keys);
+}
+
+criterion_group!(benches, chunks);
+criterion_main!(benches);
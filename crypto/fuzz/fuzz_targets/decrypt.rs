@@ -0,0 +1,28 @@
+//! Feed arbitrary bytes to `CryptoReader`, with a fixed AES key.
+//! The first byte selects the strict mode and the size of the `read` calls, to exercise the
+//! chunk accumulation logic.
+#![no_main]
+
+use crypto::CryptoReader;
+use libfuzzer_sys::fuzz_target;
+use std::io::Read as _;
+
+const KEY: [u8; 32] = [7; 32];
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&settings, stream)) = data.split_first() else {
+        return;
+    };
+    let Ok(mut reader) = CryptoReader::<_, 16>::from_raw_key(stream, &KEY) else {
+        return;
+    };
+    reader.set_strict(settings & 0x80 != 0);
+
+    let mut buf = vec![0; (settings & 0x7f) as usize + 1];
+    while let Ok(read) = reader.read(&mut buf) {
+        if read == 0 {
+            break;
+        }
+        assert!(read <= buf.len());
+    }
+});
@@ -0,0 +1,19 @@
+//! Parse arbitrary bytes as a stream header.
+//! A parsed header must be written back, and parsed again to the same header.
+#![no_main]
+
+use crypto::Header;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(header) = Header::parse(&mut &data[..]) else {
+        return;
+    };
+    let mut encoded = Vec::new();
+    header
+        .write(&mut encoded)
+        .expect("a parsed header can be written");
+    assert_eq!(encoded.len(), header.len());
+    let parsed = Header::parse(&mut encoded.as_slice()).expect("a written header can be parsed");
+    assert_eq!(header, parsed);
+});
@@ -0,0 +1,60 @@
+//! Mutate a valid ciphertext, and decrypt it.
+//! The decryption must either fail, or return the original plaintext.
+#![no_main]
+
+use crypto::{split, CryptoReader, RsaKeys, WriterOptions};
+use libfuzzer_sys::fuzz_target;
+use std::io::{Read as _, Write as _};
+use std::sync::OnceLock;
+
+const PLAINTEXT: &[u8] =
+    b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod.";
+
+/// A valid ciphertext of `PLAINTEXT`, and its AES key.
+fn stream() -> &'static (Vec<u8>, Vec<u8>) {
+    static STREAM: OnceLock<(Vec<u8>, Vec<u8>)> = OnceLock::new();
+    STREAM.get_or_init(|| {
+        let public_key = RsaKeys::from_public_key_pem(include_str!("../../tests/test.pub"))
+            .expect("failed to load the public key")
+            .split()
+            .1
+            .expect("missing public key");
+        let mut encrypted = Vec::new();
+        // A single share holds the AES key
+        let (mut writer, shares) = split::new_writer_with_shares::<_, 16>(
+            &mut encrypted,
+            public_key,
+            &WriterOptions::default(),
+            1,
+            1,
+        )
+        .expect("failed to create the writer");
+        writer.write_all(PLAINTEXT).expect("failed to write");
+        writer.flush().expect("failed to flush");
+        drop(writer);
+        let key = split::combine_shares(&shares).expect("failed to recover the key");
+        (encrypted, key.to_vec())
+    })
+}
+
+fuzz_target!(|mutations: Vec<(u16, u8)>| {
+    let (encrypted, key) = stream();
+    let mut mutated = encrypted.clone();
+    for (position, value) in mutations {
+        let position = position as usize % (mutated.len() + 1);
+        match position.checked_sub(mutated.len()) {
+            // Append a byte
+            Some(_) => mutated.push(value),
+            None => mutated[position] ^= value,
+        }
+    }
+
+    let Ok(mut reader) = CryptoReader::<_, 16>::from_raw_key(mutated.as_slice(), key) else {
+        return;
+    };
+    reader.set_strict(true);
+    let mut decrypted = Vec::new();
+    if reader.read_to_end(&mut decrypted).is_ok() {
+        assert_eq!(decrypted, PLAINTEXT);
+    }
+});
@@ -0,0 +1,415 @@
+//! This module provides append-only encrypted files (e.g. logs): `AppendWriter` reopens an existing
+//! file and appends a new segment, encrypted under a fresh data key, so a long-running service can
+//! keep writing to the same file across restarts, without the private key. `AppendReader` decrypts
+//! the segments in order, and checks that they are chained.
+//!
+//! Each segment is a regular stream (see `CryptoWriter`), followed by a trailer in clear:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |    Segment 0    |   |    Trailer 0    |   |       ...       |   |    Trailer N    |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |     Stream      |   | LEN + INDEX +   |   |                 |   | LEN + INDEX +   |
+//! |                 |   | CHAIN + MAGIC   |   |                 |   | CHAIN + MAGIC   |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |       LEN       |   | 8 + 8 + 32 + 8  |   |                 |   | 8 + 8 + 32 + 8  |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The trailer holds the length of the stream, the index of the segment, its chain hash and the
+//! `CRYPTLOG` magic. The chain hash of a segment is the SHA-256 of the chain hash of the previous
+//! segment (zeros for the first one) followed by the bytes of the stream. The plaintext of each
+//! stream starts with a chain record (the index of the segment and the chain hash of the previous
+//! segment), encrypted with the data: the writer reads the previous chain hash from the last
+//! trailer, and the reader checks each record against the segments it read.
+//!
+//! The chunks of each segment are chained too (see `WriterOptions::chain_tags`): each chunk
+//! authenticates the tag of the previous one. `verify_log` reads a whole file, and checks both
+//! chains.
+//!
+//! So removing, reordering, replacing or modifying a segment is detected by the reader. (The
+//! plaintext of the following segments can't be read, so it can't be chained again) Appending
+//! segments only takes the public key, and removing the last segments leaves a valid file:
+//! compare the chain hash returned by `AppendWriter::finish` with `AppendReader::head` to detect
+//! it. (e.g. keep it in another system)
+use super::{
+    error::{error, Result},
+    identity::{Identity, Recipient},
+    tee::{TeeReader, TeeWriter},
+    CryptoReader, CryptoWriter, WriterOptions,
+};
+use sha2::{Digest as _, Sha256};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// The magic ending each trailer.
+const MAGIC: [u8; 8] = *b"CRYPTLOG";
+/// The length of a trailer: stream length, index, chain hash and magic.
+const TRAILER_LEN: usize = 8 + 8 + 32 + 8;
+/// The length of the chain record starting the plaintext of each segment: index and previous
+/// chain hash.
+const RECORD_LEN: usize = 8 + 32;
+
+/// The trailer following each segment.
+struct Trailer {
+    stream_len: u64,
+    index: u64,
+    chain: [u8; 32],
+}
+
+impl Trailer {
+    fn to_bytes(&self) -> [u8; TRAILER_LEN] {
+        let mut bytes = [0; TRAILER_LEN];
+        bytes[..8].copy_from_slice(&self.stream_len.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.index.to_be_bytes());
+        bytes[16..48].copy_from_slice(&self.chain);
+        bytes[48..].copy_from_slice(&MAGIC);
+        bytes
+    }
+
+    /// Read the trailer ending at `end`.
+    fn read_at<R: Read + Seek>(reader: &mut R, end: u64) -> Result<Self> {
+        let no_trailer = || {
+            error!(
+                InvalidData,
+                "No segment trailer (unterminated segment, or not an append-only file)"
+            )
+        };
+        let start = end.checked_sub(TRAILER_LEN as u64).ok_or_else(no_trailer)?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut bytes = [0; TRAILER_LEN];
+        reader.read_exact(&mut bytes)?;
+        if bytes[48..] != MAGIC {
+            return Err(no_trailer());
+        }
+        Ok(Self {
+            stream_len: u64::from_be_bytes(bytes[..8].try_into().expect("8 bytes")),
+            index: u64::from_be_bytes(bytes[8..16].try_into().expect("8 bytes")),
+            chain: bytes[16..48].try_into().expect("32 bytes"),
+        })
+    }
+}
+
+/// A writer appending a segment to an append-only encrypted file. (See the module documentation)
+///
+/// The segment is ended by `finish`, or when the writer is dropped.
+pub struct AppendWriter<W: Read + Write + Seek, const BUFFER_SIZE: usize> {
+    writer: CryptoWriter<TeeWriter<W, Sha256>, BUFFER_SIZE>,
+    index: u64,
+    finished: bool,
+}
+
+impl<W: Read + Write + Seek, const BUFFER_SIZE: usize> AppendWriter<W, BUFFER_SIZE> {
+    /// Open an append-only file (possibly empty), and start a new segment at its end.
+    ///
+    /// # Arguments
+    /// - `writer`: The file, readable to read its last trailer.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the file doesn't end with a trailer. (e.g. the previous writer
+    ///   crashed before ending its segment)
+    /// - The errors of `CryptoWriter::new`.
+    ///
+    pub fn open(writer: W, key: impl Into<Recipient>) -> Result<Self> {
+        Self::open_with_options(writer, key, &WriterOptions::default())
+    }
+
+    /// Open an append-only file (possibly empty), and start a new segment at its end, with the
+    /// given options. (The chunks are always chained, see `WriterOptions::chain_tags`)
+    ///
+    /// # Errors
+    /// The errors of `open` and `CryptoWriter::new_with_options`.
+    ///
+    pub fn open_with_options(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        let end = writer.seek(SeekFrom::End(0))?;
+        let (index, previous) = if end == 0 {
+            (0, [0; 32])
+        } else {
+            let trailer = Trailer::read_at(&mut writer, end)?;
+            (trailer.index + 1, trailer.chain)
+        };
+        writer.seek(SeekFrom::End(0))?;
+
+        let options = WriterOptions {
+            chain_tags: true,
+            ..options.clone()
+        };
+        let writer = TeeWriter::with_hasher(writer, Sha256::new_with_prefix(previous));
+        let mut writer = CryptoWriter::new_with_options(writer, key, &options)?;
+        writer.write_all(&index.to_be_bytes())?;
+        writer.write_all(&previous)?;
+        Ok(Self {
+            writer,
+            index,
+            finished: false,
+        })
+    }
+
+    /// The index of the segment being written. (0 for the first segment of the file)
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// End the segment: write the final chunk and the trailer.
+    ///
+    /// # Returns
+    /// The chain hash of the segment. (See `AppendReader::head`)
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(mut self) -> Result<[u8; 32]> {
+        self.inner_finish()
+    }
+
+    fn inner_finish(&mut self) -> Result<[u8; 32]> {
+        // Never retried on drop
+        self.finished = true;
+        self.writer.flush()?;
+        let writer = self.writer.writer_mut();
+        let chain: [u8; 32] = writer.hasher().clone().finalize().into();
+        let trailer = Trailer {
+            stream_len: writer.len(),
+            index: self.index,
+            chain,
+        };
+        // The trailer is not part of the chain hash
+        writer.get_mut().write_all(&trailer.to_bytes())?;
+        writer.get_mut().flush()?;
+        Ok(chain)
+    }
+}
+
+impl<W: Read + Write + Seek, const BUFFER_SIZE: usize> Write for AppendWriter<W, BUFFER_SIZE> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    /// Flush the encrypted chunks to the file. (The partial chunk stays buffered until `finish`,
+    /// as it would end the segment)
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.writer_mut().flush()
+    }
+}
+
+/// End the segment before dropping the `AppendWriter` instance.
+impl<W: Read + Write + Seek, const BUFFER_SIZE: usize> Drop for AppendWriter<W, BUFFER_SIZE> {
+    /// End the segment before dropping the `AppendWriter` instance.
+    ///
+    /// # Panics
+    /// If an I/O error occurs while ending the segment. (Call `finish` to handle it)
+    ///
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(e) = self.inner_finish() {
+            panic!("Failed to finish the segment: {}", e);
+        }
+    }
+}
+
+/// A segment of the file, located from its trailer.
+struct Segment {
+    start: u64,
+    trailer: Trailer,
+}
+
+/// A reader of an append-only encrypted file, decrypting the segments in order and checking their
+/// chain. (See the module documentation)
+///
+/// The reader can't be used after an error.
+pub struct AppendReader<R: Read + Seek, const BUFFER_SIZE: usize> {
+    key: Identity,
+    segments: Vec<Segment>,
+    /// The index of the next segment to open.
+    next: usize,
+    /// The reader of the current segment, hashing its bytes.
+    current: Option<CryptoReader<TeeReader<io::Take<R>, Sha256>, BUFFER_SIZE>>,
+    /// The underlying reader, between two segments.
+    reader: Option<R>,
+}
+
+impl<R: Read + Seek, const BUFFER_SIZE: usize> AppendReader<R, BUFFER_SIZE> {
+    /// Open an append-only file, locating its segments from their trailers.
+    ///
+    /// # Arguments
+    /// - `reader`: The file.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the file doesn't end with a trailer, or a trailer is invalid.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(mut reader: R, key: impl Into<Identity>) -> Result<Self> {
+        let mut end = reader.seek(SeekFrom::End(0))?;
+        let mut segments = Vec::new();
+        while end > 0 {
+            let trailer = Trailer::read_at(&mut reader, end)?;
+            let start = (end - TRAILER_LEN as u64)
+                .checked_sub(trailer.stream_len)
+                .ok_or_else(|| error!(InvalidData, "Invalid segment trailer"))?;
+            segments.push(Segment { start, trailer });
+            end = start;
+        }
+        segments.reverse();
+        let in_order = segments
+            .iter()
+            .enumerate()
+            .all(|(index, segment)| segment.trailer.index == index as u64);
+        if !in_order {
+            Err(error!(InvalidData, "Segments are missing or out of order"))?;
+        }
+        Ok(Self {
+            key: key.into(),
+            segments,
+            next: 0,
+            current: None,
+            reader: Some(reader),
+        })
+    }
+
+    /// The number of segments of the file.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// The chain hash of the last segment (zeros for an empty file), to compare with the one
+    /// returned by `AppendWriter::finish`.
+    ///
+    /// # Notes
+    /// The chain hash is read from the last trailer: it is only checked once the file is read to
+    /// the end.
+    ///
+    pub fn head(&self) -> [u8; 32] {
+        self.segments
+            .last()
+            .map_or([0; 32], |segment| segment.trailer.chain)
+    }
+
+    /// The chain hash preceding the segment `index`.
+    fn previous_chain(&self, index: usize) -> [u8; 32] {
+        index
+            .checked_sub(1)
+            .map_or([0; 32], |index| self.segments[index].trailer.chain)
+    }
+
+    /// Open the next segment, and check its chain record.
+    fn open_segment(&mut self) -> Result<()> {
+        let index = self.next;
+        let previous = self.previous_chain(index);
+        let segment = &self.segments[index];
+        let mut reader = self
+            .reader
+            .take()
+            .ok_or_else(|| error!(Other, "The reader failed on a previous error"))?;
+        reader.seek(SeekFrom::Start(segment.start))?;
+        let reader = TeeReader::with_hasher(
+            reader.take(segment.trailer.stream_len),
+            Sha256::new_with_prefix(previous),
+        );
+        let mut current = CryptoReader::new(reader, self.key.clone())?;
+        current.set_strict(true);
+        let mut record = [0; RECORD_LEN];
+        current.read_exact(&mut record)?;
+        if record[..8] != (index as u64).to_be_bytes() || record[8..] != previous {
+            Err(error!(
+                InvalidData,
+                "Segment {} is not chained to the previous segment", index
+            ))?;
+        }
+        self.current = Some(current);
+        Ok(())
+    }
+
+    /// Close the current segment, read to its end, and check its chain hash.
+    fn close_segment(&mut self) -> Result<()> {
+        let current = self.current.take().expect("a segment is open");
+        let (reader, chain) = current.into_inner().finalize();
+        let segment = &self.segments[self.next];
+        if reader.limit() != 0 || chain[..] != segment.trailer.chain {
+            Err(error!(
+                InvalidData,
+                "Segment {} doesn't match its trailer", self.next
+            ))?;
+        }
+        self.reader = Some(reader.into_inner());
+        self.next += 1;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek, const BUFFER_SIZE: usize> Read for AppendReader<R, BUFFER_SIZE> {
+    /// Read the plaintext of the segments, in order. (Without the chain records)
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(current) = self.current.as_mut() else {
+                if self.next == self.segments.len() {
+                    return Ok(0);
+                }
+                self.open_segment()?;
+                continue;
+            };
+            let read = current.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.close_segment()?;
+        }
+    }
+}
+
+/// The result of `verify_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogReport {
+    /// The number of segments.
+    pub segments: usize,
+    /// The length of the plaintext of the segments. (Without the chain records)
+    pub plaintext_len: u64,
+    /// The chain hash of the last segment. (See `AppendReader::head`)
+    pub head: [u8; 32],
+}
+
+/// Decrypt a whole append-only file without writing the plaintext, and check the chain of its
+/// segments and of their chunks.
+///
+/// # Arguments
+/// - `reader`: The file.
+/// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+/// - `expected_head`: The chain hash returned by the last `AppendWriter::finish`, if kept
+///   elsewhere, to detect the removal of the last segments.
+///
+/// # Returns
+/// The number of segments, the plaintext length and the chain hash of the file.
+///
+/// # Errors
+/// - `InvalidData`: If a segment or a trailer is modified, removed or reordered, or if the chain
+///   hash doesn't match `expected_head`.
+/// - `Error::CorruptedChunk`: If a chunk is modified, removed or reordered.
+/// - `UnexpectedEof`: If a segment is truncated.
+/// - `Io`: If an I/O error occurs.
+///
+pub fn verify_log<R: Read + Seek, const BUFFER_SIZE: usize>(
+    reader: R,
+    key: impl Into<Identity>,
+    expected_head: Option<&[u8; 32]>,
+) -> Result<LogReport> {
+    let mut reader = AppendReader::<_, BUFFER_SIZE>::new(reader, key)?;
+    let head = reader.head();
+    if expected_head.is_some_and(|expected_head| *expected_head != head) {
+        Err(error!(
+            InvalidData,
+            "The log doesn't end with the expected segment (truncated or rolled back)"
+        ))?;
+    }
+    let plaintext_len = io::copy(&mut reader, &mut io::sink())?;
+    Ok(LogReport {
+        segments: reader.segment_count(),
+        plaintext_len,
+        head,
+    })
+}
@@ -0,0 +1,453 @@
+//! This module provides encrypted archives: `ArchiveWriter` encrypts several members (e.g. files)
+//! into a single stream, followed by an encrypted manifest listing them (names, sizes, SHA-256 of
+//! the plaintext and offsets), so `ArchiveReader` can list the members and check their integrity
+//! without decrypting the whole archive.
+//!
+//! An archive is a regular stream (see `CryptoWriter`), whose plaintext is:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |    Member 1     |   |       ...       |   |    Manifest     |   |     Trailer     |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |                 |   |                 |   | See `Manifest`  |   |  OFFSET + MAGIC |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |      SIZE       |   |                 |   |        -        |   |     8 + 8       |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The trailer holds the offset of the manifest in the plaintext (`u64 BE`), and the `CRYPTARC`
+//! magic. The chunks holding a plaintext range are located from the chunk size (see the `format`
+//! module), so the reader only decrypts the final chunks (the manifest and the trailer), and the
+//! chunks of the members it extracts (see `ArchiveReader::extract`). The archive is a single
+//! stream: opening it takes a single RSA operation.
+//!
+//! The manifest is encoded as:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |     VERSION     |   |   CHUNK SIZE    |   |      COUNT      |   |     ENTRIES     |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |       u8        |   |     u32 BE      |   |     u32 BE      |   |                 |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        1        |   |        4        |   |        4        |   |        -        |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! With each entry encoded as a `u16 BE` name length, the name (UTF-8), the offset and the size of
+//! the member in the plaintext (`u64 BE`), and the SHA-256 of the member.
+use super::{
+    decrypt::{unwrap_data_key, SlotKey},
+    encrypt::WriterOptions,
+    error::{error, Result},
+    format::TAG_LEN,
+    header::{Chunking, Header},
+    identity::{Identity, Recipient},
+    padding::Padding,
+    tee::TeeReader,
+    CryptoReader, CryptoWriter,
+};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use sha2::Sha256;
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
+
+/// The magic ending the plaintext of an archive.
+const MAGIC: [u8; 8] = *b"CRYPTARC";
+/// The length of the trailer: the manifest offset and the magic.
+const TRAILER_LEN: u64 = 16;
+/// The version of the manifest encoding.
+const MANIFEST_VERSION: u8 = 1;
+
+/// A member of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The name of the member. (e.g. a relative path)
+    pub name: String,
+    /// The offset of the member in the plaintext of the archive.
+    pub offset: u64,
+    /// The size of the member.
+    pub size: u64,
+    /// The SHA-256 of the member.
+    pub sha256: [u8; 32],
+}
+
+/// The list of the members of an archive. (See the module documentation)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    /// The chunk size of the archive. (`BUFFER_SIZE`)
+    pub chunk_size: u32,
+    /// The members, in the order they were added.
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// The member with the given name, if any.
+    pub fn get(&self, name: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// The indexes of the chunks holding a member. (Empty for an empty member)
+    pub fn chunks(&self, entry: &ManifestEntry) -> Range<u64> {
+        let chunk_size = u64::from(self.chunk_size);
+        let start = entry.offset / chunk_size;
+        if entry.size == 0 {
+            return start..start;
+        }
+        start..(entry.offset + entry.size).div_ceil(chunk_size)
+    }
+
+    /// The total size of the members.
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// Encode the manifest.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.push(MANIFEST_VERSION);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            // The name length is checked by `ArchiveWriter::add`
+            bytes.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(entry.name.as_bytes());
+            bytes.extend_from_slice(&entry.offset.to_be_bytes());
+            bytes.extend_from_slice(&entry.size.to_be_bytes());
+            bytes.extend_from_slice(&entry.sha256);
+        }
+        bytes
+    }
+
+    /// Decode a manifest encoded by `to_bytes`.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the manifest is malformed, or its version is unsupported.
+    ///
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let invalid = || error!(InvalidData, "Invalid archive manifest");
+        let mut take = |len: usize| -> Result<&[u8]> {
+            let (taken, rest) = bytes.split_at_checked(len).ok_or_else(invalid)?;
+            bytes = rest;
+            Ok(taken)
+        };
+        let version = take(1)?[0];
+        if version != MANIFEST_VERSION {
+            Err(error!(
+                InvalidData,
+                "Unsupported archive manifest version: {}", version
+            ))?;
+        }
+        let chunk_size = u32::from_be_bytes(take(4)?.try_into().expect("length is checked"));
+        let count = u32::from_be_bytes(take(4)?.try_into().expect("length is checked"));
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name_len = u16::from_be_bytes(take(2)?.try_into().expect("length is checked"));
+            let name = std::str::from_utf8(take(name_len as usize)?)
+                .map_err(|_| invalid())?
+                .to_string();
+            let offset = u64::from_be_bytes(take(8)?.try_into().expect("length is checked"));
+            let size = u64::from_be_bytes(take(8)?.try_into().expect("length is checked"));
+            let sha256 = take(32)?.try_into().expect("length is checked");
+            entries.push(ManifestEntry {
+                name,
+                offset,
+                size,
+                sha256,
+            });
+        }
+        if !bytes.is_empty() || chunk_size == 0 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            chunk_size,
+            entries,
+        })
+    }
+}
+
+/// A writer of an encrypted archive. (See the module documentation)
+///
+/// **Warning**: `finish` must be called once all the members are added, otherwise the archive has
+/// no manifest, and can only be read as a regular stream.
+pub struct ArchiveWriter<W: Write, const BUFFER_SIZE: usize> {
+    writer: CryptoWriter<W, BUFFER_SIZE>,
+    manifest: Manifest,
+    /// The offset of the next member in the plaintext.
+    offset: u64,
+}
+
+impl<W: Write, const BUFFER_SIZE: usize> ArchiveWriter<W, BUFFER_SIZE> {
+    /// Create a new `ArchiveWriter`, and write the header of the archive.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer of the archive.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Errors
+    /// The errors of `CryptoWriter::new`.
+    ///
+    pub fn new(writer: W, key: impl Into<Recipient>) -> Result<Self> {
+        Self::new_with_options(writer, key, &WriterOptions::default())
+    }
+
+    /// Create a new `ArchiveWriter` with the given options, and write the header of the archive.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the options set a padding or chained chunks. (The members are located
+    ///   from the length of the archive, and decrypted on their own)
+    /// - The errors of `CryptoWriter::new_with_options`.
+    ///
+    pub fn new_with_options(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        if options.padding != Padding::None || options.chain_tags {
+            Err(error!(
+                InvalidInput,
+                "Archives don't support padding or chained chunks"
+            ))?;
+        }
+        Ok(Self {
+            writer: CryptoWriter::new_with_options(writer, key, options)?,
+            manifest: Manifest {
+                chunk_size: BUFFER_SIZE as u32,
+                entries: Vec::new(),
+            },
+            offset: 0,
+        })
+    }
+
+    /// Add a member, reading its data to the end.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the member. (Unique in the archive)
+    /// - `reader`: The data of the member.
+    ///
+    /// # Returns
+    /// The manifest entry of the member.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the name is longer than 65535 bytes, or already used.
+    /// - `Io`: If an I/O error occurs. (The archive is then unusable)
+    ///
+    pub fn add<R: Read>(&mut self, name: &str, reader: R) -> Result<&ManifestEntry> {
+        if name.len() > u16::MAX as usize {
+            Err(error!(InvalidInput, "Member name is too long"))?;
+        }
+        if self.manifest.get(name).is_some() {
+            Err(error!(InvalidInput, "Duplicate member name: {}", name))?;
+        }
+        let mut reader = TeeReader::<_, Sha256>::new(reader);
+        let size = io::copy(&mut reader, &mut self.writer)?;
+        let (_, sha256) = reader.finalize();
+        self.manifest.entries.push(ManifestEntry {
+            name: name.to_string(),
+            offset: self.offset,
+            size,
+            sha256: sha256.into(),
+        });
+        self.offset += size;
+        Ok(self.manifest.entries.last().expect("an entry was pushed"))
+    }
+
+    /// The manifest of the members added so far.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Write the manifest and the trailer, and end the stream.
+    ///
+    /// # Returns
+    /// The manifest of the archive.
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(mut self) -> Result<Manifest> {
+        self.writer.write_all(&self.manifest.to_bytes())?;
+        self.writer.write_all(&self.offset.to_be_bytes())?;
+        self.writer.write_all(&MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.manifest)
+    }
+}
+
+/// A reader of an encrypted archive, decrypting only the chunks it needs. (See the module
+/// documentation)
+pub struct ArchiveReader<R: Read + Seek, const BUFFER_SIZE: usize> {
+    reader: R,
+    header: Header,
+    raw_aes_key: Zeroizing<Vec<u8>>,
+    /// The position of the first chunk in the underlying reader.
+    chunks_start: u64,
+    manifest: Manifest,
+}
+
+impl<R: Read + Seek, const BUFFER_SIZE: usize> ArchiveReader<R, BUFFER_SIZE> {
+    /// Open an archive, starting at the current position of `reader`, and decrypt its manifest.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader of the archive.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the stream is not an archive, or is truncated or corrupted.
+    /// - `Error::WrongKey`: If the key doesn't decrypt the archive.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(mut reader: R, key: impl Into<Identity>) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let header = Header::parse(&mut reader)?;
+        if header.chunking != Chunking::Fixed
+            || header.chunk_size != Some(BUFFER_SIZE as u32)
+            || !header.end_marker
+            || header.padding != Padding::None
+            || header.chained
+        {
+            Err(error!(
+                InvalidData,
+                "Not an archive of {} bytes chunks", BUFFER_SIZE
+            ))?;
+        }
+        let (_, raw_aes_key) =
+            unwrap_data_key(&header, &[SlotKey::Rsa(key.into().into_rsa())], false)?;
+        let chunks_start = start + header.len() as u64;
+
+        // The plaintext length, from the length of the chunks (the final one holds a tag)
+        let chunks_len = reader
+            .seek(SeekFrom::End(0))?
+            .checked_sub(chunks_start)
+            .ok_or_else(|| error!(UnexpectedEof, "Truncated archive"))?;
+        let chunk_len = (BUFFER_SIZE + TAG_LEN) as u64;
+        let final_len = (chunks_len % chunk_len)
+            .checked_sub(TAG_LEN as u64)
+            .ok_or_else(|| error!(UnexpectedEof, "Truncated archive"))?;
+        let plaintext_len = chunks_len / chunk_len * BUFFER_SIZE as u64 + final_len;
+
+        let mut archive = Self {
+            reader,
+            header,
+            raw_aes_key,
+            chunks_start,
+            manifest: Manifest::default(),
+        };
+        let not_an_archive = || error!(InvalidData, "Not an archive: no manifest");
+        let trailer_offset = plaintext_len
+            .checked_sub(TRAILER_LEN)
+            .ok_or_else(not_an_archive)?;
+        let mut trailer = [0; TRAILER_LEN as usize];
+        archive
+            .read_range(trailer_offset, TRAILER_LEN)?
+            .read_exact(&mut trailer)?;
+        let (manifest_offset, magic) = trailer.split_at(8);
+        let manifest_offset = u64::from_be_bytes(manifest_offset.try_into().expect("8 bytes"));
+        if magic != MAGIC || manifest_offset > trailer_offset {
+            return Err(not_an_archive());
+        }
+
+        let mut manifest = Vec::new();
+        archive
+            .read_range(manifest_offset, trailer_offset - manifest_offset)?
+            .read_to_end(&mut manifest)?;
+        let manifest = Manifest::from_bytes(&manifest)?;
+        let in_bounds = |entry: &ManifestEntry| {
+            entry
+                .offset
+                .checked_add(entry.size)
+                .is_some_and(|end| end <= manifest_offset)
+        };
+        if manifest.chunk_size != BUFFER_SIZE as u32 || !manifest.entries.iter().all(in_bounds) {
+            Err(error!(InvalidData, "Invalid archive manifest"))?;
+        }
+        archive.manifest = manifest;
+        Ok(archive)
+    }
+
+    /// The manifest of the archive.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Decrypt a single member into `writer`, only decrypting the chunks holding it (see
+    /// `Manifest::chunks`), and check it against its SHA-256.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the member.
+    /// - `writer`: The writer of the plaintext of the member.
+    ///
+    /// # Returns
+    /// The size of the member.
+    ///
+    /// # Errors
+    /// - `NotFound`: If the archive has no member with this name.
+    /// - `InvalidData`: If the member doesn't match its SHA-256.
+    /// - `Error::CorruptedChunk`: If a chunk fails the authentication.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    /// # Notes
+    /// The member is written as it is decrypted: on error, discard what was written.
+    ///
+    pub fn extract<W: Write>(&mut self, name: &str, writer: &mut W) -> Result<u64> {
+        let entry = self
+            .manifest
+            .get(name)
+            .cloned()
+            .ok_or_else(|| error!(NotFound, "No member named {} in the archive", name))?;
+        self.copy_member(&entry, writer)
+    }
+
+    /// Check every member against its SHA-256, decrypting the whole archive. (Nothing is
+    /// written)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If a member doesn't match its SHA-256.
+    /// - `Error::CorruptedChunk`: If a chunk fails the authentication.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn verify(&mut self) -> Result<()> {
+        for index in 0..self.manifest.entries.len() {
+            let entry = self.manifest.entries[index].clone();
+            self.copy_member(&entry, &mut io::sink())?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt a member into `writer`, and check it against its SHA-256.
+    fn copy_member<W: Write>(&mut self, entry: &ManifestEntry, writer: &mut W) -> Result<u64> {
+        let mut reader = TeeReader::<_, Sha256>::new(self.read_range(entry.offset, entry.size)?);
+        let size = io::copy(&mut reader, writer)?;
+        let (_, sha256) = reader.finalize();
+        if size != entry.size || sha256[..] != entry.sha256 {
+            Err(error!(
+                InvalidData,
+                "Member {} doesn't match its checksum", entry.name
+            ))?;
+        }
+        Ok(size)
+    }
+
+    /// A reader of the plaintext range `offset..offset + len`, only decrypting the chunks holding
+    /// it.
+    fn read_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<io::Take<CryptoReader<&mut R, BUFFER_SIZE>>> {
+        let chunk = offset / BUFFER_SIZE as u64;
+        let chunk_len = (BUFFER_SIZE + TAG_LEN) as u64;
+        self.reader
+            .seek(SeekFrom::Start(self.chunks_start + chunk * chunk_len))?;
+        let mut reader =
+            CryptoReader::new_with_data_key(&mut self.reader, &self.header, &self.raw_aes_key)?;
+        reader.start_at_chunk(chunk);
+        // Skip the start of the first chunk
+        let skip = offset % BUFFER_SIZE as u64;
+        if io::copy(&mut (&mut reader).take(skip), &mut io::sink())? != skip {
+            Err(error!(UnexpectedEof, "Truncated archive"))?;
+        }
+        Ok(reader.take(len))
+    }
+}
@@ -0,0 +1,169 @@
+//! This module provides the audit hooks: an `AuditSink` installed with `set_audit_sink` receives a
+//! structured `AuditEvent` when a private key is loaded, a stream is started, a decryption fails,
+//! or a signature is verified. So an application can feed its security audit log without wrapping
+//! every call site.
+//!
+//! The sink is global to the process, and called synchronously by the thread doing the operation:
+//! it should be fast, and never call back into this crate. (e.g. push the event to a channel)
+//!
+//! The events never contain secret material: keys are identified by their fingerprint. (See
+//! `seal::fingerprint`)
+use super::{cipher::Cipher, header::Format, seal::fingerprint};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::{
+    io,
+    sync::{Arc, RwLock},
+};
+
+/// The sink installed by `set_audit_sink`.
+static AUDIT_SINK: RwLock<Option<Arc<dyn AuditSink>>> = RwLock::new(None);
+
+/// Where a private key was loaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeySource {
+    /// A PEM encoded key. (`RsaKeys::from_key_pem`, `RsaKeys::from_private_key_pem`)
+    Pem,
+    /// A DER encoded key. (`RsaKeys::from_pkcs1_der`, `RsaKeys::from_pkcs8_der`)
+    Der,
+    /// A key of a `KeyStore`. (`KeyStore::export`)
+    KeyStore,
+    /// A key sealed to a TPM. (`tpm::TpmSealedKey::unseal`)
+    Tpm,
+    /// A key of a PKCS#12 file. (`RsaKeys::from_pkcs12`)
+    Pkcs12,
+}
+
+/// The direction of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The stream is written by a `CryptoWriter`.
+    Encrypt,
+    /// The stream is read by a `CryptoReader`.
+    Decrypt,
+}
+
+/// The data covered by a verified signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Signed {
+    /// The payload of sealed data. (`seal::open`)
+    Sealed,
+    /// The self-signature of a `KeyBundle`. (`KeyBundle::verify`)
+    KeyBundle,
+}
+
+/// A security relevant event. (See the module documentation)
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AuditEvent<'a> {
+    /// A private key was loaded.
+    KeyLoaded {
+        source: KeySource,
+        /// The name of the key, if any. (e.g. in a `KeyStore`)
+        name: Option<&'a str>,
+        /// The fingerprint of the public key.
+        fingerprint: &'a str,
+    },
+    /// The header of a stream was written, or read and its AES key decrypted.
+    StreamStarted {
+        direction: Direction,
+        format: Format,
+        cipher: Cipher,
+        /// The number of wrapped key slots of the header.
+        recipients: usize,
+    },
+    /// The decryption of a stream failed. (Wrong key, corrupted or truncated stream, I/O error)
+    DecryptionFailed { error: &'a io::Error },
+    /// A signature was verified.
+    SignatureVerified {
+        signed: Signed,
+        /// The fingerprint of the public key of the signer.
+        signer: &'a str,
+        valid: bool,
+    },
+}
+
+/// A receiver of the audit events. (See `set_audit_sink`)
+///
+/// Implemented by the closures taking an `&AuditEvent`.
+pub trait AuditSink: Send + Sync {
+    /// Record an event.
+    fn record(&self, event: &AuditEvent<'_>);
+}
+
+impl<F: Fn(&AuditEvent<'_>) + Send + Sync> AuditSink for F {
+    fn record(&self, event: &AuditEvent<'_>) {
+        self(event)
+    }
+}
+
+/// Install the audit sink of the process.
+///
+/// # Returns
+/// The sink previously installed, if any.
+///
+pub fn set_audit_sink(sink: Arc<dyn AuditSink>) -> Option<Arc<dyn AuditSink>> {
+    lock_sink().replace(sink)
+}
+
+/// Remove the audit sink of the process.
+///
+/// # Returns
+/// The sink removed, if any.
+///
+pub fn clear_audit_sink() -> Option<Arc<dyn AuditSink>> {
+    lock_sink().take()
+}
+
+/// Record an event, if a sink is installed. The event is only built in that case.
+pub(crate) fn audit(event: impl FnOnce(&mut dyn FnMut(&AuditEvent<'_>))) {
+    // Release the lock before calling the sink, which may install another sink
+    let sink = AUDIT_SINK
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    if let Some(sink) = sink {
+        event(&mut |event| sink.record(event));
+    }
+}
+
+/// Record a decryption failure. (`WouldBlock` and `Interrupted` are not failures)
+pub(crate) fn audit_failure(error: &io::Error) {
+    if !matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    ) {
+        audit(|record| record(&AuditEvent::DecryptionFailed { error }));
+    }
+}
+
+/// Record the loading of a private key.
+pub(crate) fn audit_key_loaded(source: KeySource, name: Option<&str>, key: &RsaPrivateKey) {
+    audit(|record| {
+        let fingerprint = fingerprint(&key.to_public_key());
+        record(&AuditEvent::KeyLoaded {
+            source,
+            name,
+            fingerprint: &fingerprint,
+        })
+    });
+}
+
+/// Record the verification of a signature.
+pub(crate) fn audit_signature(signed: Signed, signer: &RsaPublicKey, valid: bool) {
+    audit(|record| {
+        let signer = fingerprint(signer);
+        record(&AuditEvent::SignatureVerified {
+            signed,
+            signer: &signer,
+            valid,
+        })
+    });
+}
+
+fn lock_sink() -> std::sync::RwLockWriteGuard<'static, Option<Arc<dyn AuditSink>>> {
+    AUDIT_SINK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
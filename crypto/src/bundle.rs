@@ -0,0 +1,313 @@
+//! This module provides `KeyBundle`: a RSA public key with the name of its owner and its validity
+//! period, self-signed by the matching private key.
+//!
+//! A bundle asserts "this key belongs to `owner`" without a certificate authority (no X.509): the
+//! self-signature proves the owner holds the private key, and that the metadata wasn't changed
+//! since. Whether the owner is who they claim to be is still up to the receiver. (e.g. by checking
+//! the fingerprint out of band, see `seal::fingerprint`)
+//!
+//! A bundle is encoded as follows: (`to_bytes`, or `to_pem` with the `CRYPTO KEY BUNDLE` label)
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |      Magic      |   |    OWNER LEN    |   |      Owner      |   |     Created     |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |    "CRYPTOKB"   |   |     u16 BE      |   |     UTF-8       |   |  u64 BE (Unix)  |   ...
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        8        |   |        2        |   |    OWNER LEN    |   |        8        |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!     |     Expires     |   |     KEY LEN     |   |   Public Key    |   |  SIGNATURE LEN  |
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ... |  u64 BE (Unix)  |   |     u16 BE      |   |   PKCS#1 DER    |   |     u16 BE      |   ...
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!     |        8        |   |        2        |   |     KEY LEN     |   |        2        |
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!
+//!     +-----------------+
+//!     |    Signature    |
+//!     +-----------------+
+//! ... |  RSASSA-PSS     |
+//!     +-----------------+
+//!     |  SIGNATURE LEN  |
+//!     +-----------------+
+//! ```
+//!
+//! The timestamps are in seconds since the Unix epoch, `Expires` is `0` if the key doesn't expire.
+//! The signature (RSASSA-PSS, SHA-256) covers every field before it.
+use super::{
+    audit::{audit_signature, Signed},
+    error::{error, Error, Result},
+    shared::setup_rng,
+};
+use rand::{CryptoRng, RngCore};
+use rsa::{
+    pkcs1::{DecodeRsaPublicKey as _, EncodeRsaPublicKey as _},
+    pkcs8::{der::pem, LineEnding},
+    Pss, RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest as _, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The magic bytes of an encoded bundle.
+const BUNDLE_MAGIC: &[u8; 8] = b"CRYPTOKB";
+/// The PEM label of a bundle.
+const BUNDLE_PEM_LABEL: &str = "CRYPTO KEY BUNDLE";
+/// Domain separation of the signed digest.
+const BUNDLE_LABEL: &[u8] = b"crypto key bundle";
+
+/// The current time, in seconds since the Unix epoch.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// A RSA public key, with the name of its owner and its validity period, self-signed by the
+/// private key.
+///
+/// The fields can be read freely, but any change breaks the signature. (See `verify`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBundle {
+    /// The public key.
+    pub public_key: RsaPublicKey,
+    /// The name of the owner of the key. (e.g. `alice@example.com`)
+    pub owner: String,
+    /// The creation time, in seconds since the Unix epoch.
+    pub created: u64,
+    /// The expiry time, in seconds since the Unix epoch. (`None` if the key doesn't expire)
+    pub expires: Option<u64>,
+    signature: Vec<u8>,
+}
+
+impl KeyBundle {
+    /// Create a bundle for the public key of `private_key`, signed by it.
+    ///
+    /// # Arguments
+    /// - `private_key`: The RSA private key.
+    /// - `owner`: The name of the owner of the key.
+    /// - `created`: The creation time, in seconds since the Unix epoch.
+    /// - `expires`: The expiry time, in seconds since the Unix epoch. (`None` if the key doesn't
+    ///   expire)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the owner is too long (more than 65535 bytes), or the key expires
+    ///   before it is created.
+    /// - `Other`: If the signature fails.
+    ///
+    pub fn sign(
+        private_key: &RsaPrivateKey,
+        owner: &str,
+        created: u64,
+        expires: Option<u64>,
+    ) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::sign_with_rng(private_key, owner, created, expires, &mut rng)
+    }
+
+    /// Create a bundle for the public key of `private_key`, signed by it, with the given random
+    /// number generator.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn sign_with_rng<R: CryptoRng + RngCore>(
+        private_key: &RsaPrivateKey,
+        owner: &str,
+        created: u64,
+        expires: Option<u64>,
+        rng: &mut R,
+    ) -> Result<Self> {
+        if expires.is_some_and(|expires| expires <= created) {
+            Err(error!(
+                InvalidInput,
+                "Key bundle expires before it is created"
+            ))?;
+        }
+        let mut bundle = Self {
+            public_key: private_key.to_public_key(),
+            owner: owner.into(),
+            created,
+            expires,
+            signature: Vec::new(),
+        };
+        bundle.signature = private_key
+            .sign_with_rng(rng, Pss::new::<Sha256>(), &bundle.signed_digest()?)
+            .map_err(|e| error!(Other, "RSA Signature error: {}", e))?;
+        Ok(bundle)
+    }
+
+    /// The self-signature of the bundle.
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Verify the self-signature of the bundle.
+    ///
+    /// This doesn't check the validity period. (See `is_expired`)
+    ///
+    /// # Errors
+    /// - `Error::BadSignature`: If the signature doesn't match the fields.
+    /// - `InvalidInput`: If a field can't be encoded.
+    ///
+    pub fn verify(&self) -> Result<()> {
+        let valid = self
+            .public_key
+            .verify(
+                Pss::new::<Sha256>(),
+                &self.signed_digest()?,
+                &self.signature,
+            )
+            .is_ok();
+        audit_signature(Signed::KeyBundle, &self.public_key, valid);
+        if !valid {
+            Err(Error::BadSignature)?;
+        }
+        Ok(())
+    }
+
+    /// Verify the self-signature of the bundle, and check that the key isn't expired at `time`.
+    /// (In seconds since the Unix epoch)
+    ///
+    /// # Errors
+    /// - `Error::KeyExpired`: If the key is expired at `time`.
+    /// - The errors of `verify`.
+    ///
+    pub fn check_valid_at(&self, time: u64) -> Result<()> {
+        self.verify()?;
+        match self.expires {
+            Some(expires) if self.is_expired_at(time) => Err(Error::KeyExpired { expires }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Verify the self-signature of the bundle, and check that the key isn't expired now.
+    ///
+    /// # Errors
+    /// The errors of `check_valid_at`.
+    ///
+    pub fn check_valid(&self) -> Result<()> {
+        self.check_valid_at(unix_now())
+    }
+
+    /// Whether the key is expired at `time`. (In seconds since the Unix epoch)
+    pub fn is_expired_at(&self, time: u64) -> bool {
+        self.expires.is_some_and(|expires| time >= expires)
+    }
+
+    /// Whether the key is expired now.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(unix_now())
+    }
+
+    /// The signed fields of the bundle.
+    fn signed_fields(&self) -> Result<Vec<u8>> {
+        let owner_len = u16::try_from(self.owner.len())
+            .map_err(|_| error!(InvalidInput, "Key bundle owner is too long"))?;
+        let key = self
+            .public_key
+            .to_pkcs1_der()
+            .map_err(|e| error!(InvalidInput, "Failed to encode the public key: {}", e))?;
+        let key_len = u16::try_from(key.as_bytes().len())
+            .map_err(|_| error!(InvalidInput, "Public key is too long"))?;
+
+        let mut fields = Vec::with_capacity(30 + self.owner.len() + key.as_bytes().len());
+        fields.extend_from_slice(BUNDLE_MAGIC);
+        fields.extend_from_slice(&owner_len.to_be_bytes());
+        fields.extend_from_slice(self.owner.as_bytes());
+        fields.extend_from_slice(&self.created.to_be_bytes());
+        fields.extend_from_slice(&self.expires.unwrap_or(0).to_be_bytes());
+        fields.extend_from_slice(&key_len.to_be_bytes());
+        fields.extend_from_slice(key.as_bytes());
+        Ok(fields)
+    }
+
+    /// The digest signed by the private key.
+    fn signed_digest(&self) -> Result<[u8; 32]> {
+        Ok(Sha256::new()
+            .chain_update(BUNDLE_LABEL)
+            .chain_update(self.signed_fields()?)
+            .finalize()
+            .into())
+    }
+
+    /// Encode the bundle.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If a field is too long.
+    ///
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.signed_fields()?;
+        let signature_len = u16::try_from(self.signature.len())
+            .map_err(|_| error!(InvalidInput, "Signature is too long"))?;
+        bytes.extend_from_slice(&signature_len.to_be_bytes());
+        bytes.extend_from_slice(&self.signature);
+        Ok(bytes)
+    }
+
+    /// Decode a bundle encoded by `to_bytes`.
+    ///
+    /// The signature is not verified. (See `verify`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the bundle is malformed.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || error!(InvalidData, "Invalid key bundle");
+        let bytes = bytes.strip_prefix(BUNDLE_MAGIC).ok_or_else(invalid)?;
+        let (owner, bytes) = split_field(bytes).ok_or_else(invalid)?;
+        let (created, bytes) = bytes.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let (expires, bytes) = bytes.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let (key, bytes) = split_field(bytes).ok_or_else(invalid)?;
+        let (signature, bytes) = split_field(bytes).ok_or_else(invalid)?;
+        if !bytes.is_empty() {
+            return Err(invalid());
+        }
+
+        let owner = core::str::from_utf8(owner)
+            .map_err(|_| error!(InvalidData, "Key bundle owner is not valid UTF-8"))?;
+        let public_key = RsaPublicKey::from_pkcs1_der(key)
+            .map_err(|e| error!(InvalidData, "Invalid public key: {}", e))?;
+        Ok(Self {
+            public_key,
+            owner: owner.into(),
+            created: u64::from_be_bytes(*created),
+            expires: Some(u64::from_be_bytes(*expires)).filter(|&expires| expires != 0),
+            signature: signature.to_vec(),
+        })
+    }
+
+    /// Encode the bundle in PEM. (`CRYPTO KEY BUNDLE` label)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If a field is too long.
+    ///
+    pub fn to_pem(&self) -> Result<String> {
+        pem::encode_string(BUNDLE_PEM_LABEL, LineEnding::LF, &self.to_bytes()?)
+            .map_err(|e| error!(InvalidInput, "Failed to encode the key bundle: {}", e))
+    }
+
+    /// Decode a bundle encoded by `to_pem`.
+    ///
+    /// The signature is not verified. (See `verify`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the PEM or the bundle is malformed.
+    ///
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let (label, bytes) = pem::decode_vec(pem.as_bytes())
+            .map_err(|e| error!(InvalidData, "Invalid key bundle PEM: {}", e))?;
+        if label != BUNDLE_PEM_LABEL {
+            Err(error!(InvalidData, "Unexpected PEM label: {}", label))?;
+        }
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Split a field prefixed by its length. (u16 BE)
+fn split_field(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, bytes) = bytes.split_first_chunk::<2>()?;
+    bytes.split_at_checked(u16::from_be_bytes(*len) as usize)
+}
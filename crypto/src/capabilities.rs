@@ -0,0 +1,95 @@
+//! This module reports the hardware acceleration used by the cipher.
+//!
+//! AES-GCM is computed with CPU instructions when available: AES-NI and PCLMULQDQ on x86, the
+//! ARMv8 cryptography extensions (AES and PMULL, alongside NEON) on aarch64. Otherwise the
+//! constant-time software implementation is used, which is several times slower.
+//!
+//! On x86, the instructions are detected at runtime (at compile time without the `std` feature).
+//! On aarch64, the RustCrypto crates only use them when built with `--cfg aes_armv8` and
+//! `--cfg polyval_armv8`. (e.g. in `RUSTFLAGS`) The `aes_force_soft` and `polyval_force_soft`
+//! flags force the software implementation.
+
+/// The hardware acceleration available to the cipher. (See `capabilities`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities {
+    /// Whether the AES rounds use CPU instructions. (AES-NI, or the ARMv8 AES instructions)
+    pub aes: bool,
+    /// Whether the GHASH authenticator of AES-GCM uses carry-less multiplication instructions.
+    /// (PCLMULQDQ, or the ARMv8 PMULL instructions)
+    pub clmul: bool,
+}
+
+impl Capabilities {
+    /// Whether AES-GCM is fully computed with CPU instructions.
+    pub fn is_accelerated(&self) -> bool {
+        self.aes && self.clmul
+    }
+}
+
+/// Report the hardware acceleration used to encrypt and decrypt the chunks.
+///
+/// # Returns
+/// The `Capabilities` of the current CPU, for the cipher as built.
+///
+/// # Notes
+/// Useful to warn before a large job on a machine without acceleration, e.g. a VM hiding the CPU
+/// flags from its guests.
+///
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        aes: aes_instructions(),
+        clmul: clmul_instructions(),
+    }
+}
+
+#[cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+macro_rules! x86_feature {
+    ($feature:tt) => {
+        std::arch::is_x86_feature_detected!($feature)
+    };
+}
+
+#[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+macro_rules! x86_feature {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+#[cfg(all(feature = "std", target_arch = "aarch64"))]
+macro_rules! aarch64_feature {
+    ($feature:tt) => {
+        std::arch::is_aarch64_feature_detected!($feature)
+    };
+}
+
+#[cfg(all(not(feature = "std"), target_arch = "aarch64"))]
+macro_rules! aarch64_feature {
+    ($feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+/// The same detection as the `aes` crate.
+fn aes_instructions() -> bool {
+    #[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(aes_force_soft)))]
+    return x86_feature!("aes");
+    #[cfg(all(target_arch = "aarch64", aes_armv8, not(aes_force_soft)))]
+    return aarch64_feature!("aes");
+    #[allow(unreachable_code)]
+    false
+}
+
+/// The same detection as the `polyval` crate. (Used by GHASH)
+fn clmul_instructions() -> bool {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        not(polyval_force_soft)
+    ))]
+    return x86_feature!("pclmulqdq");
+    // PMULL is part of the ARMv8 AES extension
+    #[cfg(all(target_arch = "aarch64", polyval_armv8, not(polyval_force_soft)))]
+    return aarch64_feature!("aes");
+    #[allow(unreachable_code)]
+    false
+}
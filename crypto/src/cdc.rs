@@ -0,0 +1,594 @@
+//! This module provides a content-defined chunking (FastCDC) writer and reader.
+//!
+//! `CryptoWriter` cuts the plaintext every `BUFFER_SIZE` bytes, so inserting a single byte at the
+//! beginning of a file changes every chunk of the ciphertext. `CdcWriter` cuts the plaintext on
+//! boundaries chosen from its content (a rolling Gear hash, as described in the FastCDC paper),
+//! so unchanged regions of a file produce the same chunks across versions.
+//!
+//! Two `Chunking` modes are available:
+//! - `Chunking::ContentDefined`: Each chunk is encrypted with the stream AES key. The boundaries
+//!   are stable, but the ciphertexts are not. (The nonce changes for each stream)
+//! - `Chunking::Convergent`: Each chunk is encrypted with a key derived from its content and a
+//!   convergence secret (`HMAC-SHA256(secret, chunk)`). The same chunk always produces the same
+//!   ciphertext, so a dedupe-capable store can recognize unchanged chunks. The chunk key is
+//!   itself encrypted with the stream AES key.
+//!
+//! The stream starts with a `Header` (with the `chunking` field set, and `chunk_size` holding the
+//! maximum chunk size), followed by one frame per chunk:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   AES DATA LEN  |   |    Chunk Key    |   |    AES Data     |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     u32 BE      |   |  (Convergent)   |   |                 |   ...
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        4        |   | 32 + AUTH TAG   |   |  AES DATA LEN   |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! In `Chunking::Convergent` mode, the `AES Data` part only depends on the chunk content and the
+//! convergence secret, so it can be used as the deduplication unit by the store.
+//!
+//! The stream ends with an empty final frame: a single tag (`AES DATA LEN` is `AUTH TAG LEN`, with
+//! no chunk key), encrypted with the stream AES key and the next nonce, and authenticated as the
+//! end of the stream. (See `Header::end_marker`) A stream truncated on a frame boundary fails with
+//! `UnexpectedEof`.
+//!
+//! Everything encrypted with the stream AES key (the chunks, or the chunk keys in
+//! `Chunking::Convergent` mode, and the final frame) authenticates the hash of the header (see
+//! `Header::digest`), so a modified header fails the first frame. The convergent chunks don't, to
+//! stay the same across streams.
+//!
+//! **Warning**: Convergent encryption reveals which chunks are equal. Anyone holding the
+//! convergence secret can check whether a stream contains a known chunk. Only share the secret
+//! between writers whose streams should be deduplicated together.
+//!
+//! **Note**: The Gear table and the cut-point rules are part of the format. Changing them would
+//! change the boundaries, and break the deduplication with existing streams.
+use super::{
+    cipher::Cipher,
+    decrypt::{unwrap_data_key, SlotKey},
+    encrypt::{generate_aes_key, key_check_value, key_commitment, wrap_data_key, WriterOptions},
+    error::{error, Result},
+    header::{Chunking, Format, Header},
+    identity::{Identity, Recipient},
+    shared::{
+        setup_rng, ChunkAad, Nonce, NonceCounter, AES_AUTH_TAG_LEN, AES_DATA_KEY_LEN,
+        HEADER_DIGEST_LEN,
+    },
+};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    AeadCore as _, Aes256Gcm, Key, KeyInit as _,
+};
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
+use rsa::{pkcs8::der::zeroize::Zeroizing, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+
+/// The length of an encrypted chunk key. (`Chunking::Convergent` only)
+const WRAPPED_CHUNK_KEY_LEN: usize = AES_DATA_KEY_LEN + AES_AUTH_TAG_LEN;
+
+/// Options used to create a `CdcWriter`.
+#[derive(Debug, Clone)]
+pub struct CdcOptions {
+    /// The minimum chunk size. (Except for the final chunk)
+    pub min_size: u32,
+    /// The expected average chunk size. (Must be a power of two)
+    pub avg_size: u32,
+    /// The maximum chunk size.
+    pub max_size: u32,
+    /// The convergence secret. If set, the stream uses `Chunking::Convergent`, otherwise
+    /// `Chunking::ContentDefined`.
+    pub convergence_secret: Option<[u8; 32]>,
+    /// Additional recipients of the stream. (See `WriterOptions`)
+    pub additional_recipients: Vec<RsaPublicKey>,
+}
+
+impl Default for CdcOptions {
+    fn default() -> Self {
+        Self {
+            min_size: 2 * 1024,
+            avg_size: 8 * 1024,
+            max_size: 64 * 1024,
+            convergence_secret: None,
+            additional_recipients: Vec::new(),
+        }
+    }
+}
+
+impl CdcOptions {
+    fn validate(&self) -> Result<()> {
+        if !self.avg_size.is_power_of_two()
+            || self.min_size < 64
+            || self.min_size >= self.avg_size
+            || self.avg_size >= self.max_size
+        {
+            Err(error!(
+                InvalidInput,
+                "Invalid chunk sizes: {} / {} / {} (min / avg / max)",
+                self.min_size,
+                self.avg_size,
+                self.max_size
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// A writer that cuts the data on content-defined boundaries, and encrypts each chunk before
+/// writing it to the writer.
+///
+/// See the module documentation for the format of the stream.
+pub struct CdcWriter<W: std::io::Write> {
+    writer: W,
+    nonce: NonceCounter,
+    cipher: Aes256Gcm,
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    chunker: Chunker,
+    convergence_secret: Option<Zeroizing<[u8; 32]>>,
+    buffer: Zeroizing<Vec<u8>>,
+    has_been_flushed: bool,
+}
+
+impl<W: std::io::Write> CdcWriter<W> {
+    /// Create a new `CdcWriter` instance.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The chunking options. (See `CdcOptions`)
+    ///
+    /// # Returns
+    /// A `CdcWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the chunk sizes are invalid.
+    /// - `Other`: If the RSA encryption fails.
+    /// - `Io`: If an I/O error occurs while writing the header.
+    ///
+    pub fn new(writer: W, key: impl Into<Recipient>, options: &CdcOptions) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::new_with_rng(writer, key, options, &mut rng)
+    }
+
+    /// Create a new `CdcWriter` instance with the given random number generator.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The chunking options. (See `CdcOptions`)
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// A `CdcWriter` instance.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn new_with_rng<R: CryptoRng + RngCore>(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        options: &CdcOptions,
+        mut rng: R,
+    ) -> Result<Self> {
+        options.validate()?;
+        let key = key.into().into_rsa();
+
+        let aes_key = generate_aes_key(&mut rng);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        let writer_options = WriterOptions {
+            additional_recipients: options.additional_recipients.clone(),
+            ..Default::default()
+        };
+
+        let header = Header {
+            chunk_size: Some(options.max_size),
+            chunking: match options.convergence_secret {
+                Some(_) => Chunking::Convergent,
+                None => Chunking::ContentDefined,
+            },
+            end_marker: true,
+            key_check: Some(key_check_value(&aes_key)),
+            commitment: Some(key_commitment(&aes_key)),
+            key_wrap: writer_options.key_wrap,
+            ..Header::new(
+                Format::LATEST,
+                wrap_data_key(&key, &aes_key, &writer_options, &mut rng)?,
+                nonce.into(),
+            )
+        };
+        header.write(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            nonce: NonceCounter::new(nonce),
+            cipher: Aes256Gcm::new(&aes_key),
+            header_digest: header.digest()?,
+            chunker: Chunker::new(options),
+            convergence_secret: options.convergence_secret.map(Zeroizing::new),
+            buffer: Zeroizing::new(Vec::with_capacity(2 * options.max_size as usize)),
+            has_been_flushed: false,
+        })
+    }
+
+    /// Encrypt and write the chunks found in the buffer.
+    /// The last chunk is kept in the buffer unless `is_final` is set, as its boundary may depend
+    /// on the data not written yet.
+    fn write_chunks(&mut self, is_final: bool) -> Result<()> {
+        let aad = ChunkAad::new(self.header_digest.as_ref(), None, false);
+        let mut start = 0;
+        while self.buffer.len() - start >= self.chunker.max_size
+            || (is_final && start < self.buffer.len())
+        {
+            let len = self.chunker.cut(&self.buffer[start..]);
+            let chunk = &self.buffer[start..start + len];
+
+            let (wrapped_chunk_key, encrypted_data) = match &self.convergence_secret {
+                Some(secret) => {
+                    let chunk_key = derive_chunk_key(secret, chunk);
+                    let encrypted_data = Aes256Gcm::new(&chunk_key)
+                        .encrypt(&Nonce::default(), chunk)
+                        .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+                    let payload = Payload {
+                        msg: chunk_key.as_slice(),
+                        aad: aad.as_bytes(),
+                    };
+                    let wrapped_chunk_key = self
+                        .cipher
+                        .encrypt(self.nonce.nonce(), payload)
+                        .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+                    (Some(wrapped_chunk_key), encrypted_data)
+                }
+                None => {
+                    let payload = Payload {
+                        msg: chunk,
+                        aad: aad.as_bytes(),
+                    };
+                    let encrypted_data = self
+                        .cipher
+                        .encrypt(self.nonce.nonce(), payload)
+                        .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+                    (None, encrypted_data)
+                }
+            };
+
+            self.writer
+                .write_all(&(encrypted_data.len() as u32).to_be_bytes())?;
+            if let Some(wrapped_chunk_key) = wrapped_chunk_key {
+                self.writer.write_all(&wrapped_chunk_key)?;
+            }
+            self.writer.write_all(&encrypted_data)?;
+
+            self.nonce.increment();
+            start += len;
+        }
+        self.buffer.drain(..start);
+        Ok(())
+    }
+
+    /// Write the empty final frame, marking the end of the stream.
+    fn write_final_frame(&mut self) -> Result<()> {
+        let aad = ChunkAad::new(self.header_digest.as_ref(), None, true);
+        let payload = Payload {
+            msg: &[],
+            aad: aad.as_bytes(),
+        };
+        let tag = self
+            .cipher
+            .encrypt(self.nonce.nonce(), payload)
+            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+        self.writer.write_all(&(tag.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&tag)?;
+        self.nonce.increment();
+        Ok(())
+    }
+}
+
+/// Drop the `CdcWriter` instance.
+/// Flush the writer before dropping the `CdcWriter` instance.
+impl<W: std::io::Write> Drop for CdcWriter<W> {
+    /// Flush the writer before dropping the `CdcWriter` instance.
+    ///
+    /// # Panics
+    /// If an I/O error occurs while flushing the writer.
+    /// If a Cryptographic error occurs while encrypting the data.
+    ///
+    fn drop(&mut self) {
+        if self.has_been_flushed {
+            return;
+        }
+        if let Err(e) = std::io::Write::flush(self) {
+            panic!("Failed to flush the writer: {}", e);
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for CdcWriter<W> {
+    /// Write data to the writer.
+    /// The data is buffered until a full chunk can be cut.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.has_been_flushed {
+            Err(error!(Other, "The writer has already been flushed"))?;
+        }
+        self.buffer.extend_from_slice(buf);
+        self.write_chunks(false)?;
+        Ok(buf.len())
+    }
+
+    /// Flush the writer.
+    /// The remaining data is written as the final chunks, followed by the final frame. As with
+    /// `CryptoWriter`, no more data can be written afterwards.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.has_been_flushed {
+            Err(error!(Other, "The writer has already been flushed"))?;
+        }
+        self.write_chunks(true)?;
+        self.write_final_frame()?;
+        self.writer.flush()?;
+        self.has_been_flushed = true;
+        Ok(())
+    }
+}
+
+/// A reader that decrypts a stream written by `CdcWriter`.
+pub struct CdcReader<R: std::io::Read> {
+    reader: R,
+    chunking: Chunking,
+    max_size: usize,
+    nonce: NonceCounter,
+    cipher: Aes256Gcm,
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    buffer: Zeroizing<Vec<u8>>,
+    buffer_pos: usize,
+    /// Whether the final frame was read.
+    finished: bool,
+}
+
+impl<R: std::io::Read> CdcReader<R> {
+    /// Create a new `CdcReader` instance.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// A `CdcReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the header is malformed, or the stream was not written by `CdcWriter`.
+    ///   (Or has no end marker)
+    /// - `Other`: If the RSA decryption fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(reader: R, key: impl Into<Identity>) -> Result<Self> {
+        let key = key.into().into_rsa();
+        Self::new_try_keys(reader, std::slice::from_ref(&key)).map(|(reader, _)| reader)
+    }
+
+    /// Create a new `CdcReader` instance, trying each of the given keys.
+    /// (See `CryptoReader::new_try_keys`)
+    ///
+    /// # Returns
+    /// A `CdcReader` instance, and the index of the key that decrypted the AES key.
+    ///
+    pub fn new_try_keys(mut reader: R, keys: &[RsaPrivateKey]) -> Result<(Self, usize)> {
+        let header = Header::parse(&mut reader)?;
+        let (Some(max_size), Chunking::ContentDefined | Chunking::Convergent) =
+            (header.chunk_size, header.chunking)
+        else {
+            Err(error!(
+                InvalidData,
+                "Stream uses {:?} chunking, read it with `{}`",
+                header.chunking,
+                header.chunking.reader()
+            ))?
+        };
+        if header.cipher != Cipher::Aes256Gcm {
+            Err(error!(
+                InvalidData,
+                "Unsupported cipher for content-defined chunks: {:?}", header.cipher
+            ))?;
+        }
+        if !header.end_marker {
+            Err(error!(
+                InvalidData,
+                "Content-defined streams require an end marker"
+            ))?;
+        }
+        let keys: Vec<_> = keys.iter().cloned().map(SlotKey::Rsa).collect();
+        let (index, raw_aes_key) = unwrap_data_key(&header, &keys, false)?;
+
+        Ok((
+            Self {
+                reader,
+                chunking: header.chunking,
+                max_size: max_size as usize,
+                nonce: NonceCounter::new(Nonce::from(header.nonce)),
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&raw_aes_key)),
+                header_digest: header.digest()?,
+                buffer: Zeroizing::new(Vec::new()),
+                buffer_pos: 0,
+                finished: false,
+            },
+            index,
+        ))
+    }
+
+    /// Read and decrypt the next frame into the inner buffer.
+    ///
+    /// # Returns
+    /// `false` once the final frame is read.
+    ///
+    /// # Errors
+    /// - `UnexpectedEof`: If the underlying reader is closed before the final frame.
+    ///
+    fn read_frame(&mut self) -> Result<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.reader.read(&mut len[read..])? {
+                0 if read == 0 => Err(error!(
+                    UnexpectedEof,
+                    "Truncated stream: the final frame is missing"
+                ))?,
+                0 => Err(error!(UnexpectedEof, "Truncated chunk length"))?,
+                n => read += n,
+            }
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len == AES_AUTH_TAG_LEN {
+            // The final frame is empty, without chunk key
+            let mut tag = [0; AES_AUTH_TAG_LEN];
+            self.reader.read_exact(&mut tag)?;
+            let aad = ChunkAad::new(self.header_digest.as_ref(), None, true);
+            let payload = Payload {
+                msg: &tag,
+                aad: aad.as_bytes(),
+            };
+            self.cipher
+                .decrypt(self.nonce.nonce(), payload)
+                .map_err(|e| error!(Other, "AES Decryption error: {}", e))?;
+            self.finished = true;
+            return Ok(false);
+        }
+        if len < AES_AUTH_TAG_LEN || len > self.max_size + AES_AUTH_TAG_LEN {
+            Err(error!(InvalidData, "Invalid chunk length: {}", len))?;
+        }
+
+        let aad = ChunkAad::new(self.header_digest.as_ref(), None, false);
+        let chunk_cipher = if self.chunking == Chunking::Convergent {
+            let mut wrapped_chunk_key = [0; WRAPPED_CHUNK_KEY_LEN];
+            self.reader.read_exact(&mut wrapped_chunk_key)?;
+            let payload = Payload {
+                msg: &wrapped_chunk_key,
+                aad: aad.as_bytes(),
+            };
+            let chunk_key = Zeroizing::new(
+                self.cipher
+                    .decrypt(self.nonce.nonce(), payload)
+                    .map_err(|e| error!(Other, "AES Decryption error: {}", e))?,
+            );
+            Some(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&chunk_key)))
+        } else {
+            None
+        };
+
+        let mut encrypted_data = vec![0; len];
+        self.reader.read_exact(&mut encrypted_data)?;
+        let data = match &chunk_cipher {
+            Some(cipher) => cipher.decrypt(&Nonce::default(), encrypted_data.as_slice()),
+            None => {
+                let payload = Payload {
+                    msg: &encrypted_data,
+                    aad: aad.as_bytes(),
+                };
+                self.cipher.decrypt(self.nonce.nonce(), payload)
+            }
+        }
+        .map_err(|e| error!(Other, "AES Decryption error: {}", e))?;
+
+        self.nonce.increment();
+        self.buffer = Zeroizing::new(data);
+        self.buffer_pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for CdcReader<R> {
+    /// Read decrypted data from the underlying reader.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut total_read = 0;
+
+        while total_read < buf.len() {
+            if self.buffer_pos == self.buffer.len() && !self.read_frame()? {
+                // The final frame is read
+                break;
+            }
+
+            let to_copy =
+                std::cmp::min(buf.len() - total_read, self.buffer.len() - self.buffer_pos);
+            buf[total_read..total_read + to_copy]
+                .copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + to_copy]);
+            self.buffer_pos += to_copy;
+            total_read += to_copy;
+        }
+
+        Ok(total_read)
+    }
+}
+
+/// Derive the key of a chunk from its content. (`Chunking::Convergent` only)
+fn derive_chunk_key(secret: &[u8; 32], chunk: &[u8]) -> Key<Aes256Gcm> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(chunk);
+    mac.finalize().into_bytes()
+}
+
+/// FastCDC cut-point finder, with normalized chunking.
+struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    /// Stricter mask, used before `avg_size`.
+    mask_s: u64,
+    /// Looser mask, used after `avg_size`.
+    mask_l: u64,
+}
+
+impl Chunker {
+    fn new(options: &CdcOptions) -> Self {
+        let bits = options.avg_size.trailing_zeros();
+        // Keep the most significant bits, which depend on the whole 64 bytes window
+        let mask = |bits: u32| !(u64::MAX >> bits);
+        Self {
+            min_size: options.min_size as usize,
+            avg_size: options.avg_size as usize,
+            max_size: options.max_size as usize,
+            mask_s: mask(bits + 2),
+            mask_l: mask(bits.saturating_sub(2)),
+        }
+    }
+
+    /// Find the length of the first chunk of `data`.
+    fn cut(&self, data: &[u8]) -> usize {
+        let end = std::cmp::min(data.len(), self.max_size);
+        if end <= self.min_size {
+            return end;
+        }
+        let normal = std::cmp::min(end, self.avg_size);
+
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal { self.mask_s } else { self.mask_l };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+/// The Gear table: 256 pseudo-random values. (SplitMix64, seeded with 0)
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
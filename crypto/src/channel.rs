@@ -0,0 +1,530 @@
+//! This module provides `CryptoChannel`, a bidirectional encrypted channel over a single stream.
+//! (e.g. a `TcpStream`)
+//!
+//! `CryptoWriter` and `CryptoReader` are one-way, and `CryptoWriter::flush` ends the stream. A
+//! channel instead needs both directions over the same connection, and must be able to send
+//! partial data whenever the caller flushes.
+//!
+//! The channel starts with a handshake. Each side sends a `Header` holding a random secret,
+//! encrypted with the RSA public key of the peer, then reads the header of the peer and decrypts
+//! its secret with its own RSA private key. The traffic keys are derived from both secrets:
+//!
+//! ```plaintext
+//! key (A -> B) = SHA-256("crypto channel" || secret A || secret B)
+//! key (B -> A) = SHA-256("crypto channel" || secret B || secret A)
+//! ```
+//!
+//! Only the holder of the expected private key can decrypt the secret sent to it, so both sides
+//! are authenticated: a peer which doesn't hold the right private key can neither read the
+//! traffic, nor send a frame that passes the authentication. The secret is decrypted with
+//! implicit rejection, so a bad secret isn't reported by the handshake (a padding oracle), but
+//! fails the first frame.
+//!
+//! After the handshake, the data is sent in frames of at most `BUFFER_SIZE` plaintext bytes:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   AES DATA LEN  |   |    SEQUENCE     |   |    AES Data     |
+//! +-----------------+   +-----------------+   +-----------------+   ...
+//! |     u32 BE      |   |     u64 BE      |   |                 |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        4        |   |        8        |   |  AES DATA LEN   |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The nonce of each direction starts at zero, and is incremented for each frame. (The keys are
+//! unique to the connection) The sequence number of a frame is the value of this nonce, so the
+//! receiver can tell a replayed frame (`Error::Replayed`) or a missing one (`Error::OutOfOrder`)
+//! apart from a tampered one. As the channel runs over an ordered stream, unlike `CryptoDatagram`
+//! no reordering is accepted: each frame must be the next one.
+//!
+//! With the `noise` feature, the handshake can instead be a Noise `XX` or `IK` handshake
+//! (`Noise_XX_25519_AESGCM_SHA256`, `Noise_IK_25519_AESGCM_SHA256`) using X25519 static keys. (See
+//! `CryptoChannel::connect_noise`) Both sides are authenticated by their static keys, and the
+//! ephemeral keys provide forward secrecy: the traffic of a recorded connection can't be
+//! decrypted even if the static keys leak later. The frames are then encrypted by Noise, and
+//! the handshake messages are sent with a `u16 BE` length prefix.
+//!
+//! **Warning**: There is no end-of-channel marker, so a connection closed by an attacker between
+//! two frames looks like a regular end of the channel. Applications must detect the end of
+//! their messages on their own.
+use super::{
+    error::{error, Error, Result},
+    header::{Format, Header},
+    key::RsaKeys,
+    key_wrap::KeyWrap,
+    shared::{setup_rng, Nonce, NonceCounter, AES_AUTH_TAG_LEN, AES_DATA_KEY_LEN},
+};
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit as _};
+use rand::{CryptoRng, RngCore};
+use rsa::{pkcs8::der::zeroize::Zeroizing, Pkcs1v15Encrypt, RsaPublicKey};
+use sha2::{Digest as _, Sha256};
+
+/// The label mixed in the traffic keys.
+const KEY_LABEL: &[u8] = b"crypto channel";
+/// The maximum length of a Noise message.
+#[cfg(feature = "noise")]
+const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+
+/// The Noise handshake pattern. (`noise` feature)
+#[cfg(feature = "noise")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoisePattern {
+    /// Both static keys are sent during the handshake. (3 messages)
+    XX,
+    /// The initiator already knows the static key of the responder. (2 messages, the initiator
+    /// identity is hidden from passive observers)
+    IK,
+}
+
+/// The side of the Noise handshake. (`noise` feature)
+#[cfg(feature = "noise")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoiseRole {
+    /// The side sending the first handshake message. (e.g. the client)
+    Initiator,
+    /// The side receiving the first handshake message. (e.g. the server)
+    Responder,
+}
+
+/// A X25519 static key pair, used by the Noise handshake. (`noise` feature)
+#[cfg(feature = "noise")]
+#[derive(Clone)]
+pub struct NoiseKeys {
+    /// The X25519 private key.
+    pub private_key: Zeroizing<[u8; 32]>,
+    /// The X25519 public key. (Shared with the peers)
+    pub public_key: [u8; 32],
+}
+
+#[cfg(feature = "noise")]
+impl NoiseKeys {
+    /// Generate a new static key pair.
+    ///
+    /// # Errors
+    /// - `Other`: If the key generation fails.
+    ///
+    pub fn generate() -> Result<Self> {
+        let keypair = snow::Builder::new(noise_params(NoisePattern::XX)?)
+            .generate_keypair()
+            .map_err(|e| error!(Other, "Noise error: {}", e))?;
+        let mut keys = Self {
+            private_key: Zeroizing::new([0; 32]),
+            public_key: [0; 32],
+        };
+        keys.private_key
+            .copy_from_slice(&Zeroizing::new(keypair.private));
+        keys.public_key.copy_from_slice(&keypair.public);
+        Ok(keys)
+    }
+}
+
+#[cfg(feature = "noise")]
+impl std::fmt::Debug for NoiseKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NoiseKeys")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The encryption of the frames, set up by the handshake.
+#[allow(clippy::large_enum_variant)] // A single one per channel
+enum Transport {
+    /// Keys derived from the RSA handshake.
+    Rsa {
+        send_cipher: Aes256Gcm,
+        send_nonce: NonceCounter,
+        recv_cipher: Aes256Gcm,
+        recv_nonce: NonceCounter,
+    },
+    /// Noise transport state. (`noise` feature)
+    #[cfg(feature = "noise")]
+    Noise(snow::TransportState),
+}
+
+impl Transport {
+    /// Encrypt an outgoing frame.
+    fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Transport::Rsa {
+                send_cipher,
+                send_nonce,
+                ..
+            } => {
+                let encrypted_data = send_cipher
+                    .encrypt(send_nonce.nonce(), data)
+                    .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+                send_nonce.increment();
+                Ok(encrypted_data)
+            }
+            #[cfg(feature = "noise")]
+            Transport::Noise(transport) => {
+                let mut encrypted_data = vec![0; data.len() + AES_AUTH_TAG_LEN];
+                let len = transport
+                    .write_message(data, &mut encrypted_data)
+                    .map_err(|e| error!(Other, "Noise error: {}", e))?;
+                encrypted_data.truncate(len);
+                Ok(encrypted_data)
+            }
+        }
+    }
+
+    /// Decrypt an incoming frame.
+    fn open(&mut self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Transport::Rsa {
+                recv_cipher,
+                recv_nonce,
+                ..
+            } => {
+                let data = recv_cipher
+                    .decrypt(recv_nonce.nonce(), encrypted_data)
+                    .map_err(|e| error!(Other, "AES Decryption error: {}", e))?;
+                recv_nonce.increment();
+                Ok(data)
+            }
+            #[cfg(feature = "noise")]
+            Transport::Noise(transport) => {
+                let mut data = vec![0; encrypted_data.len()];
+                let len = transport
+                    .read_message(encrypted_data, &mut data)
+                    .map_err(|e| error!(Other, "Noise error: {}", e))?;
+                data.truncate(len);
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// A bidirectional encrypted channel over a stream.
+///
+/// The channel implements both `std::io::Read` and `std::io::Write`. The written data is sent
+/// when `BUFFER_SIZE` bytes are buffered, or when `flush` is called.
+pub struct CryptoChannel<S: std::io::Read + std::io::Write, const BUFFER_SIZE: usize> {
+    stream: S,
+    transport: Transport,
+    send_buffer: Zeroizing<Vec<u8>>,
+    recv_buffer: Zeroizing<Vec<u8>>,
+    recv_pos: usize,
+    /// The sequence number of the next frame to send.
+    send_sequence: u64,
+    /// The sequence number of the next frame to receive.
+    recv_sequence: u64,
+}
+
+impl<S: std::io::Read + std::io::Write, const BUFFER_SIZE: usize> CryptoChannel<S, BUFFER_SIZE> {
+    /// Perform the handshake, and create a new `CryptoChannel` instance.
+    ///
+    /// # Arguments
+    /// - `stream`: The stream connected to the peer.
+    /// - `my_keys`: The RSA keys of this side. (The private key is required)
+    /// - `peer_public_key`: The RSA public key of the peer.
+    ///
+    /// # Returns
+    /// A `CryptoChannel` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `my_keys` has no private key.
+    /// - `InvalidData`: If the peer sends a malformed handshake, or uses another `BUFFER_SIZE`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    /// # Notes
+    /// Both sides send their handshake before reading the one of the peer, so both can call
+    /// `connect` at the same time.
+    ///
+    /// The secret of the peer is decrypted with implicit rejection (see
+    /// `CryptoReader::set_implicit_rejection`): a secret which can't be decrypted with the private
+    /// key doesn't fail the handshake, but the first frame received.
+    ///
+    pub fn connect(stream: S, my_keys: &RsaKeys, peer_public_key: &RsaPublicKey) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::connect_with_rng(stream, my_keys, peer_public_key, &mut rng)
+    }
+
+    /// Perform the handshake with the given random number generator, and create a new
+    /// `CryptoChannel` instance.
+    ///
+    /// # Arguments
+    /// - `stream`: The stream connected to the peer.
+    /// - `my_keys`: The RSA keys of this side. (The private key is required)
+    /// - `peer_public_key`: The RSA public key of the peer.
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// A `CryptoChannel` instance.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn connect_with_rng<R: CryptoRng + RngCore>(
+        mut stream: S,
+        my_keys: &RsaKeys,
+        peer_public_key: &RsaPublicKey,
+        mut rng: R,
+    ) -> Result<Self> {
+        let private_key = my_keys
+            .private_key()
+            .ok_or_else(|| error!(InvalidInput, "The channel requires a private key"))?;
+        let chunk_size = u32::try_from(BUFFER_SIZE)
+            .map_err(|_| error!(InvalidInput, "BUFFER_SIZE does not fit in the header"))?;
+
+        // Send our secret
+        let mut my_secret = Zeroizing::new([0; AES_DATA_KEY_LEN]);
+        rng.fill_bytes(my_secret.as_mut());
+        let wrapped_secret = peer_public_key
+            .encrypt(&mut rng, Pkcs1v15Encrypt, my_secret.as_ref())
+            .map_err(|e| error!(Other, "RSA Encryption error: {}", e))?;
+        let mut nonce = [0; 12];
+        rng.fill_bytes(&mut nonce);
+        Header {
+            chunk_size: Some(chunk_size),
+            ..Header::new(Format::LATEST, vec![wrapped_secret], nonce)
+        }
+        .write(&mut stream)?;
+        stream.flush()?;
+
+        // Receive the secret of the peer
+        let header = Header::parse(&mut stream)?;
+        if header.format < Format::V1 || header.chunk_size != Some(chunk_size) {
+            Err(error!(
+                InvalidData,
+                "Peer channel uses chunk size {:?}, expected {}", header.chunk_size, BUFFER_SIZE
+            ))?;
+        }
+        let wrapped_secret = header
+            .wrapped_keys
+            .first()
+            .ok_or_else(|| error!(InvalidData, "Peer handshake has no secret"))?;
+        // With implicit rejection, a bad secret only fails the first frame (no padding oracle)
+        let (peer_secret, _) =
+            KeyWrap::Pkcs1v15.unwrap_implicit(private_key, wrapped_secret, AES_DATA_KEY_LEN);
+
+        let transport = Transport::Rsa {
+            send_cipher: Aes256Gcm::new(&derive_key(my_secret.as_ref(), &peer_secret)),
+            send_nonce: NonceCounter::new(Nonce::default()),
+            recv_cipher: Aes256Gcm::new(&derive_key(&peer_secret, my_secret.as_ref())),
+            recv_nonce: NonceCounter::new(Nonce::default()),
+        };
+        Ok(Self::with_transport(stream, transport))
+    }
+
+    /// Perform a Noise handshake, and create a new `CryptoChannel` instance. (`noise` feature)
+    ///
+    /// # Arguments
+    /// - `stream`: The stream connected to the peer.
+    /// - `role`: The side of the handshake. (One peer must be the initiator, the other the
+    ///   responder)
+    /// - `pattern`: The handshake pattern. (Must be the same on both sides)
+    /// - `my_keys`: The static keys of this side.
+    /// - `peer_public_key`: The static public key of the peer.
+    ///
+    /// # Returns
+    /// A `CryptoChannel` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `BUFFER_SIZE` doesn't fit in a Noise message.
+    /// - `Other`: If the handshake fails, or the static key of the peer is not `peer_public_key`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    #[cfg(feature = "noise")]
+    pub fn connect_noise(
+        mut stream: S,
+        role: NoiseRole,
+        pattern: NoisePattern,
+        my_keys: &NoiseKeys,
+        peer_public_key: &[u8; 32],
+    ) -> Result<Self> {
+        if BUFFER_SIZE + AES_AUTH_TAG_LEN > NOISE_MAX_MESSAGE_LEN {
+            Err(error!(
+                InvalidInput,
+                "BUFFER_SIZE is too large for a Noise message"
+            ))?;
+        }
+
+        let mut builder = snow::Builder::new(noise_params(pattern)?)
+            .local_private_key(my_keys.private_key.as_ref());
+        if (pattern, role) == (NoisePattern::IK, NoiseRole::Initiator) {
+            builder = builder.remote_public_key(peer_public_key);
+        }
+        let mut handshake = match role {
+            NoiseRole::Initiator => builder.build_initiator(),
+            NoiseRole::Responder => builder.build_responder(),
+        }
+        .map_err(|e| error!(Other, "Noise error: {}", e))?;
+
+        let mut message = vec![0; NOISE_MAX_MESSAGE_LEN];
+        let mut payload = vec![0; NOISE_MAX_MESSAGE_LEN];
+        while !handshake.is_handshake_finished() {
+            if handshake.is_my_turn() {
+                let len = handshake
+                    .write_message(&[], &mut message)
+                    .map_err(|e| error!(Other, "Noise error: {}", e))?;
+                stream.write_all(&(len as u16).to_be_bytes())?;
+                stream.write_all(&message[..len])?;
+                stream.flush()?;
+            } else {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                let len = u16::from_be_bytes(len) as usize;
+                stream.read_exact(&mut message[..len])?;
+                handshake
+                    .read_message(&message[..len], &mut payload)
+                    .map_err(|e| error!(Other, "Noise error: {}", e))?;
+            }
+        }
+
+        if handshake.get_remote_static() != Some(peer_public_key.as_slice()) {
+            Err(error!(Other, "Noise error: unexpected peer static key"))?;
+        }
+        let transport = handshake
+            .into_transport_mode()
+            .map_err(|e| error!(Other, "Noise error: {}", e))?;
+        Ok(Self::with_transport(stream, Transport::Noise(transport)))
+    }
+
+    fn with_transport(stream: S, transport: Transport) -> Self {
+        Self {
+            stream,
+            transport,
+            send_buffer: Zeroizing::new(Vec::with_capacity(BUFFER_SIZE)),
+            recv_buffer: Zeroizing::new(Vec::with_capacity(BUFFER_SIZE)),
+            recv_pos: 0,
+            send_sequence: 0,
+            recv_sequence: 0,
+        }
+    }
+
+    /// Encrypt and send the buffered data as a frame.
+    fn send_frame(&mut self) -> Result<()> {
+        if self.send_buffer.is_empty() {
+            return Ok(());
+        }
+        let encrypted_data = self.transport.seal(&self.send_buffer)?;
+        self.stream
+            .write_all(&(encrypted_data.len() as u32).to_be_bytes())?;
+        self.stream.write_all(&self.send_sequence.to_be_bytes())?;
+        self.stream.write_all(&encrypted_data)?;
+
+        self.send_sequence += 1;
+        self.send_buffer.clear();
+        Ok(())
+    }
+
+    /// Receive and decrypt the next frame.
+    ///
+    /// # Returns
+    /// `false` if the peer closed the stream.
+    ///
+    fn recv_frame(&mut self) -> Result<bool> {
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.stream.read(&mut len[read..])? {
+                0 if read == 0 => return Ok(false),
+                0 => Err(error!(UnexpectedEof, "Truncated frame length"))?,
+                n => read += n,
+            }
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len <= AES_AUTH_TAG_LEN || len > BUFFER_SIZE + AES_AUTH_TAG_LEN {
+            Err(error!(InvalidData, "Invalid frame length: {}", len))?;
+        }
+
+        let mut sequence = [0; 8];
+        self.stream.read_exact(&mut sequence)?;
+        let sequence = u64::from_be_bytes(sequence);
+        let mut encrypted_data = vec![0; len];
+        self.stream.read_exact(&mut encrypted_data)?;
+
+        if sequence < self.recv_sequence {
+            Err(Error::Replayed { sequence })?;
+        }
+        if sequence > self.recv_sequence {
+            Err(Error::OutOfOrder { sequence })?;
+        }
+        // The sequence number is bound to the nonce, so a forged one fails the decryption
+        let data = self.transport.open(&encrypted_data)?;
+
+        self.recv_sequence += 1;
+        self.recv_buffer = Zeroizing::new(data);
+        self.recv_pos = 0;
+        Ok(true)
+    }
+}
+
+/// Drop the `CryptoChannel` instance.
+/// The buffered data is sent on a best effort basis: call `flush` to handle the errors.
+impl<S: std::io::Read + std::io::Write, const BUFFER_SIZE: usize> Drop
+    for CryptoChannel<S, BUFFER_SIZE>
+{
+    fn drop(&mut self) {
+        _ = std::io::Write::flush(self);
+    }
+}
+
+impl<S: std::io::Read + std::io::Write, const BUFFER_SIZE: usize> std::io::Write
+    for CryptoChannel<S, BUFFER_SIZE>
+{
+    /// Write data to the channel.
+    /// A frame is sent each time `BUFFER_SIZE` bytes are buffered.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let to_write = std::cmp::min(buf.len(), BUFFER_SIZE - self.send_buffer.len());
+        self.send_buffer.extend_from_slice(&buf[..to_write]);
+        if self.send_buffer.len() == BUFFER_SIZE {
+            self.send_frame()?;
+        }
+        Ok(to_write)
+    }
+
+    /// Send the buffered data, and flush the stream.
+    /// Unlike `CryptoWriter`, the channel can still be written to afterwards.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_frame()?;
+        self.stream.flush()
+    }
+}
+
+impl<S: std::io::Read + std::io::Write, const BUFFER_SIZE: usize> std::io::Read
+    for CryptoChannel<S, BUFFER_SIZE>
+{
+    /// Read data from the channel.
+    /// Blocks until a frame is received, and returns the data of at most one frame.
+    ///
+    /// # Notes
+    /// If the number of bytes read is 0, the peer closed the stream.
+    ///
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.recv_pos == self.recv_buffer.len() && !self.recv_frame()? {
+            return Ok(0);
+        }
+        let to_copy = std::cmp::min(buf.len(), self.recv_buffer.len() - self.recv_pos);
+        buf[..to_copy].copy_from_slice(&self.recv_buffer[self.recv_pos..self.recv_pos + to_copy]);
+        self.recv_pos += to_copy;
+        Ok(to_copy)
+    }
+}
+
+/// The Noise protocol name of the pattern.
+#[cfg(feature = "noise")]
+fn noise_params(pattern: NoisePattern) -> Result<snow::params::NoiseParams> {
+    match pattern {
+        NoisePattern::XX => "Noise_XX_25519_AESGCM_SHA256",
+        NoisePattern::IK => "Noise_IK_25519_AESGCM_SHA256",
+    }
+    .parse()
+    .map_err(|e| error!(Other, "Noise error: {}", e))
+}
+
+/// Derive the traffic key of one direction.
+fn derive_key(sender_secret: &[u8], receiver_secret: &[u8]) -> Key<Aes256Gcm> {
+    Sha256::new()
+        .chain_update(KEY_LABEL)
+        .chain_update(sender_secret)
+        .chain_update(receiver_secret)
+        .finalize()
+}
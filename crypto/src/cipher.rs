@@ -0,0 +1,165 @@
+//! This module provides the `Cipher` encrypting the chunks of a stream.
+//!
+//! AES-256-GCM is the default. AES-128-GCM uses a smaller key and fewer rounds, which is faster on
+//! some embedded chips (e.g. with an AES-128 only accelerator), with a security margin that is
+//! still considered sufficient for most uses.
+//!
+//! The cipher is recorded in the `Header`, so `CryptoReader` reads the streams of every cipher.
+//! The length of the AES key (and of the wrapped keys) follows the cipher.
+use super::{
+    error::{error, Result},
+    io,
+    shared::Nonce,
+};
+use aes_gcm::{
+    aead::{Aead, AeadInPlace, Payload},
+    Aes128Gcm, Aes256Gcm, KeyInit as _,
+};
+use alloc::{vec, vec::Vec};
+use rand::{CryptoRng, RngCore};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+
+/// The AEAD cipher encrypting the chunks of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Cipher {
+    /// AES-256-GCM. (32 bytes key)
+    #[default]
+    Aes256Gcm,
+    /// AES-128-GCM. (16 bytes key)
+    Aes128Gcm,
+}
+
+impl Cipher {
+    /// The length of the AES key in bytes.
+    pub fn key_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 32,
+            Cipher::Aes128Gcm => 16,
+        }
+    }
+
+    /// Generate a random AES key for this cipher.
+    pub(crate) fn generate_key<R: CryptoRng + RngCore>(self, rng: &mut R) -> Zeroizing<Vec<u8>> {
+        let mut key = Zeroizing::new(vec![0; self.key_len()]);
+        rng.fill_bytes(&mut key);
+        key
+    }
+}
+
+impl From<Cipher> for u8 {
+    fn from(cipher: Cipher) -> Self {
+        match cipher {
+            Cipher::Aes256Gcm => 0,
+            Cipher::Aes128Gcm => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Cipher {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Cipher::Aes256Gcm),
+            1 => Ok(Cipher::Aes128Gcm),
+            _ => Err(error!(InvalidData, "Unsupported cipher: {}", value)),
+        }
+    }
+}
+
+/// An instance of a `Cipher`, keyed with the AES key of a stream.
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)] // A single one per stream
+pub(crate) enum AeadCipher {
+    Aes256Gcm(Aes256Gcm),
+    Aes128Gcm(Aes128Gcm),
+}
+
+impl AeadCipher {
+    /// Create the cipher of the given AES key. The cipher is selected by the key length.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the key length doesn't match any cipher.
+    ///
+    pub(crate) fn new(aes_key: &[u8]) -> Result<Self> {
+        match aes_key.len() {
+            32 => Ok(Self::Aes256Gcm(
+                Aes256Gcm::new_from_slice(aes_key).expect("length is checked"),
+            )),
+            16 => Ok(Self::Aes128Gcm(
+                Aes128Gcm::new_from_slice(aes_key).expect("length is checked"),
+            )),
+            len => Err(error!(InvalidInput, "Invalid AES key length: {}", len)),
+        }
+    }
+
+    /// Create the cipher of the given AES key, checking the key length against `cipher`.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the key length doesn't match `cipher`.
+    ///
+    pub(crate) fn with_cipher(cipher: Cipher, aes_key: &[u8]) -> Result<Self> {
+        if aes_key.len() != cipher.key_len() {
+            Err(error!(
+                InvalidInput,
+                "{:?} requires a {} bytes key, got {}",
+                cipher,
+                cipher.key_len(),
+                aes_key.len()
+            ))?;
+        }
+        Self::new(aes_key)
+    }
+
+    pub(crate) fn encrypt(
+        &self,
+        nonce: &Nonce,
+        payload: Payload<'_, '_>,
+    ) -> core::result::Result<Vec<u8>, aes_gcm::Error> {
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt(nonce, payload),
+            Self::Aes128Gcm(cipher) => cipher.encrypt(nonce, payload),
+        }
+    }
+
+    pub(crate) fn decrypt(
+        &self,
+        nonce: &Nonce,
+        payload: Payload<'_, '_>,
+    ) -> core::result::Result<Vec<u8>, aes_gcm::Error> {
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt(nonce, payload),
+            Self::Aes128Gcm(cipher) => cipher.decrypt(nonce, payload),
+        }
+    }
+
+    /// Encrypt the plaintext of `buffer` in place, and append the tag. (No allocation if the
+    /// buffer has the capacity for the tag)
+    pub(crate) fn encrypt_in_place(
+        &self,
+        nonce: &Nonce,
+        aad: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> core::result::Result<(), aes_gcm::Error> {
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt_in_place(nonce, aad, buffer),
+            Self::Aes128Gcm(cipher) => cipher.encrypt_in_place(nonce, aad, buffer),
+        }
+    }
+
+    /// Decrypt the ciphertext of `buffer` in place, checking the detached `tag`.
+    /// The buffer is left unchanged if the tag doesn't match.
+    pub(crate) fn decrypt_in_place_detached(
+        &self,
+        nonce: &Nonce,
+        aad: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8],
+    ) -> core::result::Result<(), aes_gcm::Error> {
+        let tag = aes_gcm::Tag::from_slice(tag);
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt_in_place_detached(nonce, aad, buffer, tag),
+            Self::Aes128Gcm(cipher) => cipher.decrypt_in_place_detached(nonce, aad, buffer, tag),
+        }
+    }
+}
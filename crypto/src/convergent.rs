@@ -0,0 +1,184 @@
+//! This module provides convergent (deterministic) encryption: the same input, encrypted with the
+//! same convergence key, always produces the same stream. (e.g. for deduplicating storage)
+//!
+//! `CryptoWriter` draws a random AES key and nonce for each stream, so encrypting the same file
+//! twice gives two unrelated streams. Here, they are derived from the plaintext instead, keyed by
+//! a secret `ConvergenceKey`:
+//!
+//! ```plaintext
+//! PRK       = HMAC-SHA256(convergence key, plaintext)   (HKDF-Extract)
+//! AES key   = HKDF-Expand(PRK, label || "key" || parameters)
+//! nonce     = HKDF-Expand(PRK, label || "nonce" || parameters)
+//! RNG seed  = HKDF-Expand(PRK, label || "rng" || parameters || RSA modulus of the recipient)
+//! ```
+//!
+//! The parameters are the chunk size, the cipher, the padding and the chaining of the stream, so
+//! two streams of the same plaintext never share a key and nonce with different chunks. The RSA
+//! wrapped keys (and the `Kem` slots) are written with a ChaCha20 RNG seeded as above, so the
+//! header is deterministic too, for a given recipient. The chunks don't depend on the recipient:
+//! the same file encrypted for two recipients has the same chunks.
+//!
+//! The streams are regular streams: `CryptoReader` decrypts them without knowing the convergence
+//! key.
+//!
+//! **Warning**: Convergent encryption reveals which streams hold the same plaintext, and anyone
+//! holding the convergence key can check whether a stream holds a guessed plaintext. (e.g. a
+//! document from a template, with a few possible values) Only use it when deduplication requires
+//! it, with a convergence key kept as secret as the data. `Kem` backends that are not
+//! deterministic themselves (e.g. `vault::VaultKem`) produce a different header each time.
+use super::{
+    ct,
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    identity::Recipient,
+};
+use hkdf::HkdfExtract;
+use rand::{RngCore, SeedableRng as _};
+use rand_chacha::ChaCha20Rng;
+use rsa::{pkcs8::der::zeroize::Zeroizing, traits::PublicKeyParts as _};
+use sha2::Sha256;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// The length of a convergence key.
+pub const CONVERGENCE_KEY_LEN: usize = 32;
+/// Domain separation of the derived values.
+const CONVERGENT_LABEL: &[u8] = b"crypto convergent";
+/// The size of the buffer used to read the input.
+const READ_BUFFER_LEN: usize = 64 * 1024;
+
+/// The secret keying the derivation of the AES keys from the plaintexts.
+///
+/// Only the streams encrypted with the same convergence key are deduplicated. Use one key per
+/// deduplication domain (e.g. per user), and keep it secret. (See the module documentation)
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConvergenceKey(Zeroizing<[u8; CONVERGENCE_KEY_LEN]>);
+
+impl ConvergenceKey {
+    /// Generate a new random convergence key.
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0; CONVERGENCE_KEY_LEN]);
+        rand::thread_rng().fill_bytes(key.as_mut());
+        Self(key)
+    }
+
+    /// Load a convergence key.
+    pub fn from_bytes(bytes: [u8; CONVERGENCE_KEY_LEN]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    /// The bytes of the convergence key.
+    pub fn to_bytes(&self) -> Zeroizing<[u8; CONVERGENCE_KEY_LEN]> {
+        self.0.clone()
+    }
+}
+
+impl core::fmt::Debug for ConvergenceKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ConvergenceKey").finish_non_exhaustive()
+    }
+}
+
+/// Encrypt the input into a deterministic stream. (See the module documentation)
+///
+/// The input is read twice, from its current position: once to derive the AES key, once to
+/// encrypt it.
+///
+/// # Arguments
+/// - `input`: The plaintext.
+/// - `writer`: The writer to write the encrypted data.
+/// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+/// - `options`: The options of the stream. (See `WriterOptions`)
+/// - `convergence_key`: The secret keying the derivation.
+///
+/// # Returns
+/// The length of the plaintext.
+///
+/// # Errors
+/// - `InvalidData`: If the input changed between the two reads. (The stream is left without its
+///   final chunk, and must be discarded)
+/// - The errors of `CryptoWriter::new_with_key_and_nonce`.
+/// - `Io`: If an I/O error occurs.
+///
+pub fn encrypt<R: Read + Seek, W: Write, const BUFFER_SIZE: usize>(
+    mut input: R,
+    writer: W,
+    key: impl Into<Recipient>,
+    options: &WriterOptions,
+    convergence_key: &ConvergenceKey,
+) -> Result<u64> {
+    let key = key.into().into_rsa();
+    let start = input.stream_position()?;
+    let mut buffer = Zeroizing::new(vec![0; READ_BUFFER_LEN]);
+    let mut extract = HkdfExtract::<Sha256>::new(Some(convergence_key.0.as_ref()));
+    loop {
+        match input.read(&mut buffer)? {
+            0 => break,
+            read => extract.input_ikm(&buffer[..read]),
+        }
+    }
+    let (prk, hkdf) = extract.finalize();
+    let parameters = parameters::<BUFFER_SIZE>(options)?;
+
+    let mut aes_key = Zeroizing::new(vec![0; options.cipher.key_len()]);
+    let mut nonce = [0; 12];
+    let mut seed = Zeroizing::new([0; 32]);
+    let modulus = key.n().to_bytes_be();
+    let expand = |info: &[&[u8]], output: &mut [u8]| {
+        hkdf.expand_multi_info(info, output)
+            .map_err(|_| error!(Other, "HKDF expand error"))
+    };
+    expand(&[CONVERGENT_LABEL, b"key", &parameters], &mut aes_key)?;
+    expand(&[CONVERGENT_LABEL, b"nonce", &parameters], &mut nonce)?;
+    expand(
+        &[CONVERGENT_LABEL, b"rng", &parameters, &modulus],
+        seed.as_mut(),
+    )?;
+
+    input.seek(SeekFrom::Start(start))?;
+    let mut writer = CryptoWriter::<_, BUFFER_SIZE>::new_with_key_and_nonce(
+        writer,
+        key,
+        &aes_key,
+        &nonce,
+        options,
+        ChaCha20Rng::from_seed(*seed),
+    )?;
+    // The key is only valid for the plaintext it was derived from: check it didn't change
+    let mut extract = HkdfExtract::<Sha256>::new(Some(convergence_key.0.as_ref()));
+    let mut len = 0;
+    loop {
+        match input.read(&mut buffer)? {
+            0 => break,
+            read => {
+                extract.input_ikm(&buffer[..read]);
+                writer.write_all(&buffer[..read])?;
+                len += read as u64;
+            }
+        }
+    }
+    if !ct::eq(&extract.finalize().0, &prk) {
+        writer.abandon();
+        Err(error!(
+            InvalidData,
+            "The input changed while being encrypted"
+        ))?;
+    }
+    writer.flush()?;
+    Ok(len)
+}
+
+/// The parameters of the stream changing its chunks, bound to the derived values.
+fn parameters<const BUFFER_SIZE: usize>(options: &WriterOptions) -> Result<[u8; 7]> {
+    let chunk_size = u32::try_from(BUFFER_SIZE)
+        .map_err(|_| error!(InvalidInput, "BUFFER_SIZE does not fit in the header"))?;
+    let [a, b, c, d] = chunk_size.to_be_bytes();
+    Ok([
+        a,
+        b,
+        c,
+        d,
+        options.cipher.into(),
+        options.padding.into(),
+        options.chain_tags.into(),
+    ])
+}
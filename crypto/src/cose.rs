@@ -0,0 +1,333 @@
+//! This module provides COSE_Encrypt serialization for small payloads. (`cose` feature, RFC 9052)
+//!
+//! The payload is encrypted with a random content encryption key, using the cipher of the
+//! `WriterOptions` (`A256GCM` or `A128GCM`). The key is wrapped once per recipient with the same
+//! backends as the header of a stream:
+//!
+//! | Recipient                      | COSE algorithm                           |
+//! |--------------------------------|------------------------------------------|
+//! | RSA, `KeyWrap::OaepSha256`     | `RSAES-OAEP w/ SHA-256` (-41, RFC 8230)  |
+//! | RSA, `KeyWrap::OaepSha512`     | `RSAES-OAEP w/ SHA-512` (-42, RFC 8230)  |
+//! | `Kem` backend of identifier id | `-65536 - id` (private use, see `kem`)   |
+//!
+//! `KeyWrap::Pkcs1v15` has no COSE algorithm: the RSA recipients require OAEP. The `Kem`
+//! recipients hold the slot of the stream header (the encapsulated secret, and the encrypted
+//! key), so any `Kem` backend (e.g. X25519, a KMS) works with COSE, between two ends of this
+//! crate.
+//!
+//! The message has the following structure (CBOR tag 96):
+//!
+//! ```plaintext
+//! COSE_Encrypt = [
+//!     protected: bstr .cbor { 1 (alg): 3 (A256GCM) or 1 (A128GCM) },
+//!     unprotected: { 5 (IV): bstr },
+//!     ciphertext: bstr,            ; Ciphertext || Tag
+//!     recipients: [+ [
+//!         protected: bstr .cbor { 1 (alg): recipient algorithm },
+//!         unprotected: {},
+//!         ciphertext: bstr,        ; The wrapped content encryption key
+//!     ]]
+//! ]
+//! ```
+//!
+//! The additional data is the `Enc_structure` (`["Encrypt", protected, h'']`), so the protected
+//! header can't be altered without the decryption failing.
+//!
+//! **Note**: The whole payload is held in memory. This mode is intended for small payloads (e.g.
+//! the messages of constrained devices). Use `CryptoWriter` and `CryptoReader` for large data.
+use super::{
+    cipher::{AeadCipher, Cipher},
+    encrypt::WriterOptions,
+    error::{error, Error, Result},
+    kem::{unwrap_kem_slot, wrap_kem_slot, Kem},
+    key_wrap::KeyWrap,
+    shared::{setup_rng, Nonce, AES_NONCE_LEN},
+};
+use aes_gcm::aead::Payload;
+use ciborium::value::{Integer, Value};
+use rand::{CryptoRng, RngCore};
+use rsa::{pkcs8::der::zeroize::Zeroizing, RsaPrivateKey, RsaPublicKey};
+
+/// The CBOR tag of a COSE_Encrypt message.
+const COSE_ENCRYPT_TAG: u64 = 96;
+/// The header label of the algorithm.
+const LABEL_ALG: i64 = 1;
+/// The header label of the IV.
+const LABEL_IV: i64 = 5;
+/// The algorithm of AES-128-GCM.
+const ALG_A128GCM: i64 = 1;
+/// The algorithm of AES-256-GCM.
+const ALG_A256GCM: i64 = 3;
+/// The algorithm of RSAES-OAEP with SHA-256.
+const ALG_RSA_OAEP_256: i64 = -41;
+/// The algorithm of RSAES-OAEP with SHA-512.
+const ALG_RSA_OAEP_512: i64 = -42;
+/// The algorithms of the `Kem` backends start below this value. (Private use)
+const ALG_KEM_BASE: i64 = -65536;
+
+/// Encrypt the payload as a COSE_Encrypt message.
+///
+/// # Arguments
+/// - `payload`: The data to encrypt.
+/// - `key`: The RSA public key of the first recipient.
+/// - `options`: The cipher, the key wrap, and the other recipients. (`additional_recipients` and
+///   `kem_recipients`, the other options don't apply)
+///
+/// # Returns
+/// The CBOR encoding of the message.
+///
+/// # Errors
+/// - `Unsupported`: If the key wrap is `KeyWrap::Pkcs1v15`, or ML-KEM keys are set.
+/// - `InvalidInput`: If a `Kem` identifier is reserved.
+/// - `Other`: If the RSA encryption (or an encapsulation), or the AES encryption fails.
+///
+pub fn encrypt(payload: &[u8], key: &RsaPublicKey, options: &WriterOptions) -> Result<Vec<u8>> {
+    let mut rng = setup_rng();
+    encrypt_with_rng(payload, key, options, &mut rng)
+}
+
+/// Encrypt the payload as a COSE_Encrypt message with the given random number generator.
+///
+/// # Arguments
+/// - `payload`: The data to encrypt.
+/// - `key`: The RSA public key of the first recipient.
+/// - `options`: The cipher, the key wrap, and the other recipients. (See `encrypt`)
+/// - `rng`: The random number generator.
+///
+/// # Returns
+/// The CBOR encoding of the message.
+///
+/// # Notes
+/// The random number generator must be cryptographically secure. And should implement the
+/// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+///
+pub fn encrypt_with_rng<R: CryptoRng + RngCore>(
+    payload: &[u8],
+    key: &RsaPublicKey,
+    options: &WriterOptions,
+    mut rng: R,
+) -> Result<Vec<u8>> {
+    let rsa_alg = match options.key_wrap {
+        KeyWrap::Pkcs1v15 => Err(error!(
+            Unsupported,
+            "COSE requires OAEP to wrap the key (see `KeyWrap`)"
+        ))?,
+        KeyWrap::OaepSha256 => ALG_RSA_OAEP_256,
+        KeyWrap::OaepSha512 => ALG_RSA_OAEP_512,
+    };
+    #[cfg(feature = "ml-kem")]
+    if !options.ml_kem_keys.is_empty() {
+        Err(error!(Unsupported, "COSE has no hybrid ML-KEM recipients"))?;
+    }
+
+    let cek = options.cipher.generate_key(&mut rng);
+    let mut recipients = Vec::new();
+    for key in core::iter::once(key).chain(&options.additional_recipients) {
+        let wrapped_key = options.key_wrap.wrap(key, &mut rng, &cek)?;
+        recipients.push(recipient(rsa_alg, wrapped_key)?);
+    }
+    for kem in &options.kem_recipients {
+        let slot = wrap_kem_slot(kem.as_ref(), &cek, &mut rng)?;
+        recipients.push(recipient(ALG_KEM_BASE - i64::from(kem.id()), slot)?);
+    }
+
+    let alg = match options.cipher {
+        Cipher::Aes256Gcm => ALG_A256GCM,
+        Cipher::Aes128Gcm => ALG_A128GCM,
+    };
+    let protected = encode(&alg_header(alg))?;
+    let mut iv = Nonce::default();
+    rng.fill_bytes(&mut iv);
+    let ciphertext = AeadCipher::with_cipher(options.cipher, &cek)?
+        .encrypt(
+            &iv,
+            Payload {
+                msg: payload,
+                aad: &enc_structure(&protected)?,
+            },
+        )
+        .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+
+    encode(&Value::Tag(
+        COSE_ENCRYPT_TAG,
+        Box::new(Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(vec![(LABEL_IV.into(), Value::Bytes(iv.to_vec()))]),
+            Value::Bytes(ciphertext),
+            Value::Array(recipients),
+        ])),
+    ))
+}
+
+/// Decrypt a COSE_Encrypt message with a RSA private key.
+///
+/// # Arguments
+/// - `message`: The CBOR encoding of the message. (Tagged or not)
+/// - `key`: The RSA private key of a recipient.
+///
+/// # Returns
+/// The decrypted payload.
+///
+/// # Errors
+/// - `InvalidData`: If the message is malformed, or uses an unsupported algorithm.
+/// - `Error::WrongKey`: If no recipient matches the key.
+/// - `Error::CorruptedChunk`: If the payload fails the authentication. (With the index 0)
+///
+pub fn decrypt(message: &[u8], key: &RsaPrivateKey) -> Result<Vec<u8>> {
+    decrypt_with(message, |alg, wrapped_key| {
+        let key_wrap = match alg {
+            ALG_RSA_OAEP_256 => KeyWrap::OaepSha256,
+            ALG_RSA_OAEP_512 => KeyWrap::OaepSha512,
+            _ => return None,
+        };
+        key_wrap.unwrap(key, wrapped_key).map(Zeroizing::new)
+    })
+}
+
+/// Decrypt a COSE_Encrypt message with a `Kem` backend.
+///
+/// # Arguments
+/// - `message`: The CBOR encoding of the message. (Tagged or not)
+/// - `kem`: The backend holding the private key of a recipient.
+///
+/// # Returns
+/// The decrypted payload.
+///
+/// # Errors
+/// The errors of `decrypt`.
+///
+pub fn decrypt_with_kem(message: &[u8], kem: &dyn Kem) -> Result<Vec<u8>> {
+    let kem_alg = ALG_KEM_BASE - i64::from(kem.id());
+    decrypt_with(message, |alg, slot| {
+        (alg == kem_alg)
+            .then(|| unwrap_kem_slot(kem, slot))
+            .flatten()
+    })
+}
+
+/// Decrypt a COSE_Encrypt message, with the content encryption key of the first recipient
+/// `unwrap` decrypts. (Called with the algorithm and the ciphertext of each recipient)
+fn decrypt_with(
+    message: &[u8],
+    mut unwrap: impl FnMut(i64, &[u8]) -> Option<Zeroizing<Vec<u8>>>,
+) -> Result<Vec<u8>> {
+    let value: Value = ciborium::de::from_reader(message)
+        .map_err(|e| error!(InvalidData, "Invalid COSE message: {}", e))?;
+    let value = match value {
+        Value::Tag(COSE_ENCRYPT_TAG, value) => *value,
+        Value::Tag(tag, _) => Err(error!(InvalidData, "Unexpected COSE tag: {}", tag))?,
+        value => value,
+    };
+    let [protected, unprotected, ciphertext, recipients] = array::<4>(value)?;
+    let protected = bytes(protected)?;
+    let cipher = match header_alg(&protected)? {
+        ALG_A256GCM => Cipher::Aes256Gcm,
+        ALG_A128GCM => Cipher::Aes128Gcm,
+        alg => Err(error!(InvalidData, "Unsupported COSE algorithm: {}", alg))?,
+    };
+    let Value::Map(unprotected) = unprotected else {
+        Err(error!(InvalidData, "Invalid COSE unprotected header"))?
+    };
+    let iv = unprotected
+        .into_iter()
+        .find(|(label, _)| label_is(label, LABEL_IV))
+        .map(|(_, iv)| bytes(iv))
+        .transpose()?
+        .filter(|iv| iv.len() == AES_NONCE_LEN)
+        .ok_or_else(|| error!(InvalidData, "Invalid COSE IV"))?;
+    let ciphertext = bytes(ciphertext)?;
+    let Value::Array(recipients) = recipients else {
+        Err(error!(InvalidData, "Invalid COSE recipients"))?
+    };
+
+    let mut cek = None;
+    for recipient in recipients {
+        let [protected, _, wrapped_key] = array::<3>(recipient)?;
+        let alg = header_alg(&bytes(protected)?)?;
+        if let Some(key) = unwrap(alg, &bytes(wrapped_key)?) {
+            if key.len() == cipher.key_len() {
+                cek = Some(key);
+                break;
+            }
+        }
+    }
+    let cek = cek.ok_or(Error::WrongKey)?;
+
+    AeadCipher::with_cipher(cipher, &cek)?
+        .decrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &ciphertext,
+                aad: &enc_structure(&protected)?,
+            },
+        )
+        .map_err(|_| Error::CorruptedChunk { index: 0 }.into())
+}
+
+/// A recipient of the given algorithm and wrapped key.
+fn recipient(alg: i64, wrapped_key: Vec<u8>) -> Result<Value> {
+    Ok(Value::Array(vec![
+        Value::Bytes(encode(&alg_header(alg))?),
+        Value::Map(Vec::new()),
+        Value::Bytes(wrapped_key),
+    ]))
+}
+
+/// A header map holding the algorithm.
+fn alg_header(alg: i64) -> Value {
+    Value::Map(vec![(LABEL_ALG.into(), alg.into())])
+}
+
+/// The algorithm of a protected header.
+fn header_alg(protected: &[u8]) -> Result<i64> {
+    let header: Value = ciborium::de::from_reader(protected)
+        .map_err(|e| error!(InvalidData, "Invalid COSE protected header: {}", e))?;
+    let Value::Map(header) = header else {
+        Err(error!(InvalidData, "Invalid COSE protected header"))?
+    };
+    header
+        .into_iter()
+        .find(|(label, _)| label_is(label, LABEL_ALG))
+        .and_then(|(_, alg)| alg.as_integer())
+        .and_then(|alg| i64::try_from(alg).ok())
+        .ok_or_else(|| error!(InvalidData, "Missing COSE algorithm"))
+}
+
+/// The additional data of the content: `["Encrypt", protected, h'']`. (RFC 9052, section 5.3)
+fn enc_structure(protected: &[u8]) -> Result<Vec<u8>> {
+    encode(&Value::Array(vec![
+        Value::Text("Encrypt".into()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+    ]))
+}
+
+fn label_is(label: &Value, expected: i64) -> bool {
+    label.as_integer() == Some(Integer::from(expected))
+}
+
+fn array<const N: usize>(value: Value) -> Result<[Value; N]> {
+    match value {
+        Value::Array(values) => values
+            .try_into()
+            .map_err(|_| error!(InvalidData, "Invalid COSE structure")),
+        _ => Err(error!(InvalidData, "Invalid COSE structure")),
+    }
+}
+
+fn bytes(value: Value) -> Result<Vec<u8>> {
+    match value {
+        Value::Bytes(bytes) => Ok(bytes),
+        _ => Err(error!(
+            InvalidData,
+            "Invalid COSE structure: expected a byte string"
+        )),
+    }
+}
+
+fn encode(value: &Value) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    ciborium::ser::into_writer(value, &mut encoded)
+        .map_err(|e| error!(Other, "CBOR encoding error: {}", e))?;
+    Ok(encoded)
+}
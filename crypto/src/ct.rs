@@ -0,0 +1,38 @@
+//! This module provides constant-time comparisons, for the secret dependent values (tags, key
+//! check values, commitments, fingerprints of private keys, ...): the time taken doesn't depend on
+//! the position of the first difference.
+//!
+//! The reader uses them on its error paths, so a wrong key, a corrupted wrapped key and a bad
+//! padding of a RSA PKCS#1 v1.5 wrapped key take the same time: every slot is tried, a failed
+//! unwrap continues with a dummy AES key, and the key check value and the commitment are both
+//! checked. (See `CryptoReader::new`) Only the public values (lengths, header fields) select a
+//! faster path, e.g. a truncated chunk is rejected before the tag is verified.
+//!
+//! The chunk tags are verified by the AEAD implementations, in constant time.
+use subtle::{Choice, ConstantTimeEq as _};
+
+/// Compare two byte strings in constant time.
+///
+/// # Returns
+/// `true` if `a` and `b` are equal.
+///
+/// # Notes
+/// The lengths are not hidden: byte strings of different lengths are rejected at once.
+///
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Compare two strings in constant time. (e.g. fingerprints)
+///
+/// # Notes
+/// The lengths are not hidden, as for `eq`.
+///
+pub fn eq_str(a: &str, b: &str) -> bool {
+    eq(a.as_bytes(), b.as_bytes())
+}
+
+/// Combine two results without short-circuit. (Both sides are always evaluated by the caller)
+pub(crate) fn and(a: bool, b: bool) -> bool {
+    (Choice::from(a as u8) & Choice::from(b as u8)).into()
+}
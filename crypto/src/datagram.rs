@@ -0,0 +1,234 @@
+//! This module provides `CryptoDatagram`, to encrypt independent messages over a lossy transport.
+//! (e.g. UDP)
+//!
+//! `CryptoReader` expects the chunks in order, without gaps: a lost or reordered chunk breaks the
+//! rest of the stream. With `CryptoDatagram`, each datagram carries its own sequence number and is
+//! authenticated on its own, so datagrams can be lost, duplicated or reordered by the network.
+//!
+//! The session is set up by one side (`CryptoDatagram::new`), which generates the AES key and
+//! returns a `Header` holding it, encrypted with the RSA public key of the peer. The header must be
+//! sent to the peer (once, over any channel), which opens the session with
+//! `CryptoDatagram::from_header`. Both sides can then seal and open datagrams.
+//!
+//! Each datagram is written as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+
+//! |    SEQUENCE     |   |    AES Data     |
+//! +-----------------+   +-----------------+
+//! |     u64 BE      |   |                 |
+//! +-----------------+   +-----------------+
+//! |        8        |   | DATA + AUTH TAG |
+//! +-----------------+   +-----------------+
+//! ```
+//!
+//! The AES nonce is built from the role of the sender (`1` for the initiator, `2` for the
+//! responder) and the sequence number, so both sides never use the same nonce, and no datagram
+//! uses the zero nonce of the key check value:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |      ROLE       |   |      0x00       |   |    SEQUENCE     |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        1        |   |        3        |   |        8        |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! Replayed datagrams are rejected: the receiver keeps track of the sequence numbers accepted in
+//! a `ReplayWindow`. Replayed datagrams fail with `Error::Replayed`, and datagrams older than the
+//! window with `Error::OutOfOrder`.
+use super::{
+    cipher::Cipher,
+    decrypt::{unwrap_data_key, SlotKey},
+    encrypt::{generate_aes_key, key_check_value, key_commitment, wrap_data_key, WriterOptions},
+    error::{error, Result},
+    header::{Format, Header},
+    identity::Recipient,
+    replay::ReplayWindow,
+    shared::{setup_rng, Nonce, NonceCounter, AES_AUTH_TAG_LEN},
+};
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit as _};
+use rand::{CryptoRng, RngCore};
+use rsa::RsaPrivateKey;
+
+/// The length of the sequence number.
+const SEQUENCE_LEN: usize = 8;
+
+/// The side of the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// The side which created the session. (`CryptoDatagram::new`)
+    Initiator = 1,
+    /// The side which opened the session from the header. (`CryptoDatagram::from_header`)
+    Responder = 2,
+}
+
+/// An encrypted datagram session.
+pub struct CryptoDatagram {
+    cipher: Aes256Gcm,
+    role: Role,
+    /// The sequence number of the next sealed datagram.
+    send_sequence: u64,
+    /// The sequence numbers opened so far.
+    recv_window: ReplayWindow,
+}
+
+impl CryptoDatagram {
+    /// Create a new datagram session.
+    ///
+    /// # Arguments
+    /// - `key`: The RSA public key of the peer.
+    ///
+    /// # Returns
+    /// The `CryptoDatagram` instance, and the `Header` to send to the peer.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new(key: impl Into<Recipient>) -> Result<(Self, Header)> {
+        let mut rng = setup_rng();
+        Self::new_with_rng(key, &mut rng)
+    }
+
+    /// Create a new datagram session with the given random number generator.
+    ///
+    /// # Arguments
+    /// - `key`: The RSA public key of the peer.
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// The `CryptoDatagram` instance, and the `Header` to send to the peer.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn new_with_rng<R: CryptoRng + RngCore>(
+        key: impl Into<Recipient>,
+        mut rng: R,
+    ) -> Result<(Self, Header)> {
+        let aes_key = generate_aes_key(&mut rng);
+        let header = Header {
+            // Not a stream, so `CryptoReader` rejects the header
+            chunk_size: Some(0),
+            key_check: Some(key_check_value(&aes_key)),
+            commitment: Some(key_commitment(&aes_key)),
+            ..Header::new(
+                Format::LATEST,
+                wrap_data_key(
+                    &key.into().into_rsa(),
+                    &aes_key,
+                    &WriterOptions::default(),
+                    &mut rng,
+                )?,
+                // The nonces are derived from the sequence numbers
+                Default::default(),
+            )
+        };
+        Ok((Self::with_key(&aes_key, Role::Initiator), header))
+    }
+
+    /// Open a datagram session from the header sent by the peer.
+    ///
+    /// # Arguments
+    /// - `header`: The header returned by `CryptoDatagram::new` on the peer side.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// A `CryptoDatagram` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the AES key can't be decrypted with `key`.
+    ///
+    pub fn from_header(header: &Header, key: &RsaPrivateKey) -> Result<Self> {
+        if header.cipher != Cipher::Aes256Gcm {
+            Err(error!(
+                InvalidData,
+                "Unsupported cipher for datagrams: {:?}", header.cipher
+            ))?;
+        }
+        let (_, raw_aes_key) = unwrap_data_key(header, &[SlotKey::Rsa(key.clone())], false)?;
+        Ok(Self::with_key(
+            Key::<Aes256Gcm>::from_slice(&raw_aes_key),
+            Role::Responder,
+        ))
+    }
+
+    fn with_key(aes_key: &Key<Aes256Gcm>, role: Role) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(aes_key),
+            role,
+            send_sequence: 0,
+            recv_window: ReplayWindow::new(),
+        }
+    }
+
+    /// Encrypt a datagram.
+    ///
+    /// # Arguments
+    /// - `data`: The payload of the datagram.
+    ///
+    /// # Returns
+    /// The encrypted datagram. (`data.len() + 24` bytes)
+    ///
+    /// # Errors
+    /// - `Other`: If the AES encryption fails, or the sequence numbers are exhausted.
+    ///
+    pub fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let sequence = self.send_sequence;
+        self.send_sequence = sequence
+            .checked_add(1)
+            .ok_or_else(|| error!(Other, "Datagram sequence numbers are exhausted"))?;
+
+        let encrypted_data = self
+            .cipher
+            .encrypt(&datagram_nonce(self.role, sequence), data)
+            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+
+        let mut datagram = Vec::with_capacity(SEQUENCE_LEN + encrypted_data.len());
+        datagram.extend_from_slice(&sequence.to_be_bytes());
+        datagram.extend_from_slice(&encrypted_data);
+        Ok(datagram)
+    }
+
+    /// Decrypt a datagram sealed by the peer.
+    ///
+    /// # Arguments
+    /// - `datagram`: The encrypted datagram.
+    ///
+    /// # Returns
+    /// The payload of the datagram.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the datagram is too short.
+    /// - `Error::Replayed`: If the datagram was already opened.
+    /// - `Error::OutOfOrder`: If the datagram is older than the replay window.
+    /// - `Other`: If the AES decryption fails. (The datagram was tampered with)
+    ///
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Vec<u8>> {
+        if datagram.len() < SEQUENCE_LEN + AES_AUTH_TAG_LEN {
+            Err(error!(InvalidData, "Datagram is too short"))?;
+        }
+        let (sequence, encrypted_data) = datagram.split_at(SEQUENCE_LEN);
+        let sequence = u64::from_be_bytes(sequence.try_into().expect("slice has SEQUENCE_LEN"));
+        self.recv_window.check(sequence)?;
+
+        let peer_role = match self.role {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        };
+        let data = self
+            .cipher
+            .decrypt(&datagram_nonce(peer_role, sequence), encrypted_data)
+            .map_err(|e| error!(Other, "AES Decryption error: {}", e))?;
+
+        // Only authenticated datagrams move the window
+        self.recv_window.accept(sequence);
+        Ok(data)
+    }
+}
+
+/// Build the AES nonce of a datagram.
+pub(crate) fn datagram_nonce(role: Role, sequence: u64) -> Nonce {
+    *NonceCounter::with_prefix(&[role as u8, 0, 0, 0], sequence).nonce()
+}
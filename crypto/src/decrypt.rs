@@ -5,13 +5,13 @@
 //! The data is read from the reader in the following format:
 //!
 //! ```plaintext
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |     Header      |   |     AES Data    |   |     AES Data    |   
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |  See `Header`   |   |                 |   |                 |   ...
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+//! +-----------------+   +-----------------+   +-----------------+
 //! ```
 //!
 //! The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data.
@@ -20,27 +20,180 @@
 //! The `CryptoReader` implements the `std::io::Read` trait. To allow seamless integration with existing
 //! Rust code that uses `std::io::Read`.
 //!
+//! The reader works on non-blocking streams: when the underlying reader returns `WouldBlock`, the
+//! error is returned to the caller, and the partially read header or chunk is kept until the next
+//! call.
+//!
 //! **Warning**: Currently the memeory of the struct is not locked. (This will be implemented in
 //! the future)
 //! So, the data can be read from the memory. (This is a security risk)
+#[cfg(feature = "ml-kem")]
+use super::pq::{self, MlKemPrivateKey};
+#[cfg(feature = "std")]
+use super::{
+    audit::{audit, audit_failure, AuditEvent, Direction},
+    session::SessionCache,
+};
 use super::{
-    dbg_println,
-    error::{error, Result},
-    shared::{increment_nonce, Nonce, AES_AUTH_TAG_LEN, AES_KEY_LEN, AES_NONCE_LEN},
+    cipher::AeadCipher,
+    ct, dbg_println,
+    encrypt::{key_check_key, key_commitment},
+    error::{error, Error, Result},
+    format,
+    header::{Chunking, Format, Header, SlotKem},
+    identity::Identity,
+    io,
+    kem::{unwrap_kem_slot, Kem},
+    key_wrap::KeyWrap,
+    metadata::{Metadata, LENGTH_LEN, MAX_METADATA_LEN},
+    padding::{unpad, Padding},
+    session::stream_key,
+    shared::{ChunkAad, Nonce, NonceCounter, AES_AUTH_TAG_LEN, HEADER_DIGEST_LEN, KEY_CHECK_AAD},
+    stats::{Stats, Timer},
 };
-use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit as _};
-use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+use aes_gcm::aead::Payload;
+use alloc::{sync::Arc, vec, vec::Vec};
+use rsa::{pkcs8::der::zeroize::Zeroizing, RsaPrivateKey};
 
-macro_rules! min {
-    ($($args:expr),*) => {
-        min!(@inner $($args),*)
-    };
-    (@inner $first:expr, $($rest:expr),*) => {
-        std::cmp::min($first, min!(@inner $($rest),*))
-    };
-    (@inner $only:expr) => {
-        $only
+/// Check the AES key against the key check value (if any) and the commitment of the header.
+/// Both are checked, so the time doesn't reveal which one failed. (See `ct`)
+///
+/// The commitment is required for `Format::V1` streams: every writer of the format commits to the
+/// AES key, so a missing commitment was stripped from the header. (See `require_commitment`)
+///
+/// For the streams of a session, the AES key is the master key of the session, and the key of the
+/// stream derived from it is checked. (See `session`)
+pub(crate) fn check_data_key(header: &Header, raw_aes_key: &[u8]) -> bool {
+    let raw_aes_key = &stream_key(header, raw_aes_key);
+    let commitment_ok = match header.commitment {
+        Some(commitment) => ct::eq(&commitment, &key_commitment(raw_aes_key)),
+        None => header.format == Format::V0,
     };
+    let key_check_ok = header.key_check.as_ref().is_none_or(|key_check| {
+        let payload = Payload {
+            msg: key_check,
+            aad: KEY_CHECK_AAD,
+        };
+        AeadCipher::with_cipher(header.cipher, &key_check_key(raw_aes_key))
+            .is_ok_and(|cipher| cipher.decrypt(&Nonce::default(), payload).is_ok())
+    });
+    ct::and(commitment_ok, key_check_ok)
+}
+
+/// A private key tried on the wrapped key slots of a header.
+#[derive(Clone)]
+#[allow(clippy::large_enum_variant)] // A few per reader
+pub(crate) enum SlotKey {
+    /// Decrypts the `SlotKem::Rsa` slots.
+    Rsa(RsaPrivateKey),
+    /// Decrypts the `SlotKem::RsaMlKem768` slots. (And the `SlotKem::Rsa` slots, with the RSA key)
+    #[cfg(feature = "ml-kem")]
+    Hybrid(RsaPrivateKey, Box<MlKemPrivateKey>),
+    /// Decrypts the `SlotKem::Kem` slots of the same identifier.
+    Kem(Arc<dyn Kem>),
+}
+
+impl SlotKey {
+    /// Decrypt the AES key of a slot.
+    ///
+    /// # Returns
+    /// The AES key, and whether it was actually decrypted. (`false` for the synthetic key of an
+    /// implicit rejection, see `KeyWrap::unwrap_implicit`) `None` if the key doesn't match, or the
+    /// slot is corrupted.
+    ///
+    fn unwrap(
+        &self,
+        header: &Header,
+        kem: SlotKem,
+        wrapped_key: &[u8],
+        implicit_rejection: bool,
+    ) -> Option<(Zeroizing<Vec<u8>>, bool)> {
+        let unwrap_rsa = |key: &RsaPrivateKey| {
+            if implicit_rejection {
+                let key_len = header.cipher.key_len();
+                Some(header.key_wrap.unwrap_implicit(key, wrapped_key, key_len))
+            } else {
+                header
+                    .key_wrap
+                    .unwrap(key, wrapped_key)
+                    .map(|raw_aes_key| (Zeroizing::new(raw_aes_key), true))
+            }
+        };
+        match (self, kem) {
+            (SlotKey::Rsa(key), SlotKem::Rsa) => unwrap_rsa(key),
+            #[cfg(feature = "ml-kem")]
+            (SlotKey::Hybrid(key, _), SlotKem::Rsa) => unwrap_rsa(key),
+            #[cfg(feature = "ml-kem")]
+            (SlotKey::Hybrid(key, ml_kem_key), SlotKem::RsaMlKem768) => {
+                pq::unwrap_hybrid(key, ml_kem_key, header.key_wrap, wrapped_key)
+                    .map(|raw_aes_key| (raw_aes_key, true))
+            }
+            (SlotKey::Kem(kem), SlotKem::Kem(id)) if kem.id() == id => {
+                unwrap_kem_slot(kem.as_ref(), wrapped_key).map(|raw_aes_key| (raw_aes_key, true))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reject a `Format::V1` header without a key commitment. (See `check_data_key`)
+///
+/// # Errors
+/// - `InvalidData`: If the header has no commitment.
+///
+fn require_commitment(header: &Header) -> Result<()> {
+    if header.format != Format::V0 && header.commitment.is_none() {
+        Err(error!(InvalidData, "The stream has no key commitment"))?;
+    }
+    Ok(())
+}
+
+/// Decrypt the AES key of the stream with the first matching private key.
+/// The AES key is checked against the key check value and the commitment of the header.
+///
+/// # Returns
+/// The index of the matching key in `keys`, and the AES key.
+///
+/// # Errors
+/// - `InvalidData`: If a `Format::V1` header has no key commitment.
+/// - `Error::WrongKey`: If no key decrypts any of the wrapped keys of the header.
+///
+pub(crate) fn unwrap_data_key(
+    header: &Header,
+    keys: &[SlotKey],
+    implicit_rejection: bool,
+) -> Result<(usize, Zeroizing<Vec<u8>>)> {
+    require_commitment(header)?;
+    // Every slot is tried, and a failed unwrap (e.g. a bad PKCS#1 v1.5 padding) is checked with a
+    // dummy AES key: the time doesn't reveal which slot matched, or why a slot failed (See `ct`)
+    let key_len = header.cipher.key_len();
+    let mut found = None;
+    // The synthetic keys of the implicit rejection only pass the checks of the `Format::V0`
+    // streams, without key check value and commitment: a decrypted key is preferred
+    let mut rejected = None;
+    for (index, key) in keys.iter().enumerate() {
+        for (slot, wrapped_key) in header.wrapped_keys.iter().enumerate() {
+            let unwrapped = key.unwrap(
+                header,
+                header.slot_kem(slot),
+                wrapped_key,
+                implicit_rejection,
+            );
+            let unwrapped_ok = unwrapped
+                .as_ref()
+                .is_some_and(|(raw_aes_key, _)| raw_aes_key.len() == key_len);
+            let (raw_aes_key, decrypted) = match unwrapped {
+                Some(unwrapped) if unwrapped_ok => unwrapped,
+                _ => (Zeroizing::new(vec![0; key_len]), false),
+            };
+            let valid = ct::and(unwrapped_ok, check_data_key(header, &raw_aes_key));
+            let candidate = if decrypted { &mut found } else { &mut rejected };
+            if valid && candidate.is_none() {
+                *candidate = Some((index, raw_aes_key));
+            }
+        }
+    }
+    found.or(rejected).ok_or_else(|| Error::WrongKey.into())
 }
 
 /// A reader that decrypts data read from an underlying reader.
@@ -50,42 +203,241 @@ macro_rules! min {
 ///
 /// The data is read from the reader in the following format:
 /// ```plaintext
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |     Header      |   |     AES Data    |   |     AES Data    |   
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |  See `Header`   |   |                 |   |                 |   ...
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+/// +-----------------+   +-----------------+   +-----------------+
 /// ```
 ///
-/// The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data.
-pub struct CryptoReader<R: std::io::Read, const BUFFER_SIZE: usize> {
+/// The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data. It must be a
+/// supported chunk size, checked at compile time. (As `CryptoWriter`)
+pub struct CryptoReader<R: io::Read, const BUFFER_SIZE: usize> {
     reader: R,
-    nonce: Nonce,
-    cipher: Aes256Gcm,
+    state: State,
+    format: Format,
+    padding: Padding,
+    end_marker: bool,
+    chained: bool,
+    /// The number of chunks recorded in the summary of the header, if any. (See `Header::summary`)
+    summary_chunks: Option<u64>,
+    /// The metadata of the stream, once read. (See `Header::metadata`)
+    metadata: Option<Metadata>,
+    /// The bytes of the metadata read so far, while it is being read.
+    pending_metadata: Option<Vec<u8>>,
+    /// The length of the metadata at the beginning of the plaintext. (See `read_range`)
+    #[cfg(feature = "std")]
+    metadata_len: u64,
+    /// The nonce of the first chunk. (See `read_range`)
+    #[cfg(feature = "std")]
+    first_nonce: Nonce,
+    /// The position of the stream in the underlying reader, once known. (See `read_range`)
+    #[cfg(feature = "std")]
+    stream_start: Option<u64>,
+    /// The length of the header. (0 until the header is read)
+    header_len: usize,
+    strict: bool,
+    /// Whether the final chunk was decrypted. (Streams with an end marker)
+    finished: bool,
+    stats: Stats,
     enc_buffer_len: usize,
-    buffer_len: usize,
     enc_buffer: Vec<u8>,
-    // auth_buffer: [u8; AES_AUTH_TAG_LEN],
+    buffer_pos: usize,
+    buffer_len: usize,
     buffer: [u8; BUFFER_SIZE],
+    /// The error hit after some bytes were returned, reported by the next `read` call.
+    pending_error: Option<io::Error>,
+    limits: ReaderLimits,
+}
+
+/// The description of a stream checked by `CryptoReader::verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamInfo {
+    /// The format of the stream.
+    pub format: Format,
+    /// The number of chunks. (The final chunk included, even if empty)
+    pub chunks: u64,
+    /// The length of the plaintext. (Without the padding and the metadata, see `metadata`)
+    pub plaintext_len: u64,
+    /// The length of the stream. (The header included)
+    pub stream_len: u64,
+    /// Whether the end of the stream is authenticated. (See `Header::end_marker`) Without it, a
+    /// stream truncated on a chunk boundary can't be detected. (Only `Format::V0` streams, checked
+    /// with `verify_legacy`)
+    pub end_marker: bool,
+}
+
+/// Limits on the data accepted by a `CryptoReader`, so a malicious or corrupted stream can't make
+/// the reader allocate or process an unbounded amount of data. (See `CryptoReader::set_limits`)
+///
+/// The default has no limit beyond the format ones. (A header holds at most two fields of 64 KiB,
+/// and the chunk size must be `BUFFER_SIZE`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ReaderLimits {
+    /// The largest chunk size accepted from the header. (Checked before the chunk size is
+    /// compared to `BUFFER_SIZE`)
+    pub max_chunk_size: Option<u32>,
+    /// The largest header accepted, in bytes. (The header bytes are buffered until it is parsed)
+    pub max_header_size: Option<usize>,
+    /// The largest stream accepted, in bytes of encrypted chunks. (Without the header)
+    pub max_total_size: Option<u64>,
+}
+
+/// The state of a `CryptoReader`.
+#[allow(clippy::large_enum_variant)] // A single one per reader
+enum State {
+    /// The header is not read yet. (Lazy reader, or the underlying reader returned `WouldBlock`)
+    Header {
+        /// The header bytes read so far.
+        bytes: Vec<u8>,
+        keys: Vec<SlotKey>,
+        min_format: Format,
+        min_key_wrap: KeyWrap,
+        /// Whether a failed RSA unwrap continues with a synthetic key. (See `set_implicit_rejection`)
+        implicit_rejection: bool,
+    },
+    /// The header is read, the chunks are being decrypted.
+    Chunks {
+        cipher: AeadCipher,
+        nonce: NonceCounter,
+        /// The index of the next chunk.
+        index: u64,
+        /// The tag of the previous chunk, when the chunks are chained. (See `Header::chained`)
+        previous_tag: Option<[u8; AES_AUTH_TAG_LEN]>,
+        /// The hash of the header, authenticated by each chunk. (See `Header::digest`)
+        header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    },
 }
 
-impl<R: std::io::Read, const BUFFER_SIZE: usize> CryptoReader<R, BUFFER_SIZE> {
+/// A reader replaying the bytes already read, before reading (and recording) new ones.
+/// Used to parse the header again from the start, once more bytes are available.
+struct Replay<'a, R: io::Read> {
+    reader: &'a mut R,
+    bytes: &'a mut Vec<u8>,
+    pos: usize,
+    /// The largest number of bytes recorded. (`ReaderLimits::max_header_size`)
+    limit: Option<usize>,
+}
+
+impl<R: io::Read> io::Read for Replay<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.bytes.len() {
+            let to_copy = core::cmp::min(buf.len(), self.bytes.len() - self.pos);
+            buf[..to_copy].copy_from_slice(&self.bytes[self.pos..self.pos + to_copy]);
+            self.pos += to_copy;
+            return Ok(to_copy);
+        }
+        let buf = match self.limit {
+            Some(limit) if self.bytes.len() + buf.len() > limit => {
+                if self.bytes.len() >= limit {
+                    Err(error!(
+                        InvalidData,
+                        "Header exceeds the limit of {} bytes", limit
+                    ))?;
+                }
+                &mut buf[..limit - self.bytes.len()]
+            }
+            _ => buf,
+        };
+        let read = self.reader.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/// The encrypted data received from an async reader, not yet consumed by the `CryptoReader`.
+/// Returns `WouldBlock` once empty, so the `CryptoReader` keeps its partial header or chunk, and
+/// the async reader reads more data before calling it again.
+#[cfg(any(
+    feature = "embedded-io-async",
+    feature = "futures-io",
+    all(feature = "uring", target_os = "linux")
+))]
+#[derive(Default)]
+pub(crate) struct Feed {
+    data: Vec<u8>,
+    pos: usize,
+    /// The length of the data, without the spare space being filled.
+    filled: usize,
+    eof: bool,
+}
+
+#[cfg(any(
+    feature = "embedded-io-async",
+    feature = "futures-io",
+    all(feature = "uring", target_os = "linux")
+))]
+impl Feed {
+    /// Drop the consumed data, and get `len` bytes of spare space to read into.
+    pub(crate) fn spare(&mut self, len: usize) -> &mut [u8] {
+        self.data.truncate(self.filled);
+        self.data.drain(..self.pos);
+        self.pos = 0;
+        self.filled = self.data.len();
+        self.data.resize(self.filled + len, 0);
+        &mut self.data[self.filled..]
+    }
+
+    /// Keep the `read` bytes read into the spare space. (`0` at the end of the stream)
+    pub(crate) fn commit(&mut self, read: usize) {
+        self.filled += read;
+        self.data.truncate(self.filled);
+        self.eof = read == 0;
+    }
+
+    /// Discard the spare space, nothing was read into it.
+    #[cfg(feature = "futures-io")]
+    pub(crate) fn discard(&mut self) {
+        self.data.truncate(self.filled);
+    }
+}
+
+#[cfg(any(
+    feature = "embedded-io-async",
+    feature = "futures-io",
+    all(feature = "uring", target_os = "linux")
+))]
+impl io::Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.filled {
+            if self.eof {
+                return Ok(0);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Waiting for the async reader",
+            ));
+        }
+        let to_copy = core::cmp::min(buf.len(), self.filled - self.pos);
+        buf[..to_copy].copy_from_slice(&self.data[self.pos..self.pos + to_copy]);
+        self.pos += to_copy;
+        Ok(to_copy)
+    }
+}
+
+impl<R: io::Read, const BUFFER_SIZE: usize> CryptoReader<R, BUFFER_SIZE> {
     /// Create a new `CryptoReader` instance.
     /// The `key` is used to decrypt the AES key.
     ///
     /// # Arguments
     /// - `reader`: The reader from which encrypted data is read.
-    /// - `key`: The RSA private key to decrypt the AES key.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
     ///
     /// # Returns
     /// A `CryptoReader` instance.
     ///
     /// # Errors
-    /// - `Invalid Rsa Key`: If the RSA key is invalid.
+    /// - `Error::WrongKey`: If the RSA key doesn't match the stream, or the header is corrupted.
     /// - `Io`: If an I/O error occurs. Details are provided in the error message.
     ///
+    /// # Notes
+    /// If the underlying reader returns `WouldBlock` before the header is complete, the
+    /// `CryptoReader` is still returned, and the header is read by the next `read` calls. (The
+    /// header errors are then returned by `read`)
+    ///
     /// # Safety
     /// The caller must ensure that the `reader` is not used before the `CryptoReader` instance
     /// is dropped.
@@ -96,70 +448,1217 @@ impl<R: std::io::Read, const BUFFER_SIZE: usize> CryptoReader<R, BUFFER_SIZE> {
     /// Here is a diagram of the data read from the reader:
     ///
     /// ```plaintext
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |     Header      |   |     AES Data    |   |     AES Data    |   
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |  See `Header`   |   |                 |   |                 |   ...
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+    /// +-----------------+   +-----------------+   +-----------------+
     /// ```
     ///
-    pub fn new(mut reader: R, key: RsaPrivateKey) -> Result<Self> {
-        let cipher = {
-            let buffer = &mut [0; AES_KEY_LEN];
-            reader.read_exact(buffer)?;
+    pub fn new(reader: R, key: impl Into<Identity>) -> Result<Self> {
+        Self::new_with_min_format(reader, key, Format::V0)
+    }
+
+    /// Create a new `CryptoReader` instance, without reading anything from the reader.
+    /// The header is read and checked by the first `read` call.
+    ///
+    /// Useful when the reader is set up before the peer has sent anything. (e.g. in a connection
+    /// handler)
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Notes
+    /// The errors of `new` (invalid header, wrong key, ...) are returned by the first `read` call.
+    ///
+    /// The implicit rejection of the RSA wrapped keys is enabled, as for network-facing readers.
+    /// (See `set_implicit_rejection`)
+    ///
+    pub fn new_lazy(reader: R, key: impl Into<Identity>) -> Self {
+        Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Rsa(key.into().into_rsa())],
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                // Lazy readers are typically network-facing
+                implicit_rejection: true,
+            },
+        )
+    }
+
+    /// Create a new `CryptoReader` instance, with limits on the data accepted from the stream.
+    /// (Recommended for untrusted streams, e.g. received from the network)
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    /// - `limits`: The limits. (See `ReaderLimits`)
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the header exceeds `limits.max_header_size`, or its chunk size exceeds
+    ///   `limits.max_chunk_size`. (The stream exceeding `limits.max_total_size` is reported by
+    ///   `read`)
+    /// - The errors of `new`.
+    ///
+    pub fn new_with_limits(
+        reader: R,
+        key: impl Into<Identity>,
+        limits: ReaderLimits,
+    ) -> Result<Self> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Rsa(key.into().into_rsa())],
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        reader.limits = limits;
+        match reader.read_header() {
+            Ok(_) => Ok(reader),
+            // The header is read by the next `read` calls
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(reader),
+            Err(e) => Err(e),
+        }
+    }
 
-            // Decrypt the AES key
-            let raw_aes_key = key
-                .decrypt(Pkcs1v15Encrypt, buffer)
-                .map_err(|e| error!(Other, "RSA Decryption error: {}", e))?;
+    /// Create a new `CryptoReader` instance, requiring the stream to use at least the given
+    /// format.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    /// - `min_format`: The oldest accepted format.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the stream format is older than `min_format`, or if the stream was
+    ///   written with a different `BUFFER_SIZE`.
+    /// - `Error::WrongKey`: If the RSA key doesn't match the stream, or the header is corrupted.
+    /// - `Io`: If an I/O error occurs. Details are provided in the error message.
+    ///
+    pub fn new_with_min_format(
+        reader: R,
+        key: impl Into<Identity>,
+        min_format: Format,
+    ) -> Result<Self> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Rsa(key.into().into_rsa())],
+                min_format,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        match reader.read_header() {
+            Ok(_) => Ok(reader),
+            // The header is read by the next `read` calls
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(reader),
+            Err(e) => Err(e),
+        }
+    }
 
-            let aes_key = Key::<Aes256Gcm>::from_slice(&raw_aes_key);
-            Aes256Gcm::new(aes_key)
+    /// Create a new `CryptoReader` instance, requiring the AES key to be wrapped with at least the
+    /// given scheme. (e.g. `KeyWrap::OaepSha256` to reject the PKCS#1 v1.5 streams)
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    /// - `min_key_wrap`: The weakest accepted key wrap scheme.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the key wrap scheme of the stream is weaker than `min_key_wrap`. (The
+    ///   RSA decryption isn't attempted)
+    /// - The errors of `new`.
+    ///
+    pub fn new_with_min_key_wrap(
+        reader: R,
+        key: impl Into<Identity>,
+        min_key_wrap: KeyWrap,
+    ) -> Result<Self> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Rsa(key.into().into_rsa())],
+                min_format: Format::V0,
+                min_key_wrap,
+                implicit_rejection: false,
+            },
+        );
+        match reader.read_header() {
+            Ok(_) => Ok(reader),
+            // The header is read by the next `read` calls
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(reader),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a new `CryptoReader` instance, trying each of the given keys.
+    /// Useful when several keys are held (e.g. rotated keys), and the one used to encrypt the
+    /// stream is unknown. Each key is tried against each wrapped key slot of the header.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `keys`: The RSA private keys to try.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance, and the index of the key that decrypted the AES key.
+    ///
+    /// # Errors
+    /// - `Error::WrongKey`: If none of the keys can decrypt the AES key.
+    /// - `InvalidData`: If the header is malformed.
+    /// - `Io`: If an I/O error occurs. Details are provided in the error message.
+    ///
+    /// # Notes
+    /// Unlike `new`, the header must be read at once: on a non-blocking reader, wait for the
+    /// reader to be readable before calling it.
+    ///
+    pub fn new_try_keys(reader: R, keys: &[RsaPrivateKey]) -> Result<(Self, usize)> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: keys.iter().cloned().map(SlotKey::Rsa).collect(),
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        let index = reader.read_header()?;
+        Ok((reader, index))
+    }
+
+    /// Create a new `CryptoReader` instance, decrypting the AES key with a `Kem` backend.
+    /// (See `WriterOptions::kem_recipients`)
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `kem`: The backend holding the private key of the recipient.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `Error::WrongKey`: If no slot of the stream can be decapsulated by `kem`. (Decapsulation
+    ///   errors included)
+    /// - The errors of `new`.
+    ///
+    pub fn new_with_kem(reader: R, kem: Arc<dyn Kem>) -> Result<Self> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Kem(kem)],
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        match reader.read_header() {
+            Ok(_) => Ok(reader),
+            // The header is read by the next `read` calls
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(reader),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a new `CryptoReader` instance for a stream written with post-quantum hybrid slots.
+    /// (See `WriterOptions::ml_kem_keys`) The streams written with RSA slots only are also read.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity of the recipient, with its RSA private key. (See `Identity`)
+    /// - `ml_kem_key`: The ML-KEM-768 private key of the recipient.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `Error::WrongKey`: If the keys don't match the stream. (Both are required to decrypt a
+    ///   hybrid slot)
+    /// - The errors of `new`.
+    ///
+    #[cfg(feature = "ml-kem")]
+    pub fn new_hybrid(
+        reader: R,
+        key: impl Into<Identity>,
+        ml_kem_key: MlKemPrivateKey,
+    ) -> Result<Self> {
+        let mut reader = Self::with_state(
+            reader,
+            State::Header {
+                bytes: Vec::new(),
+                keys: vec![SlotKey::Hybrid(key.into().into_rsa(), Box::new(ml_kem_key))],
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        match reader.read_header() {
+            Ok(_) => Ok(reader),
+            // The header is read by the next `read` calls
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(reader),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create a new `CryptoReader` instance with an already known AES key, instead of a RSA
+    /// private key. The wrapped keys of the header are ignored.
+    ///
+    /// Useful when the AES key is handled outside of the stream, and to drive the reader from
+    /// tests and fuzz targets without RSA operations.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `raw_aes_key`: The AES key of the stream. (32 bytes)
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the header is malformed, or the key length is invalid.
+    /// - `Error::WrongKey`: If the key doesn't match the key check value or the commitment of
+    ///   the header.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn from_raw_key(mut reader: R, raw_aes_key: &[u8]) -> Result<Self> {
+        let header = Header::parse(&mut reader)?;
+        Self::new_with_data_key(reader, &header, raw_aes_key)
+    }
+
+    /// Create a new `CryptoReader` instance for a stream written with a `SessionCache`. (See
+    /// `CryptoWriter::new_with_session`) The streams written without a session are also read.
+    ///
+    /// The master key unwrapped from the first stream of a session is kept in `sessions`, so the
+    /// next streams of the session are read without a RSA decryption.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the master key of a new session.
+    /// - `sessions`: The cache of the sessions.
+    ///
+    /// # Returns
+    /// A `CryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `NotFound`: If the stream belongs to a session whose first stream was not read with
+    ///   `sessions`.
+    /// - The errors of `new`.
+    ///
+    /// # Notes
+    /// Unlike `new`, the header must be read at once: on a non-blocking reader, wait for the
+    /// reader to be readable before calling it.
+    ///
+    #[cfg(feature = "std")]
+    pub fn new_with_session(
+        mut reader: R,
+        key: impl Into<Identity>,
+        sessions: &SessionCache,
+    ) -> Result<Self> {
+        let header = Header::parse(&mut reader)?;
+        let cached = header
+            .session
+            .and_then(|session| sessions.master_key(&session.id));
+        let data_key = match cached {
+            Some(master_key) => master_key,
+            // The streams after the first one of a session have an empty wrapped key
+            None if header.session.is_some() && header.wrapped_keys.iter().all(Vec::is_empty) => {
+                Err(error!(
+                    NotFound,
+                    "Unknown session: its first stream was not read"
+                ))?
+            }
+            None => unwrap_data_key(&header, &[SlotKey::Rsa(key.into().into_rsa())], false)?.1,
+        };
+        let reader = Self::new_with_data_key(reader, &header, &data_key)?;
+        if let Some(session) = header.session {
+            sessions.insert_master_key(session.id, &data_key);
+        }
+        Ok(reader)
+    }
+
+    /// Read the header, decrypt the AES key, then read the metadata, if any.
+    /// Resumes from the bytes read by the previous calls, if any.
+    ///
+    /// # Returns
+    /// The index of the key that decrypted the AES key.
+    ///
+    fn read_header(&mut self) -> Result<usize> {
+        let index = self.read_header_logged()?;
+        self.read_metadata()?;
+        Ok(index)
+    }
+
+    fn read_header_logged(&mut self) -> Result<usize> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("read_header").entered();
+        let result = self.read_header_state();
+        #[cfg(any(feature = "std", feature = "tracing"))]
+        if let Err(e) = &result {
+            #[cfg(feature = "tracing")]
+            if e.kind() != io::ErrorKind::WouldBlock {
+                tracing::debug!(error = %e, "header rejected");
+            }
+            #[cfg(feature = "std")]
+            audit_failure(e);
+        }
+        result
+    }
+
+    /// Read the metadata at the beginning of the plaintext, if the stream has metadata not read
+    /// yet. (See `Header::metadata`)
+    /// Resumes from the bytes read by the previous calls, if any.
+    fn read_metadata(&mut self) -> Result<()> {
+        // Taken while reading, so `read` returns the plaintext
+        let Some(mut bytes) = self.pending_metadata.take() else {
+            return Ok(());
         };
-        let nonce = {
-            let buffer = &mut [0; AES_NONCE_LEN];
-            reader.read_exact(buffer)?;
-            *Nonce::from_slice(buffer.as_slice())
+        match self.read_metadata_bytes(&mut bytes) {
+            Ok(metadata) => {
+                self.metadata = Some(metadata);
+                #[cfg(feature = "std")]
+                {
+                    self.metadata_len = bytes.len() as u64;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                // The data is never returned before the metadata
+                self.pending_metadata = Some(bytes);
+                Err(e)
+            }
+        }
+    }
+
+    fn read_metadata_bytes(&mut self, bytes: &mut Vec<u8>) -> Result<Metadata> {
+        loop {
+            // The length prefix, then the metadata
+            let target = match bytes.get(..LENGTH_LEN) {
+                Some(prefix) => {
+                    let len = u32::from_be_bytes(prefix.try_into().expect("length is checked"));
+                    if len as usize > MAX_METADATA_LEN {
+                        Err(error!(
+                            InvalidData,
+                            "Stream metadata of {} bytes exceeds the limit of {}",
+                            len,
+                            MAX_METADATA_LEN
+                        ))?;
+                    }
+                    if bytes.len() == LENGTH_LEN + len as usize {
+                        return Metadata::from_bytes(&bytes[LENGTH_LEN..]);
+                    }
+                    LENGTH_LEN + len as usize
+                }
+                None => LENGTH_LEN,
+            };
+            let start = bytes.len();
+            bytes.resize(target, 0);
+            let result = io::Read::read(self, &mut bytes[start..]);
+            bytes.truncate(start + *result.as_ref().unwrap_or(&0));
+            if result? == 0 {
+                Err(error!(UnexpectedEof, "Truncated stream metadata"))?;
+            }
+        }
+    }
+
+    fn read_header_state(&mut self) -> Result<usize> {
+        let State::Header {
+            bytes,
+            keys,
+            min_format,
+            min_key_wrap,
+            implicit_rejection,
+        } = &mut self.state
+        else {
+            unreachable!("the header is already read");
         };
+        let header = Header::parse(&mut Replay {
+            reader: &mut self.reader,
+            bytes,
+            pos: 0,
+            limit: self.limits.max_header_size,
+        })?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            format = ?header.format,
+            cipher = ?header.cipher,
+            key_wrap = ?header.key_wrap,
+            slots = header.wrapped_keys.len(),
+            "header parsed"
+        );
+        if header.format < *min_format {
+            Err(error!(
+                InvalidData,
+                "Stream format {:?} is older than the required {:?}", header.format, min_format
+            ))?;
+        }
+        if header.key_wrap < *min_key_wrap {
+            Err(error!(
+                InvalidData,
+                "Stream key wrap {:?} is weaker than the required {:?}",
+                header.key_wrap,
+                min_key_wrap
+            ))?;
+        }
+
+        let (index, raw_aes_key) = unwrap_data_key(&header, keys, *implicit_rejection)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = index, "AES key unwrapped");
+        self.start_chunks(&header, &raw_aes_key)?;
+        Ok(index)
+    }
 
-        Ok(Self {
+    /// Create a new `CryptoReader` instance from an already parsed header and the decrypted AES
+    /// key. Used when the AES key is recovered without the RSA private key. (e.g. from shares)
+    pub(crate) fn new_with_data_key(
+        reader: R,
+        header: &Header,
+        raw_aes_key: &[u8],
+    ) -> Result<Self> {
+        // Placeholder state, replaced by `start_chunks`
+        let mut reader = Self::with_state(
             reader,
-            nonce,
-            cipher,
-            enc_buffer: vec![0; BUFFER_SIZE + AES_AUTH_TAG_LEN],
+            State::Header {
+                bytes: Vec::new(),
+                keys: Vec::new(),
+                min_format: Format::V0,
+                min_key_wrap: KeyWrap::Pkcs1v15,
+                implicit_rejection: false,
+            },
+        );
+        reader.start_chunks(header, raw_aes_key)?;
+        Ok(reader)
+    }
+
+    fn with_state(reader: R, state: State) -> Self {
+        const {
+            assert!(
+                format::is_supported_chunk_size(BUFFER_SIZE),
+                "BUFFER_SIZE must be between format::MIN_CHUNK_SIZE and format::MAX_CHUNK_SIZE"
+            )
+        };
+        Self {
+            reader,
+            state,
+            format: Format::V0,
+            padding: Padding::None,
+            end_marker: false,
+            chained: false,
+            summary_chunks: None,
+            metadata: None,
+            pending_metadata: None,
+            #[cfg(feature = "std")]
+            metadata_len: 0,
+            #[cfg(feature = "std")]
+            first_nonce: Nonce::default(),
+            #[cfg(feature = "std")]
+            stream_start: None,
+            header_len: 0,
+            strict: false,
+            finished: false,
+            stats: Stats::default(),
+            // One extra byte to look ahead for the end of the stream
+            enc_buffer: vec![0; BUFFER_SIZE + AES_AUTH_TAG_LEN + 1],
             buffer: [0; BUFFER_SIZE],
             enc_buffer_len: 0,
+            buffer_pos: 0,
             buffer_len: 0,
+            pending_error: None,
+            limits: ReaderLimits::default(),
+        }
+    }
+
+    /// Check the header, and start decrypting the chunks with the given AES key.
+    fn start_chunks(&mut self, header: &Header, raw_aes_key: &[u8]) -> Result<()> {
+        if header.chunking != Chunking::Fixed {
+            Err(error!(
+                InvalidData,
+                "Stream uses {:?} chunking, read it with `{}`",
+                header.chunking,
+                header.chunking.reader()
+            ))?;
+        }
+        if let Some(chunk_size) = header.chunk_size {
+            if let Some(max_chunk_size) = self.limits.max_chunk_size {
+                if chunk_size > max_chunk_size {
+                    Err(error!(
+                        InvalidData,
+                        "Stream chunk size {} exceeds the limit of {}", chunk_size, max_chunk_size
+                    ))?;
+                }
+            }
+            if chunk_size as usize != BUFFER_SIZE {
+                Err(error!(
+                    InvalidData,
+                    "Stream chunk size is {}, expected {}", chunk_size, BUFFER_SIZE
+                ))?;
+            }
+        }
+        require_commitment(header)?;
+        let cipher = AeadCipher::with_cipher(header.cipher, &stream_key(header, raw_aes_key))
+            .map_err(|e| error!(InvalidData, "{}", e))?;
+        if !check_data_key(header, raw_aes_key) {
+            Err(Error::WrongKey)?;
+        }
+        let header_digest = header.digest()?;
+
+        #[cfg(feature = "std")]
+        audit(|record| {
+            record(&AuditEvent::StreamStarted {
+                direction: Direction::Decrypt,
+                format: header.format,
+                cipher: header.cipher,
+                recipients: header.wrapped_keys.len(),
+            })
+        });
+
+        self.format = header.format;
+        self.padding = header.padding;
+        self.end_marker = header.end_marker;
+        self.chained = header.chained;
+        self.summary_chunks = header.summary.map(|summary| summary.chunks);
+        self.pending_metadata = header.metadata.then(Vec::new);
+        self.header_len = header.len();
+        #[cfg(feature = "std")]
+        {
+            self.first_nonce = Nonce::from(header.nonce);
+        }
+        self.state = State::Chunks {
+            cipher,
+            nonce: NonceCounter::new(Nonce::from(header.nonce)),
+            index: 0,
+            previous_tag: None,
+            header_digest,
+        };
+        Ok(())
+    }
+
+    /// Start decrypting at the given chunk, the underlying reader being positioned at its
+    /// start. (Right after `new_with_data_key`, before any `read`)
+    #[cfg(feature = "std")]
+    pub(crate) fn start_at_chunk(&mut self, chunk: u64) {
+        if let State::Chunks { nonce, index, .. } = &mut self.state {
+            nonce.advance(chunk);
+            *index = chunk;
+        }
+    }
+
+    /// The format of the stream being read.
+    /// (`Format::V0` until the header is read)
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The encrypted metadata of the stream, if any. (See `CryptoWriter::new_with_metadata`)
+    ///
+    /// The metadata is read along with the header: by the constructors reading the header, or by
+    /// the first `read`. (`None` until then)
+    ///
+    /// # Notes
+    /// The metadata is the beginning of the plaintext, it is not returned by `read`. It is
+    /// counted in the plaintext bytes of the statistics, and in `predicted_plaintext_len`.
+    ///
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The exact length of the plaintext of a stream of `stream_len` bytes, header included. (The
+    /// inverse of `CryptoWriter::predicted_len`)
+    ///
+    /// # Arguments
+    /// - `stream_len`: The length of the whole stream. (e.g. the `Content-Length` of an encrypted
+    ///   HTTP body)
+    ///
+    /// # Returns
+    /// The length of the plaintext, or `None` if it can't be predicted: the header is not read
+    /// yet, the final chunk is padded (only its padded length is known), or `stream_len` can't be
+    /// the length of a valid stream.
+    ///
+    pub fn predicted_plaintext_len(&self, stream_len: u64) -> Option<u64> {
+        if self.header_len == 0 || self.padding != Padding::None {
+            return None;
+        }
+        let chunk_len = (BUFFER_SIZE + AES_AUTH_TAG_LEN) as u64;
+        let chunks_len = stream_len.checked_sub(self.header_len as u64)?;
+        let (full_chunks, remaining) = (chunks_len / chunk_len, chunks_len % chunk_len);
+        let final_len = match remaining {
+            // The final chunk is always written with an end marker (even if empty)
+            0 if !self.end_marker => 0,
+            remaining => remaining.checked_sub(AES_AUTH_TAG_LEN as u64)?,
+        };
+        Some(full_chunks * BUFFER_SIZE as u64 + final_len)
+    }
+
+    /// The statistics of the reader: chunks read, plaintext and ciphertext bytes, and time spent
+    /// decrypting. (See `Stats`)
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Iterate over the decrypted chunks of the stream, one chunk per item.
+    ///
+    /// Useful for records aligned to the chunk boundaries. (e.g. length-prefixed messages
+    /// written one per chunk, padded to `BUFFER_SIZE`)
+    ///
+    /// # Returns
+    /// An iterator of `Result<Vec<u8>>`, ending at the end of the stream.
+    ///
+    /// # Notes
+    /// If some data of the current chunk was already consumed with `read`, the first item is the
+    /// rest of this chunk. Empty chunks (e.g. the final chunk of a stream ending on a chunk
+    /// boundary) are skipped.
+    ///
+    /// The errors of `read` (including `WouldBlock`) are returned as items, the iteration can go
+    /// on after them.
+    ///
+    pub fn chunks(&mut self) -> Chunks<'_, R, BUFFER_SIZE> {
+        Chunks { reader: self }
+    }
+
+    /// Check a whole stream without returning its plaintext: the key, the authentication tag of
+    /// every chunk (so their order and chaining), and the end of the stream. (e.g. for the
+    /// integrity checks of stored backups)
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// The description of the stream. (See `StreamInfo`)
+    ///
+    /// # Errors
+    /// - `Error::CorruptedChunk`: If a chunk fails the authentication. (Modified, removed or
+    ///   reordered chunks)
+    /// - `UnexpectedEof`: If the stream is truncated.
+    /// - `InvalidData`: If the stream has no end marker, if data follows the end of the stream, or
+    ///   if the number of chunks doesn't match the summary of the header.
+    /// - The errors of `new`.
+    ///
+    /// # Notes
+    /// The stream is read in strict mode. (See `set_strict`) A stream without end marker can't be
+    /// told apart from a truncated one, so it fails the check: use `verify_legacy` to check the
+    /// `Format::V0` streams.
+    ///
+    /// The chunks are decrypted (a GCM tag can't be checked otherwise), one at a time in the inner
+    /// buffer, which is cleared once the stream is checked. Nothing is written anywhere.
+    ///
+    pub fn verify(reader: R, key: impl Into<Identity>) -> Result<StreamInfo> {
+        Self::verify_with(reader, key, false)
+    }
+
+    /// Check a whole stream as `verify`, accepting the `Format::V0` streams, which have no end
+    /// marker.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// The description of the stream. (See `StreamInfo`)
+    ///
+    /// # Errors
+    /// The errors of `verify`. (`Format::V1` streams without end marker still fail)
+    ///
+    /// # Notes
+    /// A `Format::V0` stream is checked up to its last chunk, and reported with
+    /// `StreamInfo::end_marker` set to `false`: a truncation on a chunk boundary can't be
+    /// detected.
+    ///
+    pub fn verify_legacy(reader: R, key: impl Into<Identity>) -> Result<StreamInfo> {
+        Self::verify_with(reader, key, true)
+    }
+
+    /// Check a whole stream. (See `verify` and `verify_legacy`)
+    fn verify_with(reader: R, key: impl Into<Identity>, allow_legacy: bool) -> Result<StreamInfo> {
+        let mut reader = Self::new(reader, key)?;
+        if let State::Header { .. } = reader.state {
+            reader.read_header()?;
+        }
+        // Only the `Format::V0` streams have no end marker, if explicitly accepted
+        let legacy = allow_legacy && reader.format == Format::V0;
+        if !reader.end_marker && !legacy {
+            Err(error!(
+                InvalidData,
+                "The stream has no end marker, its truncation can't be detected"
+            ))?;
+        }
+        // The metadata is read with the header, the rest of its chunk is plaintext
+        let metadata_len =
+            reader.stats.plaintext_bytes - (reader.buffer_len - reader.buffer_pos) as u64;
+        reader.strict = reader.end_marker;
+        let result = reader.skip_chunks();
+        reader.buffer.fill(0);
+        result?;
+        Ok(StreamInfo {
+            format: reader.format,
+            chunks: reader.stats.chunks,
+            plaintext_len: reader.stats.plaintext_bytes - metadata_len,
+            stream_len: reader.header_len as u64 + reader.stats.ciphertext_bytes,
+            end_marker: reader.end_marker,
         })
     }
 
-    /// Decrypt the data read from the reader.
-    fn decrypt_buffer(&mut self) -> Result<()> {
-        assert!(self.enc_buffer.len() > AES_AUTH_TAG_LEN);
-        dbg_println!(
-            "Block to decrypt: {} | {}",
-            self.enc_buffer.len(),
-            self.enc_buffer_len
+    /// Decrypt the remaining chunks, discarding their plaintext.
+    fn skip_chunks(&mut self) -> Result<()> {
+        while self.read_chunk(None)?.is_some() {
+            self.buffer_pos = self.buffer_len;
+        }
+        Ok(())
+    }
+
+    /// Enable or disable the strict mode. (Disabled by default)
+    ///
+    /// In strict mode, the stream must have an end marker (`Header::end_marker`), and any data
+    /// following the final chunk is an error. Otherwise, the data following a full-length final
+    /// chunk (e.g. with `Padding::Block`) is ignored.
+    ///
+    /// # Notes
+    /// Streams without an end marker (`Format::V0`, or written by older versions) fail to be
+    /// read in strict mode, as their end can't be told apart from a truncation.
+    ///
+    /// Data appended to a shorter final chunk fails the decryption in both modes, as the end of
+    /// the chunk can't be located.
+    ///
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Set the limits on the data accepted from the stream. (See `ReaderLimits`)
+    ///
+    /// # Notes
+    /// The header limits are only effective before the header is read. (e.g. on a lazy reader,
+    /// before the first `read`)
+    ///
+    pub fn set_limits(&mut self, limits: ReaderLimits) {
+        self.limits = limits;
+    }
+
+    /// The limits on the data accepted from the stream.
+    pub fn limits(&self) -> ReaderLimits {
+        self.limits
+    }
+
+    /// Enable or disable the implicit rejection of the RSA wrapped keys. (Enabled by default for
+    /// the lazy readers, see `new_lazy`, disabled otherwise)
+    ///
+    /// With implicit rejection, a RSA decryption failure (e.g. a bad PKCS#1 v1.5 padding)
+    /// continues with a synthetic AES key instead of failing at once, as TLS does: the failure is
+    /// reported as a key check failure (`Error::WrongKey`), or, for the streams without a key
+    /// check value or commitment, as a corrupted first chunk. (`Error::CorruptedChunk`) A peer
+    /// sending forged wrapped keys can't tell a padding failure from a wrong key. (Bleichenbacher
+    /// style padding oracle)
+    ///
+    /// # Notes
+    /// Only effective before the header is read. (e.g. on a lazy reader, before the first `read`)
+    ///
+    pub fn set_implicit_rejection(&mut self, enabled: bool) {
+        if let State::Header {
+            implicit_rejection, ..
+        } = &mut self.state
+        {
+            *implicit_rejection = enabled;
+        }
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the underlying reader.
+    ///
+    /// # Notes
+    /// Reading from the underlying reader directly corrupts the stream, unless the `CryptoReader`
+    /// is not used afterwards.
+    ///
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Get back the underlying reader, e.g. to continue using a connection after the stream.
+    ///
+    /// # Returns
+    /// The underlying reader.
+    ///
+    /// # Notes
+    /// The reader reads one byte past each chunk (to locate the final one), and up to the end of
+    /// the underlying reader. To read the rest of a connection, bound the encrypted payload, e.g.
+    /// with `Read::take` and a length prefix, then unwrap the `Take`:
+    ///
+    /// ```ignore
+    /// let mut reader = CryptoReader::<_, 4096>::new((&mut stream).take(payload_len), key)?;
+    /// reader.read_to_end(&mut payload)?;
+    /// let stream = reader.into_inner().into_inner();
+    /// ```
+    ///
+    /// The encrypted bytes read but not decrypted yet (e.g. if the stream isn't read to the end)
+    /// are lost.
+    ///
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Whether the reader must know which chunk is the final one.
+    /// This requires reading one byte past the current chunk before decrypting it.
+    fn needs_final_chunk(&self) -> bool {
+        self.padding != Padding::None || self.end_marker
+    }
+
+    /// Read and decrypt the next chunk into the inner buffer, or into `out` if given. (It must
+    /// hold at least `BUFFER_SIZE` bytes)
+    ///
+    /// # Returns
+    /// The length of the decrypted chunk, or `None` if the underlying reader is closed, and no
+    /// chunk is left.
+    ///
+    fn read_chunk(&mut self, out: Option<&mut [u8]>) -> Result<Option<usize>> {
+        let result = self.decrypt_next_chunk(out);
+        #[cfg(feature = "std")]
+        if let Err(e) = &result {
+            audit_failure(e);
+        }
+        result
+    }
+
+    fn decrypt_next_chunk(&mut self, mut out: Option<&mut [u8]>) -> Result<Option<usize>> {
+        if self.finished {
+            return Ok(None);
+        }
+        if self.strict && !self.end_marker {
+            Err(error!(
+                InvalidData,
+                "Stream has no end marker, it can't be read in strict mode"
+            ))?;
+        }
+
+        let chunk_len = BUFFER_SIZE + AES_AUTH_TAG_LEN;
+        let target_len = if self.needs_final_chunk() {
+            chunk_len + 1
+        } else {
+            chunk_len
+        };
+
+        while self.enc_buffer_len < target_len {
+            let read = match self
+                .reader
+                .read(&mut self.enc_buffer[self.enc_buffer_len..target_len])
+            {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // The bytes read so far stay in the encrypted buffer
+                Err(e) => return Err(e),
+            };
+            if read == 0 {
+                // The reader is closed
+                break;
+            }
+            self.enc_buffer_len += read;
+        }
+
+        if self.enc_buffer_len == 0 {
+            if self.end_marker {
+                Err(error!(
+                    UnexpectedEof,
+                    "Truncated stream: the final chunk is missing"
+                ))?;
+            }
+            // The reader is closed
+            return Ok(None);
+        }
+
+        let is_final = self.enc_buffer_len < target_len;
+        let consumed = core::cmp::min(self.enc_buffer_len, chunk_len);
+        if let Some(max_total_size) = self.limits.max_total_size {
+            if self.stats.ciphertext_bytes + consumed as u64 > max_total_size {
+                Err(error!(
+                    InvalidData,
+                    "Stream exceeds the limit of {} bytes", max_total_size
+                ))?;
+            }
+        }
+        let mut len = if !self.end_marker {
+            self.decrypt_buffer(consumed, false, out.as_deref_mut())?
+        } else if is_final {
+            let len = self.decrypt_buffer(consumed, true, out.as_deref_mut())?;
+            self.finished = true;
+            len
+        } else {
+            match self.decrypt_buffer(consumed, false, out.as_deref_mut()) {
+                Ok(len) => len,
+                // Data follows, but the chunk may still be the final one
+                Err(e) => {
+                    let Ok(len) = self.decrypt_buffer(consumed, true, out.as_deref_mut()) else {
+                        return Err(e);
+                    };
+                    if self.strict {
+                        Err(error!(
+                            InvalidData,
+                            "Trailing data after the end of the stream"
+                        ))?;
+                    }
+                    self.finished = true;
+                    len
+                }
+            }
+        };
+
+        // Keep the look ahead byte for the next chunk
+        self.enc_buffer
+            .copy_within(consumed..self.enc_buffer_len, 0);
+        self.enc_buffer_len -= consumed;
+
+        if (is_final || self.finished) && self.padding != Padding::None {
+            len = match out {
+                Some(out) => unpad(&out[..len])?,
+                None => {
+                    self.buffer_len = unpad(&self.buffer[..len])?;
+                    self.buffer_len
+                }
+            };
+        }
+        self.stats.chunks += 1;
+        self.stats.plaintext_bytes += len as u64;
+        self.stats.ciphertext_bytes += consumed as u64;
+        if let (true, Some(chunks), State::Chunks { index, .. }) =
+            (self.finished, self.summary_chunks, &self.state)
+        {
+            if *index != chunks {
+                Err(error!(
+                    InvalidData,
+                    "Stream has {} chunks, its header records {}", index, chunks
+                ))?;
+            }
+        }
+        Ok(Some(len))
+    }
+
+    /// Decrypt the first `len` bytes of the encrypted buffer into the inner buffer, or into `out`
+    /// if given.
+    ///
+    /// # Returns
+    /// The length of the decrypted chunk.
+    ///
+    fn decrypt_buffer(
+        &mut self,
+        len: usize,
+        is_final: bool,
+        out: Option<&mut [u8]>,
+    ) -> Result<usize> {
+        if len < AES_AUTH_TAG_LEN {
+            Err(error!(UnexpectedEof, "Truncated chunk"))?;
+        }
+        let State::Chunks {
+            cipher,
+            nonce,
+            index,
+            previous_tag,
+            header_digest,
+        } = &mut self.state
+        else {
+            unreachable!("the header is read before the chunks");
+        };
+        dbg_println!("Block to decrypt: {}", len);
+        let timer = Timer::start();
+        // Decrypt in place: the tag is checked first, so a failure leaves the chunk unchanged
+        let (data, tag) = self.enc_buffer[..len].split_at_mut(len - AES_AUTH_TAG_LEN);
+        let aad = ChunkAad::new(header_digest.as_ref(), previous_tag.as_ref(), is_final);
+        let result = cipher
+            .decrypt_in_place_detached(nonce.nonce(), aad.as_bytes(), data, tag)
+            .map_err(|_| Error::CorruptedChunk { index: *index });
+        let elapsed = timer.elapsed();
+        self.stats.cipher_time += elapsed;
+        // A failure may be retried as the final chunk, so it's only traced
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            chunk = *index,
+            len,
+            is_final,
+            ok = result.is_ok(),
+            ?elapsed,
+            "chunk decrypted"
         );
-        let result = self
-            .cipher
-            .decrypt(&self.nonce, self.enc_buffer[..self.enc_buffer_len].as_ref())
-            .map_err(|e| error!(Other, "AES Decryption error: {}", e))?;
-        dbg_println!("Block decrypted: {}", result.len());
-        increment_nonce(&mut self.nonce);
-        // Setup buffer
-        self.buffer_len = self.enc_buffer_len - AES_AUTH_TAG_LEN;
-        self.buffer[..self.buffer_len].copy_from_slice(result.as_slice());
-        // Reset encrpyted buffer
-        self.enc_buffer = vec![0; BUFFER_SIZE + AES_AUTH_TAG_LEN];
+        result?;
+        if self.chained {
+            *previous_tag = Some((&*tag).try_into().expect("the tag length is checked"));
+        }
+        dbg_println!("Block decrypted: {}", data.len());
+        nonce.increment();
+        *index += 1;
+        let decrypted_len = data.len();
+        match out {
+            // Decrypted straight into the caller's buffer: the inner buffer stays empty
+            Some(out) => out[..decrypted_len].copy_from_slice(data),
+            None => {
+                self.buffer[..decrypted_len].copy_from_slice(data);
+                self.buffer_pos = 0;
+                self.buffer_len = decrypted_len;
+            }
+        }
+        // Clear the plaintext left in the encrypted buffer (only the consumed region)
+        data.fill(0);
+        Ok(decrypted_len)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: io::Read + std::io::Seek, const BUFFER_SIZE: usize> CryptoReader<R, BUFFER_SIZE> {
+    /// Read the plaintext range `offset..offset + len`, only decrypting the chunks covering it.
+    /// (e.g. to serve the seeks into a large encrypted video)
+    ///
+    /// # Arguments
+    /// - `offset`: The position of the range in the plaintext. (As returned by `read`, so without
+    ///   the metadata)
+    /// - `len`: The length of the range.
+    ///
+    /// # Returns
+    /// The plaintext of the range. Shorter than `len` if the range runs past the end of the
+    /// plaintext. (Empty if it starts at or after the end)
+    ///
+    /// # Errors
+    /// - `Error::CorruptedChunk`: If a chunk of the range fails the authentication.
+    /// - `UnexpectedEof`: If the stream is truncated in the range.
+    /// - The errors of `read` for the header, if it is not read yet.
+    /// - `Io`: If an I/O error occurs. (Seeking included)
+    ///
+    /// # Notes
+    /// Each chunk is authenticated on its own, with its position (so a chunk moved elsewhere in
+    /// the stream fails), and the final chunk with the end marker. The chunks outside of the
+    /// range are not checked: use `verify` to check a whole stream.
+    ///
+    /// The reader is left at the end of the range: `read` goes on from there. The position of
+    /// the stream in the underlying reader is computed on the first call, from the bytes read
+    /// so far: the underlying reader must not have been moved before.
+    ///
+    /// A source only read at given offsets (e.g. a blob read with HTTP range requests) is made
+    /// seekable by `range::RangeReader`.
+    ///
+    pub fn read_range(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if let State::Header { .. } = self.state {
+            self.read_header()?;
+        }
+        let stream_start = match self.stream_start {
+            Some(stream_start) => stream_start,
+            None => {
+                let read = self.header_len as u64
+                    + self.stats.ciphertext_bytes
+                    + self.enc_buffer_len as u64;
+                let stream_start = self
+                    .reader
+                    .stream_position()?
+                    .checked_sub(read)
+                    .ok_or_else(|| error!(InvalidInput, "The underlying reader was moved"))?;
+                *self.stream_start.insert(stream_start)
+            }
+        };
+        let offset = offset
+            .checked_add(self.metadata_len)
+            .ok_or_else(|| error!(InvalidInput, "Offset {} is too large", offset))?;
+        let chunk = offset / BUFFER_SIZE as u64;
+        let chunk_len = (BUFFER_SIZE + AES_AUTH_TAG_LEN) as u64;
+        let chunk_start = chunk
+            .checked_mul(chunk_len)
+            .and_then(|position| position.checked_add(stream_start + self.header_len as u64))
+            .ok_or_else(|| error!(InvalidInput, "Offset {} is too large", offset))?;
+        if chunk > 0 && chunk_start >= self.reader.seek(std::io::SeekFrom::End(0))? {
+            // Past the final chunk
+            return Ok(Vec::new());
+        }
+
+        // A chained chunk authenticates the tag of the previous one (read, not checked)
+        let previous_tag = if self.chained && chunk > 0 {
+            let mut tag = [0; AES_AUTH_TAG_LEN];
+            self.reader.seek(std::io::SeekFrom::Start(
+                chunk_start - AES_AUTH_TAG_LEN as u64,
+            ))?;
+            self.reader.read_exact(&mut tag)?;
+            Some(tag)
+        } else {
+            self.reader.seek(std::io::SeekFrom::Start(chunk_start))?;
+            None
+        };
+        let State::Chunks {
+            nonce,
+            index,
+            previous_tag: tag,
+            ..
+        } = &mut self.state
+        else {
+            unreachable!("the header is read");
+        };
+        *nonce = NonceCounter::new(self.first_nonce);
+        nonce.advance(chunk);
+        *index = chunk;
+        *tag = previous_tag;
         self.enc_buffer_len = 0;
-        Ok(())
+        self.buffer_pos = 0;
+        self.buffer_len = 0;
+        self.finished = false;
+        self.pending_error = None;
+
+        // Skip the start of the first chunk
+        if self.read_chunk(None)?.is_none() {
+            return Ok(Vec::new());
+        }
+        self.buffer_pos = core::cmp::min((offset % BUFFER_SIZE as u64) as usize, self.buffer_len);
+        let mut data = Vec::new();
+        io::Read::read_to_end(&mut io::Read::take(&mut *self, len as u64), &mut data)?;
+        Ok(data)
+    }
+}
+
+/// An iterator over the decrypted chunks of a `CryptoReader`. (See `CryptoReader::chunks`)
+pub struct Chunks<'a, R: io::Read, const BUFFER_SIZE: usize> {
+    reader: &'a mut CryptoReader<R, BUFFER_SIZE>,
+}
+
+impl<R: io::Read, const BUFFER_SIZE: usize> Iterator for Chunks<'_, R, BUFFER_SIZE> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut *self.reader;
+        if let State::Header { .. } = reader.state {
+            if let Err(e) = reader.read_header() {
+                return Some(Err(e));
+            }
+        }
+        if let Err(e) = reader.read_metadata() {
+            return Some(Err(e));
+        }
+        while reader.buffer_pos == reader.buffer_len {
+            match reader.read_chunk(None) {
+                Ok(Some(_)) => {}
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let chunk = reader.buffer[reader.buffer_pos..reader.buffer_len].to_vec();
+        reader.buffer_pos = reader.buffer_len;
+        Some(Ok(chunk))
     }
 }
 
-impl<R: std::io::Read, const BUFFER_SIZE: usize> std::io::Read for CryptoReader<R, BUFFER_SIZE> {
+impl<R: io::Read, const BUFFER_SIZE: usize> io::Read for CryptoReader<R, BUFFER_SIZE> {
     /// Read decrypted data from the underlying reader.
     ///
     /// # Arguments
@@ -170,61 +1669,84 @@ impl<R: std::io::Read, const BUFFER_SIZE: usize> std::io::Read for CryptoReader<
     ///
     /// # Notes
     ///
-    /// If the number of bytes read is 0, it means:
-    /// - The buffer is empty.
-    /// - The underlying reader is closed.
+    /// The chunks are decrypted until `buf` is full: the chunk boundaries never cause a short
+    /// read. Fewer bytes than `buf.len()` are only returned:
+    /// - At the end of the stream. (0 once no byte is left)
+    /// - If the underlying reader returns `WouldBlock`. The bytes already decrypted are returned
+    ///   first, and the error is returned once no decrypted byte is left. The partially read
+    ///   header or chunk is kept, so the next call resumes where this one stopped.
+    /// - If an error occurs after some bytes were decrypted. The bytes are returned, and the
+    ///   error is returned by the next call.
+    ///
+    /// A chunk failing the authentication is reported as `Error::CorruptedChunk`.
+    ///
+    /// While `buf` has room for a whole chunk (`BUFFER_SIZE` bytes), the chunks are decrypted
+    /// straight into it, without going through the inner buffer. (e.g. `std::io::copy` with a
+    /// buffer larger than the chunks)
     ///
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let target_len = buf.len();
-        if target_len == 0 {
-            // Nothing to read
-            return Ok(0);
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
         }
-        let mut total_read = 0;
-
-        // Check if there are any decrypted data in the buffer
-        if self.buffer_len > 0 {
-            let to_copy = std::cmp::min(target_len, self.buffer_len);
-            let buffer_start_idx = BUFFER_SIZE - self.buffer_len;
-            buf[..to_copy]
-                .copy_from_slice(&self.buffer[buffer_start_idx..buffer_start_idx + to_copy]);
-            self.buffer_len -= to_copy;
-            total_read += to_copy;
+        if let State::Header { .. } = self.state {
+            self.read_header()?;
         }
+        self.read_metadata()?;
 
-        if total_read == target_len {
-            return Ok(total_read);
-        }
+        let mut total_read = 0;
 
-        while total_read < target_len {
-            loop {
-                let read = self
-                    .reader
-                    .read(&mut self.enc_buffer[self.enc_buffer_len..])?;
-                if read == 0 {
+        while total_read < buf.len() {
+            if self.buffer_pos == self.buffer_len {
+                // A whole chunk fits: decrypt it straight into `buf`, saving a copy
+                let direct = buf.len() - total_read >= BUFFER_SIZE;
+                let out = direct.then(|| &mut buf[total_read..]);
+                match self.read_chunk(out) {
+                    Ok(Some(read)) if direct => {
+                        total_read += read;
+                        continue;
+                    }
+                    Ok(Some(_)) => {}
                     // The reader is closed
-                    break;
-                }
-                self.enc_buffer_len += read;
-                if self.enc_buffer_len == BUFFER_SIZE + AES_AUTH_TAG_LEN {
-                    break;
+                    Ok(None) => break,
+                    Err(e) if total_read > 0 => {
+                        // Return the decrypted bytes first (`WouldBlock` is returned again)
+                        if e.kind() != io::ErrorKind::WouldBlock {
+                            self.pending_error = Some(e);
+                        }
+                        break;
+                    }
+                    Err(e) => return Err(e),
                 }
             }
 
-            if self.enc_buffer_len == 0 {
-                // The reader is closed
-                break;
-            }
-
-            // Decrypt the buffer
-            self.decrypt_buffer()?;
-
-            let to_copy = min!(target_len - total_read, BUFFER_SIZE, self.buffer_len);
-            buf[total_read..total_read + to_copy].copy_from_slice(&self.buffer[..to_copy]);
-            self.buffer_len -= to_copy;
+            let to_copy = core::cmp::min(buf.len() - total_read, self.buffer_len - self.buffer_pos);
+            buf[total_read..total_read + to_copy]
+                .copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + to_copy]);
+            self.buffer_pos += to_copy;
             total_read += to_copy;
         }
 
         Ok(total_read)
     }
+
+    /// Read the exact number of bytes required to fill `buf`.
+    ///
+    /// As `read` fills `buf` across the chunk boundaries, a single call is enough, except with a
+    /// non-blocking underlying reader.
+    ///
+    /// # Errors
+    /// - `UnexpectedEof`: If the stream ends before `buf` is full.
+    /// - The errors of `read`.
+    ///
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => Err(error!(UnexpectedEof, "failed to fill whole buffer"))?,
+                Ok(read) => buf = &mut buf[read..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 }
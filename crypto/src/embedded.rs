@@ -0,0 +1,314 @@
+//! This module provides the `embedded-io` implementations of the writer and the reader.
+//!
+//! With the `embedded-io` feature, `CryptoWriter` implements `embedded_io::Write` and
+//! `CryptoReader` implements `embedded_io::Read`, so a stream can be produced on a microcontroller
+//! (see the `no_std` support) and consumed by the desktop CLI. `FromEmbedded` adapts an
+//! `embedded_io` transport (UART, flash storage, ...) to the `io` traits used by the writer and
+//! the reader.
+//!
+//! With the `embedded-io-async` feature, `AsyncCryptoWriter` and `AsyncCryptoReader` implement
+//! the `embedded_io_async` traits over an async transport. They produce and consume the same
+//! stream format as `CryptoWriter` and `CryptoReader`.
+//!
+//! The errors are the `io::Error` of the crate, mapped from the `embedded_io::ErrorKind` of the
+//! transport.
+#[cfg(feature = "embedded-io-async")]
+use super::decrypt::Feed;
+use super::{decrypt::CryptoReader, encrypt::CryptoWriter, io};
+#[cfg(feature = "embedded-io-async")]
+use super::{
+    encrypt::WriterOptions,
+    error::Result,
+    identity::{Identity, Recipient},
+    stats::Stats,
+};
+#[cfg(feature = "embedded-io-async")]
+use alloc::vec::Vec;
+use embedded_io::ErrorKind;
+#[cfg(feature = "embedded-io-async")]
+use rand::{CryptoRng, RngCore};
+
+/// Convert the error of an `embedded_io` transport.
+fn from_embedded_error<E: embedded_io::Error>(error: E) -> io::Error {
+    let kind = match error.kind() {
+        ErrorKind::InvalidInput => io::ErrorKind::InvalidInput,
+        ErrorKind::InvalidData => io::ErrorKind::InvalidData,
+        ErrorKind::Interrupted => io::ErrorKind::Interrupted,
+        ErrorKind::WriteZero => io::ErrorKind::WriteZero,
+        ErrorKind::Unsupported => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, alloc::format!("{:?}", error))
+}
+
+/// `embedded_io::Error` is implemented for `std::io::Error` by `embedded-io` itself.
+#[cfg(not(feature = "std"))]
+impl embedded_io::Error for io::Error {
+    fn kind(&self) -> ErrorKind {
+        match io::Error::kind(self) {
+            io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+            io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+            io::ErrorKind::WriteZero => ErrorKind::WriteZero,
+            io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// An adapter implementing the `io` traits for an `embedded_io` transport.
+///
+/// # Example
+/// ```ignore
+/// let mut writer = CryptoWriter::<_, 256>::new_with_rng(FromEmbedded::new(uart), key, rng)?;
+/// ```
+///
+pub struct FromEmbedded<T> {
+    inner: T,
+}
+
+impl<T> FromEmbedded<T> {
+    /// Wrap an `embedded_io` transport.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Get back the `embedded_io` transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: embedded_io::Read> io::Read for FromEmbedded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(from_embedded_error)
+    }
+}
+
+impl<T: embedded_io::Write> io::Write for FromEmbedded<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(from_embedded_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(from_embedded_error)
+    }
+}
+
+impl<W: io::Write, const BUFFER_SIZE: usize> embedded_io::ErrorType
+    for CryptoWriter<W, BUFFER_SIZE>
+{
+    type Error = io::Error;
+}
+
+/// Same behavior as the `io::Write` implementation: `flush` writes the final chunk, and ends the
+/// stream.
+impl<W: io::Write, const BUFFER_SIZE: usize> embedded_io::Write for CryptoWriter<W, BUFFER_SIZE> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(self)
+    }
+}
+
+impl<R: io::Read, const BUFFER_SIZE: usize> embedded_io::ErrorType
+    for CryptoReader<R, BUFFER_SIZE>
+{
+    type Error = io::Error;
+}
+
+impl<R: io::Read, const BUFFER_SIZE: usize> embedded_io::Read for CryptoReader<R, BUFFER_SIZE> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(self, buf)
+    }
+}
+
+/// An async writer that encrypts the data before writing it to an `embedded_io_async` writer.
+///
+/// The data is encrypted by a `CryptoWriter` into a buffer, which is written to the underlying
+/// writer by each `write` call.
+///
+/// **Warning**: `flush` must be called once all the data is written, as it writes the final
+/// chunk. (Dropping the writer can't write it asynchronously)
+#[cfg(feature = "embedded-io-async")]
+pub struct AsyncCryptoWriter<W: embedded_io_async::Write, const BUFFER_SIZE: usize> {
+    inner: CryptoWriter<Vec<u8>, BUFFER_SIZE>,
+    writer: W,
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<W: embedded_io_async::Write, const BUFFER_SIZE: usize> AsyncCryptoWriter<W, BUFFER_SIZE> {
+    /// Create a new `AsyncCryptoWriter` instance with the given random number generator.
+    /// The `key` is used to encrypt the AES key. The header is written by the first `write` (or
+    /// `flush`) call.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// An `AsyncCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new_with_rng<G: CryptoRng + RngCore>(
+        writer: W,
+        key: impl Into<Recipient>,
+        rng: G,
+    ) -> Result<Self> {
+        Self::new_with_options_and_rng(writer, key, &WriterOptions::default(), rng)
+    }
+
+    /// Create a new `AsyncCryptoWriter` instance with the given options and random number
+    /// generator.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the writer.
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// An `AsyncCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new_with_options_and_rng<G: CryptoRng + RngCore>(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        rng: G,
+    ) -> Result<Self> {
+        Ok(Self {
+            inner: CryptoWriter::new_with_options_and_rng(Vec::new(), key, options, rng)?,
+            writer,
+        })
+    }
+
+    /// The statistics of the writer. (See `CryptoWriter::stats`)
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Write the encrypted data buffered by the inner `CryptoWriter`.
+    async fn drain(&mut self) -> io::Result<()> {
+        let data = core::mem::take(self.inner.writer_mut());
+        self.writer
+            .write_all(&data)
+            .await
+            .map_err(from_embedded_error)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<W: embedded_io_async::Write, const BUFFER_SIZE: usize> embedded_io::ErrorType
+    for AsyncCryptoWriter<W, BUFFER_SIZE>
+{
+    type Error = io::Error;
+}
+
+/// Same behavior as `CryptoWriter`: `flush` writes the final chunk, and ends the stream.
+#[cfg(feature = "embedded-io-async")]
+impl<W: embedded_io_async::Write, const BUFFER_SIZE: usize> embedded_io_async::Write
+    for AsyncCryptoWriter<W, BUFFER_SIZE>
+{
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = io::Write::write(&mut self.inner, buf)?;
+        self.drain().await?;
+        Ok(written)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(&mut self.inner)?;
+        self.drain().await?;
+        self.writer.flush().await.map_err(from_embedded_error)
+    }
+}
+
+/// An async reader that decrypts the data read from an `embedded_io_async` reader.
+///
+/// The encrypted data is read from the underlying reader when the inner `CryptoReader` needs
+/// more of it, and decrypted as `CryptoReader` does.
+#[cfg(feature = "embedded-io-async")]
+pub struct AsyncCryptoReader<R: embedded_io_async::Read, const BUFFER_SIZE: usize> {
+    inner: CryptoReader<Feed, BUFFER_SIZE>,
+    reader: R,
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<R: embedded_io_async::Read, const BUFFER_SIZE: usize> AsyncCryptoReader<R, BUFFER_SIZE> {
+    /// Create a new `AsyncCryptoReader` instance.
+    /// The header is read and checked by the first `read` call.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// An `AsyncCryptoReader` instance.
+    ///
+    /// # Notes
+    /// The errors of `CryptoReader::new` (invalid header, wrong key, ...) are returned by the
+    /// first `read` call.
+    ///
+    pub fn new(reader: R, key: impl Into<Identity>) -> Self {
+        Self {
+            inner: CryptoReader::new_lazy(Feed::default(), key),
+            reader,
+        }
+    }
+
+    /// Enable or disable the strict mode. (See `CryptoReader::set_strict`)
+    pub fn set_strict(&mut self, strict: bool) {
+        self.inner.set_strict(strict);
+    }
+
+    /// Enable or disable the implicit rejection of the RSA wrapped keys. (Enabled by default, see
+    /// `CryptoReader::set_implicit_rejection`)
+    pub fn set_implicit_rejection(&mut self, enabled: bool) {
+        self.inner.set_implicit_rejection(enabled);
+    }
+
+    /// The statistics of the reader. (See `CryptoReader::stats`)
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Read more encrypted data from the underlying reader.
+    async fn fill(&mut self) -> io::Result<()> {
+        let feed = self.inner.get_mut();
+        let read = self
+            .reader
+            .read(feed.spare(BUFFER_SIZE))
+            .await
+            .map_err(from_embedded_error)?;
+        feed.commit(read);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<R: embedded_io_async::Read, const BUFFER_SIZE: usize> embedded_io::ErrorType
+    for AsyncCryptoReader<R, BUFFER_SIZE>
+{
+    type Error = io::Error;
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<R: embedded_io_async::Read, const BUFFER_SIZE: usize> embedded_io_async::Read
+    for AsyncCryptoReader<R, BUFFER_SIZE>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match io::Read::read(&mut self.inner, buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.fill().await?,
+                result => return result,
+            }
+        }
+    }
+}
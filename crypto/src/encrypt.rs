@@ -1,20 +1,26 @@
 //! This module provides a writer that encrypts the data before writing it to the writer.
 //!
-//! The data is encrypted using AES-256-GCM. The AES key is encrypted using the RSA public key.
+//! The data is encrypted using AES-256-GCM (or AES-128-GCM, see `Cipher`). The AES key is
+//! encrypted using the RSA public key.
 //!
 //! The data is written to the writer in the following format:
 //! ```plaintext
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-//! |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |     Header      |   |     AES Data    |   |     AES Data    |   
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |  See `Header`   |   |                 |   |                 |   ...
+//! +-----------------+   +-----------------+   +-----------------+   
+//! |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+//! +-----------------+   +-----------------+   +-----------------+
 //! ```
 //!
 //! The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data.
 //!
+//! The final chunk (possibly empty) is authenticated with additional data marking the end of the
+//! stream, so the reader detects a stream truncated on a chunk boundary. (See `Header::end_marker`)
+//! Each chunk also authenticates the SHA-256 hash of the header (without the key slots), so the
+//! end marker (or any other field) can't be removed from the header.
+//!
 //! This module provides a writer that encrypts the data before writing it to the writer.
 //! The `CryptoWriter` implements the `std::io::Write` trait. To allow seamless integration with existing
 //! Rust code that uses `std::io::Write`.
@@ -22,18 +28,190 @@
 //! **Warning**: Currently the memeory of the struct is not locked. (This will be implemented in
 //! the future)
 //! So, the data can be read from the memory. (This is a security risk)
+#[cfg(feature = "std")]
+use super::audit::{audit, AuditEvent, Direction};
+#[cfg(feature = "ml-kem")]
+use super::pq::{self, MlKemPublicKey};
+#[cfg(feature = "std")]
+use super::{
+    bundle::KeyBundle, header::Summary, metadata::Metadata, session::SessionCache,
+    shared::setup_rng,
+};
 use super::{
+    cipher::{AeadCipher, Cipher},
     dbg_println,
     error::{error, Result},
-    shared::{increment_nonce, setup_rng, Nonce},
+    format,
+    header::{Format, Header, SlotKem},
+    identity::Recipient,
+    io::{self, Write as _},
+    kem::{wrap_kem_slot, Kem},
+    key_wrap::KeyWrap,
+    padding::{pad, Padding},
+    shared::{
+        ChunkAad, Nonce, NonceCounter, AES_AUTH_TAG_LEN, HEADER_DIGEST_LEN, KEY_CHECK_AAD,
+        KEY_CHECK_KEY_INFO, KEY_COMMITMENT_LEN, KEY_COMMITMENT_PREFIX,
+    },
+    stats::{Stats, Timer},
 };
-use aes_gcm::{aead::Aead, AeadCore as _, Aes256Gcm, Key, KeyInit as _};
+use aes_gcm::{aead::Payload, AeadCore as _, Aes256Gcm};
+use alloc::{sync::Arc, vec, vec::Vec};
+use hkdf::Hkdf;
 use rand::{CryptoRng, RngCore};
-use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
-use std::io::Write as _;
+use rsa::{pkcs8::der::zeroize::Zeroizing, RsaPublicKey};
+use sha2::{Digest as _, Sha256};
 
-fn generate_aes_key<R: CryptoRng + RngCore>(rng: &mut R) -> Key<Aes256Gcm> {
-    Aes256Gcm::generate_key(rng)
+#[cfg(feature = "std")]
+pub(crate) fn generate_aes_key<R: CryptoRng + RngCore>(rng: &mut R) -> aes_gcm::Key<Aes256Gcm> {
+    <Aes256Gcm as aes_gcm::KeyInit>::generate_key(rng)
+}
+
+/// Compute the key check value of the AES key. (See `Header::key_check`)
+///
+/// The value is the tag of an empty message, encrypted with a zero nonce under the key check key.
+/// (See `key_check_key`) The AES key itself never encrypts with the zero nonce of the key check,
+/// whatever the nonces of the chunks.
+///
+/// # Panics
+/// If the key length doesn't match any `Cipher`.
+///
+pub(crate) fn key_check_value(aes_key: &[u8]) -> [u8; AES_AUTH_TAG_LEN] {
+    let payload = Payload {
+        msg: &[],
+        aad: KEY_CHECK_AAD,
+    };
+    AeadCipher::new(&key_check_key(aes_key))
+        .expect("the AES key has a valid length")
+        .encrypt(&Nonce::default(), payload)
+        .expect("an empty message can be encrypted")
+        .try_into()
+        .expect("the tag has AES_AUTH_TAG_LEN")
+}
+
+/// Derive the key of the key check value from the AES key, with HKDF-SHA256. (Same length as the
+/// AES key)
+pub(crate) fn key_check_key(aes_key: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0; aes_key.len()]);
+    Hkdf::<Sha256>::new(None, aes_key)
+        .expand(KEY_CHECK_KEY_INFO, &mut key)
+        .expect("the key is shorter than 255 SHA-256 hashes");
+    key
+}
+
+/// Compute the commitment to the AES key. (See `Header::commitment`)
+///
+/// The commitment is `SHA-256("crypto key commitment" || key)`: finding two keys with the same
+/// commitment requires a SHA-256 collision.
+pub(crate) fn key_commitment(aes_key: &[u8]) -> [u8; KEY_COMMITMENT_LEN] {
+    Sha256::new()
+        .chain_update(KEY_COMMITMENT_PREFIX)
+        .chain_update(aes_key)
+        .finalize()
+        .into()
+}
+
+/// Encrypt the AES key with the RSA public key, the public key of each additional recipient, and
+/// each `Kem` recipient.
+///
+/// # Errors
+/// - `InvalidInput`: If `options.ml_kem_keys` doesn't hold one key per RSA recipient, or a `Kem`
+///   identifier is reserved.
+/// - `Other`: If the RSA encryption (or an encapsulation) fails.
+///
+pub(crate) fn wrap_data_key<R: CryptoRng + RngCore>(
+    key: &RsaPublicKey,
+    aes_key: &[u8],
+    options: &WriterOptions,
+    rng: &mut R,
+) -> Result<Vec<Vec<u8>>> {
+    let mut wrapped_keys = wrap_rsa_slots(key, aes_key, options, rng)?;
+    for kem in &options.kem_recipients {
+        wrapped_keys.push(wrap_kem_slot(kem.as_ref(), aes_key, rng)?);
+    }
+    Ok(wrapped_keys)
+}
+
+/// Encrypt the AES key for the RSA recipients. Each slot is hybrid (see `pq`) if
+/// `options.ml_kem_keys` is set.
+fn wrap_rsa_slots<R: CryptoRng + RngCore>(
+    key: &RsaPublicKey,
+    aes_key: &[u8],
+    options: &WriterOptions,
+    rng: &mut R,
+) -> Result<Vec<Vec<u8>>> {
+    let keys = core::iter::once(key).chain(options.additional_recipients.iter());
+    #[cfg(feature = "ml-kem")]
+    if !options.ml_kem_keys.is_empty() {
+        if options.ml_kem_keys.len() != options.additional_recipients.len() + 1 {
+            Err(error!(
+                InvalidInput,
+                "{} ML-KEM keys for {} recipients",
+                options.ml_kem_keys.len(),
+                options.additional_recipients.len() + 1
+            ))?;
+        }
+        return keys
+            .zip(&options.ml_kem_keys)
+            .map(|(key, ml_kem_key)| {
+                pq::wrap_hybrid(key, ml_kem_key, options.key_wrap, aes_key, rng)
+            })
+            .collect();
+    }
+    keys.map(|key| options.key_wrap.wrap(key, rng, aes_key))
+        .collect()
+}
+
+/// The key encapsulation of the slots written by `wrap_data_key`. (See `Header::slot_kems`)
+pub(crate) fn slot_kems(options: &WriterOptions) -> Vec<SlotKem> {
+    #[cfg(feature = "ml-kem")]
+    let rsa_kem = match options.ml_kem_keys.is_empty() {
+        true => SlotKem::Rsa,
+        false => SlotKem::RsaMlKem768,
+    };
+    #[cfg(not(feature = "ml-kem"))]
+    let rsa_kem = SlotKem::Rsa;
+
+    if rsa_kem == SlotKem::Rsa && options.kem_recipients.is_empty() {
+        return Vec::new();
+    }
+    core::iter::repeat_n(rsa_kem, options.additional_recipients.len() + 1)
+        .chain(
+            options
+                .kem_recipients
+                .iter()
+                .map(|kem| SlotKem::Kem(kem.id())),
+        )
+        .collect()
+}
+
+/// Options used to create a `CryptoWriter`.
+///
+/// The options are recorded in the `Header`, so `CryptoReader` doesn't need to know them.
+#[derive(Debug, Clone, Default)]
+pub struct WriterOptions {
+    /// The padding applied to the final chunk. (Default: `Padding::None`)
+    pub padding: Padding,
+    /// Additional recipients of the stream. The AES key is also encrypted with each of these
+    /// public keys, so any of the matching private keys can decrypt the stream.
+    pub additional_recipients: Vec<RsaPublicKey>,
+    /// The RSA encryption scheme wrapping the AES key. (Default: `KeyWrap::Pkcs1v15`, readable by
+    /// older versions)
+    pub key_wrap: KeyWrap,
+    /// The cipher encrypting the chunks. (Default: `Cipher::Aes256Gcm`, readable by older
+    /// versions)
+    pub cipher: Cipher,
+    /// Whether each chunk authenticates the tag of the previous chunk as additional data, so a
+    /// modified, removed or reordered chunk breaks the chain, e.g. for tamper-evident logs. (See
+    /// `append::verify_log`, default: `false`, readable by older versions)
+    pub chain_tags: bool,
+    /// The ML-KEM-768 public keys of the recipients, in the same order as the RSA keys. (The key,
+    /// then `additional_recipients`) When set, every slot is a post-quantum hybrid slot. (See
+    /// `pq`, default: empty)
+    #[cfg(feature = "ml-kem")]
+    pub ml_kem_keys: Vec<MlKemPublicKey>,
+    /// Recipients of the stream using a `Kem` backend. (X25519, KMS, HSM, ...) The AES key is
+    /// wrapped in one `SlotKem::Kem` slot each, after the RSA slots. (Default: empty)
+    pub kem_recipients: Vec<Arc<dyn Kem>>,
 }
 
 /// A writer that encrypts the data before writing it to the writer.
@@ -43,32 +221,94 @@ fn generate_aes_key<R: CryptoRng + RngCore>(rng: &mut R) -> Key<Aes256Gcm> {
 ///
 /// The data is written to the writer in the following format:
 /// ```plaintext
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-/// |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-/// +-----------------+   +-----------------+   +-----------------+   +-----------------+
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |     Header      |   |     AES Data    |   |     AES Data    |   
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |  See `Header`   |   |                 |   |                 |   ...
+/// +-----------------+   +-----------------+   +-----------------+   
+/// |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+/// +-----------------+   +-----------------+   +-----------------+
 /// ```
 ///
-/// The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data.
-pub struct CryptoWriter<W: std::io::Write, const BUFFER_SIZE: usize> {
+/// The `BUFFER_SIZE` is the size of the buffer used to store the encrypted data. It must be a
+/// supported chunk size (see `format::is_supported_chunk_size`), or the constructors fail to
+/// build:
+///
+/// ```compile_fail
+/// # use crypto::{CryptoWriter, RsaKeys};
+/// let keys = RsaKeys::from_public_key_pem(include_str!("../tests/test.pub")).unwrap();
+/// let writer = CryptoWriter::<_, 0>::new(Vec::new(), keys.public_key().unwrap().clone());
+/// ```
+pub struct CryptoWriter<W: io::Write, const BUFFER_SIZE: usize> {
     writer: W,
-    nonce: Nonce,
-    cipher: Aes256Gcm,
+    nonce: NonceCounter,
+    cipher: AeadCipher,
     buffer: [u8; BUFFER_SIZE],
     buffer_len: usize,
+    /// The encrypted chunk, reused for every chunk. (Encrypted in place)
+    enc_buffer: Vec<u8>,
+    /// The tag of the previous chunk, when the chunks are chained. (See `WriterOptions::chain_tags`)
+    previous_tag: Option<[u8; AES_AUTH_TAG_LEN]>,
+    /// The length of the header of the current stream.
+    header_len: usize,
+    /// The hash of the header of the current stream, authenticated by each chunk. (See
+    /// `Header::digest`)
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    /// The RSA public key and the options, to write the header of the next stream. (See `reset`)
+    key: RsaPublicKey,
+    options: WriterOptions,
     has_been_flushed: bool,
+    stats: Stats,
+    /// The header to backfill with the summary of the chunks. (See `new_seekable`)
+    #[cfg(feature = "std")]
+    backfill: Option<Backfill<W>>,
+}
+
+/// The header of a stream written by a seekable writer, rewritten with the summary of the chunks
+/// once the stream is finished. (See `CryptoWriter::new_seekable`)
+#[cfg(feature = "std")]
+struct Backfill<W> {
+    header: Header,
+    /// The position of the header in the writer.
+    position: u64,
+    /// The hash of the chunks written so far.
+    digest: Sha256,
+    /// The `Seek::seek` method of the writer. (So the `CryptoWriter` doesn't require `Seek`)
+    seek: fn(&mut W, std::io::SeekFrom) -> io::Result<u64>,
 }
 
-impl<W: std::io::Write, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
+#[cfg(feature = "std")]
+impl<W: io::Write> Backfill<W> {
+    /// Start the backfill of a header about to be written at the current position.
+    fn start(&mut self, writer: &mut W, header: &Header) -> Result<()> {
+        self.position = (self.seek)(writer, std::io::SeekFrom::Current(0))?;
+        self.header = header.clone();
+        self.digest = Sha256::new();
+        Ok(())
+    }
+
+    /// Rewrite the header with the summary, and seek back to the end of the stream.
+    fn finish(&mut self, writer: &mut W, stats: &Stats) -> Result<()> {
+        self.header.summary = Some(Summary {
+            chunks: stats.chunks,
+            ciphertext_len: stats.ciphertext_bytes,
+            digest: self.digest.finalize_reset().into(),
+        });
+        let end = (self.seek)(writer, std::io::SeekFrom::Current(0))?;
+        (self.seek)(writer, std::io::SeekFrom::Start(self.position))?;
+        self.header.write(writer)?;
+        (self.seek)(writer, std::io::SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+impl<W: io::Write, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
     /// Create a new `CryptoWriter` instance.
     /// The `key` is used to encrypt the AES key.
     ///
     /// # Arguments
     /// - `writer`: The writer to write the encrypted data.
-    /// - `key`: The RSA public key to encrypt the AES key.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
     ///
     /// # Returns
     /// A `CryptoWriter` instance.
@@ -87,16 +327,17 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
     /// Here is a diagram of the data written to the writer:
     ///
     /// ```plaintext
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |     AES Key     |   |    AES NONCE    |   |     AES Data    |   |     AES Data    |   
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |     RSA Enc     |   |                 |   |                 |   |                 |   ...
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+   
-    /// |   AES KEY LEN   |   |  AES NONCE LEN  |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
-    /// +-----------------+   +-----------------+   +-----------------+   +-----------------+
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |     Header      |   |     AES Data    |   |     AES Data    |   
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |  See `Header`   |   |                 |   |                 |   ...
+    /// +-----------------+   +-----------------+   +-----------------+   
+    /// |   HEADER LEN    |   |   BUFFER_SIZE   |   |   BUFFER_SIZE   |  
+    /// +-----------------+   +-----------------+   +-----------------+
     /// ```
     ///
-    pub fn new(writer: W, key: RsaPublicKey) -> Result<Self> {
+    #[cfg(feature = "std")]
+    pub fn new(writer: W, key: impl Into<Recipient>) -> Result<Self> {
         // TODO: memlock secrets in memory
         let mut rng = setup_rng();
         Self::new_with_rng(writer, key, &mut rng)
@@ -107,7 +348,7 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
     ///
     /// # Arguments
     /// - `writer`: The writer to write the encrypted data.
-    /// - `key`: The RSA public key to encrypt the AES key.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
     /// - `rng`: The random number generator.
     ///
     /// # Returns
@@ -118,67 +359,625 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
     /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
     ///
     pub fn new_with_rng<R: CryptoRng + RngCore>(
-        mut writer: W,
+        writer: W,
+        key: impl Into<Recipient>,
+        rng: R,
+    ) -> Result<Self> {
+        Self::new_with_options_and_rng(writer, key, &WriterOptions::default(), rng)
+    }
+
+    /// Create a new `CryptoWriter` instance with the given options.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    #[cfg(feature = "std")]
+    pub fn new_with_options(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::new_with_options_and_rng(writer, key, options, &mut rng)
+    }
+
+    /// Create a new `CryptoWriter` instance for the public key of a `KeyBundle`, with the given
+    /// options. Unlike the other constructors, the key is refused if it is expired.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `bundle`: The bundle of the recipient. (See `KeyBundle`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Error::KeyExpired`: If the key of the recipient is expired.
+    /// - `Error::BadSignature`: If the self-signature of the bundle is invalid.
+    /// - The errors of `new_with_options`.
+    ///
+    /// # Notes
+    /// Only the key of the bundle is checked, not the `additional_recipients` of the options. Use
+    /// `new_with_options` with `bundle.public_key` to encrypt for an expired key anyway.
+    ///
+    #[cfg(feature = "std")]
+    pub fn new_with_bundle(writer: W, bundle: &KeyBundle, options: &WriterOptions) -> Result<Self> {
+        bundle.check_valid()?;
+        Self::new_with_options(writer, bundle.public_key.clone(), options)
+    }
+
+    /// Create a new `CryptoWriter` instance with the given options and random number generator.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn new_with_options_and_rng<R: CryptoRng + RngCore>(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        mut rng: R,
+    ) -> Result<Self> {
+        let aes_key = options.cipher.generate_key(&mut rng);
+        Self::new_with_data_key(writer, key.into().into_rsa(), &aes_key, options, rng)
+    }
+
+    /// Create a new `CryptoWriter` instance with a fixed AES key and nonce.
+    /// The `key` is used to encrypt the AES key, with the given random number generator.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `aes_key`: The AES key encrypting the data. (Of `options.cipher.key_len()` bytes)
+    /// - `nonce`: The nonce of the first chunk.
+    /// - `options`: The options of the writer.
+    /// - `rng`: The random number generator. (Used by the RSA encryption)
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the length of `aes_key` doesn't match `options.cipher`.
+    /// - The errors of `new_with_options_and_rng`.
+    ///
+    /// # Notes
+    /// Used to produce deterministic streams, e.g. known-answer test vectors. With a seeded `rng`,
+    /// the output only depends on the inputs.
+    ///
+    /// **Warning**: Reusing an AES key and nonce pair for two streams breaks the confidentiality
+    /// and the authenticity of both. Only use this constructor with values that are never reused.
+    ///
+    pub fn new_with_key_and_nonce<R: CryptoRng + RngCore>(
+        writer: W,
+        key: impl Into<Recipient>,
+        aes_key: &[u8],
+        nonce: &[u8; 12],
+        options: &WriterOptions,
+        mut rng: R,
+    ) -> Result<Self> {
+        Self::new_with_data_key_and_nonce(
+            writer,
+            key.into().into_rsa(),
+            aes_key,
+            Nonce::from(*nonce),
+            options,
+            &mut rng,
+        )
+    }
+
+    /// Create a new `CryptoWriter` instance encrypting the data with the given AES key.
+    /// Used when the AES key must also be handled outside of the stream. (e.g. split in shares)
+    pub(crate) fn new_with_data_key<R: CryptoRng + RngCore>(
+        writer: W,
         key: RsaPublicKey,
+        aes_key: &[u8],
+        options: &WriterOptions,
         mut rng: R,
     ) -> Result<Self> {
-        let aes_key = generate_aes_key(&mut rng);
         let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        Self::new_with_data_key_and_nonce(writer, key, aes_key, nonce, options, &mut rng)
+    }
 
-        {
-            let raw_aes_key = aes_key.as_slice();
-            let data = key
-                .encrypt(&mut rng, Pkcs1v15Encrypt, raw_aes_key)
-                .map_err(|e| error!(Other, "RSA Encryption error: {}", e))?;
-
-            if writer.write(&data)? != data.len() {
-                Err(error!(Other, "Failed to write the encrypted AES key"))?;
-            };
-            if writer.write(&nonce)? != nonce.len() {
-                Err(error!(Other, "Failed to write the AES nonce"))?;
-            };
+    fn new_with_data_key_and_nonce<R: CryptoRng + RngCore>(
+        mut writer: W,
+        key: RsaPublicKey,
+        aes_key: &[u8],
+        nonce: Nonce,
+        options: &WriterOptions,
+        rng: &mut R,
+    ) -> Result<Self> {
+        let cipher = AeadCipher::with_cipher(options.cipher, aes_key)?;
+        let wrapped_keys = wrap_data_key(&key, aes_key, options, rng)?;
+        let header = Self::stream_header(wrapped_keys, aes_key, nonce, options)?;
+        let (header_len, header_digest) = Self::write_header(&mut writer, &header)?;
+        Ok(Self::with_cipher(
+            writer,
+            cipher,
+            nonce,
+            header_len,
+            header_digest,
+            key,
+            options,
+        ))
+    }
+
+    /// Create a new `CryptoWriter` instance, as the next stream of the session to the recipient.
+    /// (See `SessionCache`)
+    ///
+    /// The first stream of a session wraps its master key with the RSA public key, the next
+    /// streams reuse it: no RSA encryption, and a header shorter by the length of the wrapped
+    /// key. (e.g. 256 bytes for a 2048 bits key)
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, with its RSA public key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    /// - `sessions`: The cache of the sessions.
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the options have additional recipients, `Kem` recipients or ML-KEM
+    ///   keys. (A session has a single RSA recipient)
+    /// - The errors of `new_with_options`.
+    ///
+    /// # Notes
+    /// The streams must be read with `CryptoReader::new_with_session`, the first stream of each
+    /// session first. `reset` starts a stream without the session.
+    ///
+    #[cfg(feature = "std")]
+    pub fn new_with_session(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        sessions: &SessionCache,
+    ) -> Result<Self> {
+        let key = key.into().into_rsa();
+        let mut rng = setup_rng();
+        let stream = sessions.next_stream(&key, options, &mut rng)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        let cipher = AeadCipher::with_cipher(options.cipher, &stream.key)?;
+        let header = Header {
+            session: Some(stream.session),
+            ..Self::stream_header(stream.wrapped_keys, &stream.key, nonce, options)?
         };
-        let cipher = Aes256Gcm::new(&aes_key);
+        let (header_len, header_digest) = Self::write_header(&mut writer, &header)?;
+        Ok(Self::with_cipher(
+            writer,
+            cipher,
+            nonce,
+            header_len,
+            header_digest,
+            key,
+            options,
+        ))
+    }
 
-        Ok(Self {
+    /// Create a new `CryptoWriter` instance, starting the stream with encrypted metadata. (e.g.
+    /// the original name of the file, see `Metadata`)
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    /// - `metadata`: The metadata of the stream.
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the metadata is too long.
+    /// - The errors of `new_with_options`.
+    ///
+    /// # Notes
+    /// The metadata is read by `CryptoReader` (see `CryptoReader::metadata`), older versions
+    /// reject the stream. The metadata counts as plaintext in `predicted_len` and in the
+    /// statistics. `reset` starts a stream without metadata.
+    ///
+    #[cfg(feature = "std")]
+    pub fn new_with_metadata(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        metadata: &Metadata,
+    ) -> Result<Self> {
+        let key = key.into().into_rsa();
+        let metadata = metadata.to_bytes()?;
+        let mut rng = setup_rng();
+        let aes_key = options.cipher.generate_key(&mut rng);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        let cipher = AeadCipher::with_cipher(options.cipher, &aes_key)?;
+        let wrapped_keys = wrap_data_key(&key, &aes_key, options, &mut rng)?;
+        let header = Header {
+            metadata: true,
+            ..Self::stream_header(wrapped_keys, &aes_key, nonce, options)?
+        };
+        let (header_len, header_digest) = Self::write_header(&mut writer, &header)?;
+        let mut crypto_writer = Self::with_cipher(
             writer,
             cipher,
             nonce,
+            header_len,
+            header_digest,
+            key,
+            options,
+        );
+        crypto_writer.write_all(&metadata)?;
+        Ok(crypto_writer)
+    }
+
+    fn with_cipher(
+        writer: W,
+        cipher: AeadCipher,
+        nonce: Nonce,
+        header_len: usize,
+        header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+        key: RsaPublicKey,
+        options: &WriterOptions,
+    ) -> Self {
+        const {
+            assert!(
+                format::is_supported_chunk_size(BUFFER_SIZE),
+                "BUFFER_SIZE must be between format::MIN_CHUNK_SIZE and format::MAX_CHUNK_SIZE"
+            )
+        };
+        Self {
+            writer,
+            cipher,
+            nonce: NonceCounter::new(nonce),
             buffer: [0; BUFFER_SIZE],
             buffer_len: 0,
+            enc_buffer: Vec::with_capacity(BUFFER_SIZE + AES_AUTH_TAG_LEN),
+            previous_tag: None,
+            header_len,
+            header_digest,
+            key,
+            options: options.clone(),
             has_been_flushed: false,
+            stats: Stats::default(),
+            #[cfg(feature = "std")]
+            backfill: None,
+        }
+    }
+
+    /// The header of a stream encrypted with the given AES key and first nonce.
+    fn stream_header(
+        wrapped_keys: Vec<Vec<u8>>,
+        aes_key: &[u8],
+        nonce: Nonce,
+        options: &WriterOptions,
+    ) -> Result<Header> {
+        // Fits: checked at compile time (See `with_cipher`)
+        let chunk_size = BUFFER_SIZE as u32;
+
+        Ok(Header {
+            chunk_size: Some(chunk_size),
+            padding: options.padding,
+            end_marker: true,
+            chained: options.chain_tags,
+            key_check: Some(key_check_value(aes_key)),
+            commitment: Some(key_commitment(aes_key)),
+            key_wrap: options.key_wrap,
+            cipher: options.cipher,
+            slot_kems: slot_kems(options),
+            ..Header::new(Format::LATEST, wrapped_keys, nonce.into())
         })
     }
 
-    fn inner_flush(&mut self) -> Result<()> {
-        if self.buffer_len == 0 {
+    /// Write the header of a stream.
+    /// Returns the length and the hash of the header. (See `Header::digest`)
+    fn write_header(
+        writer: &mut W,
+        header: &Header,
+    ) -> Result<(usize, Option<[u8; HEADER_DIGEST_LEN]>)> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("write_header", cipher = ?header.cipher).entered();
+        header.write(writer)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            format = ?header.format,
+            slots = header.wrapped_keys.len(),
+            "header written"
+        );
+
+        #[cfg(feature = "std")]
+        audit(|record| {
+            record(&AuditEvent::StreamStarted {
+                direction: Direction::Encrypt,
+                format: header.format,
+                cipher: header.cipher,
+                recipients: header.wrapped_keys.len(),
+            })
+        });
+        Ok((header.len(), header.digest()?))
+    }
+
+    /// End the current stream, and start a new one on `writer`.
+    ///
+    /// The current stream is finalized (unless already flushed), then a fresh AES key and nonce
+    /// are generated, and the header of the new stream is written to `writer`. The RSA key, the
+    /// options and the buffers are reused, so a pooled connection (or a per-message stream)
+    /// doesn't need a new `CryptoWriter`.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer of the new stream.
+    ///
+    /// # Returns
+    /// The writer of the previous stream.
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs while finalizing the current stream (the writer is left
+    ///   unchanged), or while writing the new header.
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    /// # Notes
+    /// The statistics restart with the new stream.
+    ///
+    #[cfg(feature = "std")]
+    pub fn reset(&mut self, writer: W) -> Result<W> {
+        self.reset_with_rng(writer, setup_rng())
+    }
+
+    /// End the current stream, and start a new one on `writer`, with the given random number
+    /// generator. (See `reset`)
+    ///
+    /// # Arguments
+    /// - `writer`: The writer of the new stream.
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// The writer of the previous stream.
+    ///
+    /// # Errors
+    /// The errors of `reset`.
+    ///
+    pub fn reset_with_rng<R: CryptoRng + RngCore>(
+        &mut self,
+        mut writer: W,
+        mut rng: R,
+    ) -> Result<W> {
+        if !self.has_been_flushed {
+            self.flush()?;
+        }
+        let aes_key = self.options.cipher.generate_key(&mut rng);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        let cipher = AeadCipher::with_cipher(self.options.cipher, &aes_key)?;
+        let wrapped_keys = wrap_data_key(&self.key, &aes_key, &self.options, &mut rng)?;
+        #[allow(unused_mut)]
+        let mut header = Self::stream_header(wrapped_keys, &aes_key, nonce, &self.options)?;
+        #[cfg(feature = "std")]
+        if let Some(backfill) = &mut self.backfill {
+            // Reserve the space of the summary
+            header.summary = Some(Summary::default());
+            backfill.start(&mut writer, &header)?;
+        }
+        let (header_len, header_digest) = Self::write_header(&mut writer, &header)?;
+
+        self.cipher = cipher;
+        self.nonce = NonceCounter::new(nonce);
+        self.buffer_len = 0;
+        self.previous_tag = None;
+        self.header_len = header_len;
+        self.header_digest = header_digest;
+        self.has_been_flushed = false;
+        self.stats = Stats::default();
+        Ok(core::mem::replace(&mut self.writer, writer))
+    }
+
+    /// The exact length of the stream once `plain_len` bytes are written and the writer is
+    /// flushed, header included. (e.g. to set the `Content-Length` of an encrypted HTTP body)
+    ///
+    /// # Arguments
+    /// - `plain_len`: The total number of bytes written to the stream. (Including the bytes
+    ///   already written)
+    ///
+    /// # Returns
+    /// The length of the stream in bytes. (See the `format` module)
+    ///
+    pub fn predicted_len(&self, plain_len: u64) -> u64 {
+        self.header_len as u64
+            + format::padded_ciphertext_len(plain_len, BUFFER_SIZE as u32, self.options.padding)
+    }
+
+    /// The statistics of the writer: chunks written, plaintext and ciphertext bytes, and time
+    /// spent encrypting. (See `Stats`)
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The underlying writer. (Used by the adapters draining the writer, e.g. the async writers,
+    /// and by `append::AppendWriter` to write its trailer)
+    #[cfg(any(
+        feature = "std",
+        feature = "embedded-io-async",
+        feature = "futures-io",
+        all(feature = "uring", target_os = "linux")
+    ))]
+    pub(crate) fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Drop the buffered data without writing the final chunk, so a stream that must be discarded
+    /// can't be read as a complete one. (Used by `convergent::encrypt`)
+    #[cfg(feature = "std")]
+    pub(crate) fn abandon(&mut self) {
+        self.buffer_len = 0;
+        self.has_been_flushed = true;
+    }
+
+    /// Encrypt and write the buffered chunk.
+    /// The final chunk is always written (even if empty), and marked as the end of the stream.
+    fn inner_flush(&mut self, is_final: bool) -> Result<()> {
+        if self.buffer_len == 0 && !is_final {
             // Nothing to flush
             return Ok(());
         }
-        dbg_println!("Block to encrypt: {}", self.buffer_len);
-        let encrypted_data = self
-            .cipher
-            .encrypt(&self.nonce, &self.buffer[..self.buffer_len])
-            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
-        dbg_println!("Block encrypted: {}", encrypted_data.len());
-        if self.writer.write(&encrypted_data)? != encrypted_data.len() {
-            Err(error!(Other, "Failed to write the encrypted data"))?;
-        }; // Write the encrypted data to the writer
+        self.enc_buffer.clear();
+        self.enc_buffer
+            .extend_from_slice(&self.buffer[..self.buffer_len]);
+        self.encrypt_chunk(is_final)?;
 
-        // Reset the buffer
+        // Reset the buffer (only the plaintext written to it)
+        self.buffer[..self.buffer_len].fill(0);
         self.buffer_len = 0;
-        self.buffer = [0; BUFFER_SIZE];
+        Ok(())
+    }
+
+    /// Encrypt and write a full chunk taken directly from the caller's data, without going
+    /// through the inner buffer. (Which must be empty)
+    fn write_chunk(&mut self, data: &[u8]) -> Result<()> {
+        debug_assert!(self.buffer_len == 0 && data.len() == BUFFER_SIZE);
+        self.enc_buffer.clear();
+        self.enc_buffer.extend_from_slice(data);
+        self.encrypt_chunk(false)
+    }
+
+    /// Encrypt the plaintext chunk of the encrypted buffer in place, and write it.
+    fn encrypt_chunk(&mut self, is_final: bool) -> Result<()> {
+        dbg_println!("Block to encrypt: {}", self.enc_buffer.len());
+        let aad = ChunkAad::new(
+            self.header_digest.as_ref(),
+            self.previous_tag.as_ref(),
+            is_final,
+        );
+        let timer = Timer::start();
+        self.cipher
+            .encrypt_in_place(self.nonce.nonce(), aad.as_bytes(), &mut self.enc_buffer)
+            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+        if self.options.chain_tags {
+            let tag = &self.enc_buffer[self.enc_buffer.len() - AES_AUTH_TAG_LEN..];
+            self.previous_tag = Some(tag.try_into().expect("the tag is appended"));
+        }
+        let elapsed = timer.elapsed();
+        self.stats.cipher_time += elapsed;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            chunk = self.stats.chunks,
+            len = self.enc_buffer.len() - AES_AUTH_TAG_LEN,
+            is_final,
+            ?elapsed,
+            "chunk encrypted"
+        );
+        dbg_println!("Block encrypted: {}", self.enc_buffer.len());
+        self.writer.write_all(&self.enc_buffer)?; // Write the encrypted data to the writer
+        #[cfg(feature = "std")]
+        if let Some(backfill) = &mut self.backfill {
+            backfill.digest.update(&self.enc_buffer);
+        }
+        self.stats.chunks += 1;
+        self.stats.ciphertext_bytes += self.enc_buffer.len() as u64;
 
         // Increment the nonce
-        increment_nonce(&mut self.nonce);
+        self.nonce.increment();
 
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
+impl<W: io::Write + std::io::Seek, const BUFFER_SIZE: usize> CryptoWriter<W, BUFFER_SIZE> {
+    /// Create a new `CryptoWriter` instance on a seekable writer (e.g. a file), with the given
+    /// options. The space of a summary of the chunks is reserved in the header, and backfilled
+    /// once the stream is finished: the number of chunks, their length and their SHA-256 hash.
+    /// (See `Header::summary`)
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data. (Positioned at the start of the
+    ///   stream)
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - The errors of `new_with_options`, and the errors of `Seek::seek`.
+    ///
+    /// # Notes
+    /// The header is rewritten by `flush`, then the writer is positioned back at the end of the
+    /// stream. The streams started by `reset` are backfilled too. Without `Seek` (e.g. a socket),
+    /// use `new_with_options`: the header has no summary.
+    ///
+    pub fn new_seekable(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        Self::new_seekable_with_rng(writer, key, options, setup_rng())
+    }
+
+    /// Create a new `CryptoWriter` instance on a seekable writer, with the given options and
+    /// random number generator. (See `new_seekable`)
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// A `CryptoWriter` instance.
+    ///
+    pub fn new_seekable_with_rng<R: CryptoRng + RngCore>(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        mut rng: R,
+    ) -> Result<Self> {
+        let key = key.into().into_rsa();
+        let aes_key = options.cipher.generate_key(&mut rng);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+        let cipher = AeadCipher::with_cipher(options.cipher, &aes_key)?;
+        let wrapped_keys = wrap_data_key(&key, &aes_key, options, &mut rng)?;
+        let header = Header {
+            // Reserve the space of the summary
+            summary: Some(Summary::default()),
+            ..Self::stream_header(wrapped_keys, &aes_key, nonce, options)?
+        };
+        let mut backfill = Backfill {
+            header: header.clone(),
+            position: 0,
+            digest: Sha256::new(),
+            seek: <W as std::io::Seek>::seek,
+        };
+        backfill.start(&mut writer, &header)?;
+        let (header_len, header_digest) = Self::write_header(&mut writer, &header)?;
+
+        let mut crypto_writer = Self::with_cipher(
+            writer,
+            cipher,
+            nonce,
+            header_len,
+            header_digest,
+            key,
+            options,
+        );
+        crypto_writer.backfill = Some(backfill);
+        Ok(crypto_writer)
+    }
+}
+
 /// Drop the `CryptoWriter` instance.
 /// Flush the writer before dropping the `CryptoWriter` instance.
-impl<W: std::io::Write, const BUFFER_SIZE: usize> Drop for CryptoWriter<W, BUFFER_SIZE> {
+impl<W: io::Write, const BUFFER_SIZE: usize> Drop for CryptoWriter<W, BUFFER_SIZE> {
     /// Flush the writer before dropping the `CryptoWriter` instance.
     ///
     /// # Panics
@@ -190,6 +989,9 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> Drop for CryptoWriter<W, BUFFE
     /// an I/O error occurs.
     ///
     fn drop(&mut self) {
+        if self.has_been_flushed {
+            return;
+        }
         if let Err(e) = self.flush() {
             panic!("Failed to flush the writer: {}", e);
         }
@@ -199,13 +1001,16 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> Drop for CryptoWriter<W, BUFFE
 /// Implement the `Write` trait for the `CryptoWriter` struct.
 /// This allows the `CryptoWriter` to be used as a writer to interact seamlessly with other
 /// writers.
-impl<W: std::io::Write, const BUFFER_SIZE: usize> std::io::Write for CryptoWriter<W, BUFFER_SIZE> {
+impl<W: io::Write, const BUFFER_SIZE: usize> io::Write for CryptoWriter<W, BUFFER_SIZE> {
     /// Write data to the writer.
     /// The data is appended to inner buffer and flushed when the buffer is full.
     ///
     /// ***Warning***: The data is not written to the writer until the buffer is full. (Or if the
     /// `flush` method is called.)
     ///
+    /// The full chunks of a large `data` are encrypted straight from it, without being copied to
+    /// the inner buffer first.
+    ///
     /// # Arguments
     /// - `data`: The data to write.
     ///
@@ -214,10 +1019,16 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> std::io::Write for CryptoWrite
     ///
     /// # Errors
     /// Errors are returned if an I/O error occurs while flushing the writer.
+    /// - `Other`: If the writer has already been flushed. (The final chunk is written: the data
+    ///   would never be part of the stream. Use `reset` to start a new stream)
     ///
     // pub fn write(&mut self, data: &[u8]) -> Result<()> {}
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.has_been_flushed {
+            Err(error!(Other, "The writer has already been flushed"))?;
+        }
         let data_len = buf.len();
+        self.stats.plaintext_bytes += data_len as u64;
 
         if self.buffer_len + data_len < BUFFER_SIZE {
             self.buffer[self.buffer_len..self.buffer_len + data_len].copy_from_slice(buf);
@@ -227,7 +1038,7 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> std::io::Write for CryptoWrite
             let remaining = BUFFER_SIZE - self.buffer_len;
             self.buffer[self.buffer_len..].copy_from_slice(&buf[..remaining]);
             self.buffer_len = BUFFER_SIZE;
-            self.inner_flush()?;
+            self.inner_flush(false)?;
             {
                 let mut data = &buf[remaining..];
                 loop {
@@ -236,10 +1047,9 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> std::io::Write for CryptoWrite
                         self.buffer_len = data.len();
                         break Ok(data_len);
                     } else {
+                        // Encrypt the full chunks straight from `buf`
                         let (left, right) = data.split_at(BUFFER_SIZE);
-                        self.buffer.copy_from_slice(left);
-                        self.buffer_len = BUFFER_SIZE;
-                        self.inner_flush()?;
+                        self.write_chunk(left)?;
                         data = right;
                     }
                 }
@@ -255,11 +1065,26 @@ impl<W: std::io::Write, const BUFFER_SIZE: usize> std::io::Write for CryptoWrite
     /// This method drops the `CryptoWriter` instance because we don't want miss-alignment in the
     /// data written to the writer.
     ///
-    fn flush(&mut self) -> std::io::Result<()> {
+    /// The final chunk is always written, even if empty, and marked as the end of the stream. If
+    /// padding is enabled, it is padded before being encrypted.
+    ///
+    fn flush(&mut self) -> io::Result<()> {
         if self.has_been_flushed {
             Err(error!(Other, "The writer has already been flushed"))?;
         }
-        self.inner_flush()?;
+        if let Some(padded_len) = self
+            .options
+            .padding
+            .padded_len(self.buffer_len, BUFFER_SIZE)
+        {
+            pad(&mut self.buffer, self.buffer_len, padded_len);
+            self.buffer_len = padded_len;
+        }
+        self.inner_flush(true)?;
+        #[cfg(feature = "std")]
+        if let Some(backfill) = &mut self.backfill {
+            backfill.finish(&mut self.writer, &self.stats)?;
+        }
         self.writer.flush()?;
         self.has_been_flushed = true;
         Ok(())
@@ -1,14 +1,83 @@
 macro_rules! error {
     ($kind:ident, $($arg:expr),+) => {
-        std::io::Error::new(std::io::ErrorKind::$kind, error!(@msg $($arg),+))
+        crate::io::Error::new(crate::io::ErrorKind::$kind, error!(@msg $($arg),+))
     };
     (@msg $pattern:literal) => {
         $pattern
     };
     (@msg $pattern:literal, $($arg:expr),+) => {
-        format!($pattern, $($arg),+)
+        alloc::format!($pattern, $($arg),+)
     };
 }
 pub(crate) use error;
 
-pub type Result<T> = std::result::Result<T, std::io::Error>;
+pub type Result<T> = core::result::Result<T, crate::io::Error>;
+
+/// Errors which callers may need to tell apart from the other failures.
+///
+/// They are returned wrapped in a `std::io::Error` (with the `InvalidData` kind), like every other
+/// error of the crate. (`io::Error` without the `std` feature) Use `Error::from_io` to get them
+/// back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Error {
+    /// The message was already received.
+    Replayed {
+        /// The sequence number of the message.
+        sequence: u64,
+    },
+    /// The message is too far from the expected sequence number. (Older than the replay window,
+    /// or not the next message of an ordered channel)
+    OutOfOrder {
+        /// The sequence number of the message.
+        sequence: u64,
+    },
+    /// None of the keys decrypts the stream. (Wrong private key, or corrupted header)
+    WrongKey,
+    /// A chunk failed the authentication. (Corrupted or tampered data)
+    CorruptedChunk {
+        /// The index of the chunk in the stream. (Starting at 0)
+        index: u64,
+    },
+    /// The signature doesn't match the data, or the signer. (See `seal::open`)
+    BadSignature,
+    /// The key is expired. (See `KeyBundle`)
+    KeyExpired {
+        /// The expiry time of the key, in seconds since the Unix epoch.
+        expires: u64,
+    },
+}
+
+impl Error {
+    /// Get the `Error` wrapped in an I/O error, if any.
+    pub fn from_io(error: &crate::io::Error) -> Option<&Self> {
+        #[cfg(feature = "std")]
+        return error.get_ref()?.downcast_ref();
+        #[cfg(not(feature = "std"))]
+        return error.typed();
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Replayed { sequence } => write!(f, "Message {} was replayed", sequence),
+            Error::OutOfOrder { sequence } => write!(f, "Message {} is out of order", sequence),
+            Error::WrongKey => write!(f, "No key matches the stream"),
+            Error::CorruptedChunk { index } => write!(f, "Chunk {} is corrupted", index),
+            Error::BadSignature => write!(f, "The signature is invalid"),
+            Error::KeyExpired { expires } => {
+                write!(f, "The key expired at {} (Unix time)", expires)
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
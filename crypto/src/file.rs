@@ -0,0 +1,390 @@
+//! This module provides `EncryptedFile`, a random-access encrypted file: any range of the plaintext
+//! can be read or overwritten in place (`read_at` / `write_at`), e.g. to back the pages of a
+//! database (like a SQLite VFS) or an object updated in parts.
+//!
+//! `CryptoWriter` streams are sequential: the nonce of a chunk follows from its position, so a
+//! chunk can't be encrypted again without reusing its nonce. Here, the plaintext is cut into
+//! blocks of `BLOCK_SIZE` bytes, each encrypted on its own with a random nonce, drawn again every
+//! time the block is written.
+//!
+//! The file starts with a `Header` (with `chunking` set to `Chunking::RandomAccess`, and
+//! `chunk_size` holding `BLOCK_SIZE`), followed by the blocks:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |      NONCE      |   |    AES Data     |   |       TAG       |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |                 |   |                 |   |                 |   ...
+//! +-----------------+   +-----------------+   +-----------------+
+//! |  AES NONCE LEN  |   |   BLOCK_SIZE    |   |  AES TAG LEN    |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! As in a stream, the final block holds the remaining bytes (possibly none) and is always
+//! written, so the plaintext length follows from the file length. Each block authenticates the
+//! hash of the header (see `Header::digest`) and its index as additional data, followed by the
+//! final chunk marker for the final block (see `Header::end_marker`), so a modified header and
+//! moved, removed or appended blocks are detected.
+//!
+//! **Warning**: A block replaced by an older version of the same block (a rollback) is not
+//! detected, and a `write_at` interrupted by a crash can leave the file unreadable. Keep a hash
+//! of the file, or a journal, elsewhere if this matters. The random nonces stay unique for about
+//! 2^32 block writes per file.
+use super::{
+    cipher::AeadCipher,
+    decrypt::{unwrap_data_key, SlotKey},
+    encrypt::{key_check_value, key_commitment, slot_kems, wrap_data_key, WriterOptions},
+    error::{error, Error, Result},
+    header::{Chunking, Format, Header},
+    identity::{Identity, Recipient},
+    padding::Padding,
+    shared::{
+        setup_rng, Nonce, AES_AUTH_TAG_LEN, AES_NONCE_LEN, FINAL_CHUNK_AAD, HEADER_DIGEST_LEN,
+    },
+};
+use rand::{CryptoRng, RngCore};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// The bytes added to the plaintext of each block: its nonce and its tag.
+pub const BLOCK_OVERHEAD: usize = AES_NONCE_LEN + AES_AUTH_TAG_LEN;
+
+/// The additional data of a block: the hash of the header and the index of the block, followed by
+/// `FINAL_CHUNK_AAD` for the final block.
+fn block_aad(
+    header_digest: Option<&[u8; HEADER_DIGEST_LEN]>,
+    index: u64,
+    is_final: bool,
+) -> Vec<u8> {
+    let mut aad = header_digest.map_or_else(Vec::new, |digest| digest.to_vec());
+    aad.extend_from_slice(&index.to_be_bytes());
+    if is_final {
+        aad.extend_from_slice(FINAL_CHUNK_AAD);
+    }
+    aad
+}
+
+/// A random-access encrypted file. (See the module documentation)
+///
+/// Every method works on the whole file: the blocks are written as soon as `write_at` returns, and
+/// nothing is buffered.
+pub struct EncryptedFile<F: Read + Write + Seek, const BLOCK_SIZE: usize> {
+    file: F,
+    cipher: AeadCipher,
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    /// The position of the first block in the file.
+    blocks_start: u64,
+    /// The length of the plaintext.
+    len: u64,
+    /// The encrypted block, reused for every block.
+    block: Vec<u8>,
+    /// The plaintext of the block being read or updated.
+    plain: Zeroizing<Vec<u8>>,
+}
+
+impl<F: Read + Write + Seek, const BLOCK_SIZE: usize> EncryptedFile<F, BLOCK_SIZE> {
+    /// Create an empty encrypted file, starting at the current position of `file`.
+    ///
+    /// # Arguments
+    /// - `file`: The file. (Empty, or ending at its current position)
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Errors
+    /// The errors of `create_with_options_and_rng`.
+    ///
+    pub fn create(file: F, key: impl Into<Recipient>) -> Result<Self> {
+        Self::create_with_options(file, key, &WriterOptions::default())
+    }
+
+    /// Create an empty encrypted file with the given options. (See `create`)
+    ///
+    /// # Errors
+    /// The errors of `create_with_options_and_rng`.
+    ///
+    pub fn create_with_options(
+        file: F,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::create_with_options_and_rng(file, key, options, &mut rng)
+    }
+
+    /// Create an empty encrypted file with the given options and random number generator.
+    ///
+    /// # Arguments
+    /// - `file`: The file. (Empty, or ending at its current position)
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The recipients, key wrap and cipher of the file. (See `WriterOptions`)
+    /// - `rng`: The random number generator of the AES key. (The nonces of the blocks are drawn
+    ///   from the thread RNG)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `BLOCK_SIZE` is 0 or doesn't fit in the header, or if the options
+    ///   set a padding or chained chunks. (The blocks are encrypted on their own)
+    /// - `Other`: If the RSA encryption fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn create_with_options_and_rng<R: CryptoRng + RngCore>(
+        mut file: F,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        mut rng: R,
+    ) -> Result<Self> {
+        let block_size = u32::try_from(BLOCK_SIZE)
+            .ok()
+            .filter(|&block_size| block_size > 0)
+            .ok_or_else(|| error!(InvalidInput, "Invalid block size: {}", BLOCK_SIZE))?;
+        if options.padding != Padding::None || options.chain_tags {
+            Err(error!(
+                InvalidInput,
+                "Encrypted files don't support padding or chained chunks"
+            ))?;
+        }
+
+        let aes_key = options.cipher.generate_key(&mut rng);
+        let mut nonce = [0; AES_NONCE_LEN];
+        rng.fill_bytes(&mut nonce);
+        let header = Header {
+            chunk_size: Some(block_size),
+            chunking: Chunking::RandomAccess,
+            end_marker: true,
+            key_check: Some(key_check_value(&aes_key)),
+            commitment: Some(key_commitment(&aes_key)),
+            key_wrap: options.key_wrap,
+            cipher: options.cipher,
+            slot_kems: slot_kems(options),
+            // The nonce is unused: each block has its own nonce
+            ..Header::new(
+                Format::LATEST,
+                wrap_data_key(&key.into().into_rsa(), &aes_key, options, &mut rng)?,
+                nonce,
+            )
+        };
+        let start = file.stream_position()?;
+        header.write(&mut file)?;
+
+        let mut encrypted_file = Self {
+            file,
+            cipher: AeadCipher::with_cipher(options.cipher, &aes_key)?,
+            header_digest: header.digest()?,
+            blocks_start: start + header.len() as u64,
+            len: 0,
+            block: Vec::with_capacity(BLOCK_SIZE + BLOCK_OVERHEAD),
+            plain: Zeroizing::new(Vec::with_capacity(BLOCK_SIZE)),
+        };
+        // The empty final block
+        encrypted_file.write_block(0, true)?;
+        encrypted_file.file.flush()?;
+        Ok(encrypted_file)
+    }
+
+    /// Open an encrypted file, starting at the current position of `file`, and check its final
+    /// block.
+    ///
+    /// # Arguments
+    /// - `file`: The file.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the file is not an encrypted file of `BLOCK_SIZE` bytes blocks.
+    /// - `Error::WrongKey`: If the key doesn't decrypt the file.
+    /// - `Error::CorruptedChunk`: If the final block fails the authentication. (e.g. the file
+    ///   was truncated)
+    /// - `UnexpectedEof`: If the file is truncated in a block.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn open(mut file: F, key: impl Into<Identity>) -> Result<Self> {
+        let start = file.stream_position()?;
+        let header = Header::parse(&mut file)?;
+        if header.chunking != Chunking::RandomAccess || header.chunk_size != Some(BLOCK_SIZE as u32)
+        {
+            Err(error!(
+                InvalidData,
+                "Not an encrypted file of {} bytes blocks", BLOCK_SIZE
+            ))?;
+        }
+        let (_, raw_aes_key) =
+            unwrap_data_key(&header, &[SlotKey::Rsa(key.into().into_rsa())], false)?;
+        let blocks_start = start + header.len() as u64;
+
+        // The plaintext length, from the length of the blocks (the final one holds an overhead)
+        let blocks_len = file
+            .seek(SeekFrom::End(0))?
+            .checked_sub(blocks_start)
+            .ok_or_else(|| error!(UnexpectedEof, "Truncated file"))?;
+        let block_len = (BLOCK_SIZE + BLOCK_OVERHEAD) as u64;
+        let final_len = (blocks_len % block_len)
+            .checked_sub(BLOCK_OVERHEAD as u64)
+            .ok_or_else(|| error!(UnexpectedEof, "Truncated file"))?;
+
+        let mut encrypted_file = Self {
+            file,
+            cipher: AeadCipher::with_cipher(header.cipher, &raw_aes_key)?,
+            header_digest: header.digest()?,
+            blocks_start,
+            len: blocks_len / block_len * BLOCK_SIZE as u64 + final_len,
+            block: Vec::with_capacity(BLOCK_SIZE + BLOCK_OVERHEAD),
+            plain: Zeroizing::new(Vec::with_capacity(BLOCK_SIZE)),
+        };
+        encrypted_file.read_block(encrypted_file.len / BLOCK_SIZE as u64)?;
+        Ok(encrypted_file)
+    }
+
+    /// The length of the plaintext.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the plaintext is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read the plaintext starting at `offset`, decrypting only the blocks holding it.
+    ///
+    /// # Arguments
+    /// - `offset`: The position in the plaintext.
+    /// - `buf`: The buffer to fill.
+    ///
+    /// # Returns
+    /// The number of bytes read: `buf.len()`, unless the end of the plaintext is reached. (0 if
+    /// `offset` is past the end)
+    ///
+    /// # Errors
+    /// - `Error::CorruptedChunk`: If a block fails the authentication.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let position = offset.saturating_add(read as u64);
+            if position >= self.len {
+                break;
+            }
+            self.read_block(position / BLOCK_SIZE as u64)?;
+            let start = (position % BLOCK_SIZE as u64) as usize;
+            let len = (self.plain.len() - start).min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&self.plain[start..start + len]);
+            read += len;
+        }
+        Ok(read)
+    }
+
+    /// Write the whole buffer at `offset`, encrypting again only the blocks holding it. The file
+    /// is extended if needed, with zeros between its previous end and `offset`.
+    ///
+    /// # Arguments
+    /// - `offset`: The position in the plaintext.
+    /// - `buf`: The data to write.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the end of the write overflows a `u64`.
+    /// - `Error::CorruptedChunk`: If a partially written block fails the authentication.
+    /// - `Other`: If the AES encryption fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or_else(|| error!(InvalidInput, "Write past the maximum file length"))?;
+        if end <= self.len && buf.is_empty() {
+            return Ok(());
+        }
+        let block_size = BLOCK_SIZE as u64;
+        let len = self.len.max(end);
+        // When the file grows, the previous final block and the new blocks are written too
+        let (first, last) = match end > self.len {
+            true => (offset.min(self.len) / block_size, len / block_size),
+            false => (offset / block_size, (end - 1) / block_size),
+        };
+        for index in first..=last {
+            let block_start = index * block_size;
+            let block_end = (block_start + block_size).min(len);
+            // The previous plaintext of the block, if partially overwritten
+            let overwritten = offset <= block_start && block_end <= end;
+            if !overwritten && block_start < self.len {
+                self.read_block(index)?;
+            } else {
+                self.plain.clear();
+            }
+            self.plain.resize((block_end - block_start) as usize, 0);
+            let (from, to) = (offset.max(block_start), end.min(block_end));
+            if from < to {
+                self.plain[(from - block_start) as usize..(to - block_start) as usize]
+                    .copy_from_slice(&buf[(from - offset) as usize..(to - offset) as usize]);
+            }
+            self.write_block(index, index == len / block_size)?;
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Flush the underlying file.
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+
+    /// Unwrap the underlying file.
+    pub fn into_inner(self) -> F {
+        self.file
+    }
+
+    /// The position of a block in the file.
+    fn block_position(&self, index: u64) -> u64 {
+        self.blocks_start + index * (BLOCK_SIZE + BLOCK_OVERHEAD) as u64
+    }
+
+    /// Read and decrypt a block into the plaintext buffer.
+    fn read_block(&mut self, index: u64) -> Result<()> {
+        let is_final = index == self.len / BLOCK_SIZE as u64;
+        let len = match is_final {
+            true => (self.len % BLOCK_SIZE as u64) as usize,
+            false => BLOCK_SIZE,
+        };
+        self.file
+            .seek(SeekFrom::Start(self.block_position(index)))?;
+        self.block.resize(len + BLOCK_OVERHEAD, 0);
+        self.file.read_exact(&mut self.block)?;
+
+        // Decrypt in place: the tag is checked first, so a failure leaves the block unchanged
+        let (nonce, block) = self.block.split_at_mut(AES_NONCE_LEN);
+        let (data, tag) = block.split_at_mut(len);
+        self.cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(nonce),
+                &block_aad(self.header_digest.as_ref(), index, is_final),
+                data,
+                tag,
+            )
+            .map_err(|_| Error::CorruptedChunk { index })?;
+        self.plain.clear();
+        self.plain.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Encrypt the plaintext buffer under a new nonce, and write it as a block.
+    fn write_block(&mut self, index: u64, is_final: bool) -> Result<()> {
+        let mut nonce = Nonce::default();
+        setup_rng().fill_bytes(&mut nonce);
+        self.block.clear();
+        self.block.extend_from_slice(&self.plain);
+        self.cipher
+            .encrypt_in_place(
+                &nonce,
+                &block_aad(self.header_digest.as_ref(), index, is_final),
+                &mut self.block,
+            )
+            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+
+        self.file
+            .seek(SeekFrom::Start(self.block_position(index)))?;
+        self.file.write_all(&nonce)?;
+        self.file.write_all(&self.block)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+//! This module describes the layout of the streams written by `CryptoWriter` (see `Header` for the
+//! header fields), so applications can pre-size network frames and storage allocations exactly.
+//!
+//! A stream is the header followed by the chunks. Every chunk holds `chunk_size` bytes of
+//! plaintext (`BUFFER_SIZE`) and an authentication tag, except the final chunk, which holds the
+//! remaining bytes (possibly none) and is always written:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |     Header      |   |      Chunk      |   |       ...       |   |   Final Chunk   |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |  See `Header`   |   |   Data + Tag    |   |                 |   |   Data + Tag    |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |   header_len    |   |  CHUNK SIZE +   |   |                 |   |  REMAINING (or  |
+//! |                 |   | CHUNK_OVERHEAD  |   |                 |   |  PADDED) + TAG  |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The lengths assume the default `WriterOptions` (a single RSA recipient, no padding), except
+//! where a `Padding` is given. `Header::len` gives the exact length of any other header.
+use super::{
+    padding::Padding,
+    shared::{AES_AUTH_TAG_LEN, AES_NONCE_LEN, KEY_COMMITMENT_LEN},
+};
+
+/// The length of the authentication tag of a chunk.
+pub const TAG_LEN: usize = AES_AUTH_TAG_LEN;
+/// The length of the AES nonce recorded in the header.
+pub const NONCE_LEN: usize = AES_NONCE_LEN;
+/// The bytes added to the plaintext of each chunk. (The tag)
+pub const CHUNK_OVERHEAD: usize = TAG_LEN;
+/// The smallest chunk size. (`BUFFER_SIZE`) Smaller chunks hold less data than their tag: a 1 byte
+/// chunk takes 17 bytes.
+pub const MIN_CHUNK_SIZE: usize = TAG_LEN;
+/// The largest chunk size. (`BUFFER_SIZE`) The chunk size field of the header is a `u32`, which
+/// keeps the chunks below the AES-GCM limit of a message. (2^36 - 32 bytes)
+pub const MAX_CHUNK_SIZE: usize = u32::MAX as usize;
+/// The length of the fields of a `Format::V1` header that don't depend on the key or the options:
+/// magic, version, chunk size, wrapped key length, nonce and extensions length.
+pub const HEADER_FIXED_LEN: usize = 6 + 1 + 4 + 2 + NONCE_LEN + 2;
+/// The length of the extensions always written by `CryptoWriter`: end marker, key check value and
+/// key commitment. (Each with a 3 bytes tag and length)
+pub const DEFAULT_EXTENSIONS_LEN: usize = 3 + (3 + TAG_LEN) + (3 + KEY_COMMITMENT_LEN);
+
+/// The length of the header written by `CryptoWriter` with the default options.
+///
+/// # Arguments
+/// - `rsa_key_bits`: The size of the RSA key of the recipient, in bits. (e.g. 2048)
+///
+/// # Returns
+/// The header length in bytes. (340 for a 2048 bits key)
+///
+pub const fn header_len(rsa_key_bits: usize) -> usize {
+    HEADER_FIXED_LEN + rsa_key_bits.div_ceil(8) + DEFAULT_EXTENSIONS_LEN
+}
+
+/// Whether a chunk size is supported: between `MIN_CHUNK_SIZE` and `MAX_CHUNK_SIZE`.
+///
+/// # Notes
+/// The `BUFFER_SIZE` of `CryptoWriter` and `CryptoReader` is checked at compile time: an
+/// unsupported size fails the build of their constructors.
+///
+pub const fn is_supported_chunk_size(chunk_size: usize) -> bool {
+    chunk_size >= MIN_CHUNK_SIZE && chunk_size <= MAX_CHUNK_SIZE
+}
+
+/// The length of the chunks encrypting `plaintext_len` bytes, without the header.
+///
+/// # Arguments
+/// - `plaintext_len`: The number of bytes written to the `CryptoWriter`.
+/// - `chunk_size`: The chunk size of the stream. (`BUFFER_SIZE`)
+///
+/// # Panics
+/// If `chunk_size` is 0.
+///
+pub const fn ciphertext_len(plaintext_len: u64, chunk_size: u32) -> u64 {
+    let chunks = plaintext_len / chunk_size as u64 + 1;
+    plaintext_len + chunks * CHUNK_OVERHEAD as u64
+}
+
+/// The length of the chunks encrypting `plaintext_len` bytes, without the header, with the final
+/// chunk padded. (See `WriterOptions::padding`)
+///
+/// # Arguments
+/// - `plaintext_len`: The number of bytes written to the `CryptoWriter`.
+/// - `chunk_size`: The chunk size of the stream. (`BUFFER_SIZE`)
+/// - `padding`: The padding of the final chunk.
+///
+/// # Panics
+/// If `chunk_size` is 0.
+///
+pub fn padded_ciphertext_len(plaintext_len: u64, chunk_size: u32, padding: Padding) -> u64 {
+    let remaining = (plaintext_len % chunk_size as u64) as usize;
+    let padded = padding
+        .padded_len(remaining, chunk_size as usize)
+        .unwrap_or(remaining);
+    ciphertext_len(plaintext_len, chunk_size) - remaining as u64 + padded as u64
+}
+
+/// The length of the stream encrypting `plaintext_len` bytes: the header and the chunks.
+///
+/// # Arguments
+/// - `plaintext_len`: The number of bytes written to the `CryptoWriter`.
+/// - `chunk_size`: The chunk size of the stream. (`BUFFER_SIZE`)
+/// - `rsa_key_bits`: The size of the RSA key of the recipient, in bits.
+///
+/// # Panics
+/// If `chunk_size` is 0.
+///
+pub const fn stream_len(plaintext_len: u64, chunk_size: u32, rsa_key_bits: usize) -> u64 {
+    header_len(rsa_key_bits) as u64 + ciphertext_len(plaintext_len, chunk_size)
+}
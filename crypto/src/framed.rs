@@ -0,0 +1,331 @@
+//! This module provides a framed variant of the stream, for transports carrying messages.
+//! (e.g. protobuf `bytes` fields, or gRPC streaming)
+//!
+//! The stream of `CryptoWriter` is a plain byte stream: the reader finds the chunk boundaries from
+//! the chunk size of the header. A transport cutting it into messages at other boundaries works,
+//! but the messages can't be handled on their own. With `FramedWriter`, the header and each chunk
+//! are emitted as a self-describing frame:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     LENGTH      |   |      TYPE       |   |      Data       |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     Varint      |   | 0: header       |   |                 |
+//! |  (as protobuf)  |   | 1: chunk        |   |                 |
+//! |                 |   | 2: final chunk  |   |                 |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     1 to 10     |   |        1        |   |     LENGTH      |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The data of the frames, concatenated, is the stream of `CryptoWriter`. So a frame maps to a
+//! message (each frame is written with one `write_all` call, or see `write_frame` and
+//! `read_frame`), and `FramedReader` turns the frames back into the stream for `CryptoReader`.
+//!
+//! **Note**: The frames are not authenticated. The data is authenticated by the `CryptoReader`
+//! reading it, so a tampered frame still fails the decryption. `FramedReader` only checks their
+//! order: the header, the chunks, then the final chunk, at the end of the transport.
+use super::{
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    identity::Recipient,
+    shared::AES_AUTH_TAG_LEN,
+    stats::Stats,
+};
+use std::io::{Read, Write};
+
+/// The maximum length of a varint. (10 bytes for a `u64`)
+pub(crate) const MAX_VARINT_LEN: usize = 10;
+
+/// The type of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    /// The header of the stream.
+    Header = 0,
+    /// A chunk of the stream.
+    Chunk = 1,
+    /// The final chunk of the stream. (Always the last frame)
+    Final = 2,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = std::io::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Header),
+            1 => Ok(Self::Chunk),
+            2 => Ok(Self::Final),
+            _ => Err(error!(InvalidData, "Unknown frame type: {}", value)),
+        }
+    }
+}
+
+/// Write a frame.
+///
+/// # Arguments
+/// - `writer`: The writer to write the frame.
+/// - `kind`: The type of the frame.
+/// - `data`: The data of the frame.
+///
+/// # Errors
+/// - `Io`: If an I/O error occurs.
+///
+/// # Notes
+/// The frame is written with one `write_all` call.
+///
+pub fn write_frame<W: Write>(mut writer: W, kind: FrameKind, data: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(MAX_VARINT_LEN + 1 + data.len());
+    encode_frame(&mut frame, kind, data);
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+/// Append a frame to a buffer.
+pub(crate) fn encode_frame(frame: &mut Vec<u8>, kind: FrameKind, data: &[u8]) {
+    encode_varint(frame, data.len() as u64);
+    frame.push(kind as u8);
+    frame.extend_from_slice(data);
+}
+
+/// Append a varint to a buffer. (As protobuf)
+pub(crate) fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Read a varint.
+///
+/// # Returns
+/// The value of the varint, or `None` at the end of the reader.
+///
+/// # Errors
+/// - `InvalidData`: If the varint overflows a `u64`.
+/// - `UnexpectedEof`: If the reader ends in the varint.
+/// - `Io`: If an I/O error occurs.
+///
+pub(crate) fn read_varint<R: Read>(reader: &mut R) -> Result<Option<u64>> {
+    let mut value = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            Err(error!(UnexpectedEof, "The frame header is truncated"))?;
+        }
+        let bits = u64::from(byte[0] & 0x7f);
+        if i == MAX_VARINT_LEN - 1 && bits > 1 {
+            Err(error!(InvalidData, "The varint overflows"))?;
+        }
+        value |= bits << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(error!(InvalidData, "The varint overflows"))
+}
+
+/// Read the length and the type of a frame.
+///
+/// # Returns
+/// The type and the length of the data of the frame, or `None` at the end of the reader.
+///
+/// # Errors
+/// - `InvalidData`: If the length is not a valid varint, or the type is unknown.
+/// - `UnexpectedEof`: If the reader ends in the frame header.
+/// - `Io`: If an I/O error occurs.
+///
+fn read_frame_header<R: Read>(reader: &mut R) -> Result<Option<(FrameKind, u64)>> {
+    let Some(len) = read_varint(reader)? else {
+        return Ok(None);
+    };
+    let mut kind = [0];
+    reader.read_exact(&mut kind)?;
+    Ok(Some((FrameKind::try_from(kind[0])?, len)))
+}
+
+/// Read a frame.
+///
+/// # Arguments
+/// - `reader`: The reader from which the frame is read.
+///
+/// # Returns
+/// The type and the data of the frame, or `None` at the end of the reader.
+///
+/// # Errors
+/// - `InvalidData`: If the frame header is invalid.
+/// - `UnexpectedEof`: If the frame is truncated.
+/// - `Io`: If an I/O error occurs.
+///
+pub fn read_frame<R: Read>(mut reader: R) -> Result<Option<(FrameKind, Vec<u8>)>> {
+    let Some((kind, len)) = read_frame_header(&mut reader)? else {
+        return Ok(None);
+    };
+    // Read as it comes: the length is not trusted for the allocation
+    let mut data = Vec::new();
+    if reader.take(len).read_to_end(&mut data)? as u64 != len {
+        Err(error!(UnexpectedEof, "The frame is truncated"))?;
+    }
+    Ok(Some((kind, data)))
+}
+
+/// A writer that encrypts the data, and writes the header and each chunk as a frame.
+///
+/// See the module documentation for the format of the frames.
+///
+/// **Warning**: `finish` must be called once all the data is written, as it writes the final
+/// frame. (Without it, `FramedReader` reports the stream as truncated)
+pub struct FramedWriter<W: Write, const BUFFER_SIZE: usize> {
+    inner: CryptoWriter<Vec<u8>, BUFFER_SIZE>,
+    writer: W,
+}
+
+impl<W: Write, const BUFFER_SIZE: usize> FramedWriter<W, BUFFER_SIZE> {
+    /// Create a new `FramedWriter` instance, and write the header frame.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the frames.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    ///
+    /// # Returns
+    /// A `FramedWriter` instance.
+    ///
+    /// # Errors
+    /// - The errors of `CryptoWriter::new_with_options`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(mut writer: W, key: impl Into<Recipient>, options: &WriterOptions) -> Result<Self> {
+        let mut inner = CryptoWriter::new_with_options(Vec::new(), key, options)?;
+        write_frame(&mut writer, FrameKind::Header, inner.writer_mut())?;
+        inner.writer_mut().clear();
+        Ok(Self { inner, writer })
+    }
+
+    /// The statistics of the writer. (See `CryptoWriter::stats`)
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Write the chunks encrypted so far as frames.
+    fn write_chunks(&mut self) -> Result<()> {
+        let chunk_len = BUFFER_SIZE + AES_AUTH_TAG_LEN;
+        let encrypted = self.inner.writer_mut();
+        for chunk in encrypted.chunks(chunk_len) {
+            write_frame(&mut self.writer, FrameKind::Chunk, chunk)?;
+        }
+        encrypted.clear();
+        Ok(())
+    }
+
+    /// Write the final chunk as the final frame, and flush the underlying writer.
+    ///
+    /// # Returns
+    /// The underlying writer.
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        // The chunks written before the final one, if the final chunk was encrypted with them
+        let chunk_len = BUFFER_SIZE + AES_AUTH_TAG_LEN;
+        let encrypted = std::mem::take(self.inner.writer_mut());
+        let final_start = encrypted.len().saturating_sub(1) / chunk_len * chunk_len;
+        for chunk in encrypted[..final_start].chunks(chunk_len) {
+            write_frame(&mut self.writer, FrameKind::Chunk, chunk)?;
+        }
+        write_frame(
+            &mut self.writer,
+            FrameKind::Final,
+            &encrypted[final_start..],
+        )?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write, const BUFFER_SIZE: usize> Write for FramedWriter<W, BUFFER_SIZE> {
+    /// Encrypt the data. The chunks are written as frames once full.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.write_chunks()?;
+        Ok(written)
+    }
+
+    /// Flush the underlying writer.
+    /// The data of the current chunk stays buffered until the chunk is full, or `finish` is
+    /// called.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A reader that returns the data of the frames written by `FramedWriter`, to be read by a
+/// `CryptoReader`.
+///
+/// The frames are checked to be in order (the header, the chunks, then the final chunk), and the
+/// final frame to be the last data of the reader.
+pub struct FramedReader<R: Read> {
+    reader: R,
+    /// The type of the current frame. (`None` before the first one)
+    kind: Option<FrameKind>,
+    /// The data left in the current frame.
+    remaining: u64,
+}
+
+impl<R: Read> FramedReader<R> {
+    /// Create a new `FramedReader` instance.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            kind: None,
+            remaining: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for FramedReader<R> {
+    /// Read the data of the frames.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If a frame is invalid, out of order, or follows the final frame.
+    /// - `UnexpectedEof`: If the reader ends before the final frame.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.remaining == 0 {
+            if self.kind == Some(FrameKind::Final) {
+                if self.reader.read(&mut [0])? != 0 {
+                    Err(error!(InvalidData, "Data follows the final frame"))?;
+                }
+                return Ok(0);
+            }
+            let (kind, len) = read_frame_header(&mut self.reader)?
+                .ok_or_else(|| error!(UnexpectedEof, "The frames end before the final frame"))?;
+            let expected = match self.kind {
+                None => kind == FrameKind::Header,
+                Some(_) => kind != FrameKind::Header,
+            };
+            if !expected {
+                Err(error!(InvalidData, "Unexpected {:?} frame", kind))?;
+            }
+            self.kind = Some(kind);
+            self.remaining = len;
+        }
+        let to_read = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = self.reader.read(&mut buf[..to_read])?;
+        if read == 0 {
+            Err(error!(UnexpectedEof, "The frame is truncated"))?;
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
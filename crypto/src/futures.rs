@@ -0,0 +1,261 @@
+//! This module provides the `futures-io` variants of the writer and the reader.
+//!
+//! `AsyncCryptoWriter` implements `futures_io::AsyncWrite` and `AsyncCryptoReader` implements
+//! `futures_io::AsyncRead`, over any `futures_io` transport, so the crate can be used with
+//! async-std, smol, or generic async code, without depending on a given runtime. They produce
+//! and consume the same stream format as `CryptoWriter` and `CryptoReader`.
+//!
+//! Unlike `CryptoWriter`, `poll_flush` only writes the chunks already encrypted: the final chunk
+//! is written by `poll_close`, which ends the stream.
+use super::{
+    decrypt::{CryptoReader, Feed},
+    encrypt::{CryptoWriter, WriterOptions},
+    error::Result,
+    identity::{Identity, Recipient},
+    stats::Stats,
+};
+use futures_io::{AsyncRead, AsyncWrite};
+use rand::{CryptoRng, RngCore};
+use std::{
+    io::{Read as _, Write as _},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// An async writer that encrypts the data before writing it to a `futures_io` writer.
+///
+/// The data is encrypted by a `CryptoWriter` into a buffer, which is written to the underlying
+/// writer before accepting more data.
+///
+/// **Warning**: The writer must be closed (`poll_close`, or `AsyncWriteExt::close`) once all the
+/// data is written, as it writes the final chunk. (Dropping the writer can't write it
+/// asynchronously)
+pub struct AsyncCryptoWriter<W: AsyncWrite + Unpin, const BUFFER_SIZE: usize> {
+    inner: CryptoWriter<Vec<u8>, BUFFER_SIZE>,
+    writer: W,
+    /// The encrypted data not yet written to the writer.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    /// Whether the final chunk was encrypted.
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin, const BUFFER_SIZE: usize> AsyncCryptoWriter<W, BUFFER_SIZE> {
+    /// Create a new `AsyncCryptoWriter` instance.
+    /// The `key` is used to encrypt the AES key. The header is written with the first chunk.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Returns
+    /// An `AsyncCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new(writer: W, key: impl Into<Recipient>) -> Result<Self> {
+        Self::new_with_options(writer, key, &WriterOptions::default())
+    }
+
+    /// Create a new `AsyncCryptoWriter` instance with the given options.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the writer.
+    ///
+    /// # Returns
+    /// An `AsyncCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new_with_options(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<Self> {
+        Ok(Self::with_inner(
+            writer,
+            CryptoWriter::new_with_options(Vec::new(), key, options)?,
+        ))
+    }
+
+    /// Create a new `AsyncCryptoWriter` instance with the given options and random number
+    /// generator.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the writer.
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// An `AsyncCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    ///
+    pub fn new_with_options_and_rng<G: CryptoRng + RngCore>(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        rng: G,
+    ) -> Result<Self> {
+        Ok(Self::with_inner(
+            writer,
+            CryptoWriter::new_with_options_and_rng(Vec::new(), key, options, rng)?,
+        ))
+    }
+
+    fn with_inner(writer: W, inner: CryptoWriter<Vec<u8>, BUFFER_SIZE>) -> Self {
+        Self {
+            inner,
+            writer,
+            pending: Vec::new(),
+            pending_pos: 0,
+            finished: false,
+        }
+    }
+
+    /// The statistics of the writer. (See `CryptoWriter::stats`)
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Write the encrypted data to the underlying writer, until none is left.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            if self.pending_pos == self.pending.len() {
+                self.pending = std::mem::take(self.inner.writer_mut());
+                self.pending_pos = 0;
+                if self.pending.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            let written = ready!(
+                Pin::new(&mut self.writer).poll_write(cx, &self.pending[self.pending_pos..])
+            )?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_pos += written;
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin, const BUFFER_SIZE: usize> AsyncWrite
+    for AsyncCryptoWriter<W, BUFFER_SIZE>
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(this.inner.write(buf))
+    }
+
+    /// Write the chunks already encrypted, and flush the underlying writer.
+    /// The data of the current chunk stays buffered until the chunk is full, or the writer is
+    /// closed.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    /// Write the final chunk, and close the underlying writer.
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.finished {
+            ready!(this.poll_drain(cx))?;
+            this.inner.flush()?;
+            this.finished = true;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
+
+/// An async reader that decrypts the data read from a `futures_io` reader.
+///
+/// The encrypted data is read from the underlying reader when the inner `CryptoReader` needs
+/// more of it, and decrypted as `CryptoReader` does.
+pub struct AsyncCryptoReader<R: AsyncRead + Unpin, const BUFFER_SIZE: usize> {
+    inner: CryptoReader<Feed, BUFFER_SIZE>,
+    reader: R,
+}
+
+impl<R: AsyncRead + Unpin, const BUFFER_SIZE: usize> AsyncCryptoReader<R, BUFFER_SIZE> {
+    /// Create a new `AsyncCryptoReader` instance.
+    /// The header is read and checked by the first `poll_read` call.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// An `AsyncCryptoReader` instance.
+    ///
+    /// # Notes
+    /// The errors of `CryptoReader::new` (invalid header, wrong key, ...) are returned by the
+    /// first `poll_read` call.
+    ///
+    pub fn new(reader: R, key: impl Into<Identity>) -> Self {
+        Self {
+            inner: CryptoReader::new_lazy(Feed::default(), key),
+            reader,
+        }
+    }
+
+    /// Enable or disable the strict mode. (See `CryptoReader::set_strict`)
+    pub fn set_strict(&mut self, strict: bool) {
+        self.inner.set_strict(strict);
+    }
+
+    /// Enable or disable the implicit rejection of the RSA wrapped keys. (Enabled by default, see
+    /// `CryptoReader::set_implicit_rejection`)
+    pub fn set_implicit_rejection(&mut self, enabled: bool) {
+        self.inner.set_implicit_rejection(enabled);
+    }
+
+    /// The statistics of the reader. (See `CryptoReader::stats`)
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<R: AsyncRead + Unpin, const BUFFER_SIZE: usize> AsyncRead
+    for AsyncCryptoReader<R, BUFFER_SIZE>
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match this.inner.read(buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                result => return Poll::Ready(result),
+            }
+
+            // Read more encrypted data
+            let feed = this.inner.get_mut();
+            match Pin::new(&mut this.reader).poll_read(cx, feed.spare(BUFFER_SIZE)) {
+                Poll::Ready(Ok(read)) => feed.commit(read),
+                Poll::Ready(Err(e)) => {
+                    feed.discard();
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending => {
+                    feed.discard();
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,723 @@
+//! This module provides the `Header` struct. Which holds the data written at the beginning of an
+//! encrypted stream.
+//!
+//! The header is written by `CryptoWriter` and read by `CryptoReader`. It can also be parsed and
+//! written on its own, so tools can inspect or build streams without instantiating the full
+//! reader/writer.
+//!
+//! Two layouts are supported. The layout is selected by the `Format` of the header.
+//!
+//! `Format::V1` (current) starts with a magic and a version byte:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |      MAGIC      |   |     VERSION     |   |   CHUNK SIZE    |   |   AES KEY LEN   |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |    "CRYPTO"     |   |       u8        |   |     u32 BE      |   |     u16 BE      |   ...
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        6        |   |        1        |   |        4        |   |        2        |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!     |     AES Key     |   |    AES NONCE    |   |  EXTENSION LEN  |   |   EXTENSIONS    |
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ... |     RSA Enc     |   |                 |   |     u16 BE      |   |  (tag, len, v)  |
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!     |   AES KEY LEN   |   |  AES NONCE LEN  |   |        2        |   |  EXTENSION LEN  |
+//!     +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The extensions hold optional fields. Each extension is encoded as a `u8` tag, a `u16 BE` length
+//! and the value. Unknown extensions are rejected, as they may change the way the stream must be
+//! decrypted.
+//!
+//! | Tag | Field          | Value                                        |
+//! |-----|----------------|----------------------------------------------|
+//! | 1   | `padding`      | `u8` (see `Padding`)                         |
+//! | 2   | `wrapped_keys` | Additional wrapped keys, each `u16 BE` + key |
+//! | 3   | `chunking`     | `u8` (see `Chunking`)                        |
+//! | 4   | `end_marker`   | Empty                                        |
+//! | 5   | `key_check`    | AES-GCM tag (16 bytes)                       |
+//! | 6   | `commitment`   | SHA-256 hash (32 bytes)                      |
+//! | 7   | `key_wrap`     | `u8` (see `KeyWrap`)                         |
+//! | 8   | `cipher`       | `u8` (see `Cipher`)                          |
+//! | 9   | `slot_kems`    | One `u8` per wrapped key (see `SlotKem`)     |
+//! | 10  | `chained`      | Empty                                        |
+//! | 11  | `session`      | Session ID (16 bytes) + stream ID (`u64 BE`) |
+//! | 12  | `summary`      | Chunks, length (`u64 BE`) + SHA-256 hash     |
+//! | 13  | `metadata`     | Empty                                        |
+//!
+//! `Format::V0` (legacy) is the unversioned layout used before the header was versioned:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+
+//! |     AES Key     |   |    AES NONCE    |
+//! +-----------------+   +-----------------+
+//! |     RSA Enc     |   |                 |
+//! +-----------------+   +-----------------+
+//! |   AES KEY LEN   |   |  AES NONCE LEN  |
+//! +-----------------+   +-----------------+
+//! ```
+//!
+//! A `V0` header is detected by the absence of the magic. (The probability for a RSA encrypted key
+//! to start with the magic is 2^-48)
+use super::{
+    cipher::Cipher,
+    error::{error, Result},
+    io,
+    kem::KEM_ID_MIN,
+    key_wrap::KeyWrap,
+    padding::Padding,
+    shared::{AES_AUTH_TAG_LEN, AES_KEY_LEN, AES_NONCE_LEN, HEADER_DIGEST_LEN, KEY_COMMITMENT_LEN},
+};
+use alloc::{vec, vec::Vec};
+use sha2::{Digest as _, Sha256};
+
+/// The magic written at the beginning of a versioned header.
+const MAGIC: [u8; 6] = *b"CRYPTO";
+
+/// Extension tags.
+const EXT_PADDING: u8 = 1;
+const EXT_RECIPIENTS: u8 = 2;
+const EXT_CHUNKING: u8 = 3;
+const EXT_END_MARKER: u8 = 4;
+const EXT_KEY_CHECK: u8 = 5;
+const EXT_COMMITMENT: u8 = 6;
+const EXT_KEY_WRAP: u8 = 7;
+const EXT_CIPHER: u8 = 8;
+const EXT_SLOT_KEMS: u8 = 9;
+const EXT_CHAINED: u8 = 10;
+const EXT_SESSION: u8 = 11;
+const EXT_SUMMARY: u8 = 12;
+const EXT_METADATA: u8 = 13;
+
+/// The length of the value of the summary extension.
+const SUMMARY_LEN: usize = 8 + 8 + 32;
+
+/// The format version of an encrypted stream.
+///
+/// Formats are ordered, so callers can require a minimum version. (e.g. `format >= Format::V1`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Format {
+    /// Unversioned layout: the wrapped key followed by the nonce.
+    V0 = 0,
+    /// Versioned layout: magic, version, chunk size, wrapped key, nonce and extensions.
+    V1 = 1,
+}
+
+impl Format {
+    /// The format written by `CryptoWriter`.
+    pub const LATEST: Format = Format::V1;
+}
+
+impl TryFrom<u8> for Format {
+    type Error = io::Error;
+
+    fn try_from(version: u8) -> Result<Self> {
+        match version {
+            1 => Ok(Format::V1),
+            // `V0` has no version byte
+            _ => Err(error!(
+                InvalidData,
+                "Unsupported format version: {}", version
+            )),
+        }
+    }
+}
+
+/// The way the plaintext is cut into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Chunking {
+    /// Chunks of `chunk_size` bytes. (Read with `CryptoReader`)
+    #[default]
+    Fixed,
+    /// Content-defined chunks of at most `chunk_size` bytes, encrypted with the stream key.
+    /// (Read with `cdc::CdcReader`)
+    ContentDefined,
+    /// Content-defined chunks of at most `chunk_size` bytes, each encrypted with a key derived
+    /// from its content. (Read with `cdc::CdcReader`)
+    Convergent,
+    /// One length-prefixed chunk per message, of at most `chunk_size` bytes. (Read with
+    /// `message::MessageReader`)
+    Message,
+    /// Blocks of `chunk_size` bytes, each encrypted with its own nonce, to be read and written
+    /// in place. (Read with `file::EncryptedFile`)
+    RandomAccess,
+}
+
+impl Chunking {
+    /// The reader of the streams using this chunking.
+    pub(crate) fn reader(self) -> &'static str {
+        match self {
+            Chunking::Fixed => "CryptoReader",
+            Chunking::ContentDefined | Chunking::Convergent => "cdc::CdcReader",
+            Chunking::Message => "message::MessageReader",
+            Chunking::RandomAccess => "file::EncryptedFile",
+        }
+    }
+}
+
+impl From<Chunking> for u8 {
+    fn from(chunking: Chunking) -> Self {
+        match chunking {
+            Chunking::Fixed => 0,
+            Chunking::ContentDefined => 1,
+            Chunking::Convergent => 2,
+            Chunking::Message => 3,
+            Chunking::RandomAccess => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for Chunking {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Chunking::Fixed),
+            1 => Ok(Chunking::ContentDefined),
+            2 => Ok(Chunking::Convergent),
+            3 => Ok(Chunking::Message),
+            4 => Ok(Chunking::RandomAccess),
+            _ => Err(error!(InvalidData, "Unsupported chunking: {}", value)),
+        }
+    }
+}
+
+/// The key encapsulation of a wrapped key slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SlotKem {
+    /// The AES key is encrypted with the RSA key. (See `KeyWrap`)
+    #[default]
+    Rsa,
+    /// The AES key is encrypted with a key derived from both a RSA wrapped secret and a
+    /// ML-KEM-768 shared secret, so the slot stays confidential if either is broken. (See
+    /// `pq`, requires the `ml-kem` feature to be decrypted)
+    RsaMlKem768,
+    /// The AES key is encrypted with a secret encapsulated by the `Kem` of the given identifier.
+    /// (At least `KEM_ID_MIN`, see `kem`)
+    Kem(u8),
+}
+
+impl From<SlotKem> for u8 {
+    fn from(kem: SlotKem) -> Self {
+        match kem {
+            SlotKem::Rsa => 0,
+            SlotKem::RsaMlKem768 => 1,
+            SlotKem::Kem(id) => id,
+        }
+    }
+}
+
+impl From<u8> for SlotKem {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => SlotKem::Rsa,
+            1 => SlotKem::RsaMlKem768,
+            id => SlotKem::Kem(id),
+        }
+    }
+}
+
+/// The length of a session ID in bytes.
+pub(crate) const SESSION_ID_LEN: usize = 16;
+
+/// The session of a stream. (See `session::SessionCache`)
+///
+/// The streams of a session share a master key, wrapped in the first stream only. The key of
+/// each stream is derived from the master key and the stream ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Session {
+    /// The random identifier of the session.
+    pub id: [u8; SESSION_ID_LEN],
+    /// The index of the stream in the session. (The first stream is `0`)
+    pub stream: u64,
+}
+
+/// The summary of the chunks of a stream, only known once the stream is finished. (See
+/// `CryptoWriter::new_seekable`)
+///
+/// The summary is written in clear, and only describes the encrypted chunks: it can be checked
+/// without the private key, e.g. to detect a truncated or corrupted copy of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Summary {
+    /// The number of chunks, the final chunk included.
+    pub chunks: u64,
+    /// The length of the chunks in bytes. (The stream without the header)
+    pub ciphertext_len: u64,
+    /// The SHA-256 hash of the chunks.
+    pub digest: [u8; 32],
+}
+
+/// The header of an encrypted stream.
+///
+/// The header holds the RSA encrypted AES key (one slot per recipient) and the initial AES
+/// nonce. Versioned headers also hold the chunk size used to write the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// The format of the stream.
+    pub format: Format,
+    /// The size of the plaintext chunks. (`None` for `Format::V0` streams)
+    pub chunk_size: Option<u32>,
+    /// The AES key, encrypted with the RSA public key of each recipient. (At least one, exactly
+    /// one for `Format::V0`)
+    pub wrapped_keys: Vec<Vec<u8>>,
+    /// The nonce used to encrypt the first chunk. (Incremented for each chunk)
+    pub nonce: [u8; AES_NONCE_LEN],
+    /// The padding applied to the final chunk. (Always `Padding::None` for `Format::V0`)
+    pub padding: Padding,
+    /// The way the plaintext is cut into chunks. (Always `Chunking::Fixed` for `Format::V0`)
+    pub chunking: Chunking,
+    /// Whether the final chunk is marked as such (authenticated as additional data), so truncated
+    /// or extended streams are detected. (Always `false` for `Format::V0`)
+    pub end_marker: bool,
+    /// Whether each chunk authenticates the tag of the previous chunk as additional data, so a
+    /// modified, removed or reordered chunk breaks the chain. (See `WriterOptions::chain_tags`,
+    /// always `false` for `Format::V0`)
+    pub chained: bool,
+    /// The key check value of the AES key, to detect a wrong key (or a corrupted header) before
+    /// decrypting any chunk. (Always `None` for `Format::V0`)
+    pub key_check: Option<[u8; AES_AUTH_TAG_LEN]>,
+    /// The commitment to the AES key. AES-GCM is not key-committing: without it, a ciphertext can
+    /// be crafted to decrypt validly under two different keys. (e.g. one per recipient) (Always
+    /// `None` for `Format::V0`)
+    pub commitment: Option<[u8; KEY_COMMITMENT_LEN]>,
+    /// The RSA encryption scheme of the wrapped keys. (Always `KeyWrap::Pkcs1v15` for
+    /// `Format::V0`)
+    pub key_wrap: KeyWrap,
+    /// The cipher encrypting the chunks. (Always `Cipher::Aes256Gcm` for `Format::V0`)
+    pub cipher: Cipher,
+    /// The key encapsulation of each wrapped key, in the same order. (Empty if every slot is
+    /// `SlotKem::Rsa`, always empty for `Format::V0`)
+    pub slot_kems: Vec<SlotKem>,
+    /// The session of the stream: the wrapped key is the master key of the session, and the
+    /// chunks are encrypted with a key derived from it. (See `session`, always `None` for
+    /// `Format::V0`)
+    pub session: Option<Session>,
+    /// The summary of the chunks, backfilled once the stream is finished. (See
+    /// `CryptoWriter::new_seekable`, always `None` for `Format::V0`)
+    pub summary: Option<Summary>,
+    /// Whether the plaintext starts with the encrypted metadata of the stream. (See `metadata`,
+    /// always `false` for `Format::V0`)
+    pub metadata: bool,
+}
+
+impl Header {
+    /// Create a header without any extension. (The layout of a `Format::V0` header)
+    ///
+    /// The other fields are set with the struct update syntax, e.g.
+    /// `Header { end_marker: true, ..Header::new(Format::V1, wrapped_keys, nonce) }`.
+    ///
+    /// # Arguments
+    /// - `format`: The format of the stream.
+    /// - `wrapped_keys`: The AES key, encrypted with the RSA public key of each recipient.
+    /// - `nonce`: The nonce used to encrypt the first chunk.
+    ///
+    pub fn new(format: Format, wrapped_keys: Vec<Vec<u8>>, nonce: [u8; AES_NONCE_LEN]) -> Self {
+        Self {
+            format,
+            chunk_size: None,
+            wrapped_keys,
+            nonce,
+            padding: Padding::None,
+            chunking: Chunking::Fixed,
+            end_marker: false,
+            chained: false,
+            key_check: None,
+            commitment: None,
+            key_wrap: KeyWrap::Pkcs1v15,
+            cipher: Cipher::Aes256Gcm,
+            slot_kems: Vec::new(),
+            session: None,
+            summary: None,
+            metadata: false,
+        }
+    }
+
+    /// Parse a header from the given reader.
+    /// The format is detected from the data: `Format::V1` if the magic is present, `Format::V0`
+    /// otherwise.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader to parse the header from.
+    ///
+    /// # Returns
+    /// The parsed `Header`.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the version is unsupported, or the header is malformed. (e.g. a
+    ///   repeated extension)
+    /// - `Io`: If an I/O error occurs. (`UnexpectedEof` if the stream is too short)
+    ///
+    /// # Notes
+    /// Exactly `Header::len` bytes are consumed from the reader.
+    ///
+    pub fn parse<R: io::Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            // Legacy layout, the magic is the beginning of the wrapped key
+            let mut wrapped_key = vec![0; AES_KEY_LEN];
+            wrapped_key[..MAGIC.len()].copy_from_slice(&magic);
+            reader.read_exact(&mut wrapped_key[MAGIC.len()..])?;
+
+            let mut nonce = [0; AES_NONCE_LEN];
+            reader.read_exact(&mut nonce)?;
+
+            return Ok(Self::new(Format::V0, vec![wrapped_key], nonce));
+        }
+
+        let format = Format::try_from(read_array::<_, 1>(reader)?[0])?;
+        let chunk_size = u32::from_be_bytes(read_array(reader)?);
+
+        let mut wrapped_key = vec![0; u16::from_be_bytes(read_array(reader)?) as usize];
+        reader.read_exact(&mut wrapped_key)?;
+
+        let nonce = read_array(reader)?;
+
+        let mut extensions = vec![0; u16::from_be_bytes(read_array(reader)?) as usize];
+        reader.read_exact(&mut extensions)?;
+
+        let mut header = Self {
+            chunk_size: Some(chunk_size),
+            ..Self::new(format, vec![wrapped_key], nonce)
+        };
+        header.parse_extensions(&extensions)?;
+        Ok(header)
+    }
+
+    /// Write the header to the given writer, using the layout of `self.format`.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the header to.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If a field can't be represented in the format.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn write<W: io::Write>(&self, writer: &mut W) -> Result<()> {
+        let (wrapped_key, additional_keys) = self
+            .wrapped_keys
+            .split_first()
+            .ok_or_else(|| error!(InvalidInput, "Header requires a wrapped key"))?;
+
+        match self.format {
+            Format::V0 => {
+                if !additional_keys.is_empty() {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header supports a single recipient"
+                    ))?;
+                }
+                if wrapped_key.len() != AES_KEY_LEN {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header requires a 2048 bits RSA key"
+                    ))?;
+                }
+                if self.padding != Padding::None {
+                    Err(error!(InvalidInput, "V0 header does not support padding"))?;
+                }
+                if self.chunking != Chunking::Fixed {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header only supports fixed chunking"
+                    ))?;
+                }
+                if self.end_marker {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header does not support end markers"
+                    ))?;
+                }
+                if self.chained {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header does not support chained chunks"
+                    ))?;
+                }
+                if self.key_check.is_some() {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header does not support key checks"
+                    ))?;
+                }
+                if self.commitment.is_some() {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header does not support key commitments"
+                    ))?;
+                }
+                if self.key_wrap != KeyWrap::Pkcs1v15 {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header only supports PKCS#1 v1.5 key wraps"
+                    ))?;
+                }
+                if self.cipher != Cipher::Aes256Gcm {
+                    Err(error!(InvalidInput, "V0 header only supports AES-256-GCM"))?;
+                }
+                if !self.slot_kems.is_empty() {
+                    Err(error!(
+                        InvalidInput,
+                        "V0 header only supports RSA key slots"
+                    ))?;
+                }
+                if self.session.is_some() {
+                    Err(error!(InvalidInput, "V0 header does not support sessions"))?;
+                }
+                if self.summary.is_some() {
+                    Err(error!(InvalidInput, "V0 header does not support summaries"))?;
+                }
+                if self.metadata {
+                    Err(error!(InvalidInput, "V0 header does not support metadata"))?;
+                }
+                writer.write_all(wrapped_key)?;
+                writer.write_all(&self.nonce)?;
+            }
+            Format::V1 => {
+                let chunk_size = self
+                    .chunk_size
+                    .ok_or_else(|| error!(InvalidInput, "V1 header requires a chunk size"))?;
+                let key_len = u16::try_from(wrapped_key.len())
+                    .map_err(|_| error!(InvalidInput, "Wrapped key is too long"))?;
+                let extensions = self.extensions()?;
+
+                writer.write_all(&MAGIC)?;
+                writer.write_all(&[self.format as u8])?;
+                writer.write_all(&chunk_size.to_be_bytes())?;
+                writer.write_all(&key_len.to_be_bytes())?;
+                writer.write_all(wrapped_key)?;
+                writer.write_all(&self.nonce)?;
+                writer.write_all(&(extensions.len() as u16).to_be_bytes())?;
+                writer.write_all(&extensions)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The key encapsulation of the wrapped key at `index`.
+    pub fn slot_kem(&self, index: usize) -> SlotKem {
+        self.slot_kems.get(index).copied().unwrap_or_default()
+    }
+
+    /// The length of the encoded header in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        match self.format {
+            Format::V0 => self.wrapped_keys.iter().map(Vec::len).sum::<usize>() + self.nonce.len(),
+            Format::V1 => {
+                MAGIC.len()
+                    + 1
+                    + 4
+                    + 2
+                    + self.wrapped_keys.first().map_or(0, Vec::len)
+                    + self.nonce.len()
+                    + 2
+                    + self.extensions().map_or(0, |extensions| extensions.len())
+            }
+        }
+    }
+
+    /// The SHA-256 hash of the header, authenticated by every chunk of a `Format::V1` stream, so a
+    /// modified header (e.g. a removed end marker) fails the first chunk. (`None` for
+    /// `Format::V0`)
+    ///
+    /// The key slots (`wrapped_keys`, `slot_kems` and `key_wrap`) are left out: a slot only
+    /// decrypts to a key passing the key check value and the commitment, and the same chunks can
+    /// be given to other recipients. (e.g. `convergent`) The summary is hashed as
+    /// `Summary::default()`: it is backfilled once the chunks are written (see
+    /// `CryptoWriter::new_seekable`), and checked against the chunks by the reader.
+    ///
+    /// # Errors
+    /// The errors of `write`.
+    ///
+    pub(crate) fn digest(&self) -> Result<Option<[u8; HEADER_DIGEST_LEN]>> {
+        if self.format == Format::V0 {
+            return Ok(None);
+        }
+        let mut bytes = Vec::with_capacity(self.len());
+        Self {
+            wrapped_keys: vec![Vec::new()],
+            slot_kems: Vec::new(),
+            key_wrap: KeyWrap::default(),
+            summary: self.summary.map(|_| Summary::default()),
+            ..self.clone()
+        }
+        .write(&mut bytes)?;
+        Ok(Some(Sha256::digest(&bytes).into()))
+    }
+
+    /// Encode the optional fields as extensions.
+    fn extensions(&self) -> Result<Vec<u8>> {
+        let mut extensions = Vec::new();
+        if self.padding != Padding::None {
+            push_extension(&mut extensions, EXT_PADDING, &[self.padding.into()])?;
+        }
+        if self.wrapped_keys.len() > 1 {
+            let mut value = Vec::new();
+            for wrapped_key in &self.wrapped_keys[1..] {
+                let key_len = u16::try_from(wrapped_key.len())
+                    .map_err(|_| error!(InvalidInput, "Wrapped key is too long"))?;
+                value.extend_from_slice(&key_len.to_be_bytes());
+                value.extend_from_slice(wrapped_key);
+            }
+            push_extension(&mut extensions, EXT_RECIPIENTS, &value)?;
+        }
+        if self.chunking != Chunking::Fixed {
+            push_extension(&mut extensions, EXT_CHUNKING, &[self.chunking.into()])?;
+        }
+        if self.end_marker {
+            push_extension(&mut extensions, EXT_END_MARKER, &[])?;
+        }
+        if self.chained {
+            push_extension(&mut extensions, EXT_CHAINED, &[])?;
+        }
+        if let Some(key_check) = &self.key_check {
+            push_extension(&mut extensions, EXT_KEY_CHECK, key_check)?;
+        }
+        if let Some(commitment) = &self.commitment {
+            push_extension(&mut extensions, EXT_COMMITMENT, commitment)?;
+        }
+        if self.key_wrap != KeyWrap::Pkcs1v15 {
+            push_extension(&mut extensions, EXT_KEY_WRAP, &[self.key_wrap.into()])?;
+        }
+        if self.cipher != Cipher::Aes256Gcm {
+            push_extension(&mut extensions, EXT_CIPHER, &[self.cipher.into()])?;
+        }
+        if !self.slot_kems.is_empty() {
+            if self.slot_kems.len() != self.wrapped_keys.len() {
+                Err(error!(
+                    InvalidInput,
+                    "Header has {} slot KEMs for {} wrapped keys",
+                    self.slot_kems.len(),
+                    self.wrapped_keys.len()
+                ))?;
+            }
+            if let Some(SlotKem::Kem(id)) = self
+                .slot_kems
+                .iter()
+                .find(|kem| matches!(kem, SlotKem::Kem(id) if *id < KEM_ID_MIN))
+            {
+                Err(error!(InvalidInput, "KEM identifier {} is reserved", id))?;
+            }
+            let value: Vec<u8> = self.slot_kems.iter().map(|&kem| kem.into()).collect();
+            push_extension(&mut extensions, EXT_SLOT_KEMS, &value)?;
+        }
+        if let Some(session) = &self.session {
+            let mut value = session.id.to_vec();
+            value.extend_from_slice(&session.stream.to_be_bytes());
+            push_extension(&mut extensions, EXT_SESSION, &value)?;
+        }
+        if let Some(summary) = &self.summary {
+            let mut value = Vec::with_capacity(SUMMARY_LEN);
+            value.extend_from_slice(&summary.chunks.to_be_bytes());
+            value.extend_from_slice(&summary.ciphertext_len.to_be_bytes());
+            value.extend_from_slice(&summary.digest);
+            push_extension(&mut extensions, EXT_SUMMARY, &value)?;
+        }
+        if self.metadata {
+            push_extension(&mut extensions, EXT_METADATA, &[])?;
+        }
+        if extensions.len() > u16::MAX as usize {
+            Err(error!(InvalidInput, "Header extensions are too long"))?;
+        }
+        Ok(extensions)
+    }
+
+    /// Decode the extensions into the optional fields.
+    fn parse_extensions(&mut self, mut data: &[u8]) -> Result<()> {
+        // Each tag appears once, so a header has a single encoding
+        let mut seen = [false; 256];
+        while !data.is_empty() {
+            if data.len() < 3 {
+                Err(error!(InvalidData, "Truncated header extension"))?;
+            }
+            let tag = data[0];
+            let len = u16::from_be_bytes([data[1], data[2]]) as usize;
+            let value = data
+                .get(3..3 + len)
+                .ok_or_else(|| error!(InvalidData, "Truncated header extension"))?;
+            data = &data[3 + len..];
+            if core::mem::replace(&mut seen[tag as usize], true) {
+                Err(error!(InvalidData, "Duplicate header extension: {}", tag))?;
+            }
+
+            match (tag, value) {
+                (EXT_PADDING, [padding]) => self.padding = Padding::try_from(*padding)?,
+                (EXT_CHUNKING, [chunking]) => self.chunking = Chunking::try_from(*chunking)?,
+                (EXT_KEY_WRAP, [key_wrap]) => self.key_wrap = KeyWrap::try_from(*key_wrap)?,
+                (EXT_CIPHER, [cipher]) => self.cipher = Cipher::try_from(*cipher)?,
+                (EXT_END_MARKER, []) => self.end_marker = true,
+                (EXT_CHAINED, []) => self.chained = true,
+                (EXT_METADATA, []) => self.metadata = true,
+                (EXT_KEY_CHECK, key_check) if key_check.len() == AES_AUTH_TAG_LEN => {
+                    self.key_check = Some(key_check.try_into().expect("length is checked"))
+                }
+                (EXT_COMMITMENT, commitment) if commitment.len() == KEY_COMMITMENT_LEN => {
+                    self.commitment = Some(commitment.try_into().expect("length is checked"))
+                }
+                (EXT_SESSION, value) if value.len() == SESSION_ID_LEN + 8 => {
+                    let (id, stream) = value.split_at(SESSION_ID_LEN);
+                    self.session = Some(Session {
+                        id: id.try_into().expect("length is checked"),
+                        stream: u64::from_be_bytes(stream.try_into().expect("length is checked")),
+                    })
+                }
+                (EXT_SUMMARY, value) if value.len() == SUMMARY_LEN => {
+                    let (chunks, value) = value.split_at(8);
+                    let (ciphertext_len, digest) = value.split_at(8);
+                    self.summary = Some(Summary {
+                        chunks: u64::from_be_bytes(chunks.try_into().expect("length is checked")),
+                        ciphertext_len: u64::from_be_bytes(
+                            ciphertext_len.try_into().expect("length is checked"),
+                        ),
+                        digest: digest.try_into().expect("length is checked"),
+                    })
+                }
+                (EXT_SLOT_KEMS, value) => {
+                    self.slot_kems = value.iter().map(|&kem| SlotKem::from(kem)).collect()
+                }
+                (EXT_RECIPIENTS, mut value) => {
+                    while !value.is_empty() {
+                        let key_len = match value {
+                            [a, b, ..] => u16::from_be_bytes([*a, *b]) as usize,
+                            _ => Err(error!(InvalidData, "Truncated wrapped key"))?,
+                        };
+                        let wrapped_key = value
+                            .get(2..2 + key_len)
+                            .ok_or_else(|| error!(InvalidData, "Truncated wrapped key"))?;
+                        self.wrapped_keys.push(wrapped_key.to_vec());
+                        value = &value[2 + key_len..];
+                    }
+                }
+                _ => Err(error!(InvalidData, "Unsupported header extension: {}", tag))?,
+            }
+        }
+        if !self.slot_kems.is_empty() && self.slot_kems.len() != self.wrapped_keys.len() {
+            Err(error!(
+                InvalidData,
+                "Header has {} slot KEMs for {} wrapped keys",
+                self.slot_kems.len(),
+                self.wrapped_keys.len()
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+fn push_extension(extensions: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| error!(InvalidInput, "Header extension {} is too long", tag))?;
+    extensions.push(tag);
+    extensions.extend_from_slice(&len.to_be_bytes());
+    extensions.extend_from_slice(value);
+    Ok(())
+}
+
+fn read_array<R: io::Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut buffer = [0; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
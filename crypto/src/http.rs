@@ -0,0 +1,153 @@
+//! This module wires the streams into HTTP services built on `http_body` (hyper, axum, ...).
+//! (`http` feature)
+//!
+//! - `CryptoBody` wraps a `CryptoReader` as a `http_body::Body` of the plaintext, one frame per
+//!   read. (e.g. to serve the decrypted file of a download)
+//! - `encrypt_body` consumes a body, and writes it to a `CryptoWriter`. (e.g. to store an upload
+//!   encrypted)
+//!
+//! ```ignore
+//! // Download
+//! let body = CryptoBody::new(CryptoReader::<_, 4096>::new(File::open(path)?, private_key)?);
+//! // Upload
+//! let len = encrypt_body(request.into_body(), CryptoWriter::<_, 4096>::new(file, public_key)?).await?;
+//! ```
+//!
+//! **Warning**: The reader and the writer are blocking. (`std::io`) They are only called for one
+//! chunk at a time, which is fine for local files, but a slow source or destination blocks the
+//! executor: use a blocking task (e.g. `tokio::task::spawn_blocking`) for them.
+use super::{decrypt::CryptoReader, encrypt::CryptoWriter, error::Result};
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
+use std::{
+    future::poll_fn,
+    io::{Read, Write},
+    pin::{pin, Pin},
+    task::{Context, Poll},
+};
+
+/// A `http_body::Body` of the plaintext of a `CryptoReader`.
+///
+/// Each frame holds the data of a `read` call on the reader, up to `BUFFER_SIZE` bytes. The
+/// body ends at the end of the stream, and its errors are the errors of `read`. (e.g.
+/// `Error::CorruptedChunk` for a tampered chunk, after the frames of the previous chunks)
+pub struct CryptoBody<R: Read, const BUFFER_SIZE: usize> {
+    reader: CryptoReader<R, BUFFER_SIZE>,
+    /// Whether the end of the stream was read.
+    finished: bool,
+}
+
+impl<R: Read, const BUFFER_SIZE: usize> CryptoBody<R, BUFFER_SIZE> {
+    /// Create a new `CryptoBody` instance, reading the plaintext from the reader.
+    pub fn new(reader: CryptoReader<R, BUFFER_SIZE>) -> Self {
+        Self {
+            reader,
+            finished: false,
+        }
+    }
+
+    /// Get the inner reader back.
+    pub fn into_inner(self) -> CryptoReader<R, BUFFER_SIZE> {
+        self.reader
+    }
+}
+
+impl<R: Read, const BUFFER_SIZE: usize> From<CryptoReader<R, BUFFER_SIZE>>
+    for CryptoBody<R, BUFFER_SIZE>
+{
+    fn from(reader: CryptoReader<R, BUFFER_SIZE>) -> Self {
+        Self::new(reader)
+    }
+}
+
+impl<R: Read + Unpin, const BUFFER_SIZE: usize> Body for CryptoBody<R, BUFFER_SIZE> {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>>>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(None);
+        }
+        let mut data = vec![0; BUFFER_SIZE];
+        match this.reader.read(&mut data) {
+            Ok(0) => {
+                this.finished = true;
+                Poll::Ready(None)
+            }
+            Ok(read) => {
+                data.truncate(read);
+                Poll::Ready(Some(Ok(Frame::data(Bytes::from(data)))))
+            }
+            Err(e) => {
+                // The stream can't be trusted after a failed chunk
+                this.finished = true;
+                Poll::Ready(Some(Err(e)))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.finished {
+            SizeHint::with_exact(0)
+        } else {
+            SizeHint::default()
+        }
+    }
+}
+
+/// Encrypt a body: write its data to the writer, then end the stream.
+///
+/// # Arguments
+/// - `body`: The body of the plaintext. (e.g. the body of an upload request)
+/// - `writer`: The writer encrypting the data. (e.g. to a file)
+///
+/// # Returns
+/// The length of the plaintext.
+///
+/// # Errors
+/// - `Other`: If the body fails. (With the error of the body as source)
+/// - `Io`: If an I/O error occurs.
+///
+/// # Notes
+/// The trailers of the body are ignored. If the body fails, the stream is left without its final
+/// chunk (so it is rejected as truncated), and must be discarded.
+///
+pub async fn encrypt_body<B, W: Write, const BUFFER_SIZE: usize>(
+    body: B,
+    mut writer: CryptoWriter<W, BUFFER_SIZE>,
+) -> Result<u64>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut body = pin!(body);
+    let mut len = 0;
+    while let Some(frame) = poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                writer.abandon();
+                return Err(std::io::Error::other(e));
+            }
+        };
+        let Ok(mut data) = frame.into_data() else {
+            continue;
+        };
+        while data.has_remaining() {
+            let chunk_len = data.chunk().len();
+            writer.write_all(data.chunk())?;
+            data.advance(chunk_len);
+            len += chunk_len as u64;
+        }
+    }
+    writer.flush()?;
+    Ok(len)
+}
@@ -0,0 +1,97 @@
+//! This module provides the `Recipient` and `Identity` types, taken by the constructors of
+//! `CryptoWriter` and `CryptoReader`, and of the other writers and readers. (e.g. `CdcWriter`,
+//! `MessageReader` or `EncryptedFile`)
+//!
+//! A `Recipient` is the public side of a key: the stream is encrypted for it. An `Identity` is the
+//! private side: it decrypts the streams of its recipient. Both are RSA keys for now, and convert
+//! from the `rsa` types, so the constructors still take a `RsaPublicKey` or a `RsaPrivateKey`:
+//!
+//! ```rust,ignore
+//! let writer = CryptoWriter::<_, 4096>::new(file, Recipient::rsa(public_key))?;
+//! let writer = CryptoWriter::<_, 4096>::new(file, public_key)?; // The same
+//! ```
+//!
+//! **Note**: The keys are held opaquely, so other backends (e.g. X25519, a KMS or an HSM) can be
+//! added without changing the constructors. `as_rsa` returns `None` for them.
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+/// The recipient of a stream: the public key wrapping its AES key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient(RecipientKey);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RecipientKey {
+    Rsa(RsaPublicKey),
+}
+
+impl Recipient {
+    /// Create a new `Recipient` instance from a RSA public key.
+    pub fn rsa(key: RsaPublicKey) -> Self {
+        Self(RecipientKey::Rsa(key))
+    }
+
+    /// The RSA public key of the recipient. (`None` for another kind of key)
+    pub fn as_rsa(&self) -> Option<&RsaPublicKey> {
+        match &self.0 {
+            RecipientKey::Rsa(key) => Some(key),
+        }
+    }
+
+    /// The RSA public key, for the streams wrapping the AES key with RSA.
+    pub(crate) fn into_rsa(self) -> RsaPublicKey {
+        match self.0 {
+            RecipientKey::Rsa(key) => key,
+        }
+    }
+}
+
+impl From<RsaPublicKey> for Recipient {
+    fn from(key: RsaPublicKey) -> Self {
+        Self::rsa(key)
+    }
+}
+
+/// The identity decrypting a stream: the private key unwrapping its AES key.
+///
+/// **Warning**: The key is not redacted by `Debug`. (As `RsaPrivateKey`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity(IdentityKey);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum IdentityKey {
+    Rsa(RsaPrivateKey),
+}
+
+impl Identity {
+    /// Create a new `Identity` instance from a RSA private key.
+    pub fn rsa(key: RsaPrivateKey) -> Self {
+        Self(IdentityKey::Rsa(key))
+    }
+
+    /// The RSA private key of the identity. (`None` for another kind of key)
+    pub fn as_rsa(&self) -> Option<&RsaPrivateKey> {
+        match &self.0 {
+            IdentityKey::Rsa(key) => Some(key),
+        }
+    }
+
+    /// The recipient of the identity: the streams encrypted for it are decrypted by the identity.
+    pub fn recipient(&self) -> Recipient {
+        match &self.0 {
+            IdentityKey::Rsa(key) => Recipient::rsa(key.to_public_key()),
+        }
+    }
+
+    /// The RSA private key, for the streams wrapping the AES key with RSA.
+    pub(crate) fn into_rsa(self) -> RsaPrivateKey {
+        match self.0 {
+            IdentityKey::Rsa(key) => key,
+        }
+    }
+}
+
+impl From<RsaPrivateKey> for Identity {
+    fn from(key: RsaPrivateKey) -> Self {
+        Self::rsa(key)
+    }
+}
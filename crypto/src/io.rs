@@ -0,0 +1,197 @@
+//! This module provides the I/O traits used by `CryptoWriter` and `CryptoReader`.
+//!
+//! With the `std` feature (default), it re-exports `std::io`. Without it, it provides a minimal
+//! `no_std` equivalent: the `Read` and `Write` traits, and the `Error` and `ErrorKind` types, so the
+//! writer and the reader can run on embedded targets. (Only `alloc` is required)
+//!
+//! On such targets, implement `Read` or `Write` for the transport (UART, flash storage, ...), or
+//! use the implementations for `&[u8]` and `Vec<u8>`.
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_io::{Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod core_io {
+    use alloc::{
+        string::{String, ToString as _},
+        vec::Vec,
+    };
+
+    /// A specialized `Result` type for I/O operations.
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// The kind of an I/O error. (A subset of `std::io::ErrorKind`)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// The data is not valid. (Malformed header, corrupted chunk, ...)
+        InvalidData,
+        /// A parameter was incorrect.
+        InvalidInput,
+        /// The data ended before the expected length.
+        UnexpectedEof,
+        /// The writer accepted no data.
+        WriteZero,
+        /// The operation was interrupted, and can be retried.
+        Interrupted,
+        /// The operation would block, and can be retried later.
+        WouldBlock,
+        /// The operation isn't supported. (e.g. by a `Kem` backend)
+        Unsupported,
+        /// Any other error.
+        Other,
+    }
+
+    /// The error type of the I/O operations.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: String,
+        error: Option<crate::Error>,
+    }
+
+    impl Error {
+        /// Create a new error from a kind and a message.
+        pub fn new<M: core::fmt::Display>(kind: ErrorKind, message: M) -> Self {
+            Self {
+                kind,
+                message: message.to_string(),
+                error: None,
+            }
+        }
+
+        /// The kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+
+        /// The typed error wrapped in this error, if any. (See `crate::Error::from_io`)
+        pub(crate) fn typed(&self) -> Option<&crate::Error> {
+            self.error.as_ref()
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Self::new(kind, "I/O error")
+        }
+    }
+
+    impl From<crate::Error> for Error {
+        fn from(error: crate::Error) -> Self {
+            Self {
+                kind: ErrorKind::InvalidData,
+                message: error.to_string(),
+                error: Some(error),
+            }
+        }
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "{}", self.message)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    /// Read bytes from a source. (The `no_std` subset of `std::io::Read`)
+    pub trait Read {
+        /// Read some bytes into `buf`, and return how many were read. (`0` at the end of the data)
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Read exactly enough bytes to fill `buf`.
+        ///
+        /// # Errors
+        /// - `UnexpectedEof`: If the data ends before `buf` is filled.
+        ///
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => break,
+                    Ok(n) => buf = &mut buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if buf.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+        }
+    }
+
+    /// Write bytes to a sink. (The `no_std` subset of `std::io::Write`)
+    pub trait Write {
+        /// Write some bytes from `buf`, and return how many were written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Flush the buffered data to the sink.
+        fn flush(&mut self) -> Result<()>;
+
+        /// Write all the bytes of `buf`.
+        ///
+        /// # Errors
+        /// - `WriteZero`: If the sink accepts no more data.
+        ///
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => {
+                        return Err(Error::new(
+                            ErrorKind::WriteZero,
+                            "failed to write whole buffer",
+                        ))
+                    }
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = core::cmp::min(buf.len(), self.len());
+            let (left, right) = self.split_at(len);
+            buf[..len].copy_from_slice(left);
+            *self = right;
+            Ok(len)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! This module provides JWE compact serialization for small payloads.
+//!
+//! The payload is encrypted using `A256GCM` (AES-256-GCM) with a randomly generated content
+//! encryption key. The content encryption key is wrapped using `RSA-OAEP` (SHA-1 / MGF1-SHA-1,
+//! as specified by RFC 7518). The same `RsaKeys` used by `CryptoWriter` and `CryptoReader` can be
+//! used here, so a single key pair covers both the stream format and JWE tokens.
+//!
+//! The token has the following layout (RFC 7516, section 7.1):
+//!
+//! ```plaintext
+//! BASE64URL(Header) . BASE64URL(Encrypted Key) . BASE64URL(IV) . BASE64URL(Ciphertext) . BASE64URL(Tag)
+//! ```
+//!
+//! The protected header is used as additional authenticated data, so it can't be altered without
+//! the decryption failing.
+//!
+//! **Note**: The whole payload is held in memory. This mode is intended for small payloads
+//! (tokens, secrets, short messages). Use `CryptoWriter` and `CryptoReader` for large data.
+use super::{
+    error::{error, Result},
+    shared::{setup_rng, AES_AUTH_TAG_LEN, AES_NONCE_LEN},
+};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    AeadCore as _, Aes256Gcm, Key, KeyInit as _, Nonce,
+};
+use base64ct::{Base64UrlUnpadded, Encoding as _};
+use rand::{CryptoRng, RngCore};
+use rsa::{pkcs8::der::zeroize::Zeroizing, Oaep, RsaPrivateKey, RsaPublicKey};
+
+/// Key management algorithm, as registered in RFC 7518.
+const ALG: &str = "RSA-OAEP";
+/// Content encryption algorithm, as registered in RFC 7518.
+const ENC: &str = "A256GCM";
+
+/// Encrypt the payload as a JWE compact token.
+///
+/// # Arguments
+/// - `payload`: The data to encrypt.
+/// - `key`: The RSA public key used to wrap the content encryption key.
+///
+/// # Returns
+/// The JWE compact token.
+///
+/// # Errors
+/// - `Other`: If the RSA or AES encryption fails.
+///
+pub fn encrypt(payload: &[u8], key: &RsaPublicKey) -> Result<String> {
+    let mut rng = setup_rng();
+    encrypt_with_rng(payload, key, &mut rng)
+}
+
+/// Encrypt the payload as a JWE compact token with the given random number generator.
+///
+/// # Arguments
+/// - `payload`: The data to encrypt.
+/// - `key`: The RSA public key used to wrap the content encryption key.
+/// - `rng`: The random number generator.
+///
+/// # Returns
+/// The JWE compact token.
+///
+/// # Notes
+/// The random number generator must be cryptographically secure. And should implement the
+/// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+///
+pub fn encrypt_with_rng<R: CryptoRng + RngCore>(
+    payload: &[u8],
+    key: &RsaPublicKey,
+    mut rng: R,
+) -> Result<String> {
+    let header = Base64UrlUnpadded::encode_string(
+        format!(r#"{{"alg":"{}","enc":"{}"}}"#, ALG, ENC).as_bytes(),
+    );
+
+    let cek = Aes256Gcm::generate_key(&mut rng);
+    let iv = Aes256Gcm::generate_nonce(&mut rng);
+
+    let encrypted_key = key
+        .encrypt(&mut rng, Oaep::new::<sha1::Sha1>(), cek.as_slice())
+        .map_err(|e| error!(Other, "RSA Encryption error: {}", e))?;
+
+    let mut ciphertext = Aes256Gcm::new(&cek)
+        .encrypt(
+            &iv,
+            Payload {
+                msg: payload,
+                aad: header.as_bytes(),
+            },
+        )
+        .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+    let tag = ciphertext.split_off(ciphertext.len() - AES_AUTH_TAG_LEN);
+
+    Ok([
+        header,
+        Base64UrlUnpadded::encode_string(&encrypted_key),
+        Base64UrlUnpadded::encode_string(&iv),
+        Base64UrlUnpadded::encode_string(&ciphertext),
+        Base64UrlUnpadded::encode_string(&tag),
+    ]
+    .join("."))
+}
+
+/// Decrypt a JWE compact token.
+///
+/// # Arguments
+/// - `token`: The JWE compact token.
+/// - `key`: The RSA private key used to unwrap the content encryption key.
+///
+/// # Returns
+/// The decrypted payload.
+///
+/// # Errors
+/// - `InvalidData`: If the token is malformed, or uses an unsupported algorithm.
+/// - `Other`: If the RSA or AES decryption fails.
+///
+pub fn decrypt(token: &str, key: &RsaPrivateKey) -> Result<Vec<u8>> {
+    let parts = token.split('.').collect::<Vec<_>>();
+    let [header, encrypted_key, iv, ciphertext, tag] = parts[..] else {
+        Err(error!(
+            InvalidData,
+            "Invalid JWE token: expected 5 parts, found {}",
+            parts.len()
+        ))?
+    };
+
+    check_header(&decode(header)?)?;
+
+    let iv = decode(iv)?;
+    if iv.len() != AES_NONCE_LEN {
+        Err(error!(InvalidData, "Invalid JWE token: bad IV length"))?;
+    }
+    let tag = decode(tag)?;
+    if tag.len() != AES_AUTH_TAG_LEN {
+        Err(error!(InvalidData, "Invalid JWE token: bad tag length"))?;
+    }
+
+    let cek = key
+        .decrypt(Oaep::new::<sha1::Sha1>(), &decode(encrypted_key)?)
+        .map(Zeroizing::new)
+        .map_err(|e| error!(Other, "RSA Decryption error: {}", e))?;
+    if cek.len() != 32 {
+        Err(error!(InvalidData, "Invalid JWE token: bad key length"))?;
+    }
+
+    let mut data = decode(ciphertext)?;
+    data.extend_from_slice(&tag);
+
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&cek))
+        .decrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &data,
+                aad: header.as_bytes(),
+            },
+        )
+        .map_err(|e| error!(Other, "AES Decryption error: {}", e))
+}
+
+/// Decode a part of the token. (Base64url, without padding)
+fn decode(part: &str) -> Result<Vec<u8>> {
+    Base64UrlUnpadded::decode_vec(part).map_err(|e| error!(InvalidData, "Invalid JWE token: {}", e))
+}
+
+/// Check that the protected header only requests supported algorithms.
+fn check_header(raw: &[u8]) -> Result<()> {
+    let header: serde_json::Value = serde_json::from_slice(raw)
+        .map_err(|e| error!(InvalidData, "Invalid JWE header: {}", e))?;
+
+    if header["alg"] != ALG {
+        Err(error!(
+            InvalidData,
+            "Unsupported JWE algorithm: {}", header["alg"]
+        ))?;
+    }
+    if header["enc"] != ENC {
+        Err(error!(
+            InvalidData,
+            "Unsupported JWE encryption: {}", header["enc"]
+        ))?;
+    }
+    if header.get("zip").is_some() || header.get("crit").is_some() {
+        Err(error!(InvalidData, "Unsupported JWE header parameter"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,249 @@
+//! This module provides the `Kem` trait, to plug key encapsulation backends into the header.
+//!
+//! The AES key of a stream is encrypted once per recipient, in a wrapped key slot of the `Header`.
+//! A `Kem` backend (X25519, ML-KEM, a KMS, an HSM, ...) encapsulates a shared secret for its
+//! recipient, and the AES key is encrypted with a key derived from that secret. The slot is
+//! written as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   KEM ENC LEN   |   |     KEM Enc     |   |     AES Key     |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     u16 BE      |   |   (wrapped)     |   |  AES-256-GCM    |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        2        |   |   KEM ENC LEN   |   | AES KEY LEN+16  |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The key encrypting the AES key is `SHA-256(label || KEM id || shared || KEM Enc)`, and the slot
+//! is recorded as `SlotKem::Kem(id)` in the header, so readers pick the matching backend.
+//!
+//! `RsaKem` is the default implementation. The RSA recipients of `CryptoWriter::new` still use
+//! the legacy `SlotKem::Rsa` slots (the AES key is encrypted directly), so the streams stay
+//! readable by older versions.
+#[cfg(feature = "tracing")]
+use super::stats::Timer;
+use super::{
+    error::{error, Result},
+    shared::{Nonce, AES_AUTH_TAG_LEN},
+};
+use aes_gcm::{
+    aead::{rand_core::CryptoRngCore, Aead, Payload},
+    Aes256Gcm, Key, KeyInit as _,
+};
+use alloc::vec::Vec;
+use rsa::{pkcs8::der::zeroize::Zeroizing, Oaep, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest as _, Sha256};
+
+/// Domain separation of the key encrypting the AES key, also used as additional data.
+const KEM_SLOT_LABEL: &[u8] = b"crypto kem slot";
+/// The first identifier available to the `Kem` backends. (`0` and `1` are the built-in slots)
+pub const KEM_ID_MIN: u8 = 2;
+/// The length of the secret encapsulated by `RsaKem`.
+const RSA_KEM_SECRET_LEN: usize = 32;
+
+/// A key encapsulation mechanism, wrapping the AES key for one recipient.
+///
+/// Implementations hold the key (or a handle to it) of the recipient: the public key is enough to
+/// encapsulate, the private key is required to decapsulate.
+pub trait Kem: core::fmt::Debug + Send + Sync {
+    /// The identifier of the mechanism, recorded in the header. (At least `KEM_ID_MIN`)
+    ///
+    /// The identifier must be stable: it selects the backend able to decapsulate the slot.
+    fn id(&self) -> u8;
+
+    /// Generate a shared secret, and encapsulate it for the recipient.
+    ///
+    /// # Arguments
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// The encapsulated secret (written in the header), and the shared secret.
+    ///
+    /// # Errors
+    /// - `Unsupported`: If the backend can't encapsulate.
+    /// - `Other`: If the encapsulation fails.
+    ///
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)>;
+
+    /// Recover the shared secret from the encapsulated secret.
+    ///
+    /// # Arguments
+    /// - `wrapped`: The encapsulated secret, as returned by `encapsulate`.
+    ///
+    /// # Returns
+    /// The shared secret.
+    ///
+    /// # Errors
+    /// - `Unsupported`: If the backend can't decapsulate. (e.g. only the public key is held)
+    /// - `Other`: If the decapsulation fails. (Wrong key, or corrupted slot)
+    ///
+    fn decapsulate(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>>;
+}
+
+/// The RSA `Kem`: a random secret is encrypted with RSAES-OAEP (SHA-256).
+#[derive(Clone)]
+pub struct RsaKem {
+    public_key: RsaPublicKey,
+    private_key: Option<RsaPrivateKey>,
+}
+
+impl RsaKem {
+    /// The identifier of `RsaKem`.
+    pub const ID: u8 = 2;
+
+    /// Create a `RsaKem` able to encapsulate only.
+    pub fn new(public_key: RsaPublicKey) -> Self {
+        Self {
+            public_key,
+            private_key: None,
+        }
+    }
+
+    /// Create a `RsaKem` able to encapsulate and decapsulate.
+    pub fn with_private_key(private_key: RsaPrivateKey) -> Self {
+        Self {
+            public_key: private_key.to_public_key(),
+            private_key: Some(private_key),
+        }
+    }
+}
+
+impl core::fmt::Debug for RsaKem {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RsaKem")
+            .field("public_key", &self.public_key)
+            .field("has_private_key", &self.private_key.is_some())
+            .finish()
+    }
+}
+
+impl Kem for RsaKem {
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        let mut shared = Zeroizing::new(alloc::vec![0; RSA_KEM_SECRET_LEN]);
+        rng.fill_bytes(&mut shared);
+        let wrapped = self
+            .public_key
+            .encrypt(&mut { rng }, Oaep::new::<Sha256>(), &shared)
+            .map_err(|e| error!(Other, "RSA Encryption error: {}", e))?;
+        Ok((wrapped, shared))
+    }
+
+    fn decapsulate(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let private_key = self.private_key.as_ref().ok_or_else(|| {
+            error!(
+                Unsupported,
+                "RsaKem requires the private key to decapsulate"
+            )
+        })?;
+        private_key
+            .decrypt(Oaep::new::<Sha256>(), wrapped)
+            .map(Zeroizing::new)
+            .map_err(|e| error!(Other, "RSA Decryption error: {}", e))
+    }
+}
+
+/// Encrypt the AES key with a single use key. (`kek`)
+pub(crate) fn seal_key(kek: &[u8; 32], aad: &[u8], aes_key: &[u8]) -> Result<Vec<u8>> {
+    // The key is used once, so the nonce can be fixed
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek))
+        .encrypt(&Nonce::default(), Payload { msg: aes_key, aad })
+        .map_err(|e| error!(Other, "AES Encryption error: {}", e))
+}
+
+/// Decrypt the AES key encrypted by `seal_key`.
+pub(crate) fn open_key(
+    kek: &[u8; 32],
+    aad: &[u8],
+    encrypted_key: &[u8],
+) -> Option<Zeroizing<Vec<u8>>> {
+    if encrypted_key.len() < AES_AUTH_TAG_LEN {
+        return None;
+    }
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek))
+        .decrypt(
+            &Nonce::default(),
+            Payload {
+                msg: encrypted_key,
+                aad,
+            },
+        )
+        .ok()
+        .map(Zeroizing::new)
+}
+
+/// Derive the key encrypting the AES key of a `Kem` slot.
+fn derive_kek(id: u8, shared: &[u8], wrapped: &[u8]) -> Zeroizing<[u8; 32]> {
+    Zeroizing::new(
+        Sha256::new()
+            .chain_update(KEM_SLOT_LABEL)
+            .chain_update([id])
+            .chain_update(shared)
+            .chain_update(wrapped)
+            .finalize()
+            .into(),
+    )
+}
+
+/// Encrypt the AES key in a `SlotKem::Kem` slot, for the recipient of `kem`.
+///
+/// # Errors
+/// - `InvalidInput`: If the identifier of `kem` is reserved, or the encapsulated secret is too
+///   long.
+/// - The errors of `Kem::encapsulate`.
+///
+pub(crate) fn wrap_kem_slot(
+    kem: &dyn Kem,
+    aes_key: &[u8],
+    rng: &mut dyn CryptoRngCore,
+) -> Result<Vec<u8>> {
+    let id = kem.id();
+    if id < KEM_ID_MIN {
+        Err(error!(InvalidInput, "KEM identifier {} is reserved", id))?;
+    }
+    #[cfg(feature = "tracing")]
+    let timer = Timer::start();
+    let (wrapped, shared) = kem.encapsulate(rng)?;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(kem = id, elapsed = ?timer.elapsed(), "KEM encapsulate");
+    let wrapped_len = u16::try_from(wrapped.len())
+        .map_err(|_| error!(InvalidInput, "Encapsulated secret is too long"))?;
+    let encrypted_key = seal_key(&derive_kek(id, &shared, &wrapped), KEM_SLOT_LABEL, aes_key)?;
+
+    let mut slot = Vec::with_capacity(2 + wrapped.len() + encrypted_key.len());
+    slot.extend_from_slice(&wrapped_len.to_be_bytes());
+    slot.extend_from_slice(&wrapped);
+    slot.extend_from_slice(&encrypted_key);
+    Ok(slot)
+}
+
+/// Decrypt the AES key of a `SlotKem::Kem` slot.
+///
+/// # Returns
+/// `None` if the key doesn't match, or the slot is corrupted. (The decapsulation errors included)
+///
+pub(crate) fn unwrap_kem_slot(kem: &dyn Kem, slot: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    let (wrapped_len, slot) = slot.split_first_chunk::<2>()?;
+    let (wrapped, encrypted_key) =
+        slot.split_at_checked(u16::from_be_bytes(*wrapped_len) as usize)?;
+    #[cfg(feature = "tracing")]
+    let timer = Timer::start();
+    let shared = kem.decapsulate(wrapped);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        kem = kem.id(),
+        ok = shared.is_ok(),
+        elapsed = ?timer.elapsed(),
+        "KEM decapsulate"
+    );
+    let shared = shared.ok()?;
+    open_key(
+        &derive_kek(kem.id(), &shared, wrapped),
+        KEM_SLOT_LABEL,
+        encrypted_key,
+    )
+}
@@ -6,30 +6,63 @@
 //! generated AES key. The AES key is then encrypted using the RSA public key. The encrypted data is
 //! written to a writer in a specific format. The data is decrypted using the RSA private key.
 //!
-//! Currently, the key length is fixed at 2048 bits. (Temporary solution)
+//! `generate` creates 2048 bits keys. Other key lengths are generated with `generate_with`,
+//! `generate_with_rng_and_progress` and `generate_from_seed`, which take the length in bits.
 //!
 //! **Warning**: Currently the memeory of the struct is not locked. (This will be implemented in
 //! the future)
 //! So, the data can be read from the memory. (This is a security risk)
-use super::shared::{setup_rng, RSA_KEY_LEN};
-use rand::{CryptoRng, RngCore};
+use super::{
+    audit::{audit_key_loaded, KeySource},
+    bundle::{unix_now, KeyBundle},
+    shared::{setup_rng, RSA_KEY_LEN},
+};
+use core::ops::ControlFlow;
+use num_bigint_dig::prime::probably_prime;
+use rand::{CryptoRng, RngCore, SeedableRng as _};
+use rand_chacha::ChaCha20Rng;
 use rsa::{
     pkcs1::{
         DecodeRsaPrivateKey as _, DecodeRsaPublicKey as _, EncodeRsaPrivateKey as _,
         EncodeRsaPublicKey as _,
     },
-    pkcs8::der::zeroize::Zeroizing,
-    RsaPrivateKey, RsaPublicKey,
+    pkcs8::{der::zeroize::Zeroizing, DecodePrivateKey as _, EncodePrivateKey as _},
+    BigUint, RsaPrivateKey, RsaPublicKey,
 };
 
+/// The public exponent of the generated keys. (As `RsaPrivateKey::new`)
+const PUBLIC_EXPONENT: u64 = 65537;
+/// The Miller-Rabin rounds of the prime search. (As `RsaPrivateKey::new`)
+const PRIME_ROUNDS: usize = 20;
+
+/// The DER encoded certificates of a key, the certificate of the key first.
+#[cfg(feature = "pkcs12")]
+type CertificateChain = Vec<Vec<u8>>;
+
+/// A prime candidate tested by `RsaKeys::generate_with`, reported to its progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempt {
+    /// The prime being searched: 0 for `p`, 1 for `q`.
+    pub prime: usize,
+    /// The number of candidates tested for this prime, including this one. (Starting at 1)
+    pub candidates: u64,
+    /// The number of candidates tested for the whole key, including this one. (Starting at 1)
+    pub total: u64,
+}
+
 /// A struct that holds the RSA public and private keys.
 /// The keys can be generated, loaded, and serialized.
 ///
-/// Currently the key length is fixed at 2048 bits. (Temporary solution)
+/// The key length is 2048 bits by default, and can be chosen when generating the keys. (See
+/// `generate_with`)
+///
+/// The keys are read with `public_key` and `private_key`, or `require_private` when the private
+/// key is needed. An instance holds at least one of them: the constructors loading a private key
+/// also derive the public key.
 ///
 pub struct RsaKeys {
-    pub public_key: Option<RsaPublicKey>,
-    pub private_key: Option<RsaPrivateKey>,
+    public_key: Option<RsaPublicKey>,
+    private_key: Option<RsaPrivateKey>,
 }
 
 impl RsaKeys {
@@ -66,12 +99,98 @@ impl RsaKeys {
         })
     }
 
-    /// Create a new `RsaKeys` instance from the given private key.
+    /// Generate a new RSA key pair of the given size, reporting each prime candidate to
+    /// `progress`, e.g. to animate a spinner in a UI. The callback can cancel the generation by
+    /// returning `ControlFlow::Break`.
     ///
     /// # Arguments
-    /// - `private_key`: The RSA private key.
+    /// - `bits`: The size of the key in bits. (e.g. 2048, at least 512)
+    /// - `progress`: Called before testing each prime candidate. (A 2048 bits key takes a few
+    ///   hundred candidates, a 4096 bits key a few thousand)
+    ///
+    /// # Returns
+    /// A new RSA key pair.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `bits` is odd or less than 512.
+    /// - `Interrupted`: If `progress` cancels the generation.
+    ///
+    /// (As `std::io::Error`, in the returned box)
+    ///
+    pub fn generate_with(
+        bits: usize,
+        progress: impl FnMut(Attempt) -> ControlFlow<()>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rng = setup_rng();
+        Self::generate_with_rng_and_progress(&mut rng, bits, progress)
+    }
+
+    /// Generate a new RSA key pair of the given size with the given random number generator,
+    /// reporting each prime candidate to `progress`. (See `generate_with`)
+    ///
+    /// # Errors
+    /// The errors of `generate_with`.
+    ///
+    pub fn generate_with_rng_and_progress<R: CryptoRng + RngCore>(
+        rng: &mut R,
+        bits: usize,
+        mut progress: impl FnMut(Attempt) -> ControlFlow<()>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if bits < 512 || !bits.is_multiple_of(2) {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid RSA key size: {} bits", bits),
+            )));
+        }
+        let mut total = 0;
+        loop {
+            let mut primes = Vec::with_capacity(2);
+            for prime in 0..2 {
+                primes.push(search_prime(rng, bits / 2, |candidates| {
+                    total += 1;
+                    progress(Attempt {
+                        prime,
+                        candidates,
+                        total,
+                    })
+                })?);
+            }
+            let [p, q] = <[BigUint; 2]>::try_from(primes).expect("two primes");
+            // Equal primes, or `e` not invertible: start again (as `RsaPrivateKey::new`)
+            if let Ok(private_key) = RsaPrivateKey::from_p_q(p, q, BigUint::from(PUBLIC_EXPONENT)) {
+                return Ok(Self::from_private_key(private_key));
+            }
+        }
+    }
+
+    /// Generate a RSA key pair deterministically from a seed, e.g. for reproducible tests and
+    /// fixtures without checking PEM files into the repository. The seed drives a ChaCha20 RNG, so
+    /// the same seed gives the same key pair on every run and machine. (For the same version of
+    /// the `rsa` crate)
     ///
+    /// # Arguments
+    /// - `seed`: The seed of the RNG.
+    /// - `bits`: The size of the key in bits. (e.g. 2048)
+    ///
+    /// # Returns
+    /// A new RSA key pair.
+    ///
+    /// **Warning**: Anyone knowing the seed can derive the private key. Only use it for test keys,
+    /// or with a seed as secret as the key itself.
+    ///
+    pub fn generate_from_seed(
+        seed: [u8; 32],
+        bits: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut rng = ChaCha20Rng::from_seed(seed);
+        let priv_key = RsaPrivateKey::new(&mut rng, bits)?;
+        Ok(Self::from_private_key(priv_key))
+    }
+
+    /// Create a new `RsaKeys` instance from the given private key.
+    /// The CRT parameters of the key are precomputed. (See `precompute`)
     pub fn from_private_key(private_key: RsaPrivateKey) -> Self {
+        let private_key = precomputed(private_key);
         let public_key = RsaPublicKey::from(&private_key);
         Self {
             public_key: Some(public_key),
@@ -79,6 +198,83 @@ impl RsaKeys {
         }
     }
 
+    /// Create a new `RsaKeys` instance from the given public key. (With only the public key)
+    pub fn from_public_key(public_key: RsaPublicKey) -> Self {
+        Self {
+            public_key: Some(public_key),
+            private_key: None,
+        }
+    }
+
+    /// The public key, if any.
+    pub fn public_key(&self) -> Option<&RsaPublicKey> {
+        self.public_key.as_ref()
+    }
+
+    /// The private key, if any.
+    pub fn private_key(&self) -> Option<&RsaPrivateKey> {
+        self.private_key.as_ref()
+    }
+
+    /// Whether the instance holds the private key.
+    pub fn has_private(&self) -> bool {
+        self.private_key.is_some()
+    }
+
+    /// The private key, for the operations needing it.
+    ///
+    /// # Errors
+    /// If the private key is not found.
+    ///
+    pub fn require_private(&self) -> Result<&RsaPrivateKey, Box<dyn std::error::Error>> {
+        self.private_key
+            .as_ref()
+            .ok_or_else(|| "private key not found".into())
+    }
+
+    /// Precompute the CRT parameters of the private key (`dp`, `dq` and `qinv`), so each RSA
+    /// decryption, like the key unwrap of every `CryptoReader`, uses the Chinese remainder theorem,
+    /// several times faster. The generated and loaded keys are already precomputed, so this is only
+    /// needed if it failed for them. (Invalid primes)
+    ///
+    /// # Errors
+    /// If the private key is not found, or its primes are invalid.
+    ///
+    pub fn precompute(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match &mut self.private_key {
+            Some(private_key) => Ok(private_key.precompute()?),
+            None => Err("private key not found".into()),
+        }
+    }
+
+    /// A copy of the keys without the private key, to hand out safely. (e.g. to serialize or
+    /// share the key pair of a server)
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With only the public key. (Derived from the private key if
+    /// needed, or none if there is no key at all)
+    ///
+    pub fn public_only(&self) -> RsaKeys {
+        let public_key = match (&self.public_key, &self.private_key) {
+            (Some(public_key), _) => Some(public_key.clone()),
+            (None, Some(private_key)) => Some(RsaPublicKey::from(private_key)),
+            (None, None) => None,
+        };
+        Self {
+            public_key,
+            private_key: None,
+        }
+    }
+
+    /// Split the instance into its keys.
+    ///
+    /// # Returns
+    /// The private key and the public key, as held by the instance.
+    ///
+    pub fn split(self) -> (Option<RsaPrivateKey>, Option<RsaPublicKey>) {
+        (self.private_key, self.public_key)
+    }
+
     /// Convert the private key to a PEM formatted string.
     ///
     /// # Returns
@@ -94,6 +290,33 @@ impl RsaKeys {
         }
     }
 
+    /// Convert the private key to a passphrase-protected PEM formatted string. (Encrypted PKCS#8:
+    /// AES-256-CBC, under a key derived from the passphrase with scrypt)
+    ///
+    /// # Arguments
+    /// - `passphrase`: The passphrase protecting the key.
+    ///
+    /// # Returns
+    /// The encrypted private key in PEM format. (`ENCRYPTED PRIVATE KEY`)
+    ///
+    /// # Errors
+    /// If the private key is not found.
+    ///
+    #[cfg(feature = "encrypted-keys")]
+    pub fn private_key_to_encrypted_pem(
+        &self,
+        passphrase: &[u8],
+    ) -> Result<Zeroizing<String>, Box<dyn std::error::Error>> {
+        match &self.private_key {
+            Some(private_key) => Ok(private_key.to_pkcs8_encrypted_pem(
+                setup_rng(),
+                passphrase,
+                rsa::pkcs8::LineEnding::LF,
+            )?),
+            None => Err("private key not found".into()),
+        }
+    }
+
     /// Convert the public key to a PEM formatted string.
     ///
     /// # Returns
@@ -109,6 +332,52 @@ impl RsaKeys {
         }
     }
 
+    /// Convert the private key to DER. (PKCS#1, the binary form of `private_key_to_pem`)
+    ///
+    /// # Returns
+    /// The private key in DER format.
+    ///
+    /// # Errors
+    /// If the private key is not found.
+    ///
+    pub fn to_pkcs1_der(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error>> {
+        match &self.private_key {
+            Some(private_key) => Ok(private_key.to_pkcs1_der()?.to_bytes()),
+            None => Err("private key not found".into()),
+        }
+    }
+
+    /// Convert the private key to DER. (PKCS#8 `PrivateKeyInfo`, as expected by most other
+    /// libraries)
+    ///
+    /// # Returns
+    /// The private key in DER format.
+    ///
+    /// # Errors
+    /// If the private key is not found.
+    ///
+    pub fn to_pkcs8_der(&self) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error>> {
+        match &self.private_key {
+            Some(private_key) => Ok(private_key.to_pkcs8_der()?.to_bytes()),
+            None => Err("private key not found".into()),
+        }
+    }
+
+    /// Convert the public key to DER. (PKCS#1, the binary form of `public_key_to_pem`)
+    ///
+    /// # Returns
+    /// The public key in DER format.
+    ///
+    /// # Errors
+    /// If the public key is not found.
+    ///
+    pub fn public_key_to_pkcs1_der(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match &self.public_key {
+            Some(public_key) => Ok(public_key.to_pkcs1_der()?.into_vec()),
+            None => Err("public key not found".into()),
+        }
+    }
+
     /// Create a new `RsaKeys` instance from the given PEM formatted key.
     ///
     /// # Arguments
@@ -116,13 +385,42 @@ impl RsaKeys {
     ///
     /// # Returns
     /// A new `RsaKeys` instance. With both the public and private keys. (Public key is derived
-    /// from the private key)
+    /// from the private key, the CRT parameters are precomputed)
     ///
     /// # Errors
     /// If the key is invalid.
     ///
     pub fn from_key_pem(pem: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let private_key = RsaPrivateKey::from_pkcs1_pem(pem)?;
+        let private_key = precomputed(RsaPrivateKey::from_pkcs1_pem(pem)?);
+        audit_key_loaded(KeySource::Pem, None, &private_key);
+        let public_key = RsaPublicKey::from(&private_key);
+        Ok(Self {
+            public_key: Some(public_key),
+            private_key: Some(private_key),
+        })
+    }
+
+    /// Create a new `RsaKeys` instance from the given passphrase-protected PEM formatted key. (See
+    /// `private_key_to_encrypted_pem`)
+    ///
+    /// # Arguments
+    /// - `pem`: The encrypted PEM formatted private key.
+    /// - `passphrase`: The passphrase protecting the key.
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With both the public and private keys. (The CRT parameters are
+    /// precomputed)
+    ///
+    /// # Errors
+    /// If the passphrase is wrong, or the key is invalid.
+    ///
+    #[cfg(feature = "encrypted-keys")]
+    pub fn from_encrypted_key_pem(
+        pem: &str,
+        passphrase: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let private_key = precomputed(RsaPrivateKey::from_pkcs8_encrypted_pem(pem, passphrase)?);
+        audit_key_loaded(KeySource::Pem, None, &private_key);
         let public_key = RsaPublicKey::from(&private_key);
         Ok(Self {
             public_key: Some(public_key),
@@ -130,19 +428,65 @@ impl RsaKeys {
         })
     }
 
+    /// Create a new `RsaKeys` instance from the key pair of a password-protected PKCS#12 file.
+    /// (`.p12` or `.pfx`, as distributed by a PKI)
+    ///
+    /// # Arguments
+    /// - `bytes`: The content of the PKCS#12 file. (DER encoded)
+    /// - `password`: The password protecting the file.
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With both the public and private keys. (The CRT parameters are
+    /// precomputed) And the certificate chain of the key, DER encoded. (The certificate of the key
+    /// first, then its issuers)
+    ///
+    /// # Errors
+    /// If the password is wrong, the file holds no private key, or the key is not an RSA key.
+    ///
+    /// # Notes
+    /// Only the first private key of the file is loaded. The certificate is the one the file pairs
+    /// with the key: it is not verified.
+    ///
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12(
+        bytes: &[u8],
+        password: &str,
+    ) -> Result<(Self, CertificateChain), Box<dyn std::error::Error>> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(bytes, password)?;
+        let Some((alias, key_chain)) = keystore.private_key_chain() else {
+            return Err("private key not found".into());
+        };
+        let private_key = precomputed(RsaPrivateKey::from_pkcs8_der(key_chain.key())?);
+        audit_key_loaded(KeySource::Pkcs12, Some(alias), &private_key);
+        let chain = key_chain
+            .chain()
+            .iter()
+            .map(|certificate| certificate.as_der().to_vec())
+            .collect();
+        let public_key = RsaPublicKey::from(&private_key);
+        Ok((
+            Self {
+                public_key: Some(public_key),
+                private_key: Some(private_key),
+            },
+            chain,
+        ))
+    }
+
     /// Create a new `RsaKeys` instance from the given PEM formatted private key.
     ///
     /// # Arguments
     /// - `pem`: The PEM formatted private key.
     ///
     /// # Returns
-    /// A new `RsaKeys` instance. With only the private key.
+    /// A new `RsaKeys` instance. With only the private key. (The CRT parameters are precomputed)
     ///
     /// # Errors
     /// If the key is invalid.
     ///
     pub fn from_private_key_pem(pem: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let private_key = RsaPrivateKey::from_pkcs1_pem(pem)?;
+        let private_key = precomputed(RsaPrivateKey::from_pkcs1_pem(pem)?);
+        audit_key_loaded(KeySource::Pem, None, &private_key);
         Ok(Self {
             public_key: None,
             private_key: Some(private_key),
@@ -164,4 +508,159 @@ impl RsaKeys {
             private_key: None,
         })
     }
+
+    /// Create a new `RsaKeys` instance from the given DER formatted private key. (See
+    /// `to_pkcs1_der`)
+    ///
+    /// # Arguments
+    /// - `der`: The PKCS#1 DER formatted private key.
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With both the public and private keys. (Public key is derived
+    /// from the private key, the CRT parameters are precomputed)
+    ///
+    /// # Errors
+    /// If the key is invalid.
+    ///
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let private_key = precomputed(RsaPrivateKey::from_pkcs1_der(der)?);
+        audit_key_loaded(KeySource::Der, None, &private_key);
+        let public_key = RsaPublicKey::from(&private_key);
+        Ok(Self {
+            public_key: Some(public_key),
+            private_key: Some(private_key),
+        })
+    }
+
+    /// Create a new `RsaKeys` instance from the given DER formatted private key. (See
+    /// `to_pkcs8_der`)
+    ///
+    /// # Arguments
+    /// - `der`: The PKCS#8 DER formatted private key. (Unencrypted)
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With both the public and private keys. (Public key is derived
+    /// from the private key, the CRT parameters are precomputed)
+    ///
+    /// # Errors
+    /// If the key is invalid, or is not an RSA key.
+    ///
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let private_key = precomputed(RsaPrivateKey::from_pkcs8_der(der)?);
+        audit_key_loaded(KeySource::Der, None, &private_key);
+        let public_key = RsaPublicKey::from(&private_key);
+        Ok(Self {
+            public_key: Some(public_key),
+            private_key: Some(private_key),
+        })
+    }
+
+    /// Create a new `RsaKeys` instance from the given DER formatted public key. (See
+    /// `public_key_to_pkcs1_der`)
+    ///
+    /// # Arguments
+    /// - `der`: The PKCS#1 DER formatted public key.
+    ///
+    /// # Returns
+    /// A new `RsaKeys` instance. With only the public key.
+    ///
+    pub fn from_public_key_pkcs1_der(der: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let public_key = RsaPublicKey::from_pkcs1_der(der)?;
+        Ok(Self {
+            public_key: Some(public_key),
+            private_key: None,
+        })
+    }
+
+    /// Export the public key in a `KeyBundle`, self-signed by the private key. The creation time
+    /// is the current time.
+    ///
+    /// # Arguments
+    /// - `owner`: The name of the owner of the key. (e.g. `alice@example.com`)
+    /// - `expires`: The expiry time, in seconds since the Unix epoch. (`None` if the key doesn't
+    ///   expire)
+    ///
+    /// # Errors
+    /// If the private key is not found, or the errors of `KeyBundle::sign`.
+    ///
+    pub fn export_bundle(
+        &self,
+        owner: &str,
+        expires: Option<u64>,
+    ) -> Result<KeyBundle, Box<dyn std::error::Error>> {
+        match &self.private_key {
+            Some(private_key) => Ok(KeyBundle::sign(private_key, owner, unix_now(), expires)?),
+            None => Err("private key not found".into()),
+        }
+    }
+
+    /// Generate a PKCS#10 certificate signing request for the key pair, signed by the private key.
+    /// (RSASSA-PKCS1-v1_5, SHA-256)
+    ///
+    /// # Arguments
+    /// - `subject`: The subject of the certificate, as a RFC 4514 string. (e.g.
+    ///   `CN=service.example.com,O=Example`)
+    ///
+    /// # Returns
+    /// The request in PEM format. (`CERTIFICATE REQUEST` label, as expected by `openssl req`)
+    ///
+    /// # Errors
+    /// If the private key is not found, the subject is invalid, or the signature fails.
+    ///
+    #[cfg(feature = "csr")]
+    pub fn generate_csr(&self, subject: &str) -> Result<String, Box<dyn std::error::Error>> {
+        use core::str::FromStr as _;
+        use x509_cert::{
+            builder::{Builder as _, RequestBuilder},
+            der::EncodePem as _,
+            name::Name,
+        };
+
+        let Some(private_key) = &self.private_key else {
+            return Err("private key not found".into());
+        };
+        let signer = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(private_key.clone());
+        let request = RequestBuilder::new(Name::from_str(subject)?, &signer)?
+            .build::<rsa::pkcs1v15::Signature>()?;
+        Ok(request.to_pem(rsa::pkcs8::LineEnding::LF)?)
+    }
+}
+
+/// Precompute the CRT parameters of a private key. (See `RsaKeys::precompute`)
+fn precomputed(mut private_key: RsaPrivateKey) -> RsaPrivateKey {
+    // Only fails for invalid primes, and the key still works without
+    let _ = private_key.precompute();
+    private_key
+}
+
+/// Search a random prime of `bits` bits, with its two top bits set (so the product of two such
+/// primes has exactly `2 * bits` bits), calling `progress` before testing each candidate.
+///
+/// # Errors
+/// - `Interrupted`: If `progress` returns `ControlFlow::Break`.
+///
+fn search_prime<R: CryptoRng + RngCore>(
+    rng: &mut R,
+    bits: usize,
+    mut progress: impl FnMut(u64) -> ControlFlow<()>,
+) -> std::io::Result<BigUint> {
+    let mut bytes = Zeroizing::new(vec![0; bits.div_ceil(8)]);
+    let mut candidates = 0;
+    loop {
+        candidates += 1;
+        if progress(candidates).is_break() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Key generation cancelled",
+            ));
+        }
+        rng.fill_bytes(&mut bytes);
+        // `bits` random bits, with the two top bits set, and odd
+        let mut candidate = BigUint::from_bytes_be(&bytes) >> (bytes.len() * 8 - bits);
+        candidate |= BigUint::from(3u8) << (bits - 2);
+        candidate |= BigUint::from(1u8);
+        if probably_prime(&candidate, PRIME_ROUNDS) {
+            return Ok(candidate);
+        }
+    }
 }
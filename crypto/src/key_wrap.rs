@@ -0,0 +1,161 @@
+//! This module provides the `KeyWrap` scheme used to encrypt the AES key with the RSA keys.
+//!
+//! PKCS#1 v1.5 encryption is kept as the default, so the streams can be read by older versions.
+//! OAEP is the recommended scheme for new deployments: PKCS#1 v1.5 decryption is exposed to
+//! padding oracle attacks (Bleichenbacher) when the decryption errors can be observed.
+//!
+//! The scheme is recorded in the `Header`, so `CryptoReader` reads the streams of every scheme.
+//! Readers can require a minimum scheme (see `CryptoReader::new_with_min_key_wrap`) to enforce
+//! OAEP while the legacy streams are migrated.
+#[cfg(feature = "tracing")]
+use super::stats::Timer;
+use super::{
+    error::{error, Result},
+    io,
+};
+use alloc::vec::Vec;
+use rand::{CryptoRng, RngCore};
+use rsa::{
+    pkcs8::der::zeroize::Zeroizing, traits::PrivateKeyParts as _, Oaep, Pkcs1v15Encrypt,
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest as _, Sha256, Sha512};
+
+/// Domain separation of the synthetic keys of the implicit rejection.
+const IMPLICIT_REJECTION_LABEL: &[u8] = b"crypto implicit rejection";
+
+/// The RSA encryption scheme wrapping the AES key of a stream.
+///
+/// Schemes are ordered, so readers can require a minimum one. (e.g. `key_wrap >=
+/// KeyWrap::OaepSha256`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum KeyWrap {
+    /// RSAES-PKCS1-v1_5. (Legacy, readable by every version)
+    #[default]
+    Pkcs1v15,
+    /// RSAES-OAEP with SHA-256. (For the hash and MGF1)
+    OaepSha256,
+    /// RSAES-OAEP with SHA-512. (For the hash and MGF1) Requires a RSA key of at least 1536 bits.
+    OaepSha512,
+}
+
+impl KeyWrap {
+    /// Encrypt the AES key with the RSA public key.
+    pub(crate) fn wrap<R: CryptoRng + RngCore>(
+        self,
+        key: &RsaPublicKey,
+        rng: &mut R,
+        aes_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        #[cfg(feature = "tracing")]
+        let timer = Timer::start();
+        let wrapped_key = match self {
+            KeyWrap::Pkcs1v15 => key.encrypt(rng, Pkcs1v15Encrypt, aes_key),
+            KeyWrap::OaepSha256 => key.encrypt(rng, Oaep::new::<Sha256>(), aes_key),
+            KeyWrap::OaepSha512 => key.encrypt(rng, Oaep::new::<Sha512>(), aes_key),
+        }
+        .map_err(|e| error!(Other, "RSA Encryption error: {}", e));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key_wrap = ?self, elapsed = ?timer.elapsed(), "RSA wrap");
+        wrapped_key
+    }
+
+    /// Decrypt the AES key with the RSA private key.
+    ///
+    /// # Returns
+    /// `None` if the key doesn't match, or the wrapped key is corrupted.
+    ///
+    pub(crate) fn unwrap(self, key: &RsaPrivateKey, wrapped_key: &[u8]) -> Option<Vec<u8>> {
+        #[cfg(feature = "tracing")]
+        let timer = Timer::start();
+        let raw_aes_key = self.decrypt(key, wrapped_key);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key_wrap = ?self, elapsed = ?timer.elapsed(), "RSA unwrap");
+        raw_aes_key
+    }
+
+    /// Decrypt the AES key with the RSA private key. (Not traced, see `unwrap_implicit`)
+    fn decrypt(self, key: &RsaPrivateKey, wrapped_key: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            KeyWrap::Pkcs1v15 => key.decrypt(Pkcs1v15Encrypt, wrapped_key),
+            KeyWrap::OaepSha256 => key.decrypt(Oaep::new::<Sha256>(), wrapped_key),
+            KeyWrap::OaepSha512 => key.decrypt(Oaep::new::<Sha512>(), wrapped_key),
+        }
+        .ok()
+    }
+
+    /// Decrypt the AES key with the RSA private key, with implicit rejection: if the decryption
+    /// fails (or the key isn't `len` bytes long), a synthetic key is returned instead, so the
+    /// failure isn't observable until the AEAD verification. (As TLS does, against Bleichenbacher
+    /// style padding oracles)
+    ///
+    /// The synthetic key is `SHA-256(label || counter || d || wrapped_key)`: it is the same for
+    /// the same wrapped key, so retrying a wrapped key doesn't reveal anything either.
+    ///
+    /// # Returns
+    /// The AES key, and whether it was actually decrypted. (`false` for a synthetic key)
+    ///
+    pub(crate) fn unwrap_implicit(
+        self,
+        key: &RsaPrivateKey,
+        wrapped_key: &[u8],
+        len: usize,
+    ) -> (Zeroizing<Vec<u8>>, bool) {
+        #[cfg(feature = "tracing")]
+        let timer = Timer::start();
+        let unwrapped = self.decrypt(key, wrapped_key).map(Zeroizing::new);
+        let synthetic = synthetic_key(key, wrapped_key, len);
+        let raw_aes_key = match unwrapped {
+            Some(raw_aes_key) if raw_aes_key.len() == len => (raw_aes_key, true),
+            _ => (synthetic, false),
+        };
+        // Traced after the rejection decision, without its outcome
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key_wrap = ?self, elapsed = ?timer.elapsed(), "RSA unwrap");
+        raw_aes_key
+    }
+}
+
+/// The key returned by `KeyWrap::unwrap_implicit` when the decryption fails. (Always computed,
+/// so both outcomes take the same time)
+fn synthetic_key(key: &RsaPrivateKey, wrapped_key: &[u8], len: usize) -> Zeroizing<Vec<u8>> {
+    let d = Zeroizing::new(key.d().to_bytes_be());
+    let mut synthetic = Zeroizing::new(Vec::with_capacity(len));
+    for counter in 0u32.. {
+        if synthetic.len() >= len {
+            break;
+        }
+        let block = Sha256::new()
+            .chain_update(IMPLICIT_REJECTION_LABEL)
+            .chain_update(counter.to_be_bytes())
+            .chain_update(d.as_slice())
+            .chain_update(wrapped_key)
+            .finalize();
+        synthetic.extend_from_slice(&block);
+    }
+    synthetic.truncate(len);
+    synthetic
+}
+
+impl From<KeyWrap> for u8 {
+    fn from(key_wrap: KeyWrap) -> Self {
+        match key_wrap {
+            KeyWrap::Pkcs1v15 => 0,
+            KeyWrap::OaepSha256 => 1,
+            KeyWrap::OaepSha512 => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for KeyWrap {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(KeyWrap::Pkcs1v15),
+            1 => Ok(KeyWrap::OaepSha256),
+            2 => Ok(KeyWrap::OaepSha512),
+            _ => Err(error!(InvalidData, "Unsupported key wrap: {}", value)),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! This module provides `KeyStore`: a directory of named key pairs, for applications managing
+//! several identities.
+//!
+//! Each key is saved with the same layout as the `keygen` command of the CLI:
+//!
+//! ```plaintext
+//! ~/.crypto/keys/
+//! ├── alice          The private key (PKCS#1 PEM, absent for imported public keys)
+//! ├── alice.pub      The public key (PKCS#1 PEM)
+//! └── alice.bundle   The owner, creation and expiry time (`KeyBundle` PEM, optional)
+//! ```
+//!
+//! The keys can be resolved by name, or by fingerprint. (See `seal::fingerprint`)
+//!
+//! **Warning**: The private keys are saved unencrypted. (Readable by the owner only on Unix) With
+//! the `os-keychain` feature, `KeyStore::from_os_keychain` keeps them in the keychain of the OS
+//! instead. (macOS Keychain, Windows Credential Manager, or Linux Secret Service)
+use super::{
+    audit::{audit_key_loaded, KeySource},
+    bundle::KeyBundle,
+    error::{error, Result},
+    key::RsaKeys,
+    seal::fingerprint,
+};
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey as _, pkcs8::der::zeroize::Zeroizing, RsaPrivateKey, RsaPublicKey,
+};
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+/// The minimum length of a fingerprint prefix accepted by `KeyStore::resolve`.
+const FINGERPRINT_PREFIX_MIN: usize = 8;
+/// The file holding the name of the default key. (Not a valid key name, so it never clashes)
+const DEFAULT_KEY_FILE: &str = ".default";
+
+/// A key of a `KeyStore`.
+#[derive(Debug, Clone)]
+pub struct KeyEntry {
+    /// The name of the key in the store.
+    pub name: String,
+    /// The fingerprint of the public key. (See `seal::fingerprint`)
+    pub fingerprint: String,
+    /// The public key.
+    pub public_key: RsaPublicKey,
+    /// Whether the private key is in the store. (`false` for imported public keys)
+    pub has_private_key: bool,
+    /// The metadata of the key, if any. (See `KeyBundle`)
+    pub bundle: Option<KeyBundle>,
+}
+
+/// A directory of named key pairs.
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    root: PathBuf,
+    /// The service name of the private keys in the OS keychain, if they are kept there.
+    #[cfg(feature = "os-keychain")]
+    keychain: Option<String>,
+}
+
+impl KeyStore {
+    /// Open the store in the given directory. The directory is created when the first key is
+    /// saved.
+    pub fn open(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            #[cfg(feature = "os-keychain")]
+            keychain: None,
+        }
+    }
+
+    /// Open the store in the default directory: `$CRYPTO_KEYSTORE` if set, else `~/.crypto/keys`.
+    ///
+    /// # Errors
+    /// - `NotFound`: If the home directory is unknown. (Neither `HOME` nor `USERPROFILE` is set)
+    ///
+    pub fn open_default() -> Result<Self> {
+        if let Some(root) = std::env::var_os("CRYPTO_KEYSTORE") {
+            return Ok(Self::open(root));
+        }
+        let home = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .ok_or_else(|| error!(NotFound, "Home directory not found"))?;
+        Ok(Self::open(Path::new(&home).join(".crypto").join("keys")))
+    }
+
+    /// Open the store in the default directory (see `open_default`), keeping the private keys in
+    /// the keychain of the OS. (See `with_os_keychain`)
+    ///
+    /// # Errors
+    /// - The errors of `open_default` and `with_os_keychain`.
+    ///
+    #[cfg(feature = "os-keychain")]
+    pub fn from_os_keychain(name: &str) -> Result<Self> {
+        Self::open_default()?.with_os_keychain(name)
+    }
+
+    /// Keep the private keys in the keychain of the OS: the macOS Keychain, the Windows Credential
+    /// Manager, or the Linux Secret Service. The public keys and the bundles stay in the
+    /// directory.
+    ///
+    /// # Arguments
+    /// - `name`: The service name of the keys in the keychain. (e.g. the name of the application)
+    ///   The key names are the account names.
+    ///
+    /// # Errors
+    /// - `Unsupported`: If the platform has no supported keychain.
+    ///
+    /// # Notes
+    /// The keychain may ask the user to unlock it, even to list the keys. (`has_private_key`)
+    ///
+    #[cfg(feature = "os-keychain")]
+    pub fn with_os_keychain(self, name: &str) -> Result<Self> {
+        if !cfg!(any(target_os = "linux", target_os = "macos", windows)) {
+            Err(error!(Unsupported, "No OS keychain on this platform"))?;
+        }
+        Ok(Self {
+            keychain: Some(name.into()),
+            ..self
+        })
+    }
+
+    /// The directory of the store.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Generate a new key pair, and save it as `name`.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the key. (Letters, digits, `-`, `_`, `.` and `@`)
+    /// - `expires`: The expiry time, in seconds since the Unix epoch. (`None` if the key doesn't
+    ///   expire)
+    ///
+    /// # Returns
+    /// The new key. Its bundle records `name` as the owner.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the name is invalid.
+    /// - `AlreadyExists`: If the name is taken.
+    /// - `Other`: If the key generation fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn create(&self, name: &str, expires: Option<u64>) -> Result<KeyEntry> {
+        self.check_available(name)?;
+        let keys = RsaKeys::generate().map_err(|e| error!(Other, "Key generation error: {}", e))?;
+        let bundle = keys
+            .export_bundle(name, expires)
+            .map_err(|e| error!(Other, "Key bundle error: {}", e))?;
+        self.save(name, &keys, Some(&bundle))
+    }
+
+    /// Save an existing key as `name`.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the key. (Letters, digits, `-`, `_`, `.` and `@`)
+    /// - `keys`: The key pair, or the public key only. (e.g. the key of a contact)
+    /// - `bundle`: The metadata of the key, if any. (See `KeyBundle`)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the name is invalid, `keys` holds no key, or the bundle is the one
+    ///   of another key.
+    /// - `AlreadyExists`: If the name is taken.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn import(
+        &self,
+        name: &str,
+        keys: &RsaKeys,
+        bundle: Option<&KeyBundle>,
+    ) -> Result<KeyEntry> {
+        self.check_available(name)?;
+        self.save(name, keys, bundle)
+    }
+
+    /// Load the key pair saved as `name`.
+    ///
+    /// # Returns
+    /// The key pair. (Public key only for imported public keys)
+    ///
+    /// # Errors
+    /// - `NotFound`: If there is no key named `name`.
+    /// - `InvalidData`: If a key file is malformed.
+    ///
+    pub fn export(&self, name: &str) -> Result<RsaKeys> {
+        check_name(name)?;
+        match self.read_private_key(name)? {
+            Some(pem) => {
+                let private_key = RsaPrivateKey::from_pkcs1_pem(&pem)
+                    .map_err(|e| error!(InvalidData, "Invalid private key {}: {}", name, e))?;
+                audit_key_loaded(KeySource::KeyStore, Some(name), &private_key);
+                Ok(RsaKeys::from_private_key(private_key))
+            }
+            None => Ok(RsaKeys::from_public_key(self.entry(name)?.public_key)),
+        }
+    }
+
+    /// Delete the key saved as `name`. (Private key, public key and bundle)
+    ///
+    /// # Errors
+    /// - `NotFound`: If there is no key named `name`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn delete(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        let public_key = self.public_key_path(name);
+        if !public_key.exists() {
+            Err(error!(NotFound, "No key named {}", name))?;
+        }
+        self.delete_private_key(name)?;
+        match std::fs::remove_file(self.root.join(format!("{}.bundle", name))) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        if self.default_name()?.as_deref() == Some(name) {
+            std::fs::remove_file(self.root.join(DEFAULT_KEY_FILE))?;
+        }
+        std::fs::remove_file(public_key)
+    }
+
+    /// Make `name` the default key of the store, e.g. to encrypt backups for oneself. (See
+    /// `default_key`)
+    ///
+    /// # Errors
+    /// - `NotFound`: If there is no key named `name`.
+    /// - `InvalidInput`: If the store only holds the public key of `name`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn set_default(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        if !self.entry(name)?.has_private_key {
+            Err(error!(
+                InvalidInput,
+                "Only the public key of {} is in the store", name
+            ))?;
+        }
+        std::fs::write(self.root.join(DEFAULT_KEY_FILE), name)
+    }
+
+    /// The default key of the store: the key set by `set_default`, else the only key pair of the
+    /// store, if there is exactly one.
+    ///
+    /// # Errors
+    /// - `NotFound`: If there is no default key.
+    /// - The errors of `list`.
+    ///
+    pub fn default_key(&self) -> Result<KeyEntry> {
+        if let Some(name) = self.default_name()? {
+            return self.entry(&name);
+        }
+        let mut key_pairs = self
+            .list()?
+            .into_iter()
+            .filter(|entry| entry.has_private_key);
+        match (key_pairs.next(), key_pairs.next()) {
+            (Some(entry), None) => Ok(entry),
+            (None, _) => Err(error!(NotFound, "No key pair in the store")),
+            (Some(_), Some(_)) => Err(error!(
+                NotFound,
+                "Several key pairs in the store, and none is the default"
+            )),
+        }
+    }
+
+    /// The name of the key set by `set_default`, if any.
+    fn default_name(&self) -> Result<Option<String>> {
+        let name = read_optional(&self.root.join(DEFAULT_KEY_FILE))?;
+        Ok(name.map(|name| name.trim().to_string()))
+    }
+
+    /// The keys of the store, sorted by name.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If a key file is malformed.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut keys = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "pub") {
+                continue;
+            }
+            match path.file_stem().and_then(|name| name.to_str()) {
+                Some(name) if check_name(name).is_ok() => keys.push(self.entry(name)?),
+                _ => continue,
+            }
+        }
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(keys)
+    }
+
+    /// Find a key by name, or by fingerprint.
+    ///
+    /// # Arguments
+    /// - `query`: The name of the key, its fingerprint, or a prefix of its fingerprint. (At least
+    ///   8 characters)
+    ///
+    /// # Errors
+    /// - `NotFound`: If no key matches.
+    /// - `InvalidInput`: If several keys match the fingerprint prefix.
+    /// - The errors of `list`.
+    ///
+    pub fn resolve(&self, query: &str) -> Result<KeyEntry> {
+        if check_name(query).is_ok() && self.public_key_path(query).exists() {
+            return self.entry(query);
+        }
+        let query = query.to_ascii_lowercase();
+        if query.len() >= FINGERPRINT_PREFIX_MIN {
+            let mut matches = self
+                .list()?
+                .into_iter()
+                .filter(|entry| entry.fingerprint.starts_with(&query));
+            match (matches.next(), matches.next()) {
+                (Some(entry), None) => return Ok(entry),
+                (Some(_), Some(_)) => Err(error!(InvalidInput, "Several keys match {}", query))?,
+                _ => {}
+            }
+        }
+        Err(error!(NotFound, "No key matches {}", query))
+    }
+
+    /// Check that `name` is valid and not taken.
+    fn check_available(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        if self.public_key_path(name).exists() {
+            Err(error!(AlreadyExists, "A key named {} already exists", name))?;
+        }
+        Ok(())
+    }
+
+    fn public_key_path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.pub", name))
+    }
+
+    /// Load the entry of `name`.
+    fn entry(&self, name: &str) -> Result<KeyEntry> {
+        let pem = read_optional(&self.public_key_path(name))?
+            .ok_or_else(|| error!(NotFound, "No key named {}", name))?;
+        let public_key = RsaKeys::from_public_key_pem(&pem)
+            .ok()
+            .and_then(|keys| keys.split().1)
+            .ok_or_else(|| error!(InvalidData, "Invalid public key {}", name))?;
+        let bundle = read_optional(&self.root.join(format!("{}.bundle", name)))?
+            .map(|pem| KeyBundle::from_pem(&pem))
+            .transpose()?;
+        Ok(KeyEntry {
+            name: name.into(),
+            fingerprint: fingerprint(&public_key),
+            has_private_key: self.has_private_key(name)?,
+            public_key,
+            bundle,
+        })
+    }
+
+    /// Save the files of a key. The public key is written last: the key is listed once complete.
+    fn save(&self, name: &str, keys: &RsaKeys, bundle: Option<&KeyBundle>) -> Result<KeyEntry> {
+        let public_key = keys
+            .public_only()
+            .split()
+            .1
+            .ok_or_else(|| error!(InvalidInput, "No key to import"))?;
+        if bundle.is_some_and(|bundle| bundle.public_key != public_key) {
+            Err(error!(InvalidInput, "The bundle is the one of another key"))?;
+        }
+        std::fs::create_dir_all(&self.root)?;
+
+        if let Some(private_key) = keys.private_key() {
+            let pem = RsaKeys::from_private_key(private_key.clone())
+                .private_key_to_pem()
+                .map_err(|e| error!(InvalidInput, "Invalid private key: {}", e))?;
+            self.write_private_key(name, &pem)?;
+        }
+        if let Some(bundle) = bundle {
+            write_new(
+                &self.root.join(format!("{}.bundle", name)),
+                bundle.to_pem()?.as_bytes(),
+                false,
+            )?;
+        }
+        let pem = RsaKeys::from_public_key(public_key)
+            .public_key_to_pem()
+            .map_err(|e| error!(InvalidInput, "Invalid public key: {}", e))?;
+        write_new(&self.public_key_path(name), pem.as_bytes(), false)?;
+        self.entry(name)
+    }
+
+    /// The keychain entry of the private key `name`, if the private keys are kept in the
+    /// keychain.
+    #[cfg(feature = "os-keychain")]
+    fn keychain_entry(&self, name: &str) -> Result<Option<keyring::Entry>> {
+        self.keychain
+            .as_ref()
+            .map(|service| keyring::Entry::new(service, name).map_err(keychain_error))
+            .transpose()
+    }
+
+    fn has_private_key(&self, name: &str) -> Result<bool> {
+        #[cfg(feature = "os-keychain")]
+        if self.keychain.is_some() {
+            return Ok(self.read_private_key(name)?.is_some());
+        }
+        Ok(self.root.join(name).exists())
+    }
+
+    fn read_private_key(&self, name: &str) -> Result<Option<Zeroizing<String>>> {
+        #[cfg(feature = "os-keychain")]
+        if let Some(entry) = self.keychain_entry(name)? {
+            return match entry.get_password() {
+                Ok(pem) => Ok(Some(Zeroizing::new(pem))),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(keychain_error(e)),
+            };
+        }
+        Ok(read_optional(&self.root.join(name))?.map(Zeroizing::new))
+    }
+
+    fn write_private_key(&self, name: &str, pem: &str) -> Result<()> {
+        #[cfg(feature = "os-keychain")]
+        if let Some(entry) = self.keychain_entry(name)? {
+            return entry.set_password(pem).map_err(keychain_error);
+        }
+        write_new(&self.root.join(name), pem.as_bytes(), true)
+    }
+
+    fn delete_private_key(&self, name: &str) -> Result<()> {
+        #[cfg(feature = "os-keychain")]
+        if let Some(entry) = self.keychain_entry(name)? {
+            return match entry.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(keychain_error(e)),
+            };
+        }
+        match std::fs::remove_file(self.root.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "os-keychain")]
+fn keychain_error(e: keyring::Error) -> crate::io::Error {
+    error!(Other, "OS keychain error: {}", e)
+}
+
+/// Check that `name` can be used as a file name in the store. (Without clashing with the files of
+/// another key)
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with(".pub")
+        && !name.ends_with(".bundle")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'));
+    if !valid {
+        Err(error!(InvalidInput, "Invalid key name: {:?}", name))?;
+    }
+    Ok(())
+}
+
+/// Read a file, or `None` if it doesn't exist.
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write a new file. (Fails if it exists) With `private`, the file is only readable by its owner.
+/// (On Unix)
+fn write_new(path: &Path, content: &[u8], private: bool) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options.open(path)?.write_all(content)
+}
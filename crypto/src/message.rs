@@ -0,0 +1,305 @@
+//! This module provides a message-oriented writer and reader.
+//!
+//! `CryptoWriter` is a byte stream: the chunk boundaries depend on `BUFFER_SIZE`, not on the
+//! writes, so RPC-style exchanges need their own framing on top of it. With `MessageWriter`, each
+//! `send` produces one individually encrypted frame, and `MessageReader::recv` returns exactly
+//! one message, whatever its size.
+//!
+//! The stream starts with a `Header` (with `chunking` set to `Chunking::Message`, and
+//! `chunk_size` holding `MAX_MESSAGE_LEN`), followed by one frame per message:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+
+//! |   AES DATA LEN  |   |    AES Data     |
+//! +-----------------+   +-----------------+
+//! |     u32 BE      |   |                 |   ...
+//! +-----------------+   +-----------------+
+//! |        4        |   |  AES DATA LEN   |
+//! +-----------------+   +-----------------+
+//! ```
+//!
+//! The nonce starts at the header nonce, and is incremented for each frame. Each frame
+//! authenticates the hash of the header (see `Header::digest`), so a modified header fails the
+//! first frame. `MessageWriter::finish` writes an empty final frame, authenticated as the end of
+//! the stream (see `Header::end_marker`), so a truncated stream is detected by the reader.
+use super::{
+    cipher::Cipher,
+    decrypt::{unwrap_data_key, SlotKey},
+    encrypt::{generate_aes_key, key_check_value, key_commitment, wrap_data_key, WriterOptions},
+    error::{error, Error, Result},
+    header::{Chunking, Format, Header},
+    identity::{Identity, Recipient},
+    shared::{setup_rng, ChunkAad, Nonce, NonceCounter, AES_AUTH_TAG_LEN, HEADER_DIGEST_LEN},
+};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    AeadCore as _, Aes256Gcm, Key, KeyInit as _,
+};
+use rand::{CryptoRng, RngCore};
+
+/// The maximum length of a message. (16 MiB)
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// A writer that encrypts each message in its own frame.
+///
+/// See the module documentation for the format of the stream.
+///
+/// **Warning**: `finish` must be called once all the messages are sent, otherwise the reader
+/// reports the stream as truncated.
+pub struct MessageWriter<W: std::io::Write> {
+    writer: W,
+    nonce: NonceCounter,
+    cipher: Aes256Gcm,
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+}
+
+impl<W: std::io::Write> MessageWriter<W> {
+    /// Create a new `MessageWriter` instance, and write the header.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted messages.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Returns
+    /// A `MessageWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    /// - `Io`: If an I/O error occurs while writing the header.
+    ///
+    pub fn new(writer: W, key: impl Into<Recipient>) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::new_with_rng(writer, key, &mut rng)
+    }
+
+    /// Create a new `MessageWriter` instance with the given random number generator.
+    /// The `key` is used to encrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted messages.
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `rng`: The random number generator.
+    ///
+    /// # Returns
+    /// A `MessageWriter` instance.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn new_with_rng<R: CryptoRng + RngCore>(
+        mut writer: W,
+        key: impl Into<Recipient>,
+        mut rng: R,
+    ) -> Result<Self> {
+        let aes_key = generate_aes_key(&mut rng);
+        let nonce = Aes256Gcm::generate_nonce(&mut rng);
+
+        let header = Header {
+            chunk_size: Some(MAX_MESSAGE_LEN as u32),
+            chunking: Chunking::Message,
+            end_marker: true,
+            key_check: Some(key_check_value(&aes_key)),
+            commitment: Some(key_commitment(&aes_key)),
+            ..Header::new(
+                Format::LATEST,
+                wrap_data_key(
+                    &key.into().into_rsa(),
+                    &aes_key,
+                    &WriterOptions::default(),
+                    &mut rng,
+                )?,
+                nonce.into(),
+            )
+        };
+        header.write(&mut writer)?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            nonce: NonceCounter::new(nonce),
+            cipher: Aes256Gcm::new(&aes_key),
+            header_digest: header.digest()?,
+        })
+    }
+
+    /// Encrypt a message, and write it in its own frame.
+    /// The underlying writer is flushed, so the message is sent right away.
+    ///
+    /// # Arguments
+    /// - `message`: The message to send. (At most `MAX_MESSAGE_LEN` bytes)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the message is longer than `MAX_MESSAGE_LEN`.
+    /// - `Other`: If the AES encryption fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn send(&mut self, message: &[u8]) -> Result<()> {
+        if message.len() > MAX_MESSAGE_LEN {
+            Err(error!(
+                InvalidInput,
+                "Message is too long: {} bytes (at most {})",
+                message.len(),
+                MAX_MESSAGE_LEN
+            ))?;
+        }
+        self.write_frame(message, false)
+    }
+
+    /// Write the final frame, marking the end of the stream.
+    ///
+    /// # Returns
+    /// The underlying writer.
+    ///
+    /// # Errors
+    /// - `Other`: If the AES encryption fails.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(mut self) -> Result<W> {
+        self.write_frame(&[], true)?;
+        Ok(self.writer)
+    }
+
+    fn write_frame(&mut self, message: &[u8], is_final: bool) -> Result<()> {
+        let aad = ChunkAad::new(self.header_digest.as_ref(), None, is_final);
+        let payload = Payload {
+            msg: message,
+            aad: aad.as_bytes(),
+        };
+        let encrypted_data = self
+            .cipher
+            .encrypt(self.nonce.nonce(), payload)
+            .map_err(|e| error!(Other, "AES Encryption error: {}", e))?;
+        self.nonce.increment();
+
+        self.writer
+            .write_all(&(encrypted_data.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&encrypted_data)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A reader that decrypts the messages written by `MessageWriter`, one at a time.
+pub struct MessageReader<R: std::io::Read> {
+    reader: R,
+    nonce: NonceCounter,
+    cipher: Aes256Gcm,
+    header_digest: Option<[u8; HEADER_DIGEST_LEN]>,
+    /// The index of the next frame.
+    index: u64,
+    /// Whether the final frame was read.
+    finished: bool,
+}
+
+impl<R: std::io::Read> MessageReader<R> {
+    /// Create a new `MessageReader` instance, and read the header.
+    /// The `key` is used to decrypt the AES key.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which the encrypted messages are read.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// A `MessageReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the header is malformed, or the stream was not written by
+    ///   `MessageWriter`.
+    /// - `Error::WrongKey`: If the RSA key doesn't match the stream.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(mut reader: R, key: impl Into<Identity>) -> Result<Self> {
+        let header = Header::parse(&mut reader)?;
+        if header.chunking != Chunking::Message {
+            Err(error!(
+                InvalidData,
+                "Stream uses {:?} chunking, read it with `{}`",
+                header.chunking,
+                header.chunking.reader()
+            ))?;
+        }
+        if header.cipher != Cipher::Aes256Gcm {
+            Err(error!(
+                InvalidData,
+                "Unsupported cipher for messages: {:?}", header.cipher
+            ))?;
+        }
+        let (_, raw_aes_key) =
+            unwrap_data_key(&header, &[SlotKey::Rsa(key.into().into_rsa())], false)?;
+
+        Ok(Self {
+            reader,
+            nonce: NonceCounter::new(Nonce::from(header.nonce)),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&raw_aes_key)),
+            header_digest: header.digest()?,
+            index: 0,
+            finished: false,
+        })
+    }
+
+    /// Read and decrypt the next message.
+    ///
+    /// # Returns
+    /// The message, or `None` once the final frame is read.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the frame length is invalid.
+    /// - `UnexpectedEof`: If the stream ends before the final frame.
+    /// - `Error::CorruptedChunk`: If the frame fails the authentication.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut len = [0; 4];
+        self.reader
+            .read_exact(&mut len)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => {
+                    error!(
+                        UnexpectedEof,
+                        "Truncated stream: the final frame is missing"
+                    )
+                }
+                _ => e,
+            })?;
+        let len = u32::from_be_bytes(len) as usize;
+        if !(AES_AUTH_TAG_LEN..=MAX_MESSAGE_LEN + AES_AUTH_TAG_LEN).contains(&len) {
+            Err(error!(InvalidData, "Invalid frame length: {}", len))?;
+        }
+        let mut encrypted_data = vec![0; len];
+        self.reader.read_exact(&mut encrypted_data)?;
+
+        let aad = ChunkAad::new(self.header_digest.as_ref(), None, false);
+        let final_aad = ChunkAad::new(self.header_digest.as_ref(), None, true);
+        let payload = |aad| Payload {
+            msg: &encrypted_data,
+            aad,
+        };
+        let message = match self
+            .cipher
+            .decrypt(self.nonce.nonce(), payload(aad.as_bytes()))
+        {
+            Ok(message) => Some(message),
+            // The final frame is empty
+            Err(_)
+                if len == AES_AUTH_TAG_LEN
+                    && self
+                        .cipher
+                        .decrypt(self.nonce.nonce(), payload(final_aad.as_bytes()))
+                        .is_ok() =>
+            {
+                None
+            }
+            Err(_) => Err(Error::CorruptedChunk { index: self.index })?,
+        };
+        self.nonce.increment();
+        self.index += 1;
+        self.finished = message.is_none();
+        Ok(message)
+    }
+}
@@ -0,0 +1,124 @@
+//! This module provides `Metadata`, the encrypted metadata of a stream: the original name of the
+//! file, and its modification time.
+//!
+//! The metadata is written by `CryptoWriter::new_with_metadata` as the first bytes of the
+//! plaintext, and flagged in the header (see `Header::metadata`). `CryptoReader` reads it before
+//! returning any data (see `CryptoReader::metadata`), so it is encrypted and authenticated as the
+//! rest of the stream, but never returned as data.
+//!
+//! The metadata is encoded as:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     LENGTH      |   |     VERSION     |   |     FIELDS      |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     u32 BE      |   |       u8        |   |  (tag, len, v)  |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        4        |   |        1        |   |        -        |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The length counts the version and the fields. Each field is encoded as a `u8` tag, a `u16 BE`
+//! length and the value, as the header extensions. Unknown fields are skipped: unlike the header,
+//! the metadata doesn't change the way the stream is decrypted.
+//!
+//! | Tag | Field      | Value                   |
+//! |-----|------------|-------------------------|
+//! | 1   | `name`     | UTF-8                   |
+//! | 2   | `modified` | Unix time (`u64 BE`)    |
+use super::error::{error, Result};
+use alloc::{string::String, vec::Vec};
+
+/// The version of the metadata encoding.
+const METADATA_VERSION: u8 = 1;
+/// The length of the length prefix.
+pub(crate) const LENGTH_LEN: usize = 4;
+/// The maximum length of the encoded metadata. (Without the length prefix)
+pub(crate) const MAX_METADATA_LEN: usize = 64 * 1024;
+
+/// Field tags.
+const FIELD_NAME: u8 = 1;
+const FIELD_MODIFIED: u8 = 2;
+
+/// The encrypted metadata of a stream. (See the module documentation)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+    /// The original name of the file. (A file name, without the directories)
+    ///
+    /// **Warning**: The name is chosen by the sender. Check it before creating a file with it,
+    /// e.g. that it has no path separator.
+    pub name: Option<String>,
+    /// The modification time of the file, as a Unix time in seconds.
+    pub modified: Option<u64>,
+}
+
+impl Metadata {
+    /// Encode the metadata, length prefix included.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If a field, or the whole metadata, is too long.
+    ///
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut fields = Vec::new();
+        if let Some(name) = &self.name {
+            push_field(&mut fields, FIELD_NAME, name.as_bytes())?;
+        }
+        if let Some(modified) = self.modified {
+            push_field(&mut fields, FIELD_MODIFIED, &modified.to_be_bytes())?;
+        }
+        let len = 1 + fields.len();
+        if len > MAX_METADATA_LEN {
+            Err(error!(InvalidInput, "Metadata is too long"))?;
+        }
+        let mut bytes = Vec::with_capacity(LENGTH_LEN + len);
+        bytes.extend_from_slice(&(len as u32).to_be_bytes());
+        bytes.push(METADATA_VERSION);
+        bytes.extend_from_slice(&fields);
+        Ok(bytes)
+    }
+
+    /// Decode metadata encoded by `to_bytes`, without the length prefix.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the metadata is malformed, or its version is unsupported.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || error!(InvalidData, "Invalid stream metadata");
+        let (&version, mut fields) = bytes.split_first().ok_or_else(invalid)?;
+        if version != METADATA_VERSION {
+            Err(error!(
+                InvalidData,
+                "Unsupported stream metadata version: {}", version
+            ))?;
+        }
+        let mut metadata = Self::default();
+        while !fields.is_empty() {
+            let (&[tag, len_hi, len_lo], rest) = fields.split_first_chunk().ok_or_else(invalid)?;
+            let len = u16::from_be_bytes([len_hi, len_lo]) as usize;
+            let (value, rest) = rest.split_at_checked(len).ok_or_else(invalid)?;
+            fields = rest;
+            match tag {
+                FIELD_NAME => {
+                    let name = core::str::from_utf8(value).map_err(|_| invalid())?;
+                    metadata.name = Some(name.into());
+                }
+                FIELD_MODIFIED => {
+                    let modified = value.try_into().map_err(|_| invalid())?;
+                    metadata.modified = Some(u64::from_be_bytes(modified));
+                }
+                // Unknown fields are skipped
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+fn push_field(fields: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<()> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| error!(InvalidInput, "Metadata field {} is too long", tag))?;
+    fields.push(tag);
+    fields.extend_from_slice(&len.to_be_bytes());
+    fields.extend_from_slice(value);
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! This module provides the decryption of memory-mapped files.
+//!
+//! `decrypt_file_mmap` maps the encrypted file in memory, and returns a `CryptoReader` reading
+//! the chunks from the map. The ciphertext is copied once, from the page cache into the chunk
+//! buffer, without the `read` calls (and the intermediate buffer) of a `BufReader<File>`. For
+//! large local files, this is noticeably faster than buffered I/O.
+//!
+//! **Warning**: The file must not be modified (nor truncated) while it is mapped. A concurrent
+//! truncation makes the process crash (`SIGBUS`) when the missing pages are read, and a concurrent
+//! modification is undefined behavior. So `MmapReader::open` and `decrypt_file_mmap` are `unsafe`:
+//! the caller guarantees that no other process (nor thread) changes the file.
+use super::{decrypt::CryptoReader, error::Result, identity::Identity};
+use memmap2::Mmap;
+use std::{fs::File, io::Read, path::Path};
+
+/// A reader over a memory-mapped file.
+pub struct MmapReader {
+    map: Mmap,
+    pos: usize,
+}
+
+impl MmapReader {
+    /// Map a file in memory.
+    ///
+    /// # Arguments
+    /// - `path`: The path of the file.
+    ///
+    /// # Returns
+    /// An `MmapReader` instance, reading from the start of the file.
+    ///
+    /// # Errors
+    /// - `Io`: If the file can't be opened or mapped.
+    ///
+    /// # Safety
+    /// The file must not be modified (nor truncated) while the `MmapReader` is alive. (By this
+    /// process or another one)
+    ///
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: The map is read-only, and the caller guarantees that the file isn't modified
+        // while it is mapped.
+        let map = unsafe { Mmap::map(&file)? };
+        #[cfg(unix)]
+        map.advise(memmap2::Advice::Sequential)?;
+        Ok(Self { map, pos: 0 })
+    }
+
+    /// The data not read yet.
+    pub fn remaining(&self) -> &[u8] {
+        &self.map[self.pos..]
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.remaining().read(buf)?;
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+/// Decrypt a file through a memory map.
+///
+/// # Arguments
+/// - `path`: The path of the encrypted file.
+/// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+///
+/// # Returns
+/// A `CryptoReader` reading the decrypted data from the mapped file.
+///
+/// # Errors
+/// - `Io`: If the file can't be opened or mapped.
+/// - The errors of `CryptoReader::new`.
+///
+/// # Safety
+/// The file must not be modified (nor truncated) while the reader is alive. (See
+/// `MmapReader::open`)
+///
+/// # Example
+/// ```ignore
+/// // SAFETY: The archive is not modified while it is decrypted.
+/// let mut reader = unsafe { decrypt_file_mmap::<_, 4096>("archive.enc", key)? };
+/// std::io::copy(&mut reader, &mut output)?;
+/// ```
+///
+pub unsafe fn decrypt_file_mmap<P: AsRef<Path>, const BUFFER_SIZE: usize>(
+    path: P,
+    key: impl Into<Identity>,
+) -> Result<CryptoReader<MmapReader, BUFFER_SIZE>> {
+    // SAFETY: Forwarded to the caller.
+    CryptoReader::new(unsafe { MmapReader::open(path)? }, key)
+}
@@ -0,0 +1,253 @@
+//! This module provides a sink for the multipart uploads of object stores. (e.g. S3)
+//!
+//! The object stores take large objects as numbered parts, uploaded one at a time, then assembled
+//! by a final request. `MultipartWriter` buffers the data written to it into parts of a fixed
+//! size, and hands each full part to a `PartSink`, which uploads it. It is a plain sink: it is
+//! meant to be wrapped by a `CryptoWriter`, so the parts hold the ciphertext.
+//!
+//! `part_size` derives the size of the parts from the chunk size and the length of the stream,
+//! within the limits of S3: at least `MIN_PART_SIZE` bytes (but the last part), and at most
+//! `MAX_PARTS` parts. The length of the stream is known before writing it. (See
+//! `format::stream_len`)
+//!
+//! A `PartSink` for the AWS SDK would look like this:
+//!
+//! ```ignore
+//! struct S3Upload {
+//!     client: aws_sdk_s3::Client,
+//!     runtime: tokio::runtime::Handle,
+//!     bucket: String,
+//!     key: String,
+//!     upload_id: String,
+//!     parts: Vec<CompletedPart>,
+//! }
+//!
+//! impl PartSink for S3Upload {
+//!     fn upload_part(&mut self, number: u32, data: Vec<u8>) -> std::io::Result<()> {
+//!         let output = self
+//!             .runtime
+//!             .block_on(
+//!                 self.client
+//!                     .upload_part()
+//!                     .bucket(&self.bucket)
+//!                     .key(&self.key)
+//!                     .upload_id(&self.upload_id)
+//!                     .part_number(number as i32)
+//!                     .body(data.into())
+//!                     .send(),
+//!             )
+//!             .map_err(std::io::Error::other)?;
+//!         self.parts.push(
+//!             CompletedPart::builder()
+//!                 .part_number(number as i32)
+//!                 .set_e_tag(output.e_tag)
+//!                 .build(),
+//!         );
+//!         Ok(())
+//!     }
+//!
+//!     fn complete(&mut self) -> std::io::Result<()> {
+//!         let upload = CompletedMultipartUpload::builder()
+//!             .set_parts(Some(std::mem::take(&mut self.parts)))
+//!             .build();
+//!         self.runtime
+//!             .block_on(
+//!                 self.client
+//!                     .complete_multipart_upload()
+//!                     .bucket(&self.bucket)
+//!                     .key(&self.key)
+//!                     .upload_id(&self.upload_id)
+//!                     .multipart_upload(upload)
+//!                     .send(),
+//!             )
+//!             .map_err(std::io::Error::other)?;
+//!         Ok(())
+//!     }
+//!
+//!     fn abort(&mut self) -> std::io::Result<()> {
+//!         // `abort_multipart_upload`, so the store drops the uploaded parts
+//!         ...
+//!     }
+//! }
+//!
+//! // With the `upload_id` of `create_multipart_upload`
+//! let mut file = File::open("backup.tar")?;
+//! let stream_len = format::stream_len(file.metadata()?.len(), 65536, 4096);
+//! let part_size = multipart::part_size::<65536>(stream_len)?;
+//! let mut upload = MultipartWriter::new(S3Upload { .. }, part_size)?;
+//! let mut writer = CryptoWriter::<_, 65536>::new(&mut upload, public_key)?;
+//! std::io::copy(&mut file, &mut writer)?;
+//! writer.flush()?;
+//! drop(writer);
+//! upload.finish()?;
+//! ```
+//!
+//! **Warning**: `finish` must be called once the `CryptoWriter` is flushed, otherwise the last part
+//! is never uploaded, and the upload never completed. On a failure, call `abort` so the store
+//! doesn't keep the uploaded parts.
+use super::{
+    error::{error, Result},
+    shared::AES_AUTH_TAG_LEN,
+};
+use std::io::Write;
+
+/// The minimum size of a part, but the last one. (5 MiB, the limit of S3)
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// The maximum size of a part. (5 GiB, the limit of S3)
+pub const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+/// The maximum number of parts of an upload. (The limit of S3)
+pub const MAX_PARTS: u32 = 10_000;
+
+/// The destination of the parts of a multipart upload.
+pub trait PartSink {
+    /// Upload a part.
+    ///
+    /// # Arguments
+    /// - `number`: The number of the part. (Starting at 1, as S3)
+    /// - `data`: The data of the part. (`part_size` bytes, but the last part)
+    ///
+    fn upload_part(&mut self, number: u32, data: Vec<u8>) -> std::io::Result<()>;
+
+    /// Complete the upload, once every part is uploaded.
+    fn complete(&mut self) -> std::io::Result<()>;
+
+    /// Abort the upload, dropping the parts already uploaded. (Nothing by default)
+    fn abort(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The size of the parts of a stream, for a multipart upload.
+///
+/// # Arguments
+/// - `stream_len`: The length of the stream. (See `format::stream_len`, or
+///   `CryptoWriter::predicted_len`)
+///
+/// # Returns
+/// The smallest multiple of the length of an encrypted chunk (`BUFFER_SIZE` + the tag) of at
+/// least `MIN_PART_SIZE` bytes, splitting the stream into at most `MAX_PARTS` parts. (So the
+/// chunks are cut at the same place in every part)
+///
+/// # Errors
+/// - `InvalidInput`: If the parts would be larger than `MAX_PART_SIZE`.
+///
+pub fn part_size<const BUFFER_SIZE: usize>(stream_len: u64) -> Result<u64> {
+    let chunk_len = (BUFFER_SIZE + AES_AUTH_TAG_LEN) as u64;
+    let min_size = std::cmp::max(MIN_PART_SIZE, stream_len.div_ceil(MAX_PARTS as u64));
+    let part_size = min_size.div_ceil(chunk_len) * chunk_len;
+    if part_size > MAX_PART_SIZE {
+        Err(error!(
+            InvalidInput,
+            "A stream of {} bytes needs parts larger than {} bytes", stream_len, MAX_PART_SIZE
+        ))?;
+    }
+    Ok(part_size)
+}
+
+/// A writer that uploads the data as the parts of a multipart upload.
+///
+/// **Warning**: `finish` must be called once all the data is written, otherwise the last part is
+/// not uploaded.
+pub struct MultipartWriter<S: PartSink> {
+    sink: S,
+    part_size: usize,
+    /// The data of the current part.
+    part: Vec<u8>,
+    part_count: u32,
+}
+
+impl<S: PartSink> MultipartWriter<S> {
+    /// Create a new `MultipartWriter` instance.
+    ///
+    /// # Arguments
+    /// - `sink`: The destination of the parts.
+    /// - `part_size`: The size of the parts, but the last one. (See `part_size`)
+    ///
+    /// # Returns
+    /// A `MultipartWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `part_size` is 0, or larger than the memory.
+    ///
+    /// # Notes
+    /// The current part is held in memory: `part_size` bytes at most.
+    ///
+    pub fn new(sink: S, part_size: u64) -> Result<Self> {
+        let part_size = usize::try_from(part_size)
+            .ok()
+            .filter(|&part_size| part_size > 0)
+            .ok_or_else(|| error!(InvalidInput, "Invalid part size: {}", part_size))?;
+        Ok(Self {
+            sink,
+            part_size,
+            part: Vec::new(),
+            part_count: 0,
+        })
+    }
+
+    /// The number of parts uploaded so far.
+    pub fn part_count(&self) -> u32 {
+        self.part_count
+    }
+
+    /// Upload the current part.
+    fn upload_part(&mut self) -> Result<()> {
+        let number = self
+            .part_count
+            .checked_add(1)
+            .ok_or_else(|| error!(Other, "Too many parts"))?;
+        self.sink
+            .upload_part(number, std::mem::take(&mut self.part))?;
+        self.part_count = number;
+        Ok(())
+    }
+
+    /// Upload the last part, and complete the upload.
+    ///
+    /// # Returns
+    /// The sink, and the number of parts. (At least one, even if no data was written)
+    ///
+    /// # Errors
+    /// - `Io`: If the upload of the last part, or the completion, fails. (The upload can still be
+    ///   aborted with `PartSink::abort`)
+    ///
+    pub fn finish(mut self) -> Result<(S, u32)> {
+        if !self.part.is_empty() || self.part_count == 0 {
+            self.upload_part()?;
+        }
+        self.sink.complete()?;
+        Ok((self.sink, self.part_count))
+    }
+
+    /// Abort the upload. (See `PartSink::abort`)
+    ///
+    /// # Errors
+    /// - `Io`: If the abort fails.
+    ///
+    pub fn abort(mut self) -> Result<S> {
+        self.sink.abort()?;
+        Ok(self.sink)
+    }
+}
+
+impl<S: PartSink> Write for MultipartWriter<S> {
+    /// Write data to the current part.
+    /// The part is uploaded once full.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.part.len() == self.part_size {
+            self.upload_part()?;
+        }
+        if self.part.capacity() == 0 {
+            self.part.reserve_exact(self.part_size);
+        }
+        let to_write = std::cmp::min(buf.len(), self.part_size - self.part.len());
+        self.part.extend_from_slice(&buf[..to_write]);
+        Ok(to_write)
+    }
+
+    /// Nothing to do: a part is only uploaded once full, or by `finish`. (The parts but the last
+    /// one can't be smaller than `part_size`)
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+//! This module provides the multiplexing of several encrypted streams over one transport. (e.g.
+//! the control messages and the bulk data of a protocol, over a single TCP connection)
+//!
+//! Each logical stream is a stream of `CryptoWriter`, with its own key and options, emitted as the
+//! frames of the `framed` module. `MuxWriter` prefixes each frame with the ID of its stream, so the
+//! frames of the streams can be interleaved:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+
+//! |    STREAM ID    |   |      Frame      |
+//! +-----------------+   +-----------------+
+//! |     Varint      |   |  See `framed`   |
+//! |  (as protobuf)  |   |                 |
+//! +-----------------+   +-----------------+
+//! |     1 to 10     |   |                 |
+//! +-----------------+   +-----------------+
+//! ```
+//!
+//! `MuxReader` demultiplexes the frames, and decrypts each stream with the identity registered for
+//! its ID:
+//!
+//! ```ignore
+//! // Sender
+//! let mut mux = MuxWriter::<_, 4096>::new(connection);
+//! mux.open(CONTROL, control_key, &WriterOptions::default())?;
+//! mux.open(DATA, data_key, &WriterOptions::default())?;
+//! mux.write(DATA, &bulk)?;
+//! mux.write(CONTROL, b"pause")?;
+//! mux.finish(CONTROL)?;
+//! mux.finish(DATA)?;
+//!
+//! // Receiver
+//! let mut demux = MuxReader::<_, 4096>::new(connection);
+//! demux.register(CONTROL, control_identity);
+//! demux.register(DATA, data_identity);
+//! while let Some(event) = demux.next_event()? {
+//!     match event {
+//!         MuxEvent::Data { stream, data } => ...,
+//!         MuxEvent::End { stream } => ...,
+//!     }
+//! }
+//! ```
+//!
+//! **Note**: The stream IDs and the frames are not authenticated. The data of each stream is
+//! authenticated by its `CryptoReader`, so a tampered frame fails the decryption of its stream,
+//! and a frame moved to another stream fails with the key of that stream. A stream is only
+//! complete once its `MuxEvent::End` is returned.
+use super::{
+    decrypt::CryptoReader,
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    framed::{encode_frame, encode_varint, read_frame, read_varint, FrameKind, MAX_VARINT_LEN},
+    identity::{Identity, Recipient},
+    shared::AES_AUTH_TAG_LEN,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+};
+
+/// A writer that interleaves several encrypted streams, each with its own key.
+///
+/// See the module documentation for the format of the frames.
+///
+/// **Warning**: Each stream must be finished (see `finish`), as it writes its final frame.
+/// (Without it, `MuxReader` reports the stream as truncated)
+pub struct MuxWriter<W: Write, const BUFFER_SIZE: usize> {
+    writer: W,
+    /// The open streams, by ID.
+    streams: HashMap<u64, CryptoWriter<Vec<u8>, BUFFER_SIZE>>,
+}
+
+impl<W: Write, const BUFFER_SIZE: usize> MuxWriter<W, BUFFER_SIZE> {
+    /// Create a new `MuxWriter` instance, without any stream.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Open a stream, and write its header frame.
+    ///
+    /// # Arguments
+    /// - `stream`: The ID of the stream.
+    /// - `key`: The recipient of the stream, whose RSA public key encrypts its AES key. (See
+    ///   `Recipient`)
+    /// - `options`: The options of the stream. (See `WriterOptions`)
+    ///
+    /// # Errors
+    /// - `AlreadyExists`: If a stream with this ID is open.
+    /// - The errors of `CryptoWriter::new_with_options`.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    /// # Notes
+    /// The ID of a finished stream can be reused, for a new stream.
+    ///
+    pub fn open(
+        &mut self,
+        stream: u64,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+    ) -> Result<()> {
+        if self.streams.contains_key(&stream) {
+            Err(error!(AlreadyExists, "Stream {} is already open", stream))?;
+        }
+        let mut inner = CryptoWriter::new_with_options(Vec::new(), key, options)?;
+        let header = std::mem::take(inner.writer_mut());
+        self.write_frame(stream, FrameKind::Header, &header)?;
+        self.streams.insert(stream, inner);
+        Ok(())
+    }
+
+    /// Encrypt data on a stream. The chunks are written as frames once full.
+    ///
+    /// # Errors
+    /// - `NotFound`: If the stream is not open.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn write(&mut self, stream: u64, data: &[u8]) -> Result<()> {
+        let inner = self
+            .streams
+            .get_mut(&stream)
+            .ok_or_else(|| error!(NotFound, "Stream {} is not open", stream))?;
+        inner.write_all(data)?;
+        let encrypted = std::mem::take(inner.writer_mut());
+        for chunk in encrypted.chunks(BUFFER_SIZE + AES_AUTH_TAG_LEN) {
+            self.write_frame(stream, FrameKind::Chunk, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Finish a stream: write its final chunk as the final frame.
+    ///
+    /// # Errors
+    /// - `NotFound`: If the stream is not open.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(&mut self, stream: u64) -> Result<()> {
+        let mut inner = self
+            .streams
+            .remove(&stream)
+            .ok_or_else(|| error!(NotFound, "Stream {} is not open", stream))?;
+        inner.flush()?;
+        // The chunks written before the final one, if the final chunk was encrypted with them
+        let chunk_len = BUFFER_SIZE + AES_AUTH_TAG_LEN;
+        let encrypted = std::mem::take(inner.writer_mut());
+        let final_start = encrypted.len().saturating_sub(1) / chunk_len * chunk_len;
+        for chunk in encrypted[..final_start].chunks(chunk_len) {
+            self.write_frame(stream, FrameKind::Chunk, chunk)?;
+        }
+        self.write_frame(stream, FrameKind::Final, &encrypted[final_start..])
+    }
+
+    /// The IDs of the open streams.
+    pub fn open_streams(&self) -> impl Iterator<Item = u64> + '_ {
+        self.streams.keys().copied()
+    }
+
+    /// Flush the underlying writer.
+    /// The data of the current chunk of each stream stays buffered until the chunk is full, or
+    /// the stream is finished.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// Get back the underlying writer, once every stream is finished.
+    ///
+    /// # Errors
+    /// - `Other`: If a stream is still open.
+    /// - `Io`: If the flush of the underlying writer fails.
+    ///
+    pub fn into_inner(mut self) -> Result<W> {
+        if let Some(stream) = self.streams.keys().next() {
+            Err(error!(Other, "Stream {} is not finished", stream))?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Write a frame of a stream, with one `write_all` call.
+    fn write_frame(&mut self, stream: u64, kind: FrameKind, data: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(2 * MAX_VARINT_LEN + 1 + data.len());
+        encode_varint(&mut frame, stream);
+        encode_frame(&mut frame, kind, data);
+        self.writer.write_all(&frame)
+    }
+}
+
+/// An event of the streams read by a `MuxReader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxEvent {
+    /// Data decrypted from a stream.
+    Data {
+        /// The ID of the stream.
+        stream: u64,
+        /// The decrypted data.
+        data: Vec<u8>,
+    },
+    /// The end of a stream: all its data is returned, and authenticated.
+    End {
+        /// The ID of the stream.
+        stream: u64,
+    },
+}
+
+/// The frames received for a stream, read by its `CryptoReader`.
+struct Inbox {
+    data: VecDeque<u8>,
+    /// Whether the final frame was received.
+    finished: bool,
+}
+
+impl Read for Inbox {
+    /// Read the data received. `WouldBlock` until the next frame, then the end of the stream
+    /// once the final frame is read.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.data.is_empty() && !self.finished {
+            Err(error!(WouldBlock, "Waiting for the next frame"))?;
+        }
+        self.data.read(buf)
+    }
+}
+
+/// The state of a stream of a `MuxReader`.
+enum StreamState<const BUFFER_SIZE: usize> {
+    /// Registered, waiting for its header frame.
+    Pending(Box<Identity>),
+    /// The header is read, the chunks are being decrypted.
+    Open(Box<CryptoReader<Inbox, BUFFER_SIZE>>),
+}
+
+/// A reader that demultiplexes the streams written by `MuxWriter`, and decrypts them.
+///
+/// The identity of each stream is registered with `register`, before its first frame is read.
+pub struct MuxReader<R: Read, const BUFFER_SIZE: usize> {
+    reader: R,
+    /// The registered streams, by ID. (Removed once finished)
+    streams: HashMap<u64, StreamState<BUFFER_SIZE>>,
+    /// The events decoded, not returned yet.
+    events: VecDeque<MuxEvent>,
+}
+
+impl<R: Read, const BUFFER_SIZE: usize> MuxReader<R, BUFFER_SIZE> {
+    /// Create a new `MuxReader` instance, without any stream.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            streams: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Register a stream, to be decrypted with the given identity.
+    ///
+    /// # Arguments
+    /// - `stream`: The ID of the stream.
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Notes
+    /// Registering an ID again replaces its identity, and restarts the stream if its header was
+    /// read. (e.g. to reuse the ID of a finished stream)
+    ///
+    pub fn register(&mut self, stream: u64, key: impl Into<Identity>) {
+        self.streams
+            .insert(stream, StreamState::Pending(Box::new(key.into())));
+    }
+
+    /// Read the frames until the next event.
+    ///
+    /// # Returns
+    /// The next event, or `None` at the end of the underlying reader.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If a frame is invalid, belongs to a stream not registered, or is out of
+    ///   order in its stream.
+    /// - `Error::CorruptedChunk`: If a chunk of a stream fails the authentication. (The errors of
+    ///   `CryptoReader`)
+    /// - `UnexpectedEof`: If the underlying reader ends before the final frame of a stream.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn next_event(&mut self) -> Result<Option<MuxEvent>> {
+        while self.events.is_empty() {
+            let Some(stream) = read_varint(&mut self.reader)? else {
+                if let Some((stream, StreamState::Open(_))) = self
+                    .streams
+                    .iter()
+                    .find(|(_, state)| matches!(state, StreamState::Open(_)))
+                {
+                    Err(error!(
+                        UnexpectedEof,
+                        "Stream {} ends before its final frame", stream
+                    ))?;
+                }
+                return Ok(None);
+            };
+            let (kind, data) = read_frame(&mut self.reader)?
+                .ok_or_else(|| error!(UnexpectedEof, "The frame header is truncated"))?;
+            self.handle_frame(stream, kind, data)?;
+        }
+        Ok(self.events.pop_front())
+    }
+
+    /// Pass a frame to its stream, and queue the events it produces.
+    fn handle_frame(&mut self, stream: u64, kind: FrameKind, data: Vec<u8>) -> Result<()> {
+        let state = self
+            .streams
+            .remove(&stream)
+            .ok_or_else(|| error!(InvalidData, "Unknown stream {}", stream))?;
+        let mut reader = match (state, kind) {
+            (StreamState::Pending(key), FrameKind::Header) => {
+                let inbox = Inbox {
+                    data: data.into(),
+                    finished: false,
+                };
+                let reader = CryptoReader::new(inbox, *key)?;
+                self.streams
+                    .insert(stream, StreamState::Open(Box::new(reader)));
+                return Ok(());
+            }
+            (StreamState::Open(reader), FrameKind::Chunk | FrameKind::Final) => reader,
+            _ => Err(error!(
+                InvalidData,
+                "Unexpected {:?} frame for stream {}", kind, stream
+            ))?,
+        };
+        let inbox = reader.get_mut();
+        inbox.data.extend(data);
+        inbox.finished = kind == FrameKind::Final;
+
+        let mut decrypted = Vec::new();
+        let finished = match reader.read_to_end(&mut decrypted) {
+            Ok(_) => true,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+            Err(e) => return Err(e),
+        };
+        if !decrypted.is_empty() {
+            self.events.push_back(MuxEvent::Data {
+                stream,
+                data: decrypted,
+            });
+        }
+        if finished {
+            self.events.push_back(MuxEvent::End { stream });
+        } else {
+            self.streams.insert(stream, StreamState::Open(reader));
+        }
+        Ok(())
+    }
+
+    /// Get back the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -0,0 +1,698 @@
+//! This module provides the file operations of the `crypto-files` CLI, so other tools can embed
+//! them: `keygen`, `encrypt_path`, `decrypt_path`, `verify_path` and `rekey`. The CLI is a thin
+//! wrapper around them.
+//!
+//! The files follow the layout of the CLI:
+//!
+//! ```plaintext
+//! alice              The private key (PKCS#1 PEM, or encrypted PKCS#8 with a passphrase)
+//! alice.pub          The public key (PKCS#1 PEM)
+//! alice.bundle       The owner, creation and expiry time of the key (see `sidecar`)
+//! data.enc           An encrypted file
+//! data.enc.001, ...  The parts of a split encrypted file (see `part_path`)
+//! data.enc.share1    A share of the AES key of an encrypted file (see `share_path`)
+//! ```
+//!
+//! The operations never prompt: an existing output file is only overwritten with the `overwrite`
+//! option, and fails with `AlreadyExists` otherwise. The errors on the files of the operation are
+//! wrapped in a `FileError`, so the input can be told from the output. The errors of the stream
+//! (e.g. `Error::WrongKey`) are returned as is.
+use super::{
+    bundle::unix_now,
+    decrypt::{CryptoReader, StreamInfo},
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    key::{Attempt, RsaKeys},
+    metadata::Metadata,
+    parts::{JoinReader, SplitWriter},
+    shared::RSA_KEY_LEN,
+    split,
+};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::{
+    io::{self, Read, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+};
+
+/// The size of the buffer used to copy the data.
+const COPY_BUFFER_LEN: usize = 8192;
+
+/// A file of an operation which couldn't be read or written.
+///
+/// It is returned wrapped in a `std::io::Error`, with the kind of the original error. Use
+/// `FileError::from_io` to get it back.
+#[derive(Debug)]
+pub struct FileError {
+    /// The file. (`None` for the writer of `Output::Writer`)
+    pub path: Option<PathBuf>,
+    /// Whether the file is an output of the operation.
+    pub output: bool,
+    /// The original error.
+    pub source: io::Error,
+}
+
+impl FileError {
+    /// Get the `FileError` wrapped in an I/O error, if any.
+    pub fn from_io(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Wrap an error on the file at `path`. (An error already wrapped is returned as is)
+    fn wrap(path: Option<&Path>, output: bool, source: io::Error) -> io::Error {
+        if Self::from_io(&source).is_some() {
+            return source;
+        }
+        let kind = source.kind();
+        let error = Self {
+            path: path.map(Path::to_path_buf),
+            output,
+            source,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+impl core::fmt::Display for FileError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let access = if self.output { "write" } else { "read" };
+        match &self.path {
+            Some(path) => write!(f, "Failed to {} {}", access, path.display())?,
+            None => write!(f, "Failed to {} the output", access)?,
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl core::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Options used by `keygen`.
+#[derive(Clone)]
+pub struct KeygenOptions {
+    /// The size of the key in bits. (Default: 2048)
+    pub bits: usize,
+    /// The owner of the key, recorded in the bundle. (Default: the file name of the private key)
+    pub owner: Option<String>,
+    /// The validity period of the key, in seconds. (Default: `None`, the key doesn't expire)
+    pub expires: Option<u64>,
+    /// Protect the private key with this passphrase. (Encrypted PKCS#8, default: `None`)
+    #[cfg(feature = "encrypted-keys")]
+    pub passphrase: Option<String>,
+    /// Overwrite the existing key files. (Default: `false`)
+    pub overwrite: bool,
+}
+
+impl Default for KeygenOptions {
+    fn default() -> Self {
+        Self {
+            bits: RSA_KEY_LEN,
+            owner: None,
+            expires: None,
+            #[cfg(feature = "encrypted-keys")]
+            passphrase: None,
+            overwrite: false,
+        }
+    }
+}
+
+/// The keys generated by `keygen`, and their files.
+pub struct GeneratedKeys {
+    /// The key pair.
+    pub keys: RsaKeys,
+    /// The file of the private key.
+    pub private_key: PathBuf,
+    /// The file of the public key. (`<private key>.pub`)
+    pub public_key: PathBuf,
+    /// The file of the key bundle. (See `sidecar`)
+    pub bundle: PathBuf,
+}
+
+/// Options used by `encrypt_path`.
+#[derive(Debug, Clone)]
+pub struct EncryptOptions {
+    /// The options of the stream. (See `WriterOptions`)
+    pub writer: WriterOptions,
+    /// Record the name and the modification time of the input in the stream. (See `Metadata`,
+    /// default: `true`, ignored with `shares`)
+    pub metadata: bool,
+    /// Split the AES key into `(shares, threshold)` shares, saved next to the output. (See
+    /// `share_path`, default: `None`)
+    pub shares: Option<(u8, u8)>,
+    /// Split the encrypted data into parts of at most this size. (See `part_path`, default:
+    /// `None`)
+    pub split_size: Option<u64>,
+    /// Overwrite the existing output files. (Default: `false`)
+    pub overwrite: bool,
+}
+
+impl Default for EncryptOptions {
+    fn default() -> Self {
+        Self {
+            writer: WriterOptions::default(),
+            metadata: true,
+            shares: None,
+            split_size: None,
+            overwrite: false,
+        }
+    }
+}
+
+/// The files written by `encrypt_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encrypted {
+    /// The encrypted file, or its parts. (In order)
+    pub outputs: Vec<PathBuf>,
+    /// The shares of the AES key. (Empty without `EncryptOptions::shares`)
+    pub shares: Vec<PathBuf>,
+    /// The size of the plaintext.
+    pub size: u64,
+}
+
+/// The output of `decrypt_path`.
+pub enum Output<'a> {
+    /// Write to this file.
+    Path(&'a Path),
+    /// Write to the original name of the file, next to the input, and restore its modification
+    /// time. (See `restored_path`) If the name is unknown, write to this writer instead.
+    Restore(&'a mut dyn Write),
+    /// Write to this writer.
+    Writer(&'a mut dyn Write),
+}
+
+/// Options used by `decrypt_path`.
+#[derive(Debug, Clone, Default)]
+pub struct DecryptOptions {
+    /// Overwrite the existing output file. (Default: `false`)
+    pub overwrite: bool,
+}
+
+/// Options used by `verify_path`.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// Accept the legacy files, without end marker. (Default: `false`, see
+    /// `CryptoReader::verify_legacy`)
+    pub allow_legacy: bool,
+}
+
+/// The result of `decrypt_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decrypted {
+    /// The file written. (`None` if the data was written to a writer)
+    pub path: Option<PathBuf>,
+    /// The metadata of the stream, if any.
+    pub metadata: Option<Metadata>,
+    /// The size of the plaintext.
+    pub size: u64,
+}
+
+/// Options used by `rekey`.
+#[derive(Debug, Clone, Default)]
+pub struct RekeyOptions {
+    /// The options of the new stream. (See `WriterOptions`)
+    pub writer: WriterOptions,
+    /// Overwrite the existing output file. (Default: `false`)
+    pub overwrite: bool,
+}
+
+/// The result of `rekey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rekeyed {
+    /// The metadata of the stream, kept in the new stream.
+    pub metadata: Option<Metadata>,
+    /// The size of the plaintext.
+    pub size: u64,
+}
+
+/// Generate a key pair, and save it as `output` (private key), `output.pub` and its bundle.
+///
+/// # Errors
+/// The errors of `keygen_with`.
+///
+pub fn keygen(output: &Path, options: &KeygenOptions) -> Result<GeneratedKeys> {
+    keygen_with(output, options, |_| ControlFlow::Continue(()))
+}
+
+/// Generate a key pair, reporting each prime candidate to `progress`, and save it as `output`
+/// (private key), `output.pub` and its bundle. (See `RsaKeys::generate_with`)
+///
+/// # Returns
+/// The keys, and their files.
+///
+/// # Errors
+/// - `AlreadyExists`: If a key file exists, without `overwrite`. (Checked before the generation)
+/// - `InvalidInput`: If the size of the key is invalid.
+/// - `Interrupted`: If `progress` cancels the generation.
+/// - `Other`: If the keys can't be encoded.
+/// - `Io`: If a key file can't be written.
+///
+pub fn keygen_with(
+    output: &Path,
+    options: &KeygenOptions,
+    progress: impl FnMut(Attempt) -> ControlFlow<()>,
+) -> Result<GeneratedKeys> {
+    let public_key = output.with_extension("pub");
+    let bundle = sidecar(output);
+    // Checked before the generation, so no key file is left half-written
+    if !options.overwrite {
+        for path in [output, &public_key, &bundle] {
+            if path.exists() {
+                let e = error!(AlreadyExists, "The file already exists");
+                return Err(FileError::wrap(Some(path), true, e));
+            }
+        }
+    }
+
+    let keys = RsaKeys::generate_with(options.bits, progress).map_err(|e| {
+        match e.downcast::<io::Error>() {
+            Ok(e) => *e,
+            Err(e) => error!(Other, "Key generation error: {}", e),
+        }
+    })?;
+    let owner = options.owner.clone().unwrap_or_else(|| {
+        output
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    let expires = options.expires.map(|expires| unix_now() + expires);
+    let bundle_pem = keys
+        .export_bundle(&owner, expires)
+        .map_err(|e| error!(Other, "Key bundle error: {}", e))?
+        .to_pem()?;
+    #[cfg(feature = "encrypted-keys")]
+    let private_pem = match &options.passphrase {
+        Some(passphrase) => keys.private_key_to_encrypted_pem(passphrase.as_bytes()),
+        None => keys.private_key_to_pem(),
+    };
+    #[cfg(not(feature = "encrypted-keys"))]
+    let private_pem = keys.private_key_to_pem();
+    let private_pem = private_pem.map_err(|e| error!(Other, "Invalid private key: {}", e))?;
+    let public_pem = keys
+        .public_key_to_pem()
+        .map_err(|e| error!(Other, "Invalid public key: {}", e))?;
+
+    write_key_file(output, private_pem.as_bytes(), true, options.overwrite)?;
+    write_key_file(&public_key, public_pem.as_bytes(), false, options.overwrite)?;
+    write_key_file(&bundle, bundle_pem.as_bytes(), false, options.overwrite)?;
+    Ok(GeneratedKeys {
+        keys,
+        private_key: output.to_path_buf(),
+        public_key,
+        bundle,
+    })
+}
+
+/// Encrypt the file `input` to `output`.
+///
+/// # Arguments
+/// - `input`: The file to encrypt.
+/// - `output`: The encrypted file. (The base name of the parts and the shares, if any)
+/// - `key`: The RSA public key of the recipient.
+/// - `options`: The options of the operation. (See `EncryptOptions`)
+///
+/// # Returns
+/// The files written.
+///
+/// # Errors
+/// - `AlreadyExists`: If an output file exists, without `overwrite`. (In a `FileError`)
+/// - `InvalidInput`: If the shares or the split size are invalid.
+/// - `Io`: If the input can't be read, or an output can't be written. (In a `FileError`)
+///
+/// # Notes
+/// A split output is written part by part: an existing part may be reported after the previous
+/// parts are written.
+///
+pub fn encrypt_path<const BUFFER_SIZE: usize>(
+    input: &Path,
+    output: &Path,
+    key: RsaPublicKey,
+    options: &EncryptOptions,
+) -> Result<Encrypted> {
+    let overwrite = options.overwrite;
+    let mut data =
+        std::fs::File::open(input).map_err(|e| FileError::wrap(Some(input), false, e))?;
+    if let Some((shares, _)) = options.shares {
+        // Checked before writing anything
+        for index in 1..=shares {
+            check_available(&share_path(output, index), overwrite)?;
+        }
+    }
+    let mut split_writer = match options.split_size {
+        Some(split_size) => Some(SplitWriter::new(split_size, |index| {
+            create(&part_path(output, index), overwrite)
+        })?),
+        None => None,
+    };
+    let file: Box<dyn Write + '_> = match split_writer.as_mut() {
+        Some(split_writer) => Box::new(split_writer),
+        None => Box::new(create(output, overwrite)?),
+    };
+
+    let mut share_paths = Vec::new();
+    let mut writer = match options.shares {
+        Some((shares, threshold)) => {
+            let (writer, shares) = split::new_writer_with_shares::<_, BUFFER_SIZE>(
+                file,
+                key,
+                &options.writer,
+                shares,
+                threshold,
+            )?;
+            for share in shares {
+                let path = share_path(output, share.index);
+                create(&path, overwrite)?
+                    .write_all(&share.to_bytes())
+                    .map_err(|e| FileError::wrap(Some(&path), true, e))?;
+                share_paths.push(path);
+            }
+            writer
+        }
+        None if options.metadata => CryptoWriter::<_, BUFFER_SIZE>::new_with_metadata(
+            file,
+            key,
+            &options.writer,
+            &file_metadata(input),
+        )?,
+        None => CryptoWriter::<_, BUFFER_SIZE>::new_with_options(file, key, &options.writer)?,
+    };
+    let size = copy(
+        &mut data,
+        &mut writer,
+        |e| FileError::wrap(Some(input), false, e),
+        |e| FileError::wrap(Some(output), true, e),
+    )?;
+    writer
+        .flush()
+        .map_err(|e| FileError::wrap(Some(output), true, e))?;
+    drop(writer);
+
+    let outputs = match split_writer {
+        Some(split_writer) => {
+            let count = split_writer
+                .finish()
+                .map_err(|e| FileError::wrap(Some(output), true, e))?;
+            (0..count).map(|index| part_path(output, index)).collect()
+        }
+        None => vec![output.to_path_buf()],
+    };
+    Ok(Encrypted {
+        outputs,
+        shares: share_paths,
+        size,
+    })
+}
+
+/// Decrypt the file `input`. (Or its parts, see `open_input`)
+///
+/// # Arguments
+/// - `input`: The encrypted file.
+/// - `output`: Where to write the decrypted data. (See `Output`)
+/// - `key`: The RSA private key of the recipient.
+/// - `options`: The options of the operation. (See `DecryptOptions`)
+///
+/// # Returns
+/// The file written, and the metadata of the stream.
+///
+/// # Errors
+/// - `AlreadyExists`: If the output file exists, without `overwrite`. (In a `FileError`)
+/// - `InvalidData`: If the stream is malformed, no key matches (`Error::WrongKey`), or a chunk
+///   is corrupted. (`Error::CorruptedChunk`)
+/// - `Io`: If the input can't be read, or the output can't be written. (In a `FileError`)
+///
+/// # Notes
+/// On failure, the partially written output file is removed.
+///
+pub fn decrypt_path<const BUFFER_SIZE: usize>(
+    input: &Path,
+    output: Output<'_>,
+    key: RsaPrivateKey,
+    options: &DecryptOptions,
+) -> Result<Decrypted> {
+    let mut reader = CryptoReader::<_, BUFFER_SIZE>::new(open_input(input)?, key)?;
+    let metadata = reader.metadata().cloned();
+    let (path, restored) = match output {
+        Output::Path(path) => (path.to_path_buf(), false),
+        Output::Restore(writer) => match restored_path(input, metadata.as_ref()) {
+            Some(path) => (path, true),
+            None => return write_decrypted(&mut reader, writer, metadata),
+        },
+        Output::Writer(writer) => return write_decrypted(&mut reader, writer, metadata),
+    };
+
+    let mut file = create(&path, options.overwrite)?;
+    let size = copy(
+        &mut reader,
+        &mut file,
+        |e| e,
+        |e| FileError::wrap(Some(&path), true, e),
+    )
+    .inspect_err(|_| {
+        // The partial output must not be mistaken for the data
+        _ = std::fs::remove_file(&path);
+    })?;
+    if let Some(modified) = metadata.as_ref().and_then(|metadata| metadata.modified) {
+        if restored {
+            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified);
+            // The modification time is informative, the data is already saved
+            _ = file.set_modified(modified);
+        }
+    }
+    Ok(Decrypted {
+        path: Some(path),
+        metadata,
+        size,
+    })
+}
+
+/// Check the file `input` without writing its plaintext anywhere. (Or its parts, see
+/// `open_input`)
+///
+/// # Arguments
+/// - `input`: The encrypted file.
+/// - `key`: The RSA private key of the recipient.
+/// - `options`: The options of the operation. (See `VerifyOptions`)
+///
+/// # Returns
+/// The description of the stream. (See `CryptoReader::verify`)
+///
+/// # Errors
+/// - `Io`: If the input can't be opened. (In a `FileError`)
+/// - The errors of `CryptoReader::verify`. (Or `CryptoReader::verify_legacy`)
+///
+pub fn verify_path<const BUFFER_SIZE: usize>(
+    input: &Path,
+    key: RsaPrivateKey,
+    options: &VerifyOptions,
+) -> Result<StreamInfo> {
+    let input = open_input(input)?;
+    if options.allow_legacy {
+        CryptoReader::<_, BUFFER_SIZE>::verify_legacy(input, key)
+    } else {
+        CryptoReader::<_, BUFFER_SIZE>::verify(input, key)
+    }
+}
+
+/// Write the decrypted data to a writer. (See `Output::Writer`)
+fn write_decrypted(
+    reader: &mut impl Read,
+    writer: &mut dyn Write,
+    metadata: Option<Metadata>,
+) -> Result<Decrypted> {
+    let size = copy(reader, writer, |e| e, |e| FileError::wrap(None, true, e))?;
+    writer.flush().map_err(|e| FileError::wrap(None, true, e))?;
+    Ok(Decrypted {
+        path: None,
+        metadata,
+        size,
+    })
+}
+
+/// Re-encrypt the file `input` for another recipient, to `output`. The metadata is kept, and the
+/// plaintext only lives in the chunk buffers: it never hits the disk.
+///
+/// # Arguments
+/// - `input`: The encrypted file. (Or its parts, see `open_input`)
+/// - `output`: The re-encrypted file. (Always written in the latest format)
+/// - `old_key`: The RSA private key decrypting `input`.
+/// - `new_key`: The RSA public key of the new recipient.
+/// - `options`: The options of the operation. (See `RekeyOptions`)
+///
+/// # Errors
+/// The errors of `decrypt_path`.
+///
+/// # Notes
+/// On failure, the partially written output file is removed.
+///
+pub fn rekey<const BUFFER_SIZE: usize>(
+    input: &Path,
+    output: &Path,
+    old_key: RsaPrivateKey,
+    new_key: RsaPublicKey,
+    options: &RekeyOptions,
+) -> Result<Rekeyed> {
+    let mut reader = CryptoReader::<_, BUFFER_SIZE>::new(open_input(input)?, old_key)?;
+    let metadata = reader.metadata().cloned();
+    let file = create(output, options.overwrite)?;
+    let mut writer = match &metadata {
+        Some(metadata) => CryptoWriter::<_, BUFFER_SIZE>::new_with_metadata(
+            file,
+            new_key,
+            &options.writer,
+            metadata,
+        ),
+        None => CryptoWriter::<_, BUFFER_SIZE>::new_with_options(file, new_key, &options.writer),
+    }
+    .inspect_err(|_| _ = std::fs::remove_file(output))?;
+    let size = copy(
+        &mut reader,
+        &mut writer,
+        |e| e,
+        |e| FileError::wrap(Some(output), true, e),
+    )
+    .and_then(|size| {
+        writer
+            .flush()
+            .map_err(|e| FileError::wrap(Some(output), true, e))?;
+        Ok(size)
+    })
+    .inspect_err(|_| {
+        // The partial output must not be mistaken for the data
+        _ = std::fs::remove_file(output);
+    })?;
+    Ok(Rekeyed { metadata, size })
+}
+
+/// Open an encrypted file. If it doesn't exist but its parts do (see `part_path`), the parts are
+/// joined back.
+///
+/// # Errors
+/// - `InvalidData`: If a part is malformed. (See `JoinReader`)
+/// - `Io`: If the file can't be read. (In a `FileError`)
+///
+pub fn open_input(input: &Path) -> Result<Box<dyn Read + '_>> {
+    let open =
+        |path: &Path| std::fs::File::open(path).map_err(|e| FileError::wrap(Some(path), false, e));
+    if !input.exists() && part_path(input, 0).exists() {
+        let parts = JoinReader::new(move |index| open(&part_path(input, index)))?;
+        Ok(Box::new(parts))
+    } else {
+        Ok(Box::new(open(input)?))
+    }
+}
+
+/// The path of the key bundle of a key file. (`alice.pub` and `alice` share `alice.bundle`)
+pub fn sidecar(path: &Path) -> PathBuf {
+    path.with_extension("bundle")
+}
+
+/// The path of a share of the AES key. (`<output>.share<index>`, numbered from 1)
+pub fn share_path(output: &Path, index: u8) -> PathBuf {
+    PathBuf::from(format!("{}.share{}", output.display(), index))
+}
+
+/// The path of a part of a split encrypted file. (`<output>.001`, ..., numbered from 001)
+pub fn part_path(output: &Path, index: u32) -> PathBuf {
+    PathBuf::from(format!("{}.{:03}", output.display(), index + 1))
+}
+
+/// The path to restore a decrypted file to: its original name, next to the encrypted file.
+/// (`None` if the name is unknown, or isn't a plain file name)
+pub fn restored_path(input: &Path, metadata: Option<&Metadata>) -> Option<PathBuf> {
+    let name = metadata?.name.as_deref()?;
+    // The name is chosen by the sender: no directory, nor `..`
+    if Path::new(name).file_name() != Some(name.as_ref()) {
+        return None;
+    }
+    Some(input.with_file_name(name))
+}
+
+/// The metadata of a file to encrypt: its name and modification time.
+fn file_metadata(input: &Path) -> Metadata {
+    let modified = std::fs::metadata(input)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok());
+    Metadata {
+        name: input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_string),
+        modified: modified.map(|modified| modified.as_secs()),
+    }
+}
+
+/// Fail with `AlreadyExists` if `path` exists, without `overwrite`.
+fn check_available(path: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && path.exists() {
+        let e = error!(AlreadyExists, "The file already exists");
+        return Err(FileError::wrap(Some(path), true, e));
+    }
+    Ok(())
+}
+
+/// Create an output file. Without `overwrite`, an existing file is not overwritten.
+fn create(path: &Path, overwrite: bool) -> Result<std::fs::File> {
+    open_output(path, overwrite, false)
+}
+
+/// Write a key file. A private key is only readable by its owner. (On Unix)
+fn write_key_file(path: &Path, content: &[u8], private: bool, overwrite: bool) -> Result<()> {
+    let mut file = open_output(path, overwrite, private)?;
+    // The mode only applies to new files
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt as _;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| FileError::wrap(Some(path), true, e))?;
+    }
+    file.write_all(content)
+        .map_err(|e| FileError::wrap(Some(path), true, e))
+}
+
+fn open_output(path: &Path, overwrite: bool, private: bool) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options
+        .open(path)
+        .map_err(|e| FileError::wrap(Some(path), true, e))
+}
+
+/// Copy `reader` to `writer`, mapping the errors of each side.
+///
+/// # Returns
+/// The number of bytes copied.
+///
+fn copy(
+    reader: &mut impl Read,
+    writer: &mut (impl Write + ?Sized),
+    read_error: impl Fn(io::Error) -> io::Error,
+    write_error: impl Fn(io::Error) -> io::Error,
+) -> Result<u64> {
+    let mut buffer = [0u8; COPY_BUFFER_LEN];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(size),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(read_error(e)),
+        };
+        writer.write_all(&buffer[..n]).map_err(&write_error)?;
+        size += n as u64;
+    }
+}
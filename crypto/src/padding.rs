@@ -0,0 +1,107 @@
+//! This module provides the `Padding` applied to the final chunk of a stream.
+//!
+//! Without padding, the length of the ciphertext reveals the exact length of the plaintext. This
+//! is a problem for short secrets like passwords and tokens. With padding, the final chunk is
+//! extended before being encrypted, and the padding is removed transparently by `CryptoReader`.
+//!
+//! The padding follows ISO/IEC 7816-4: a `0x80` byte followed by as many `0x00` bytes as needed.
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   Final Data    |   |      0x80       |   |   0x00 ... 0x00 |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   DATA LEN      |   |        1        |   |   PADDING LEN   |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The padding is recorded in the `Header`, so the reader knows it has to remove it.
+use super::{
+    error::{error, Result},
+    io,
+};
+
+/// The padding applied to the final chunk of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Padding {
+    /// No padding. The ciphertext length reveals the plaintext length.
+    #[default]
+    None,
+    /// The final chunk is padded to the full chunk size. The ciphertext length only reveals the
+    /// number of chunks.
+    Block,
+    /// The final chunk is padded to the next Padmé length (capped at the chunk size). The
+    /// ciphertext length leaks at most O(log log n) bits of the final chunk length, with an
+    /// overhead of at most 12%.
+    Padme,
+}
+
+impl Padding {
+    /// The length of the final chunk once padded.
+    ///
+    /// # Arguments
+    /// - `len`: The length of the final chunk data. (Must be less than `chunk_size`)
+    /// - `chunk_size`: The size of a full chunk.
+    ///
+    /// # Returns
+    /// `None` if no padding is applied, the padded length otherwise.
+    ///
+    pub(crate) fn padded_len(self, len: usize, chunk_size: usize) -> Option<usize> {
+        match self {
+            Padding::None => None,
+            Padding::Block => Some(chunk_size),
+            Padding::Padme => Some(core::cmp::min(padme(len + 1), chunk_size)),
+        }
+    }
+}
+
+impl From<Padding> for u8 {
+    fn from(padding: Padding) -> Self {
+        match padding {
+            Padding::None => 0,
+            Padding::Block => 1,
+            Padding::Padme => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for Padding {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Padding::None),
+            1 => Ok(Padding::Block),
+            2 => Ok(Padding::Padme),
+            _ => Err(error!(InvalidData, "Unsupported padding: {}", value)),
+        }
+    }
+}
+
+/// Pad the data in place, from `len` to `padded_len`.
+pub(crate) fn pad(data: &mut [u8], len: usize, padded_len: usize) {
+    data[len] = 0x80;
+    data[len + 1..padded_len].fill(0);
+}
+
+/// Find the length of the padded data.
+///
+/// # Errors
+/// - `InvalidData`: If the padding is malformed.
+///
+pub(crate) fn unpad(data: &[u8]) -> Result<usize> {
+    match data.iter().rposition(|&b| b != 0) {
+        Some(idx) if data[idx] == 0x80 => Ok(idx),
+        _ => Err(error!(InvalidData, "Invalid padding in the final chunk")),
+    }
+}
+
+/// Padmé length: keep the `floor(log2(log2(len))) + 1` most significant bits of the length.
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let e = usize::BITS - 1 - len.leading_zeros(); // floor(log2(len))
+    let s = u32::BITS - e.leading_zeros(); // floor(log2(e)) + 1
+    let mask = (1usize << (e - s)) - 1;
+    (len + mask) & !mask
+}
@@ -0,0 +1,298 @@
+//! This module provides a writer that splits a stream into fixed-size parts, and a reader that
+//! joins them back.
+//!
+//! Some stores limit the size of a single object. `SplitWriter` cuts the data written to it into
+//! sequentially numbered parts of at most `part_size` bytes, each part being created by a caller
+//! provided function. (e.g. a file per part) `JoinReader` reads the parts back in order.
+//!
+//! `SplitWriter` is a plain sink: it is meant to be wrapped by a `CryptoWriter`, so the parts hold
+//! the ciphertext. (The split happens after the encryption)
+//!
+//! Each part is written as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |      MAGIC      |   |     SET ID      |   |      INDEX      |   |      Data       |   |     TRAILER     |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |   "CRYPTOPART"  |   |     Random      |   |     u32 BE      |   |                 |   |  0: more, 1: end|
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |       10        |   |       16        |   |        4        |   |    DATA LEN     |   |        1        |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The set id is shared by all the parts of a stream, so parts of different streams can't be
+//! mixed. The index detects reordered parts, and the trailer detects missing final parts.
+//!
+//! **Note**: The part headers are not authenticated. The data itself is authenticated by the
+//! `CryptoReader` reading the joined stream, so a tampered part still fails the decryption.
+use super::{
+    error::{error, Result},
+    shared::setup_rng,
+};
+use rand::{CryptoRng, RngCore};
+use std::io::{Read, Write};
+
+/// The magic written at the beginning of each part.
+const MAGIC: [u8; 10] = *b"CRYPTOPART";
+/// The length of the random id shared by the parts of a stream.
+const SET_ID_LEN: usize = 16;
+/// Trailers.
+const TRAILER_MORE: u8 = 0;
+const TRAILER_LAST: u8 = 1;
+
+/// The number of bytes added to each part. (Header and trailer)
+pub const PART_OVERHEAD: u64 = (MAGIC.len() + SET_ID_LEN + 4 + 1) as u64;
+
+/// A writer that splits the data into parts of at most `part_size` bytes.
+///
+/// **Warning**: `finish` must be called once all the data is written, otherwise the last part has
+/// no trailer and `JoinReader` rejects the stream.
+pub struct SplitWriter<W: Write, F: FnMut(u32) -> std::io::Result<W>> {
+    open: F,
+    data_size: u64,
+    set_id: [u8; SET_ID_LEN],
+    part: Option<W>,
+    part_count: u32,
+    part_len: u64,
+}
+
+impl<W: Write, F: FnMut(u32) -> std::io::Result<W>> SplitWriter<W, F> {
+    /// Create a new `SplitWriter` instance.
+    ///
+    /// # Arguments
+    /// - `part_size`: The maximum size of a part, including the part header and trailer.
+    /// - `open`: Called with the index of each new part (starting at 0), returns its writer.
+    ///
+    /// # Returns
+    /// A `SplitWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `part_size` is not greater than `PART_OVERHEAD`.
+    ///
+    /// # Notes
+    /// The parts are only created once data is written to them.
+    ///
+    pub fn new(part_size: u64, open: F) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::new_with_rng(part_size, open, &mut rng)
+    }
+
+    /// Create a new `SplitWriter` instance with the given random number generator.
+    ///
+    /// # Arguments
+    /// - `part_size`: The maximum size of a part, including the part header and trailer.
+    /// - `open`: Called with the index of each new part (starting at 0), returns its writer.
+    /// - `rng`: The random number generator. (Used for the set id)
+    ///
+    /// # Returns
+    /// A `SplitWriter` instance.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn new_with_rng<R: CryptoRng + RngCore>(
+        part_size: u64,
+        open: F,
+        mut rng: R,
+    ) -> Result<Self> {
+        if part_size <= PART_OVERHEAD {
+            Err(error!(
+                InvalidInput,
+                "Part size must be greater than {} bytes", PART_OVERHEAD
+            ))?;
+        }
+        let mut set_id = [0; SET_ID_LEN];
+        rng.fill_bytes(&mut set_id);
+
+        Ok(Self {
+            open,
+            data_size: part_size - PART_OVERHEAD,
+            set_id,
+            part: None,
+            part_count: 0,
+            part_len: 0,
+        })
+    }
+
+    /// Close the current part (if any), and open the next one.
+    fn next_part(&mut self) -> Result<()> {
+        if let Some(mut part) = self.part.take() {
+            part.write_all(&[TRAILER_MORE])?;
+            part.flush()?;
+        }
+        let mut part = (self.open)(self.part_count)?;
+        part.write_all(&MAGIC)?;
+        part.write_all(&self.set_id)?;
+        part.write_all(&self.part_count.to_be_bytes())?;
+
+        self.part_count = self
+            .part_count
+            .checked_add(1)
+            .ok_or_else(|| error!(Other, "Too many parts"))?;
+        self.part = Some(part);
+        self.part_len = 0;
+        Ok(())
+    }
+
+    /// Write the trailer of the last part.
+    ///
+    /// # Returns
+    /// The number of parts written. (At least one, even if no data was written)
+    ///
+    /// # Errors
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn finish(mut self) -> Result<u32> {
+        if self.part.is_none() {
+            self.next_part()?;
+        }
+        let part = self.part.as_mut().expect("a part is open");
+        part.write_all(&[TRAILER_LAST])?;
+        part.flush()?;
+        Ok(self.part_count)
+    }
+}
+
+impl<W: Write, F: FnMut(u32) -> std::io::Result<W>> Write for SplitWriter<W, F> {
+    /// Write data to the current part.
+    /// A new part is opened when the current one is full.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.part.is_none() || self.part_len == self.data_size {
+            self.next_part()?;
+        }
+        let part = self.part.as_mut().expect("a part is open");
+        let to_write = std::cmp::min(buf.len() as u64, self.data_size - self.part_len) as usize;
+        let written = part.write(&buf[..to_write])?;
+        self.part_len += written as u64;
+        Ok(written)
+    }
+
+    /// Flush the current part.
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.part.as_mut() {
+            Some(part) => part.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A reader that joins the parts written by `SplitWriter`.
+pub struct JoinReader<R: Read, F: FnMut(u32) -> std::io::Result<R>> {
+    open: F,
+    set_id: [u8; SET_ID_LEN],
+    index: u32,
+    part: R,
+    /// The last byte read from the part, which is the trailer at the end of the part.
+    pending: Option<u8>,
+    done: bool,
+}
+
+impl<R: Read, F: FnMut(u32) -> std::io::Result<R>> JoinReader<R, F> {
+    /// Create a new `JoinReader` instance.
+    ///
+    /// # Arguments
+    /// - `open`: Called with the index of each part (starting at 0), returns its reader.
+    ///
+    /// # Returns
+    /// A `JoinReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the first part header is malformed.
+    /// - `UnexpectedEof`: If the first part is missing.
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn new(mut open: F) -> Result<Self> {
+        let (part, set_id) = open_part(&mut open, 0, None)?;
+        Ok(Self {
+            open,
+            set_id,
+            index: 0,
+            part,
+            pending: None,
+            done: false,
+        })
+    }
+}
+
+impl<R: Read, F: FnMut(u32) -> std::io::Result<R>> Read for JoinReader<R, F> {
+    /// Read the data of the parts, in order.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If a part is out of order, belongs to another stream, or is malformed.
+    /// - `UnexpectedEof`: If a part is missing or truncated.
+    ///
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            let n = self.part.read(buf)?;
+            if n == 0 {
+                match self.pending.take() {
+                    Some(TRAILER_LAST) => self.done = true,
+                    Some(TRAILER_MORE) => {
+                        self.index = self
+                            .index
+                            .checked_add(1)
+                            .ok_or_else(|| error!(InvalidData, "Too many parts"))?;
+                        self.part = open_part(&mut self.open, self.index, Some(self.set_id))?.0;
+                    }
+                    Some(trailer) => Err(error!(InvalidData, "Invalid part trailer: {}", trailer))?,
+                    None => Err(error!(UnexpectedEof, "Truncated part {}", self.index))?,
+                }
+                continue;
+            }
+
+            // Hold back the last byte, until the end of the part is known
+            match self.pending.replace(buf[n - 1]) {
+                Some(pending) => {
+                    buf.copy_within(..n - 1, 1);
+                    buf[0] = pending;
+                    return Ok(n);
+                }
+                None if n > 1 => return Ok(n - 1),
+                None => continue,
+            }
+        }
+    }
+}
+
+/// Open the part `index`, and check its header.
+fn open_part<R: Read, F: FnMut(u32) -> std::io::Result<R>>(
+    open: &mut F,
+    index: u32,
+    set_id: Option<[u8; SET_ID_LEN]>,
+) -> Result<(R, [u8; SET_ID_LEN])> {
+    let mut part = open(index).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => error!(UnexpectedEof, "Missing part {}", index),
+        _ => e,
+    })?;
+
+    let mut header = [0; MAGIC.len() + SET_ID_LEN + 4];
+    part.read_exact(&mut header)?;
+    let (magic, rest) = header.split_at(MAGIC.len());
+    let (part_set_id, part_index) = rest.split_at(SET_ID_LEN);
+    let part_set_id: [u8; SET_ID_LEN] = part_set_id.try_into().expect("slice has SET_ID_LEN");
+
+    if magic != MAGIC {
+        Err(error!(InvalidData, "Part {} is not a stream part", index))?;
+    }
+    if set_id.is_some_and(|set_id| set_id != part_set_id) {
+        Err(error!(
+            InvalidData,
+            "Part {} belongs to another stream", index
+        ))?;
+    }
+    let part_index = u32::from_be_bytes(part_index.try_into().expect("slice has 4 bytes"));
+    if part_index != index {
+        Err(error!(
+            InvalidData,
+            "Part {} found in place of part {}", part_index, index
+        ))?;
+    }
+    Ok((part, part_set_id))
+}
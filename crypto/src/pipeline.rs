@@ -0,0 +1,342 @@
+//! This module provides a writer encrypting, and a reader decrypting, on a background thread.
+//!
+//! `PipelinedCryptoWriter` hands the filled chunks to a worker thread through a bounded channel.
+//! The worker encrypts and writes them with a `CryptoWriter`, while the caller keeps filling the
+//! next chunk, so the encryption and the output I/O overlap with the production of the data.
+//! (e.g. reading the source file)
+//!
+//! `PrefetchCryptoReader` does the opposite: a worker thread reads and decrypts the next chunks
+//! with a `CryptoReader` while the caller processes the current one, hiding the latency of the
+//! source. (e.g. a `TcpStream`)
+//!
+//! The streams are the same as the ones of `CryptoWriter` and `CryptoReader`. The channels hold at
+//! most `depth` chunks, which bounds the memory used to `depth * BUFFER_SIZE` bytes.
+use super::{
+    decrypt::CryptoReader,
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    identity::{Identity, Recipient},
+};
+use std::{
+    io::{Read, Write},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::JoinHandle,
+};
+
+/// The number of chunks queued between the caller and the worker thread by default.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 4;
+
+/// A writer stopping to forward the data after the first error.
+/// So the worker can drop its `CryptoWriter` after an I/O error without a second failure (and a
+/// panic) while writing the final chunk.
+struct Fused<W: Write> {
+    writer: W,
+    failed: bool,
+}
+
+impl<W: Write> Write for Fused<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.failed {
+            return Ok(buf.len());
+        }
+        self.writer.write(buf).inspect_err(|_| self.failed = true)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        self.writer.flush().inspect_err(|_| self.failed = true)
+    }
+}
+
+/// A writer that encrypts the data on a background thread.
+///
+/// See the module documentation.
+///
+/// **Warning**: As with `CryptoWriter`, `flush` ends the stream. It must be called once all the
+/// data is written, to get the errors of the worker thread. (Dropping the writer flushes it, and
+/// panics on error)
+pub struct PipelinedCryptoWriter<const BUFFER_SIZE: usize> {
+    buffer: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+impl<const BUFFER_SIZE: usize> PipelinedCryptoWriter<BUFFER_SIZE> {
+    /// Create a new `PipelinedCryptoWriter` instance, with `DEFAULT_PIPELINE_DEPTH` queued
+    /// chunks. The header is written before returning.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data. (Moved to the worker thread)
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    ///
+    /// # Returns
+    /// A `PipelinedCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `Other`: If the RSA encryption fails.
+    /// - `Io`: If an I/O error occurs while writing the header.
+    ///
+    pub fn new<W: Write + Send + 'static>(writer: W, key: impl Into<Recipient>) -> Result<Self> {
+        Self::new_with_options(
+            writer,
+            key,
+            &WriterOptions::default(),
+            DEFAULT_PIPELINE_DEPTH,
+        )
+    }
+
+    /// Create a new `PipelinedCryptoWriter` instance with the given options.
+    ///
+    /// # Arguments
+    /// - `writer`: The writer to write the encrypted data. (Moved to the worker thread)
+    /// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+    /// - `options`: The options of the writer.
+    /// - `depth`: The maximum number of chunks queued for the worker thread.
+    ///
+    /// # Returns
+    /// A `PipelinedCryptoWriter` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `depth` is 0.
+    /// - `Other`: If the RSA encryption fails.
+    /// - `Io`: If an I/O error occurs while writing the header.
+    ///
+    pub fn new_with_options<W: Write + Send + 'static>(
+        writer: W,
+        key: impl Into<Recipient>,
+        options: &WriterOptions,
+        depth: usize,
+    ) -> Result<Self> {
+        if depth == 0 {
+            Err(error!(
+                InvalidInput,
+                "The pipeline depth must be at least 1"
+            ))?;
+        }
+        let writer = Fused {
+            writer,
+            failed: false,
+        };
+        let writer = CryptoWriter::<_, BUFFER_SIZE>::new_with_options(writer, key, options)?;
+        let (sender, receiver) = sync_channel(depth);
+        let worker = std::thread::Builder::new()
+            .name("crypto-writer".into())
+            .spawn(move || encrypt_chunks(writer, receiver))?;
+
+        Ok(Self {
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    /// Queue the buffered chunk for the worker thread.
+    fn send(&mut self) -> Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(BUFFER_SIZE));
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or_else(|| error!(Other, "The writer has already been flushed"))?;
+        if sender.send(chunk).is_err() {
+            // The worker stopped on an error
+            self.join()?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the worker thread, and get its result.
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        match self.worker.take() {
+            Some(worker) => worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Err(error!(Other, "The writer has already been flushed")),
+        }
+    }
+}
+
+/// Encrypt and write the chunks received from the caller, then the final chunk once the channel
+/// is closed.
+fn encrypt_chunks<W: Write, const BUFFER_SIZE: usize>(
+    mut writer: CryptoWriter<W, BUFFER_SIZE>,
+    chunks: Receiver<Vec<u8>>,
+) -> Result<()> {
+    for chunk in chunks {
+        writer.write_all(&chunk)?;
+    }
+    writer.flush()
+}
+
+impl<const BUFFER_SIZE: usize> Write for PipelinedCryptoWriter<BUFFER_SIZE> {
+    /// Append the data to the current chunk. The chunk is queued for the worker thread once
+    /// full. (This blocks if the queue is full)
+    ///
+    /// # Errors
+    /// The errors of the worker thread are returned by the next `write` (or `flush`) call.
+    ///
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let to_copy = std::cmp::min(buf.len(), BUFFER_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..to_copy]);
+        if self.buffer.len() == BUFFER_SIZE {
+            self.send()?;
+        }
+        Ok(to_copy)
+    }
+
+    /// Queue the last data, and wait for the worker thread to write the final chunk.
+    /// As with `CryptoWriter`, this ends the stream.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.send()?;
+        }
+        self.join()
+    }
+}
+
+/// Flush the writer before dropping it.
+impl<const BUFFER_SIZE: usize> Drop for PipelinedCryptoWriter<BUFFER_SIZE> {
+    /// Flush the writer before dropping the `PipelinedCryptoWriter` instance.
+    ///
+    /// # Panics
+    /// If the worker thread failed. (Call `flush` before dropping the writer to get the error)
+    ///
+    fn drop(&mut self) {
+        if self.worker.is_none() {
+            return;
+        }
+        if let Err(e) = self.flush() {
+            panic!("Failed to flush the writer: {}", e);
+        }
+    }
+}
+
+/// A reader that reads and decrypts the next chunks on a background thread.
+///
+/// See the module documentation.
+///
+/// # Notes
+/// Dropping the reader doesn't wait for the worker thread: it stops once its pending read
+/// returns.
+pub struct PrefetchCryptoReader<const BUFFER_SIZE: usize> {
+    chunk: Vec<u8>,
+    pos: usize,
+    receiver: Receiver<Result<Vec<u8>>>,
+    worker: Option<JoinHandle<()>>,
+    /// Whether an error was returned. (The worker stops on the first error)
+    failed: bool,
+}
+
+impl<const BUFFER_SIZE: usize> PrefetchCryptoReader<BUFFER_SIZE> {
+    /// Create a new `PrefetchCryptoReader` instance, with `DEFAULT_PIPELINE_DEPTH` prefetched
+    /// chunks. The header is read before returning.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which encrypted data is read. (Moved to the worker thread)
+    /// - `key`: The identity, whose RSA private key decrypts the AES key. (See `Identity`)
+    ///
+    /// # Returns
+    /// A `PrefetchCryptoReader` instance.
+    ///
+    /// # Errors
+    /// The errors of `CryptoReader::new`.
+    ///
+    pub fn new<R: Read + Send + 'static>(reader: R, key: impl Into<Identity>) -> Result<Self> {
+        Self::from_reader(CryptoReader::new(reader, key)?, DEFAULT_PIPELINE_DEPTH)
+    }
+
+    /// Create a new `PrefetchCryptoReader` instance from a `CryptoReader`. (e.g. in strict mode,
+    /// or built with `CryptoReader::new_try_keys`)
+    ///
+    /// # Arguments
+    /// - `reader`: The `CryptoReader` to read from. (Moved to the worker thread)
+    /// - `depth`: The maximum number of chunks decrypted ahead of the caller.
+    ///
+    /// # Returns
+    /// A `PrefetchCryptoReader` instance.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If `depth` is 0.
+    /// - `Io`: If the worker thread can't be spawned.
+    ///
+    pub fn from_reader<R: Read + Send + 'static>(
+        reader: CryptoReader<R, BUFFER_SIZE>,
+        depth: usize,
+    ) -> Result<Self> {
+        if depth == 0 {
+            Err(error!(
+                InvalidInput,
+                "The pipeline depth must be at least 1"
+            ))?;
+        }
+        let (sender, receiver) = sync_channel(depth);
+        let worker = std::thread::Builder::new()
+            .name("crypto-reader".into())
+            .spawn(move || decrypt_chunks(reader, sender))?;
+
+        Ok(Self {
+            chunk: Vec::new(),
+            pos: 0,
+            receiver,
+            worker: Some(worker),
+            failed: false,
+        })
+    }
+}
+
+/// Read and decrypt the chunks, and send them to the caller, until the end of the stream or the
+/// first error.
+fn decrypt_chunks<R: Read, const BUFFER_SIZE: usize>(
+    mut reader: CryptoReader<R, BUFFER_SIZE>,
+    chunks: SyncSender<Result<Vec<u8>>>,
+) {
+    for chunk in reader.chunks() {
+        let failed = chunk.is_err();
+        if chunks.send(chunk).is_err() || failed {
+            // The caller dropped the reader, or the stream can't be read further
+            break;
+        }
+    }
+}
+
+impl<const BUFFER_SIZE: usize> Read for PrefetchCryptoReader<BUFFER_SIZE> {
+    /// Read decrypted data, waiting for the worker thread if no chunk is ready.
+    ///
+    /// # Errors
+    /// The errors of `CryptoReader::read`. Once an error is returned, the next calls fail with
+    /// `Other`, as the worker thread stopped.
+    ///
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.failed {
+                Err(error!(Other, "The reader stopped on a previous error"))?;
+            }
+            match self.receiver.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(e)) => {
+                    self.failed = true;
+                    return Err(e);
+                }
+                // The worker stopped at the end of the stream (or panicked)
+                Err(_) => {
+                    if let Some(worker) = self.worker.take() {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                    }
+                    return Ok(0);
+                }
+            }
+        }
+
+        let to_copy = std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..to_copy].copy_from_slice(&self.chunk[self.pos..self.pos + to_copy]);
+        self.pos += to_copy;
+        Ok(to_copy)
+    }
+}
@@ -0,0 +1,254 @@
+//! This module provides the post-quantum hybrid key wrap. (`SlotKem::RsaMlKem768`)
+//!
+//! A RSA wrapped key can be recorded today and decrypted once a large enough quantum computer
+//! exists. (Harvest-now, decrypt-later) In hybrid mode, each wrapped key slot combines a RSA
+//! encrypted secret and a ML-KEM-768 encapsulated secret: the AES key stays confidential as long
+//! as either RSA or ML-KEM-768 is unbroken.
+//!
+//! The slot is written as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |   RSA ENC LEN   |   |     RSA Enc     |   |   ML-KEM Enc    |   |     AES Key     |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |     u16 BE      |   |  (RSA secret)   |   |   (ML-KEM ct)   |   |  AES-256-GCM    |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        2        |   |   RSA ENC LEN   |   |      1088       |   | AES KEY LEN+16  |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The RSA secret is encrypted with the `KeyWrap` scheme of the header. The AES key is encrypted
+//! with a key derived from both secrets and both ciphertexts:
+//! `SHA-256(label || RSA secret || ML-KEM secret || ML-KEM ct || RSA Enc)`.
+//!
+//! Use `WriterOptions::ml_kem_keys` to write hybrid slots, and `CryptoReader::new_hybrid` to read
+//! them.
+//!
+//! The ML-KEM-768 keys also implement `Kem` on their own (`ML_KEM_768_ID`), for post-quantum only
+//! slots. (See `WriterOptions::kem_recipients`)
+use super::{
+    error::{error, Result},
+    kem::{open_key, seal_key, Kem},
+    key_wrap::KeyWrap,
+};
+use aes_gcm::aead::rand_core::CryptoRngCore;
+use ml_kem::{
+    kem::{Decapsulate as _, DecapsulationKey, Encapsulate as _, EncapsulationKey},
+    EncodedSizeUser as _, KemCore as _, MlKem768, MlKem768Params,
+};
+use rand::{CryptoRng, RngCore};
+use rsa::{pkcs8::der::zeroize::Zeroizing, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest as _, Sha256};
+
+/// The length of a ML-KEM-768 ciphertext.
+const ML_KEM_CIPHERTEXT_LEN: usize = 1088;
+/// The length of a ML-KEM-768 public key. (Encapsulation key)
+const ML_KEM_PUBLIC_KEY_LEN: usize = 1184;
+/// The length of a ML-KEM-768 private key. (Decapsulation key)
+const ML_KEM_PRIVATE_KEY_LEN: usize = 2400;
+/// The length of the RSA encrypted secret.
+const RSA_SECRET_LEN: usize = 32;
+/// Domain separation of the key encrypting the AES key, also used as additional data.
+const HYBRID_LABEL: &[u8] = b"crypto hybrid rsa ml-kem-768";
+/// The `Kem` identifier of ML-KEM-768.
+pub const ML_KEM_768_ID: u8 = 3;
+
+/// A ML-KEM-768 public key. (Encapsulation key)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MlKemPublicKey(EncapsulationKey<MlKem768Params>);
+
+impl MlKemPublicKey {
+    /// Load a public key from its encoding. (1184 bytes, FIPS 203)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the length is invalid.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().map_err(|_| {
+            error!(
+                InvalidData,
+                "ML-KEM-768 public key must be {} bytes, got {}",
+                ML_KEM_PUBLIC_KEY_LEN,
+                bytes.len()
+            )
+        })?;
+        Ok(Self(EncapsulationKey::from_bytes(bytes)))
+    }
+
+    /// The encoding of the public key. (1184 bytes, FIPS 203)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.as_bytes().to_vec()
+    }
+}
+
+/// A ML-KEM-768 private key. (Decapsulation key)
+#[derive(Clone)]
+pub struct MlKemPrivateKey(DecapsulationKey<MlKem768Params>);
+
+impl MlKemPrivateKey {
+    /// Generate a new private key.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn generate<R: CryptoRng + RngCore>(rng: &mut R) -> Self {
+        let (private_key, _) = MlKem768::generate(rng);
+        Self(private_key)
+    }
+
+    /// The public key matching this private key.
+    pub fn public_key(&self) -> MlKemPublicKey {
+        MlKemPublicKey(self.0.encapsulation_key().clone())
+    }
+
+    /// Load a private key from its encoding. (2400 bytes, FIPS 203)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the length is invalid.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes = bytes.try_into().map_err(|_| {
+            error!(
+                InvalidData,
+                "ML-KEM-768 private key must be {} bytes, got {}",
+                ML_KEM_PRIVATE_KEY_LEN,
+                bytes.len()
+            )
+        })?;
+        Ok(Self(DecapsulationKey::from_bytes(bytes)))
+    }
+
+    /// The encoding of the private key. (2400 bytes, FIPS 203)
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.0.as_bytes().to_vec())
+    }
+}
+
+impl core::fmt::Debug for MlKemPrivateKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MlKemPrivateKey").finish_non_exhaustive()
+    }
+}
+
+impl Kem for MlKemPublicKey {
+    fn id(&self) -> u8 {
+        ML_KEM_768_ID
+    }
+
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        let (ciphertext, shared) = self
+            .0
+            .encapsulate(&mut { rng })
+            .map_err(|_| error!(Other, "ML-KEM encapsulation error"))?;
+        Ok((ciphertext.to_vec(), Zeroizing::new(shared.to_vec())))
+    }
+
+    fn decapsulate(&self, _wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        Err(error!(
+            Unsupported,
+            "ML-KEM-768 requires the private key to decapsulate"
+        ))
+    }
+}
+
+impl Kem for MlKemPrivateKey {
+    fn id(&self) -> u8 {
+        ML_KEM_768_ID
+    }
+
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        self.public_key().encapsulate(rng)
+    }
+
+    fn decapsulate(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let ciphertext = wrapped
+            .try_into()
+            .map_err(|_| error!(Other, "Invalid ML-KEM-768 ciphertext length"))?;
+        let shared = self
+            .0
+            .decapsulate(ciphertext)
+            .map_err(|_| error!(Other, "ML-KEM decapsulation error"))?;
+        Ok(Zeroizing::new(shared.to_vec()))
+    }
+}
+
+/// Derive the key encrypting the AES key of a hybrid slot.
+fn derive_kek(
+    rsa_secret: &[u8],
+    ml_kem_secret: &[u8],
+    ml_kem_ciphertext: &[u8],
+    rsa_ciphertext: &[u8],
+) -> Zeroizing<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(HYBRID_LABEL);
+    hasher.update(rsa_secret);
+    hasher.update(ml_kem_secret);
+    hasher.update(ml_kem_ciphertext);
+    hasher.update(rsa_ciphertext);
+    Zeroizing::new(hasher.finalize().into())
+}
+
+/// Encrypt the AES key in a hybrid slot, for the given RSA and ML-KEM-768 public keys.
+pub(crate) fn wrap_hybrid<R: CryptoRng + RngCore>(
+    key: &RsaPublicKey,
+    ml_kem_key: &MlKemPublicKey,
+    key_wrap: KeyWrap,
+    aes_key: &[u8],
+    rng: &mut R,
+) -> Result<Vec<u8>> {
+    let mut rsa_secret = Zeroizing::new([0; RSA_SECRET_LEN]);
+    rng.fill_bytes(rsa_secret.as_mut());
+    let rsa_ciphertext = key_wrap.wrap(key, rng, rsa_secret.as_ref())?;
+    let rsa_len = u16::try_from(rsa_ciphertext.len())
+        .map_err(|_| error!(InvalidInput, "RSA key is too long"))?;
+
+    let (ml_kem_ciphertext, ml_kem_secret) = ml_kem_key.encapsulate(rng)?;
+
+    let kek = derive_kek(
+        rsa_secret.as_ref(),
+        &ml_kem_secret,
+        &ml_kem_ciphertext,
+        &rsa_ciphertext,
+    );
+    let encrypted_key = seal_key(&kek, HYBRID_LABEL, aes_key)?;
+
+    let mut slot =
+        Vec::with_capacity(2 + rsa_ciphertext.len() + ML_KEM_CIPHERTEXT_LEN + encrypted_key.len());
+    slot.extend_from_slice(&rsa_len.to_be_bytes());
+    slot.extend_from_slice(&rsa_ciphertext);
+    slot.extend_from_slice(&ml_kem_ciphertext);
+    slot.extend_from_slice(&encrypted_key);
+    Ok(slot)
+}
+
+/// Decrypt the AES key of a hybrid slot with the given RSA and ML-KEM-768 private keys.
+///
+/// # Returns
+/// `None` if the keys don't match, or the slot is corrupted.
+///
+pub(crate) fn unwrap_hybrid(
+    key: &RsaPrivateKey,
+    ml_kem_key: &MlKemPrivateKey,
+    key_wrap: KeyWrap,
+    slot: &[u8],
+) -> Option<Zeroizing<Vec<u8>>> {
+    let (rsa_len, slot) = slot.split_first_chunk::<2>()?;
+    let rsa_len = u16::from_be_bytes(*rsa_len) as usize;
+    let (rsa_ciphertext, slot) = slot.split_at_checked(rsa_len)?;
+    let (ml_kem_ciphertext, encrypted_key) = slot.split_at_checked(ML_KEM_CIPHERTEXT_LEN)?;
+
+    let rsa_secret = Zeroizing::new(key_wrap.unwrap(key, rsa_ciphertext)?);
+    if rsa_secret.len() != RSA_SECRET_LEN {
+        return None;
+    }
+    let ml_kem_secret = ml_kem_key.decapsulate(ml_kem_ciphertext).ok()?;
+
+    let kek = derive_kek(
+        &rsa_secret,
+        &ml_kem_secret,
+        ml_kem_ciphertext,
+        rsa_ciphertext,
+    );
+    open_key(&kek, HYBRID_LABEL, encrypted_key)
+}
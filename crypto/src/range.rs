@@ -0,0 +1,205 @@
+//! This module provides the random access to a stream stored out of reach of `Seek`. (e.g. a
+//! blob in an object store, read with HTTP range requests)
+//!
+//! `CryptoReader::read_range` decrypts the chunks covering a plaintext range of a seekable
+//! stream. A `RangeRead` source only reads the bytes at a given offset, and `RangeReader` turns it
+//! into a seekable reader for `CryptoReader`, so a range of a remote stream is decrypted without
+//! downloading the whole stream. `RangeRead` is implemented for `File` (on Unix and Windows) and
+//! the byte slices.
+//!
+//! A `RangeRead` for HTTP range requests would look like this:
+//!
+//! ```ignore
+//! struct HttpBlob {
+//!     agent: ureq::Agent,
+//!     url: String,
+//! }
+//!
+//! impl RangeRead for HttpBlob {
+//!     fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+//!         let end = offset + buf.len() as u64 - 1;
+//!         let response = self
+//!             .agent
+//!             .get(&self.url)
+//!             .set("Range", &format!("bytes={}-{}", offset, end))
+//!             .call();
+//!         match response {
+//!             // Past the end of the blob
+//!             Err(ureq::Error::Status(416, _)) => Ok(0),
+//!             Err(e) => Err(std::io::Error::other(e)),
+//!             Ok(response) => {
+//!                 let mut data = response.into_reader().take(buf.len() as u64);
+//!                 let mut read = 0;
+//!                 while read < buf.len() {
+//!                     match data.read(&mut buf[read..])? {
+//!                         0 => break,
+//!                         n => read += n,
+//!                     }
+//!                 }
+//!                 Ok(read)
+//!             }
+//!         }
+//!     }
+//!
+//!     fn len(&self) -> std::io::Result<u64> {
+//!         let response = self.agent.head(&self.url).call().map_err(std::io::Error::other)?;
+//!         response
+//!             .header("Content-Length")
+//!             .and_then(|len| len.parse().ok())
+//!             .ok_or_else(|| std::io::Error::other("No Content-Length"))
+//!     }
+//! }
+//!
+//! let blob = RangeReader::new(HttpBlob { agent, url });
+//! let mut reader = CryptoReader::<_, 65536>::new(blob, private_key)?;
+//! let data = reader.read_range(offset, len)?;
+//! ```
+//!
+//! **Note**: Each `read` of the `RangeReader` is a `read_at` of the source. `CryptoReader` reads
+//! the chunks whole, but the header is read in small parts: wrap the `RangeReader` in a
+//! `BufReader` (which is seekable too) if the requests are costly.
+use super::error::{error, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+/// A source read at arbitrary offsets. (e.g. a file, or a blob read with HTTP range requests)
+pub trait RangeRead {
+    /// Read the bytes at the given offset.
+    ///
+    /// # Arguments
+    /// - `buf`: The buffer to store the bytes.
+    /// - `offset`: The position of the bytes in the source.
+    ///
+    /// # Returns
+    /// The number of bytes read. Shorter than `buf` only at the end of the source. (0 if `offset`
+    /// is at or after the end)
+    ///
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+
+    /// The length of the source.
+    fn len(&self) -> Result<u64>;
+
+    /// Whether the source is empty.
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl RangeRead for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            #[cfg(unix)]
+            let n =
+                std::os::unix::fs::FileExt::read_at(self, &mut buf[read..], offset + read as u64);
+            #[cfg(windows)]
+            let n = std::os::windows::fs::FileExt::seek_read(
+                self,
+                &mut buf[read..],
+                offset + read as u64,
+            );
+            match n {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl RangeRead for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let start = usize::try_from(offset).map_or(self.len(), |offset| offset.min(self.len()));
+        let data = &self[start..];
+        let read = std::cmp::min(buf.len(), data.len());
+        buf[..read].copy_from_slice(&data[..read]);
+        Ok(read)
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+}
+
+impl RangeRead for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+
+    fn len(&self) -> Result<u64> {
+        RangeRead::len(self.as_slice())
+    }
+}
+
+impl<S: RangeRead + ?Sized> RangeRead for &S {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+
+    fn len(&self) -> Result<u64> {
+        (**self).len()
+    }
+}
+
+/// A seekable reader over a `RangeRead` source, for `CryptoReader` (and its `read_range`).
+pub struct RangeReader<S: RangeRead> {
+    source: S,
+    pos: u64,
+}
+
+impl<S: RangeRead> RangeReader<S> {
+    /// Create a new `RangeReader` instance, reading from the start of the source.
+    pub fn new(source: S) -> Self {
+        Self { source, pos: 0 }
+    }
+
+    /// A reference to the source.
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    /// Consume the reader, and return the source.
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: RangeRead> Read for RangeReader<S> {
+    /// Read the bytes at the current position, with one `read_at` call.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let read = self.source.read_at(buf, self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: RangeRead> Seek for RangeReader<S> {
+    /// Move the position. (`SeekFrom::End` asks the source for its length)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the position would be negative, or overflow.
+    /// - The errors of `RangeRead::len`.
+    ///
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.source.len()?.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| error!(InvalidInput, "Invalid seek position"))?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.pos)
+    }
+}
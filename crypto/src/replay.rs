@@ -0,0 +1,83 @@
+//! This module provides the `ReplayWindow`, which rejects replayed messages.
+//!
+//! The window keeps track of the highest sequence number accepted, and of the sequence numbers
+//! accepted among the `REPLAY_WINDOW` previous ones. (As in IPsec, RFC 4303)
+//!
+//! ```plaintext
+//!            REPLAY_WINDOW
+//!   <---------------------------->
+//! +---+---+---+---+---+---+---+---+
+//! | 1 | 0 | 0 | 1 | 1 | 0 | 1 | 1 |  <- bit i: `highest - i` was accepted
+//! +---+---+---+---+---+---+---+---+
+//!                               ^
+//!                            highest
+//! ```
+//!
+//! A message is rejected if it was already accepted (`Error::Replayed`), or if it is older than
+//! the window (`Error::OutOfOrder`), as it can't be told apart from a replay.
+use super::error::{Error, Result};
+
+/// The number of sequence numbers tracked behind the highest accepted one.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// A sliding window of accepted sequence numbers.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow {
+    /// The highest sequence number accepted so far. (`None` before the first message)
+    highest: Option<u64>,
+    /// Bit `i` is set if the message `highest - i` was accepted.
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    /// Create a new empty window.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check that the sequence number can be accepted.
+    ///
+    /// # Errors
+    /// - `Error::Replayed`: If the sequence number was already accepted.
+    /// - `Error::OutOfOrder`: If the sequence number is older than the window.
+    ///
+    /// # Notes
+    /// The window is not updated: call `accept` once the message is authenticated, so forged
+    /// messages can't move the window.
+    ///
+    pub fn check(&self, sequence: u64) -> Result<()> {
+        let Some(highest) = self.highest else {
+            return Ok(());
+        };
+        if sequence > highest {
+            return Ok(());
+        }
+        let offset = highest - sequence;
+        if offset >= REPLAY_WINDOW {
+            Err(Error::OutOfOrder { sequence })?;
+        }
+        if self.bitmap & (1 << offset) != 0 {
+            Err(Error::Replayed { sequence })?;
+        }
+        Ok(())
+    }
+
+    /// Mark the sequence number as accepted.
+    pub fn accept(&mut self, sequence: u64) {
+        match self.highest {
+            Some(highest) if sequence <= highest => {
+                if highest - sequence < REPLAY_WINDOW {
+                    self.bitmap |= 1 << (highest - sequence);
+                }
+            }
+            Some(highest) if sequence - highest < REPLAY_WINDOW => {
+                self.bitmap = (self.bitmap << (sequence - highest)) | 1;
+                self.highest = Some(sequence);
+            }
+            _ => {
+                self.bitmap = 1;
+                self.highest = Some(sequence);
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! This module provides sign-then-encrypt: the payload is signed with the RSA private key of the
+//! sender, then the payload and the signature are encrypted for the recipient.
+//!
+//! The sealed data is a regular stream (see `CryptoWriter`), whose plaintext is:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |   SIGNER LEN    |   |   Signer Key    |   |  SIGNATURE LEN  |   |    Signature    |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |     u16 BE      |   |   PKCS#1 DER    |   |     u16 BE      |   |  RSASSA-PSS     |   ...
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        2        |   |   SIGNER LEN    |   |        2        |   |  SIGNATURE LEN  |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!
+//!     +-----------------+
+//!     |     Payload     |
+//!     +-----------------+
+//! ... |                 |
+//!     +-----------------+
+//! ```
+//!
+//! The signature (RSASSA-PSS, SHA-256) covers the fingerprint of the recipient key and the
+//! payload, so a recipient can't forward the sealed data to a third party as if it was sent to
+//! them.
+//!
+//! **Note**: The signer key is read from the sealed data: `open` proves the payload was signed by
+//! that key, check its fingerprint (see `fingerprint`) to know who the sender is.
+use super::{
+    audit::{audit_signature, Signed},
+    decrypt::CryptoReader,
+    encrypt::CryptoWriter,
+    error::{error, Error, Result},
+    shared::setup_rng,
+};
+use rand::{CryptoRng, RngCore};
+use rsa::{
+    pkcs1::{DecodeRsaPublicKey as _, EncodeRsaPublicKey as _},
+    Pss, RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest as _, Sha256};
+use std::io::{Read, Write};
+
+/// Domain separation of the signed digest.
+const SEAL_LABEL: &[u8] = b"crypto seal";
+
+/// The result of `open`: the payload, and the key that signed it.
+#[derive(Debug, Clone)]
+pub struct Opened {
+    /// The verified payload.
+    pub payload: Vec<u8>,
+    /// The public key of the signer.
+    pub signer: RsaPublicKey,
+}
+
+/// The fingerprint of a RSA public key: the SHA-256 hash of its PKCS#1 DER encoding, in
+/// lowercase hexadecimal.
+///
+/// # Panics
+/// If the key can't be encoded. (Not for the keys loaded or generated by this crate)
+///
+pub fn fingerprint(key: &RsaPublicKey) -> String {
+    let der = key.to_pkcs1_der().expect("a RSA public key can be encoded");
+    Sha256::digest(der.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The digest signed by the sender.
+fn signed_digest(recipient: &RsaPublicKey, payload: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(SEAL_LABEL)
+        .chain_update(fingerprint(recipient))
+        .chain_update(payload)
+        .finalize()
+        .into()
+}
+
+/// Sign the payload, and encrypt it with its signature for the recipient.
+///
+/// # Arguments
+/// - `writer`: The writer to write the sealed data.
+/// - `recipient`: The RSA public key of the recipient.
+/// - `signer`: The RSA private key of the sender.
+/// - `payload`: The data to seal.
+///
+/// # Returns
+/// The underlying writer.
+///
+/// # Errors
+/// - `Other`: If the signature or the RSA encryption fails.
+/// - `Io`: If an I/O error occurs.
+///
+pub fn seal<W: Write, const BUFFER_SIZE: usize>(
+    writer: W,
+    recipient: RsaPublicKey,
+    signer: &RsaPrivateKey,
+    payload: &[u8],
+) -> Result<W> {
+    let mut rng = setup_rng();
+    seal_with_rng::<_, _, BUFFER_SIZE>(writer, recipient, signer, payload, &mut rng)
+}
+
+/// Sign the payload, and encrypt it with its signature for the recipient, with the given random
+/// number generator.
+///
+/// # Arguments
+/// - `writer`: The writer to write the sealed data.
+/// - `recipient`: The RSA public key of the recipient.
+/// - `signer`: The RSA private key of the sender.
+/// - `payload`: The data to seal.
+/// - `rng`: The random number generator.
+///
+/// # Returns
+/// The underlying writer.
+///
+/// # Notes
+/// The random number generator must be cryptographically secure. And should implement the
+/// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+///
+pub fn seal_with_rng<W: Write, R: CryptoRng + RngCore, const BUFFER_SIZE: usize>(
+    mut writer: W,
+    recipient: RsaPublicKey,
+    signer: &RsaPrivateKey,
+    payload: &[u8],
+    rng: &mut R,
+) -> Result<W> {
+    let signature = signer
+        .sign_with_rng(
+            rng,
+            Pss::new::<Sha256>(),
+            &signed_digest(&recipient, payload),
+        )
+        .map_err(|e| error!(Other, "RSA Signature error: {}", e))?;
+    let signer_key = signer
+        .to_public_key()
+        .to_pkcs1_der()
+        .map_err(|e| error!(Other, "Failed to encode the signer key: {}", e))?;
+
+    let mut crypto_writer =
+        CryptoWriter::<_, BUFFER_SIZE>::new_with_rng(&mut writer, recipient, rng)?;
+    for field in [signer_key.as_bytes(), &signature] {
+        let len = u16::try_from(field.len())
+            .map_err(|_| error!(InvalidInput, "Seal field is too long"))?;
+        crypto_writer.write_all(&len.to_be_bytes())?;
+        crypto_writer.write_all(field)?;
+    }
+    crypto_writer.write_all(payload)?;
+    crypto_writer.flush()?;
+    drop(crypto_writer);
+    Ok(writer)
+}
+
+/// Decrypt sealed data, and verify its signature.
+///
+/// # Arguments
+/// - `reader`: The reader from which the sealed data is read.
+/// - `key`: The RSA private key of the recipient.
+///
+/// # Returns
+/// The payload, and the public key of the signer.
+///
+/// # Errors
+/// - `Error::BadSignature`: If the signature doesn't match the payload, or the data was sealed
+///   for another recipient.
+/// - `InvalidData`: If the sealed data is malformed.
+/// - The errors of `CryptoReader::new` and `CryptoReader::read`.
+///
+pub fn open<R: Read, const BUFFER_SIZE: usize>(reader: R, key: RsaPrivateKey) -> Result<Opened> {
+    let recipient = key.to_public_key();
+    let mut reader = CryptoReader::<_, BUFFER_SIZE>::new(reader, key)?;
+    let mut read_field = || -> Result<Vec<u8>> {
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let mut field = vec![0; u16::from_be_bytes(len) as usize];
+        reader.read_exact(&mut field)?;
+        Ok(field)
+    };
+    let signer_key = read_field()?;
+    let signature = read_field()?;
+    let signer = RsaPublicKey::from_pkcs1_der(&signer_key)
+        .map_err(|e| error!(InvalidData, "Invalid signer key: {}", e))?;
+
+    let mut payload = Vec::new();
+    reader.read_to_end(&mut payload)?;
+    let valid = signer
+        .verify(
+            Pss::new::<Sha256>(),
+            &signed_digest(&recipient, &payload),
+            &signature,
+        )
+        .is_ok();
+    audit_signature(Signed::Sealed, &signer, valid);
+    if !valid {
+        Err(Error::BadSignature)?;
+    }
+    Ok(Opened { payload, signer })
+}
@@ -0,0 +1,218 @@
+//! This module provides the `SessionCache`, so repeated short streams to the same recipient share
+//! a single RSA wrapped key.
+//!
+//! Each stream normally wraps its AES key with the RSA public key of the recipient: a RSA
+//! encryption per stream, and a 256 bytes wrapped key in each header (for a 2048 bits key). For
+//! small messages, both dominate the cost of the stream.
+//!
+//! The streams of a session share a random master key instead. The first stream of the session
+//! wraps the master key as usual, the next streams carry an empty wrapped key. The key of each
+//! stream is derived from the master key and the ID of the stream (see `Header::session`):
+//!
+//! ```plaintext
+//! stream key = HKDF-SHA256(master key, salt = session ID, info = "crypto session stream" || stream ID)
+//! ```
+//!
+//! So the streams never share a key, and the key check value and the commitment of each header
+//! are computed on its stream key.
+//!
+//! On the reading side, the master key unwrapped from the first stream of a session is kept, to
+//! read the next streams without a RSA decryption. The streams after the first can't be read
+//! without it: the first stream of a session must be read first, with the same `SessionCache`.
+//! (e.g. the messages of an ordered connection)
+use super::header::{Header, Session};
+use alloc::{vec, vec::Vec};
+use hkdf::Hkdf;
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use sha2::Sha256;
+#[cfg(feature = "std")]
+use {
+    super::{
+        cipher::Cipher,
+        encrypt::WriterOptions,
+        error::{error, Result},
+        header::SESSION_ID_LEN,
+        key_wrap::KeyWrap,
+    },
+    rand::{CryptoRng, RngCore},
+    rsa::RsaPublicKey,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+/// The info of the stream key derivation, followed by the stream ID.
+const STREAM_KEY_INFO: &[u8] = b"crypto session stream";
+
+/// The key encrypting the chunks of a stream, from the data key wrapped in its header.
+///
+/// The data key is the key of the stream itself, or for the streams of a session, the master key
+/// of the session. (See `Header::session`)
+pub(crate) fn stream_key(header: &Header, data_key: &[u8]) -> Zeroizing<Vec<u8>> {
+    match &header.session {
+        Some(session) => derive_stream_key(data_key, session, header.cipher.key_len()),
+        None => Zeroizing::new(data_key.to_vec()),
+    }
+}
+
+/// Derive the key of a stream of the session from the master key.
+fn derive_stream_key(master_key: &[u8], session: &Session, key_len: usize) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0; key_len]);
+    Hkdf::<Sha256>::new(Some(&session.id), master_key)
+        .expand_multi_info(&[STREAM_KEY_INFO, &session.stream.to_be_bytes()], &mut key)
+        .expect("the key is shorter than 255 SHA-256 hashes");
+    key
+}
+
+/// A stream of an outgoing session. (See `SessionCache::next_stream`)
+#[cfg(feature = "std")]
+pub(crate) struct SessionStream {
+    pub(crate) session: Session,
+    /// The wrapped master key for the first stream, a single empty key otherwise.
+    pub(crate) wrapped_keys: Vec<Vec<u8>>,
+    /// The key of the stream.
+    pub(crate) key: Zeroizing<Vec<u8>>,
+}
+
+/// An outgoing session, to a recipient.
+#[cfg(feature = "std")]
+struct Outgoing {
+    id: [u8; SESSION_ID_LEN],
+    master_key: Zeroizing<Vec<u8>>,
+    wrapped_key: Vec<u8>,
+    /// The ID of the next stream.
+    next_stream: u64,
+}
+
+/// The outgoing sessions are keyed by recipient, and by the options the master key depends on.
+#[cfg(feature = "std")]
+type OutgoingKey = (RsaPublicKey, Cipher, KeyWrap);
+
+/// A cache of sessions, shared by the writers and readers of repeated streams. (See the module
+/// documentation)
+///
+/// Opt-in: only the streams created with `CryptoWriter::new_with_session` (resp. read with
+/// `CryptoReader::new_with_session`) use it. The cache is thread-safe, so it can be shared by
+/// the connections of a server.
+///
+/// # Notes
+/// The master keys are kept in memory (zeroized on drop) until the cache is dropped or cleared.
+/// Compromising a master key compromises every stream of its session: a new session is started
+/// every `max_streams` streams.
+///
+#[cfg(feature = "std")]
+pub struct SessionCache {
+    max_streams: u64,
+    outgoing: Mutex<HashMap<OutgoingKey, Outgoing>>,
+    incoming: Mutex<HashMap<[u8; SESSION_ID_LEN], Zeroizing<Vec<u8>>>>,
+}
+
+#[cfg(feature = "std")]
+impl SessionCache {
+    /// Create a new empty cache.
+    ///
+    /// # Arguments
+    /// - `max_streams`: The number of streams written in a session before a new one is started.
+    ///   (At least 1)
+    ///
+    pub fn new(max_streams: u64) -> Self {
+        Self {
+            max_streams: max_streams.max(1),
+            outgoing: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget every session, outgoing and incoming. (The next stream to each recipient starts a
+    /// new session, and the streams of the current incoming sessions can't be read anymore)
+    pub fn clear(&self) {
+        lock(&self.outgoing).clear();
+        lock(&self.incoming).clear();
+    }
+
+    /// Get the next stream of the session to the recipient, starting a new session if needed.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the options have more than one recipient. (Sessions are per recipient)
+    /// - `Other`: If the RSA encryption of a new master key fails.
+    ///
+    pub(crate) fn next_stream<R: CryptoRng + RngCore>(
+        &self,
+        key: &RsaPublicKey,
+        options: &WriterOptions,
+        rng: &mut R,
+    ) -> Result<SessionStream> {
+        #[cfg(feature = "ml-kem")]
+        let hybrid = !options.ml_kem_keys.is_empty();
+        #[cfg(not(feature = "ml-kem"))]
+        let hybrid = false;
+        if !options.additional_recipients.is_empty() || !options.kem_recipients.is_empty() || hybrid
+        {
+            Err(error!(
+                InvalidInput,
+                "Sessions support a single RSA recipient"
+            ))?;
+        }
+
+        let mut outgoing = lock(&self.outgoing);
+        let entry = (key.clone(), options.cipher, options.key_wrap);
+        if outgoing
+            .get(&entry)
+            .is_none_or(|session| session.next_stream >= self.max_streams)
+        {
+            let master_key = options.cipher.generate_key(rng);
+            let wrapped_key = options.key_wrap.wrap(key, rng, &master_key)?;
+            let mut id = [0; SESSION_ID_LEN];
+            rng.fill_bytes(&mut id);
+            let session = Outgoing {
+                id,
+                master_key,
+                wrapped_key,
+                next_stream: 0,
+            };
+            outgoing.insert(entry.clone(), session);
+        }
+        let session = outgoing.get_mut(&entry).expect("the session is started");
+
+        let stream = Session {
+            id: session.id,
+            stream: session.next_stream,
+        };
+        session.next_stream += 1;
+        let wrapped_key = match stream.stream {
+            0 => session.wrapped_key.clone(),
+            _ => Vec::new(),
+        };
+        Ok(SessionStream {
+            session: stream,
+            wrapped_keys: vec![wrapped_key],
+            key: derive_stream_key(&session.master_key, &stream, options.cipher.key_len()),
+        })
+    }
+
+    /// The master key of an incoming session, if its first stream was read.
+    pub(crate) fn master_key(&self, id: &[u8; SESSION_ID_LEN]) -> Option<Zeroizing<Vec<u8>>> {
+        lock(&self.incoming).get(id).cloned()
+    }
+
+    /// Keep the master key of an incoming session, unwrapped from its first stream.
+    pub(crate) fn insert_master_key(&self, id: [u8; SESSION_ID_LEN], master_key: &[u8]) {
+        lock(&self.incoming).insert(id, Zeroizing::new(master_key.to_vec()));
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for SessionCache {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The master keys are not printed
+        f.debug_struct("SessionCache")
+            .field("max_streams", &self.max_streams)
+            .field("outgoing", &lock(&self.outgoing).len())
+            .field("incoming", &lock(&self.incoming).len())
+            .finish()
+    }
+}
+
+/// Lock the mutex, ignoring the poisoning. (The maps are always left consistent)
+#[cfg(feature = "std")]
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -1,41 +1,157 @@
 use aes_gcm::{
     aead::{
-        consts::{B0, B1},
-        generic_array::GenericArray,
+        generic_array::{typenum::Unsigned as _, ArrayLength, GenericArray},
+        AeadCore,
     },
-    aes::cipher::typenum::{UInt, UTerm},
+    Aes128Gcm, Aes256Gcm,
 };
+#[cfg(feature = "std")]
 use rand::rngs::ThreadRng;
 
 // Enforce 2048 bits key length. (Temporary solution)
+#[cfg(feature = "std")]
 pub(crate) const RSA_KEY_LEN: usize = 2048;
 // RSA 2048 bits creates a 256 bytes encrypted data chunk.
 pub(crate) const AES_KEY_LEN: usize = 256;
-// 96 bits nonce for AES-GCM.
-pub(crate) const AES_NONCE_LEN: usize = 12;
+// 256 bits key for AES-256-GCM.
+#[cfg(feature = "std")]
+pub(crate) const AES_DATA_KEY_LEN: usize = 32;
+// 96 bits nonce for AES-GCM. (The nonce size of the cipher)
+pub(crate) const AES_NONCE_LEN: usize = <Aes256Gcm as AeadCore>::NonceSize::USIZE;
 // 128 bits authentication tag for AES-GCM.
-pub(crate) const AES_AUTH_TAG_LEN: usize = 16; // [Currently not used but present in the encryption scheme]
+pub(crate) const AES_AUTH_TAG_LEN: usize = 16;
+// Additional data of the final chunk, for streams with an end marker.
+pub(crate) const FINAL_CHUNK_AAD: &[u8] = b"final chunk";
+// Additional data of the key check value. (An empty message, encrypted with a zero nonce)
+pub(crate) const KEY_CHECK_AAD: &[u8] = b"key check";
+// Info of the derivation of the key check key from the AES key.
+pub(crate) const KEY_CHECK_KEY_INFO: &[u8] = b"crypto key check";
+// SHA-256 hash of the header, authenticated by the chunks of a `Format::V1` stream.
+pub(crate) const HEADER_DIGEST_LEN: usize = 32;
+// SHA-256 commitment to the AES key.
+pub(crate) const KEY_COMMITMENT_LEN: usize = 32;
+// Prefix of the hashed key, for the key commitment.
+pub(crate) const KEY_COMMITMENT_PREFIX: &[u8] = b"crypto key commitment";
 
+#[cfg(feature = "std")]
 pub(crate) fn setup_rng() -> ThreadRng {
     rand::thread_rng()
 }
-pub(crate) type Nonce = GenericArray<u8, UInt<UInt<UInt<UInt<UTerm, B1>, B1>, B0>, B0>>;
-
-pub(crate) fn increment_nonce(nonce: &mut Nonce) {
-    let mut has_been_incremented = false;
-    for i in (0..nonce.len()).rev() {
-        if nonce[i] == u8::MAX {
-            nonce[i] = 0;
-        } else {
-            nonce[i] += 1;
-            has_been_incremented = true;
-            break;
+
+/// The nonce of an AEAD cipher. (`AeadCore::NonceSize` bytes)
+pub(crate) type NonceOf<A> = GenericArray<u8, <A as AeadCore>::NonceSize>;
+/// The nonce of the chunks. (The ciphers of `Cipher` share it)
+pub(crate) type Nonce = NonceOf<Aes256Gcm>;
+
+// The ciphers of `Cipher` share the nonce of the chunks.
+const _: () = assert!(<Aes128Gcm as AeadCore>::NonceSize::USIZE == AES_NONCE_LEN);
+
+// 64 bits counter of `NonceCounter`, at the end of the nonce.
+const NONCE_COUNTER_LEN: usize = 8;
+
+/// The nonces of a sequence of messages (e.g. the chunks of a stream): a fixed prefix, followed by
+/// a 64 bits big-endian counter.
+///
+/// **Note**: The counter carries into the prefix when it overflows, so the whole nonce counts as
+/// one big-endian integer, and wraps to zero. (The nonces of the chunks of a stream, from the
+/// random nonce of the header)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NonceCounter<N: ArrayLength<u8> = <Aes256Gcm as AeadCore>::NonceSize> {
+    nonce: GenericArray<u8, N>,
+}
+
+impl<N: ArrayLength<u8>> NonceCounter<N> {
+    /// Create a new `NonceCounter` instance, starting at the given nonce. (Its last 8 bytes are
+    /// the counter)
+    pub(crate) fn new(nonce: GenericArray<u8, N>) -> Self {
+        const {
+            assert!(
+                N::USIZE >= NONCE_COUNTER_LEN,
+                "The nonce is smaller than the counter"
+            )
+        };
+        Self { nonce }
+    }
+
+    /// Create a new `NonceCounter` instance from a prefix, and the first value of the counter.
+    ///
+    /// # Panics
+    /// If the prefix is not `NONCE_COUNTER_LEN` bytes shorter than the nonce.
+    ///
+    #[cfg(feature = "std")]
+    pub(crate) fn with_prefix(prefix: &[u8], counter: u64) -> Self {
+        let mut nonce = GenericArray::default();
+        let (nonce_prefix, nonce_counter) = nonce.split_at_mut(N::USIZE - NONCE_COUNTER_LEN);
+        nonce_prefix.copy_from_slice(prefix);
+        nonce_counter.copy_from_slice(&counter.to_be_bytes());
+        Self::new(nonce)
+    }
+
+    /// The current nonce.
+    pub(crate) fn nonce(&self) -> &GenericArray<u8, N> {
+        &self.nonce
+    }
+
+    /// Move to the next nonce.
+    pub(crate) fn increment(&mut self) {
+        self.advance(1);
+    }
+
+    /// Advance by `count` nonces, as `count` calls to `increment` would. (e.g. to decrypt a
+    /// stream from a given chunk)
+    pub(crate) fn advance(&mut self, count: u64) {
+        let (prefix, counter) = self.nonce.split_at_mut(N::USIZE - NONCE_COUNTER_LEN);
+        let value = u64::from_be_bytes(counter.try_into().expect("the counter is 8 bytes"));
+        let (value, overflow) = value.overflowing_add(count);
+        counter.copy_from_slice(&value.to_be_bytes());
+        if overflow {
+            // Carry into the prefix (wrapping to zero)
+            for byte in prefix.iter_mut().rev() {
+                let (sum, carry) = byte.overflowing_add(1);
+                *byte = sum;
+                if !carry {
+                    break;
+                }
+            }
         }
     }
-    if !has_been_incremented {
-        // Reset the nonce
-        for i in 0..nonce.len() {
-            nonce[i] = 0;
+}
+
+/// The additional data of a chunk: the hash of the header for `Format::V1` streams (see
+/// `Header::digest`), the tag of the previous chunk when the chunks are chained (see
+/// `Header::chained`), followed by `FINAL_CHUNK_AAD` for the final chunk of a stream with an end
+/// marker.
+pub(crate) struct ChunkAad {
+    buffer: [u8; HEADER_DIGEST_LEN + AES_AUTH_TAG_LEN + FINAL_CHUNK_AAD.len()],
+    len: usize,
+}
+
+impl ChunkAad {
+    pub(crate) fn new(
+        header_digest: Option<&[u8; HEADER_DIGEST_LEN]>,
+        previous_tag: Option<&[u8; AES_AUTH_TAG_LEN]>,
+        is_final: bool,
+    ) -> Self {
+        let mut aad = Self {
+            buffer: [0; HEADER_DIGEST_LEN + AES_AUTH_TAG_LEN + FINAL_CHUNK_AAD.len()],
+            len: 0,
+        };
+        if let Some(header_digest) = header_digest {
+            aad.buffer[..HEADER_DIGEST_LEN].copy_from_slice(header_digest);
+            aad.len = HEADER_DIGEST_LEN;
+        }
+        if let Some(previous_tag) = previous_tag {
+            aad.buffer[aad.len..aad.len + AES_AUTH_TAG_LEN].copy_from_slice(previous_tag);
+            aad.len += AES_AUTH_TAG_LEN;
+        }
+        if is_final {
+            aad.buffer[aad.len..aad.len + FINAL_CHUNK_AAD.len()].copy_from_slice(FINAL_CHUNK_AAD);
+            aad.len += FINAL_CHUNK_AAD.len();
         }
+        aad
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
     }
 }
@@ -0,0 +1,274 @@
+//! This module provides Shamir secret sharing of the AES data key of a stream.
+//!
+//! The AES key used to encrypt a stream is split into `N` shares, with a threshold `K`. Any `K`
+//! shares are enough to recover the AES key and decrypt the stream without the RSA private key.
+//! Less than `K` shares reveal nothing about the key. This is intended for disaster-recovery
+//! escrow: the shares can be handed to different custodians.
+//!
+//! The stream itself is unchanged: the AES key is still wrapped with the RSA public key in the
+//! header, so the recipient can decrypt it as usual.
+//!
+//! The sharing is done byte by byte over GF(2^8) (AES polynomial `x^8 + x^4 + x^3 + x + 1`).
+//!
+//! A share is serialized as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |    THRESHOLD    |   |      INDEX      |   |   Share Data    |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        1        |   |        1        |   |   AES KEY LEN   |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! **Warning**: The shares are as sensitive as the AES key once `K` of them are gathered.
+use super::{
+    decrypt::CryptoReader,
+    encrypt::{CryptoWriter, WriterOptions},
+    error::{error, Result},
+    header::Header,
+    identity::Recipient,
+    shared::setup_rng,
+};
+use rand::{CryptoRng, RngCore};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+
+/// A share of a secret.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    /// The number of shares needed to recover the secret.
+    pub threshold: u8,
+    /// The index of the share. (The x coordinate, never 0)
+    pub index: u8,
+    /// The share data. (The y coordinates, one per secret byte)
+    pub data: Zeroizing<Vec<u8>>,
+}
+
+impl std::fmt::Debug for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Share")
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Share {
+    /// Serialize the share.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(2 + self.data.len()));
+        bytes.push(self.threshold);
+        bytes.push(self.index);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Deserialize a share.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the share is malformed.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [threshold, index, data @ ..] if *threshold > 0 && *index > 0 && !data.is_empty() => {
+                Ok(Self {
+                    threshold: *threshold,
+                    index: *index,
+                    data: Zeroizing::new(data.to_vec()),
+                })
+            }
+            _ => Err(error!(InvalidData, "Invalid share")),
+        }
+    }
+}
+
+/// Split the secret into `shares` shares, any `threshold` of them being enough to recover it.
+///
+/// # Arguments
+/// - `secret`: The secret to split.
+/// - `shares`: The number of shares to create.
+/// - `threshold`: The number of shares needed to recover the secret.
+/// - `rng`: The random number generator.
+///
+/// # Returns
+/// The shares, with indices `1..=shares`.
+///
+/// # Errors
+/// - `InvalidInput`: If `threshold` is 0 or greater than `shares`.
+///
+pub fn split_secret<R: CryptoRng + RngCore>(
+    secret: &[u8],
+    shares: u8,
+    threshold: u8,
+    rng: &mut R,
+) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > shares {
+        Err(error!(
+            InvalidInput,
+            "Invalid threshold {} for {} shares", threshold, shares
+        ))?;
+    }
+
+    let mut result = (1..=shares)
+        .map(|index| Share {
+            threshold,
+            index,
+            data: Zeroizing::new(Vec::with_capacity(secret.len())),
+        })
+        .collect::<Vec<_>>();
+
+    // One random polynomial per byte, with the secret byte as constant term
+    let mut coefficients = Zeroizing::new(vec![0; threshold as usize]);
+    for &byte in secret {
+        coefficients[0] = byte;
+        rng.fill_bytes(&mut coefficients[1..]);
+        for share in result.iter_mut() {
+            // Horner's method
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| gf_mul(acc, share.index) ^ c);
+            share.data.push(y);
+        }
+    }
+    Ok(result)
+}
+
+/// Recover the secret from the given shares.
+///
+/// # Arguments
+/// - `shares`: At least `threshold` shares, with distinct indices.
+///
+/// # Returns
+/// The recovered secret.
+///
+/// # Errors
+/// - `InvalidInput`: If there are not enough shares, or if the shares are inconsistent.
+///
+/// # Notes
+/// Shares of different secrets can't be detected: the recovered secret is then garbage.
+///
+pub fn combine_shares(shares: &[Share]) -> Result<Zeroizing<Vec<u8>>> {
+    let first = shares
+        .first()
+        .ok_or_else(|| error!(InvalidInput, "No share provided"))?;
+    let threshold = first.threshold as usize;
+    if shares.len() < threshold {
+        Err(error!(
+            InvalidInput,
+            "Not enough shares: {} provided, {} needed",
+            shares.len(),
+            threshold
+        ))?;
+    }
+    let shares = &shares[..threshold];
+    for (i, share) in shares.iter().enumerate() {
+        if share.threshold != first.threshold || share.data.len() != first.data.len() {
+            Err(error!(InvalidInput, "Shares are not from the same secret"))?;
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            Err(error!(InvalidInput, "Duplicate share: {}", share.index))?;
+        }
+    }
+
+    // Lagrange interpolation at x = 0
+    let basis = shares
+        .iter()
+        .map(|share| {
+            shares
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |acc, other| {
+                    gf_mul(acc, gf_div(other.index, other.index ^ share.index))
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut secret = Zeroizing::new(vec![0; first.data.len()]);
+    for (share, &l) in shares.iter().zip(basis.iter()) {
+        for (s, &y) in secret.iter_mut().zip(share.data.iter()) {
+            *s ^= gf_mul(y, l);
+        }
+    }
+    Ok(secret)
+}
+
+/// Create a new `CryptoWriter`, and split its AES key into shares.
+///
+/// # Arguments
+/// - `writer`: The writer to write the encrypted data.
+/// - `key`: The recipient, whose RSA public key encrypts the AES key. (See `Recipient`)
+/// - `options`: The options of the stream. (See `WriterOptions`)
+/// - `shares`: The number of shares to create.
+/// - `threshold`: The number of shares needed to recover the AES key.
+///
+/// # Returns
+/// The `CryptoWriter` instance and the shares of its AES key.
+///
+/// # Errors
+/// - `InvalidInput`: If `threshold` is 0 or greater than `shares`.
+/// - `Io`: If an I/O error occurs while writing the header.
+///
+pub fn new_writer_with_shares<W: std::io::Write, const BUFFER_SIZE: usize>(
+    writer: W,
+    key: impl Into<Recipient>,
+    options: &WriterOptions,
+    shares: u8,
+    threshold: u8,
+) -> Result<(CryptoWriter<W, BUFFER_SIZE>, Vec<Share>)> {
+    let mut rng = setup_rng();
+    let aes_key = options.cipher.generate_key(&mut rng);
+    let shares = split_secret(&aes_key, shares, threshold, &mut rng)?;
+    let writer = CryptoWriter::new_with_data_key(
+        writer,
+        key.into().into_rsa(),
+        &aes_key,
+        options,
+        &mut rng,
+    )?;
+    Ok((writer, shares))
+}
+
+/// Create a new `CryptoReader`, recovering the AES key from the given shares.
+/// The RSA private key is not needed.
+///
+/// # Arguments
+/// - `reader`: The reader from which encrypted data is read.
+/// - `shares`: At least `threshold` shares of the AES key.
+///
+/// # Returns
+/// A `CryptoReader` instance.
+///
+/// # Errors
+/// - `InvalidInput`: If there are not enough shares, or if the shares are inconsistent.
+/// - `InvalidData`: If the header is malformed.
+/// - `Io`: If an I/O error occurs.
+///
+pub fn new_reader_with_shares<R: std::io::Read, const BUFFER_SIZE: usize>(
+    mut reader: R,
+    shares: &[Share],
+) -> Result<CryptoReader<R, BUFFER_SIZE>> {
+    let aes_key = combine_shares(shares)?;
+    let header = Header::parse(&mut reader)?;
+    CryptoReader::new_with_data_key(reader, &header, &aes_key)
+}
+
+/// Multiplication in GF(2^8). (Constant time: 8 rounds, without branches on the operands)
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    for _ in 0..8 {
+        result ^= a & 0u8.wrapping_sub(b & 1);
+        a = (a << 1) ^ (0x1b & 0u8.wrapping_sub(a >> 7));
+        b >>= 1;
+    }
+    result
+}
+
+/// Division in GF(2^8). (`b` must not be 0)
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^-1 = b^254
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = gf_mul(inverse, b);
+    }
+    gf_mul(a, inverse)
+}
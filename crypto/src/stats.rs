@@ -0,0 +1,42 @@
+//! This module provides the `Stats` struct, returned by `CryptoWriter::stats` and
+//! `CryptoReader::stats`.
+//!
+//! The statistics help to tune `BUFFER_SIZE` for a given workload: small chunks have a higher
+//! overhead (one authentication tag and one cipher call per chunk), large chunks use more memory
+//! and delay the data.
+use core::time::Duration;
+
+/// The statistics of a writer or a reader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Stats {
+    /// The number of chunks encrypted or decrypted.
+    pub chunks: u64,
+    /// The number of plaintext bytes. (Without the padding)
+    pub plaintext_bytes: u64,
+    /// The number of ciphertext bytes of the chunks. (Without the header)
+    pub ciphertext_bytes: u64,
+    /// The time spent in the cipher operations. (Always zero without the `std` feature)
+    pub cipher_time: Duration,
+}
+
+/// Measures the time spent in a cipher operation. (No clock without the `std` feature)
+pub(crate) struct Timer {
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(feature = "std")]
+        return self.start.elapsed();
+        #[cfg(not(feature = "std"))]
+        return Duration::ZERO;
+    }
+}
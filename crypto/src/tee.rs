@@ -0,0 +1,159 @@
+//! This module provides `TeeWriter` and `TeeReader`: adapters hashing the data passing through a
+//! writer or a reader, so the checksum of the plaintext (e.g. for a manifest) is computed in the
+//! same pass as the encryption or the decryption.
+//!
+//! Any `Digest` of the RustCrypto hashes can be used. (e.g. `sha2::Sha256`) Wrap the
+//! `CryptoWriter` in a `TeeWriter` (or the `CryptoReader` in a `TeeReader`), and once the data is
+//! written (or read), `finalize` returns the inner writer (or reader) and the hash. For a
+//! `CryptoWriter`, flush the returned writer to write the final chunk.
+use super::io;
+use sha2::digest::{Digest, Output};
+
+/// A writer hashing the data written to the inner writer. (See the module documentation)
+///
+/// Only the bytes accepted by the inner writer are hashed.
+#[derive(Debug)]
+pub struct TeeWriter<W: io::Write, H: Digest> {
+    writer: W,
+    hasher: H,
+    len: u64,
+}
+
+impl<W: io::Write, H: Digest> TeeWriter<W, H> {
+    /// Create a new `TeeWriter`, with a new hasher.
+    pub fn new(writer: W) -> Self {
+        Self::with_hasher(writer, H::new())
+    }
+
+    /// Create a new `TeeWriter`, with the given hasher. (e.g. already fed with a prefix)
+    pub fn with_hasher(writer: W, hasher: H) -> Self {
+        Self {
+            writer,
+            hasher,
+            len: 0,
+        }
+    }
+
+    /// The number of bytes hashed so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether no byte was hashed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a reference to the hasher, fed with the data written so far. (e.g. to clone it for an
+    /// intermediate hash)
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Get a mutable reference to the inner writer.
+    ///
+    /// # Notes
+    /// The data written directly to the inner writer is not hashed.
+    ///
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Consume the `TeeWriter`, returning the inner writer and the hash of the data written.
+    ///
+    /// # Notes
+    /// The inner writer is not flushed. (For a `CryptoWriter`, `flush` writes the final chunk)
+    ///
+    pub fn finalize(self) -> (W, Output<H>) {
+        (self.writer, self.hasher.finalize())
+    }
+}
+
+impl<W: io::Write, H: Digest> io::Write for TeeWriter<W, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    /// Flush the inner writer. (For a `CryptoWriter`, this ends the stream)
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A reader hashing the data read from the inner reader. (See the module documentation)
+///
+/// The hash covers the data read so far: read the inner reader to its end before finalizing.
+#[derive(Debug)]
+pub struct TeeReader<R: io::Read, H: Digest> {
+    reader: R,
+    hasher: H,
+    len: u64,
+}
+
+impl<R: io::Read, H: Digest> TeeReader<R, H> {
+    /// Create a new `TeeReader`, with a new hasher.
+    pub fn new(reader: R) -> Self {
+        Self::with_hasher(reader, H::new())
+    }
+
+    /// Create a new `TeeReader`, with the given hasher. (e.g. already fed with a prefix)
+    pub fn with_hasher(reader: R, hasher: H) -> Self {
+        Self {
+            reader,
+            hasher,
+            len: 0,
+        }
+    }
+
+    /// The number of bytes hashed so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether no byte was hashed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a reference to the hasher, fed with the data read so far. (e.g. to clone it for an
+    /// intermediate hash)
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Get a mutable reference to the inner reader.
+    ///
+    /// # Notes
+    /// The data read directly from the inner reader is not hashed.
+    ///
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Consume the `TeeReader`, returning the inner reader and the hash of the data read.
+    pub fn finalize(self) -> (R, Output<H>) {
+        (self.reader, self.hasher.finalize())
+    }
+}
+
+impl<R: io::Read, H: Digest> io::Read for TeeReader<R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
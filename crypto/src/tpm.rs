@@ -0,0 +1,396 @@
+//! This module provides TPM 2.0 sealed private keys: the RSA private key can only be decrypted by
+//! the TPM of the machine, in the expected boot state, so a stolen disk (or key file) is useless.
+//!
+//! A TPM sealed object holds at most 128 bytes, so a random master key is sealed instead, under a
+//! policy on the given PCRs (see `PcrSelection`), and the RSA private key is encrypted with it.
+//! The sealed key is written as follows:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |      Magic      |   |    PCR COUNT    |   |      PCRs       |   |   Sealed Pub    |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |    "CRYPTOTS"   |   |       u8        |   |  u8 (SHA-256)   |   | u16 BE + TPM2B  |   ...
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//! |        8        |   |        1        |   |    PCR COUNT    |   |        -        |
+//! +-----------------+   +-----------------+   +-----------------+   +-----------------+
+//!
+//!     +-----------------+   +-----------------+
+//!     |   Sealed Priv   |   |   Private Key   |
+//!     +-----------------+   +-----------------+
+//! ... | u16 BE + TPM2B  |   | u16 BE + AES-GCM|
+//!     +-----------------+   +-----------------+
+//!     |        -        |   |        -        |
+//!     +-----------------+   +-----------------+
+//! ```
+//!
+//! The private key is the PKCS#1 DER, encrypted with AES-256-GCM under the master key.
+//!
+//! The TPM is driven through the `tpm2-tools` commands (`tpm2_createprimary`, `tpm2_create`,
+//! `tpm2_unseal`, ...), which must be installed. The TPM is selected by the `TPM2TOOLS_TCTI`
+//! environment variable, as for the commands themselves. (e.g. `swtpm:port=2321` for a simulator)
+//! The sealed object is a child of the ECC P-256 primary key of the owner hierarchy, which the TPM
+//! derives again on each use: nothing is stored in the TPM.
+use super::{
+    audit::{audit_key_loaded, KeySource},
+    decrypt::CryptoReader,
+    error::{error, Result},
+    kem::{open_key, seal_key},
+    shared::setup_rng,
+};
+use rand::{CryptoRng, RngCore};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey as _, EncodeRsaPrivateKey as _},
+    pkcs8::der::zeroize::Zeroizing,
+    RsaPrivateKey,
+};
+use std::{
+    io::{Read, Write as _},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// The magic bytes of a sealed key.
+const SEALED_MAGIC: &[u8; 8] = b"CRYPTOTS";
+/// Domain separation of the encrypted private key, used as additional data.
+const SEALED_LABEL: &[u8] = b"crypto tpm sealed key";
+/// The number of PCRs of a TPM 2.0 bank.
+const PCR_COUNT: u8 = 24;
+
+/// The PCRs (SHA-256 bank) whose values must match to unseal a key.
+///
+/// The values are the ones at sealing time: an update of the firmware, the boot loader or the
+/// kernel (depending on the PCRs) requires to seal the key again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcrSelection {
+    pcrs: Vec<u8>,
+}
+
+impl PcrSelection {
+    /// Select PCRs of the SHA-256 bank. (e.g. `[0, 7]`: the firmware and the Secure Boot state)
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If a PCR index is out of range (at least 24), or no PCR is selected.
+    ///
+    pub fn sha256(pcrs: &[u8]) -> Result<Self> {
+        if pcrs.is_empty() {
+            Err(error!(InvalidInput, "No PCR selected"))?;
+        }
+        if let Some(pcr) = pcrs.iter().find(|&&pcr| pcr >= PCR_COUNT) {
+            Err(error!(InvalidInput, "PCR {} is out of range", pcr))?;
+        }
+        let mut pcrs = pcrs.to_vec();
+        pcrs.sort_unstable();
+        pcrs.dedup();
+        Ok(Self { pcrs })
+    }
+
+    /// The selected PCRs, in increasing order.
+    pub fn pcrs(&self) -> &[u8] {
+        &self.pcrs
+    }
+}
+
+impl core::fmt::Display for PcrSelection {
+    /// The selection in the syntax of `tpm2-tools`. (e.g. `sha256:0,7`)
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "sha256:")?;
+        for (index, pcr) in self.pcrs.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", pcr)?;
+        }
+        Ok(())
+    }
+}
+
+/// A RSA private key sealed to a TPM. (See the module documentation)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TpmSealedKey {
+    pcrs: PcrSelection,
+    sealed_public: Vec<u8>,
+    sealed_private: Vec<u8>,
+    encrypted_key: Vec<u8>,
+}
+
+impl TpmSealedKey {
+    /// Seal a private key to the TPM, with a policy on the current values of the given PCRs.
+    ///
+    /// # Errors
+    /// - `Unsupported`: If `tpm2-tools` is not installed.
+    /// - `Other`: If a TPM command fails. (e.g. no TPM, or no access to it)
+    /// - `Io`: If an I/O error occurs.
+    ///
+    pub fn seal(private_key: &RsaPrivateKey, pcrs: &PcrSelection) -> Result<Self> {
+        let mut rng = setup_rng();
+        Self::seal_with_rng(private_key, pcrs, &mut rng)
+    }
+
+    /// Seal a private key to the TPM, with the given random number generator. (Generating the
+    /// master key)
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn seal_with_rng<R: CryptoRng + RngCore>(
+        private_key: &RsaPrivateKey,
+        pcrs: &PcrSelection,
+        rng: &mut R,
+    ) -> Result<Self> {
+        let mut master_key = Zeroizing::new([0; 32]);
+        rng.fill_bytes(master_key.as_mut());
+        let der = private_key
+            .to_pkcs1_der()
+            .map_err(|e| error!(InvalidInput, "Failed to encode the private key: {}", e))?;
+        let encrypted_key = seal_key(&master_key, SEALED_LABEL, der.as_bytes())?;
+
+        let dir = TempDir::new()?;
+        create_primary(&dir)?;
+        tpm2(
+            "tpm2_createpolicy",
+            &[
+                "--policy-pcr".as_ref(),
+                "-l".as_ref(),
+                pcrs.to_string().as_ref(),
+                "-L".as_ref(),
+                dir.path("policy.digest").as_os_str(),
+            ],
+            None,
+        )?;
+        // Without `userwithauth`, the PCR policy is the only way to unseal
+        tpm2(
+            "tpm2_create",
+            &[
+                "-C".as_ref(),
+                dir.path("primary.ctx").as_os_str(),
+                "-L".as_ref(),
+                dir.path("policy.digest").as_os_str(),
+                "-a".as_ref(),
+                "fixedtpm|fixedparent|noda".as_ref(),
+                "-i".as_ref(),
+                "-".as_ref(),
+                "-u".as_ref(),
+                dir.path("seal.pub").as_os_str(),
+                "-r".as_ref(),
+                dir.path("seal.priv").as_os_str(),
+            ],
+            Some(master_key.as_ref()),
+        )?;
+
+        Ok(Self {
+            pcrs: pcrs.clone(),
+            sealed_public: std::fs::read(dir.path("seal.pub"))?,
+            sealed_private: std::fs::read(dir.path("seal.priv"))?,
+            encrypted_key,
+        })
+    }
+
+    /// The PCRs of the policy.
+    pub fn pcrs(&self) -> &PcrSelection {
+        &self.pcrs
+    }
+
+    /// Unseal the private key with the TPM.
+    ///
+    /// # Errors
+    /// - `Unsupported`: If `tpm2-tools` is not installed.
+    /// - `PermissionDenied`: If the TPM refuses to unseal the master key. (Another TPM, or the
+    ///   PCR values changed)
+    /// - `InvalidData`: If the sealed key is corrupted.
+    /// - `Other`: If a TPM command fails.
+    ///
+    pub fn unseal(&self) -> Result<RsaPrivateKey> {
+        let dir = TempDir::new()?;
+        create_primary(&dir)?;
+        std::fs::write(dir.path("seal.pub"), &self.sealed_public)?;
+        std::fs::write(dir.path("seal.priv"), &self.sealed_private)?;
+        tpm2(
+            "tpm2_load",
+            &[
+                "-C".as_ref(),
+                dir.path("primary.ctx").as_os_str(),
+                "-u".as_ref(),
+                dir.path("seal.pub").as_os_str(),
+                "-r".as_ref(),
+                dir.path("seal.priv").as_os_str(),
+                "-c".as_ref(),
+                dir.path("seal.ctx").as_os_str(),
+            ],
+            None,
+        )?;
+        let master_key = Zeroizing::new(
+            tpm2(
+                "tpm2_unseal",
+                &[
+                    "-c".as_ref(),
+                    dir.path("seal.ctx").as_os_str(),
+                    "-p".as_ref(),
+                    format!("pcr:{}", self.pcrs).as_ref(),
+                ],
+                None,
+            )
+            .map_err(|e| error!(PermissionDenied, "The TPM refused to unseal the key: {}", e))?,
+        );
+        let master_key: &[u8; 32] = master_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| error!(InvalidData, "Invalid TPM sealed master key"))?;
+
+        let der = open_key(master_key, SEALED_LABEL, &self.encrypted_key)
+            .ok_or_else(|| error!(InvalidData, "Invalid TPM sealed key"))?;
+        let private_key = RsaPrivateKey::from_pkcs1_der(&der)
+            .map_err(|e| error!(InvalidData, "Invalid private key: {}", e))?;
+        audit_key_loaded(KeySource::Tpm, None, &private_key);
+        Ok(private_key)
+    }
+
+    /// Encode the sealed key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SEALED_MAGIC);
+        bytes.push(self.pcrs.pcrs.len() as u8);
+        bytes.extend_from_slice(&self.pcrs.pcrs);
+        for field in [
+            &self.sealed_public,
+            &self.sealed_private,
+            &self.encrypted_key,
+        ] {
+            // The TPM2B structures and the encrypted RSA key are well below 64 KiB
+            bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes
+    }
+
+    /// Decode a sealed key encoded by `to_bytes`.
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the sealed key is malformed.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || error!(InvalidData, "Invalid TPM sealed key");
+        let bytes = bytes.strip_prefix(SEALED_MAGIC).ok_or_else(invalid)?;
+        let (&count, bytes) = bytes.split_first().ok_or_else(invalid)?;
+        let (pcrs, bytes) = bytes.split_at_checked(count as usize).ok_or_else(invalid)?;
+        let pcrs = PcrSelection::sha256(pcrs).map_err(|_| invalid())?;
+
+        let mut fields = [Vec::new(), Vec::new(), Vec::new()];
+        let mut bytes = bytes;
+        for field in &mut fields {
+            let (len, rest) = bytes.split_first_chunk::<2>().ok_or_else(invalid)?;
+            let (value, rest) = rest
+                .split_at_checked(u16::from_be_bytes(*len) as usize)
+                .ok_or_else(invalid)?;
+            *field = value.to_vec();
+            bytes = rest;
+        }
+        if !bytes.is_empty() {
+            return Err(invalid());
+        }
+        let [sealed_public, sealed_private, encrypted_key] = fields;
+        Ok(Self {
+            pcrs,
+            sealed_public,
+            sealed_private,
+            encrypted_key,
+        })
+    }
+}
+
+impl<R: Read, const BUFFER_SIZE: usize> CryptoReader<R, BUFFER_SIZE> {
+    /// Create a new `CryptoReader` instance, unsealing the private key with the TPM.
+    ///
+    /// # Arguments
+    /// - `reader`: The reader from which the encrypted data is read.
+    /// - `key`: The RSA private key sealed to the TPM.
+    ///
+    /// # Errors
+    /// - The errors of `TpmSealedKey::unseal` and `CryptoReader::new`.
+    ///
+    pub fn new_tpm_sealed(reader: R, key: &TpmSealedKey) -> Result<Self> {
+        Self::new(reader, key.unseal()?)
+    }
+}
+
+/// Derive the primary key of the owner hierarchy. (Same template, same key)
+fn create_primary(dir: &TempDir) -> Result<()> {
+    tpm2(
+        "tpm2_createprimary",
+        &[
+            "-C".as_ref(),
+            "o".as_ref(),
+            "-g".as_ref(),
+            "sha256".as_ref(),
+            "-G".as_ref(),
+            "ecc256".as_ref(),
+            "-c".as_ref(),
+            dir.path("primary.ctx").as_os_str(),
+        ],
+        None,
+    )
+    .map(drop)
+}
+
+/// Run a `tpm2-tools` command, and return its output.
+fn tpm2(command: &str, args: &[&std::ffi::OsStr], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                error!(Unsupported, "{} not found (install tpm2-tools)", command)
+            }
+            _ => e,
+        })?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        Err(error!(
+            Other,
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))?;
+    }
+    Ok(output.stdout)
+}
+
+/// A private temporary directory for the TPM objects, removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Result<Self> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "crypto-tpm-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&path)?;
+        Ok(Self(path))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,244 @@
+//! This module provides `VaultKem`, a `Kem` backend wrapping the AES keys with the transit engine
+//! of HashiCorp Vault (or OpenBao): the private key never leaves Vault, and each unwrap is
+//! authorized (and audited) by Vault.
+//!
+//! The shared secret of a slot is a data key generated by Vault (`datakey/plaintext`), and the
+//! encapsulated secret is the Vault ciphertext of that data key, with the name of the Vault key:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |    NAME LEN     |   |    Key Name     |   |   Ciphertext    |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |       u8        |   |     (UTF-8)     |   | (`vault:v1:...`)|
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        1        |   |    NAME LEN     |   |        -        |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The key name recorded in the header is the one used to unwrap (`decrypt`), so a reader can
+//! decrypt the streams of several Vault keys with a single `VaultKem`.
+//!
+//! Use `WriterOptions::kem_recipients` to write Vault slots, and `CryptoReader::new_with_kem` to
+//! read them.
+use super::{
+    error::{error, Result},
+    kem::Kem,
+};
+use aes_gcm::aead::rand_core::CryptoRngCore;
+use base64ct::{Base64, Encoding as _};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+
+/// The identifier of `VaultKem`.
+pub const VAULT_TRANSIT_ID: u8 = 4;
+/// The size of the data keys generated by Vault, in bits.
+const DATA_KEY_BITS: u32 = 256;
+
+/// A `Kem` backend using a key of the Vault transit engine. (See the module documentation)
+///
+/// The token needs the `update` capability on `<mount>/datakey/plaintext/<key>` to write, and on
+/// `<mount>/decrypt/<key>` to read.
+#[derive(Clone)]
+pub struct VaultKem {
+    address: String,
+    token: Zeroizing<String>,
+    namespace: Option<String>,
+    mount: String,
+    key_name: String,
+    agent: ureq::Agent,
+}
+
+impl VaultKem {
+    /// The identifier of `VaultKem`.
+    pub const ID: u8 = VAULT_TRANSIT_ID;
+
+    /// Create a `VaultKem` for a key of the transit engine mounted at `transit`.
+    ///
+    /// # Arguments
+    /// - `address`: The address of the Vault server. (e.g. `https://vault.example.com:8200`)
+    /// - `token`: The Vault token.
+    /// - `key_name`: The name of the transit key wrapping the AES keys.
+    ///
+    /// # Errors
+    /// - `InvalidInput`: If the key name is empty, longer than 255 bytes, `.` or `..`, or contains
+    ///   other characters than `A-Z`, `a-z`, `0-9`, `_`, `.` and `-`.
+    ///
+    pub fn new(address: &str, token: &str, key_name: &str) -> Result<Self> {
+        check_key_name(key_name)?;
+        Ok(Self {
+            address: address.trim_end_matches('/').to_string(),
+            token: Zeroizing::new(token.to_string()),
+            namespace: None,
+            mount: "transit".to_string(),
+            key_name: key_name.to_string(),
+            agent: ureq::Agent::new(),
+        })
+    }
+
+    /// Create a `VaultKem` from the environment variables of the Vault CLI. (`VAULT_ADDR`,
+    /// `VAULT_TOKEN`, and the optional `VAULT_NAMESPACE`)
+    ///
+    /// # Errors
+    /// - `NotFound`: If `VAULT_ADDR` or `VAULT_TOKEN` is not set.
+    /// - The errors of `new`.
+    ///
+    pub fn from_env(key_name: &str) -> Result<Self> {
+        let var = |name| std::env::var(name).map_err(|_| error!(NotFound, "{} is not set", name));
+        let kem = Self::new(&var("VAULT_ADDR")?, &var("VAULT_TOKEN")?, key_name)?;
+        Ok(match std::env::var("VAULT_NAMESPACE") {
+            Ok(namespace) if !namespace.is_empty() => kem.with_namespace(&namespace),
+            _ => kem,
+        })
+    }
+
+    /// Use the transit engine mounted at the given path. (Default: `transit`)
+    pub fn with_mount(mut self, mount: &str) -> Self {
+        self.mount = mount.trim_matches('/').to_string();
+        self
+    }
+
+    /// Send the requests to the given Vault Enterprise namespace.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// The name of the transit key wrapping the AES keys.
+    pub fn key_name(&self) -> &str {
+        &self.key_name
+    }
+
+    /// The name of the transit key recorded in an encapsulated secret. (See `Header::wrapped_keys`)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the encapsulated secret is malformed.
+    ///
+    pub fn recorded_key_name(wrapped: &[u8]) -> Result<&str> {
+        split_wrapped(wrapped).map(|(key_name, _)| key_name)
+    }
+
+    /// Send a request to the transit engine, and return the `data` object of the response.
+    fn request(&self, path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let url = format!("{}/v1/{}/{}", self.address, self.mount, path);
+        let mut request = self
+            .agent
+            .post(&url)
+            .set("X-Vault-Token", &self.token)
+            .set("X-Vault-Request", "true");
+        if let Some(namespace) = &self.namespace {
+            request = request.set("X-Vault-Namespace", namespace);
+        }
+        let response = match request.send_string(&body.to_string()) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                let kind = match status {
+                    403 => std::io::ErrorKind::PermissionDenied,
+                    _ => std::io::ErrorKind::Other,
+                };
+                return Err(std::io::Error::new(
+                    kind,
+                    format!(
+                        "Vault request {} failed ({}): {}",
+                        path,
+                        status,
+                        response.into_string().unwrap_or_default()
+                    ),
+                ));
+            }
+            Err(e) => Err(error!(Other, "Vault request {} failed: {}", path, e))?,
+        };
+        let response: serde_json::Value = serde_json::from_str(&response.into_string()?)
+            .map_err(|e| error!(InvalidData, "Invalid Vault response: {}", e))?;
+        match response.get("data") {
+            Some(data) => Ok(data.clone()),
+            None => Err(error!(InvalidData, "Invalid Vault response: no data")),
+        }
+    }
+}
+
+impl core::fmt::Debug for VaultKem {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("VaultKem")
+            .field("address", &self.address)
+            .field("namespace", &self.namespace)
+            .field("mount", &self.mount)
+            .field("key_name", &self.key_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Kem for VaultKem {
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+
+    fn encapsulate(&self, _rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        // The data key is generated by Vault
+        let data = self.request(
+            &format!("datakey/plaintext/{}", self.key_name),
+            serde_json::json!({ "bits": DATA_KEY_BITS }),
+        )?;
+        let ciphertext = string_field(&data, "ciphertext")?;
+        let shared = decode_plaintext(&data)?;
+
+        let mut wrapped = Vec::with_capacity(1 + self.key_name.len() + ciphertext.len());
+        wrapped.push(self.key_name.len() as u8);
+        wrapped.extend_from_slice(self.key_name.as_bytes());
+        wrapped.extend_from_slice(ciphertext.as_bytes());
+        Ok((wrapped, shared))
+    }
+
+    fn decapsulate(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let (key_name, ciphertext) = split_wrapped(wrapped)?;
+        let data = self.request(
+            &format!("decrypt/{}", key_name),
+            serde_json::json!({ "ciphertext": ciphertext }),
+        )?;
+        decode_plaintext(&data)
+    }
+}
+
+/// Check a transit key name, recorded in the header and used in the request paths. (Only the
+/// charset of the Vault names, so a crafted header can't reach other paths of the mount)
+fn check_key_name(key_name: &str) -> Result<()> {
+    let charset = |c: u8| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'-');
+    if key_name.is_empty()
+        || key_name.len() > u8::MAX as usize
+        || !key_name.bytes().all(charset)
+        || key_name == "."
+        || key_name == ".."
+    {
+        Err(error!(
+            InvalidInput,
+            "Invalid Vault key name: {:?}", key_name
+        ))?;
+    }
+    Ok(())
+}
+
+/// Split an encapsulated secret into the key name and the Vault ciphertext.
+fn split_wrapped(wrapped: &[u8]) -> Result<(&str, &str)> {
+    let invalid = || error!(InvalidData, "Invalid Vault slot");
+    let (&len, rest) = wrapped.split_first().ok_or_else(invalid)?;
+    let (key_name, ciphertext) = rest.split_at_checked(len as usize).ok_or_else(invalid)?;
+    let key_name = core::str::from_utf8(key_name).map_err(|_| invalid())?;
+    let ciphertext = core::str::from_utf8(ciphertext).map_err(|_| invalid())?;
+    check_key_name(key_name).map_err(|_| invalid())?;
+    if !ciphertext.starts_with("vault:") {
+        return Err(invalid());
+    }
+    Ok((key_name, ciphertext))
+}
+
+/// A string field of the `data` object of a response.
+fn string_field<'a>(data: &'a serde_json::Value, name: &str) -> Result<&'a str> {
+    data.get(name)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| error!(InvalidData, "Invalid Vault response: no {}", name))
+}
+
+/// Decode the base64 `plaintext` field of a response.
+fn decode_plaintext(data: &serde_json::Value) -> Result<Zeroizing<Vec<u8>>> {
+    Base64::decode_vec(string_field(data, "plaintext")?)
+        .map(Zeroizing::new)
+        .map_err(|_| error!(InvalidData, "Invalid Vault response: invalid plaintext"))
+}
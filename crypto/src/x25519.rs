@@ -0,0 +1,191 @@
+//! This module provides the anonymous sender mode: X25519 recipients, with an ephemeral key pair
+//! per stream. (`x25519` feature)
+//!
+//! The writer generates a new X25519 key pair for each stream, and derives the shared secret of
+//! the slot from a Diffie-Hellman between the ephemeral private key and the static public key of
+//! the recipient. (ECIES) The ephemeral public key is the encapsulated secret of the slot, and the
+//! ephemeral private key is dropped once the slot is written:
+//!
+//! ```plaintext
+//! +-----------------+   +-----------------+   +-----------------+
+//! |   KEM ENC LEN   |   |  Ephemeral Key  |   |     AES Key     |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |     u16 BE      |   |  (X25519 public)|   |  AES-256-GCM    |
+//! +-----------------+   +-----------------+   +-----------------+
+//! |        2        |   |       32        |   | AES KEY LEN+16  |
+//! +-----------------+   +-----------------+   +-----------------+
+//! ```
+//!
+//! The shared secret is `SHA-256(label || DH || ephemeral key || recipient key)`. (See `kem` for
+//! the rest of the slot)
+//!
+//! So the stream carries no long-term key of the sender, and nothing linking two streams of the
+//! same sender: the recipient can read the stream, but can't tell (nor prove to anyone else) who
+//! wrote it. The RSA slots are anonymous too (a random AES key encrypted for the recipient), unlike
+//! `seal`, which reveals the signer key to the recipient.
+//!
+//! Use `WriterOptions::kem_recipients` to write X25519 slots, and `CryptoReader::new_with_kem`
+//! with the `X25519PrivateKey` to read them.
+//!
+//! **Warning**: Anyone holding the public key of the recipient can write such a stream: there is
+//! no sender authentication at all. Sign the payload (see `seal`) when the sender matters.
+use super::{
+    error::{error, Result},
+    kem::Kem,
+};
+use aes_gcm::aead::rand_core::CryptoRngCore;
+use rand::{CryptoRng, RngCore};
+use rsa::pkcs8::der::zeroize::Zeroizing;
+use sha2::{Digest as _, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// The length of a X25519 key. (Public or private)
+const X25519_KEY_LEN: usize = 32;
+/// Domain separation of the shared secret.
+const X25519_LABEL: &[u8] = b"crypto x25519 ephemeral";
+/// The `Kem` identifier of X25519.
+pub const X25519_ID: u8 = 5;
+
+/// A X25519 public key, of a recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct X25519PublicKey(PublicKey);
+
+impl X25519PublicKey {
+    /// Load a public key from its encoding. (32 bytes, RFC 7748)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the length is invalid.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; X25519_KEY_LEN] = bytes.try_into().map_err(|_| {
+            error!(
+                InvalidData,
+                "X25519 public key must be {} bytes, got {}",
+                X25519_KEY_LEN,
+                bytes.len()
+            )
+        })?;
+        Ok(Self(PublicKey::from(bytes)))
+    }
+
+    /// The encoding of the public key. (32 bytes, RFC 7748)
+    pub fn to_bytes(&self) -> [u8; X25519_KEY_LEN] {
+        self.0.to_bytes()
+    }
+}
+
+/// A X25519 private key, of a recipient.
+#[derive(Clone)]
+pub struct X25519PrivateKey(StaticSecret);
+
+impl X25519PrivateKey {
+    /// Generate a new private key.
+    ///
+    /// # Notes
+    /// The random number generator must be cryptographically secure. And should implement the
+    /// `CryptoRng` and `RngCore` traits. (From the `rand` crate)
+    ///
+    pub fn generate<R: CryptoRng + RngCore>(rng: &mut R) -> Self {
+        Self(StaticSecret::random_from_rng(rng))
+    }
+
+    /// The public key matching this private key.
+    pub fn public_key(&self) -> X25519PublicKey {
+        X25519PublicKey(PublicKey::from(&self.0))
+    }
+
+    /// Load a private key from its encoding. (32 bytes, RFC 7748)
+    ///
+    /// # Errors
+    /// - `InvalidData`: If the length is invalid.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; X25519_KEY_LEN] = bytes.try_into().map_err(|_| {
+            error!(
+                InvalidData,
+                "X25519 private key must be {} bytes, got {}",
+                X25519_KEY_LEN,
+                bytes.len()
+            )
+        })?;
+        Ok(Self(StaticSecret::from(bytes)))
+    }
+
+    /// The encoding of the private key. (32 bytes, RFC 7748)
+    pub fn to_bytes(&self) -> Zeroizing<[u8; X25519_KEY_LEN]> {
+        Zeroizing::new(self.0.to_bytes())
+    }
+}
+
+impl core::fmt::Debug for X25519PrivateKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("X25519PrivateKey").finish_non_exhaustive()
+    }
+}
+
+impl Kem for X25519PublicKey {
+    fn id(&self) -> u8 {
+        X25519_ID
+    }
+
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        let ephemeral = EphemeralSecret::random_from_rng(rng);
+        let ephemeral_key = PublicKey::from(&ephemeral);
+        let dh = ephemeral.diffie_hellman(&self.0);
+        if !dh.was_contributory() {
+            Err(error!(InvalidInput, "Invalid X25519 public key"))?;
+        }
+        let shared = derive_shared(dh.as_bytes(), &ephemeral_key, &self.0);
+        Ok((ephemeral_key.as_bytes().to_vec(), shared))
+    }
+
+    fn decapsulate(&self, _wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        Err(error!(
+            Unsupported,
+            "X25519 requires the private key to decapsulate"
+        ))
+    }
+}
+
+impl Kem for X25519PrivateKey {
+    fn id(&self) -> u8 {
+        X25519_ID
+    }
+
+    fn encapsulate(&self, rng: &mut dyn CryptoRngCore) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>)> {
+        self.public_key().encapsulate(rng)
+    }
+
+    fn decapsulate(&self, wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let ephemeral_key: [u8; X25519_KEY_LEN] = wrapped
+            .try_into()
+            .map_err(|_| error!(Other, "Invalid X25519 ephemeral key length"))?;
+        let ephemeral_key = PublicKey::from(ephemeral_key);
+        let dh = self.0.diffie_hellman(&ephemeral_key);
+        if !dh.was_contributory() {
+            Err(error!(Other, "Invalid X25519 ephemeral key"))?;
+        }
+        Ok(derive_shared(
+            dh.as_bytes(),
+            &ephemeral_key,
+            &PublicKey::from(&self.0),
+        ))
+    }
+}
+
+/// Derive the shared secret of a slot, bound to both public keys.
+fn derive_shared(
+    dh: &[u8; X25519_KEY_LEN],
+    ephemeral_key: &PublicKey,
+    recipient_key: &PublicKey,
+) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(
+        Sha256::new()
+            .chain_update(X25519_LABEL)
+            .chain_update(dh)
+            .chain_update(ephemeral_key.as_bytes())
+            .chain_update(recipient_key.as_bytes())
+            .finalize()
+            .to_vec(),
+    )
+}
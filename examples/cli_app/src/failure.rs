@@ -0,0 +1,89 @@
+//! The failures of the CLI: a message on stderr, and an exit code per kind of failure, so scripts
+//! can tell a wrong key from a corrupted file.
+//!
+//! | Code | Failure                                                                  |
+//! |------|--------------------------------------------------------------------------|
+//! | 1    | `Other`: e.g. an overwrite refused, or a failed self-test                |
+//! | 2    | Invalid arguments (reported by clap)                                     |
+//! | 3    | `BadKey`: missing or malformed key, wrong passphrase, or no matching key |
+//! | 4    | `BadInput`: unreadable input, or not an encrypted file                   |
+//! | 5    | `Authentication`: corrupted or tampered data, or a bad signature         |
+//! | 6    | `Io`: the output can't be written                                        |
+use crate::output::{paint, Stream, Style};
+use crypto::ops::FileError;
+use std::{any::Any, fmt::Display, io::Write as _};
+
+/// A kind of failure, with its exit code. (See the module documentation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Other = 1,
+    BadKey = 3,
+    BadInput = 4,
+    Authentication = 5,
+    Io = 6,
+}
+
+/// Print the message on stderr, and exit with the code of the failure.
+pub fn fail(failure: Failure, message: impl Display) -> ! {
+    // The data already written to stdout comes first
+    _ = std::io::stdout().flush();
+    eprintln!("{}", paint(message, Style::Error, Stream::Stderr));
+    std::process::exit(failure as i32)
+}
+
+/// Exit on a failed result, instead of panicking.
+pub trait OrFail<T> {
+    /// The value, or exit with the failure and the message. (Followed by the error, if any)
+    ///
+    /// The errors of the library are classified by themselves: a wrong key is a `BadKey`, a
+    /// corrupted chunk or a bad signature an `Authentication` failure, whatever `failure` is.
+    fn or_fail(self, failure: Failure, message: &str) -> T;
+}
+
+impl<T, E: Display + 'static> OrFail<T> for Result<T, E> {
+    fn or_fail(self, failure: Failure, message: &str) -> T {
+        self.unwrap_or_else(|e| {
+            let failure = classify(&e, failure);
+            // The file errors of `crypto::ops` already name the file
+            match file_error(&e) {
+                Some(file) => fail(failure, file),
+                None => fail(failure, format_args!("{message}: {e}")),
+            }
+        })
+    }
+}
+
+impl<T> OrFail<T> for Option<T> {
+    fn or_fail(self, failure: Failure, message: &str) -> T {
+        self.unwrap_or_else(|| fail(failure, message))
+    }
+}
+
+/// The failure of an error of the library, if it is typed. (See `crypto::Error` and
+/// `crypto::ops::FileError`)
+pub fn classify<E: 'static>(error: &E, failure: Failure) -> Failure {
+    if let Some(file) = file_error(error) {
+        return if file.output {
+            Failure::Io
+        } else {
+            Failure::BadInput
+        };
+    }
+    let error: &dyn Any = error;
+    let typed = error
+        .downcast_ref::<std::io::Error>()
+        .and_then(crypto::Error::from_io)
+        .or_else(|| error.downcast_ref());
+    match typed {
+        Some(crypto::Error::WrongKey | crypto::Error::KeyExpired { .. }) => Failure::BadKey,
+        Some(crypto::Error::CorruptedChunk { .. } | crypto::Error::BadSignature) => {
+            Failure::Authentication
+        }
+        _ => failure,
+    }
+}
+
+fn file_error<E: 'static>(error: &E) -> Option<&FileError> {
+    let error: &dyn Any = error;
+    FileError::from_io(error.downcast_ref()?)
+}
@@ -1,14 +1,49 @@
-use clap::{Parser, Subcommand};
-use crypto::{CryptoReader, CryptoWriter, RsaKeys};
+use clap::{CommandFactory, Parser, Subcommand};
+use crypto::{
+    archive::{ArchiveReader, ArchiveWriter},
+    ops, seal, split, KeyBundle, KeyStore, RsaKeys,
+};
+use failure::{fail, Failure, OrFail as _};
+use output::{Stream, Style};
 use std::{
-    io::{Read, Write as _},
-    path::PathBuf,
+    io::{IsTerminal as _, Read, Write},
+    path::{Path, PathBuf},
 };
 
+mod failure;
+#[macro_use]
+mod output;
+mod passphrase;
+mod selftest;
+mod trust;
+
 #[derive(Parser)]
+#[clap(version, about = "Encrypt and decrypt files with RSA and AES-GCM")]
 struct Args {
     #[clap(subcommand)]
     subcommand: Subcommands,
+    #[clap(
+        long,
+        global = true,
+        help = "Read the passphrase of the protected keys from the first line of this file (else from $CRYPTO_PASSPHRASE, or a prompt)"
+    )]
+    passphrase_file: Option<PathBuf>,
+    #[clap(
+        short,
+        long,
+        global = true,
+        conflicts_with = "verbose",
+        help = "Only print the results and the errors"
+    )]
+    quiet: bool,
+    #[clap(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Print the debug events of the library (-vv for the trace events)"
+    )]
+    verbose: u8,
 }
 
 #[derive(Subcommand)]
@@ -18,141 +53,1264 @@ enum Subcommands {
             help = "File to save the private key. Public key will be saved in the same directory with the same name but with a .pub extension (e.g. like ssh-keygen utility)"
         )]
         output: PathBuf,
+        #[clap(
+            long,
+            help = "Owner of the key, recorded in the <output>.bundle sidecar (default: the file name)"
+        )]
+        owner: Option<String>,
+        #[clap(
+            long,
+            value_parser = parse_duration,
+            help = "Validity period of the key (e.g. 90d, 52w, 1y), after which it is refused as a recipient"
+        )]
+        expires: Option<u64>,
+        #[clap(long, default_value_t = 2048, help = "Size of the key in bits")]
+        bits: usize,
+        #[clap(
+            long,
+            num_args = 0..=1,
+            value_name = "PASSPHRASE",
+            help = "Protect the private key with a passphrase (encrypted PKCS#8). Without a value, it is read from --passphrase-file, $CRYPTO_PASSPHRASE or a prompt"
+        )]
+        passphrase: Option<Option<String>>,
+        #[clap(long, help = "Overwrite the existing key files without asking")]
+        force: bool,
     },
     Encrypt {
         #[clap(help = "File to encrypt")]
         input: PathBuf,
-        #[clap(help = "Public key to encrypt the data")]
-        key: PathBuf,
+        #[clap(
+            required_unless_present_any = ["to", "self_"],
+            help = "Public key to encrypt the data (or the name of a trusted key)"
+        )]
+        key: Option<PathBuf>,
         #[clap(help = "File to save the encrypted data (default: <data>.enc)")]
         output: Option<PathBuf>,
+        #[clap(
+            long,
+            help = "Name of a trusted key (or a public key) to encrypt the data, instead of the <KEY> argument"
+        )]
+        to: Option<PathBuf>,
+        #[clap(
+            long = "self",
+            conflicts_with = "to",
+            help = "Encrypt for your default key ($CRYPTO_DEFAULT_KEY, else the default key of the key store, see `keys default`), instead of the <KEY> argument"
+        )]
+        self_: bool,
+        #[clap(
+            long,
+            requires = "threshold",
+            help = "Split the AES key into this many shares, saved as <output>.share<N>"
+        )]
+        shares: Option<u8>,
+        #[clap(
+            long,
+            requires = "shares",
+            help = "Number of shares needed to recover the AES key"
+        )]
+        threshold: Option<u8>,
+        #[clap(
+            long,
+            value_parser = parse_size,
+            help = "Split the encrypted data into parts of at most this size (e.g. 100MB), saved as <output>.001, <output>.002, ..."
+        )]
+        split_size: Option<u64>,
+        #[clap(long, help = "Overwrite the existing output files without asking")]
+        force: bool,
     },
     Decrypt {
         #[clap(help = "File to decrypt")]
         input: PathBuf,
-        #[clap(help = "Private key to decrypt the data")]
+        #[clap(help = "Private key to decrypt the data (or the name of a key of the key store)")]
+        key: PathBuf,
+        #[clap(
+            help = "File to save the decrypted data (default: the original name of the file, next to <INPUT>, or stdout if unknown)"
+        )]
+        output: Option<String>,
+        #[clap(
+            long,
+            help = "Write to stdout instead of the original name of the file, if no output is given"
+        )]
+        no_restore_name: bool,
+        #[clap(long, help = "Overwrite the existing output file without asking")]
+        force: bool,
+    },
+    #[clap(about = "Check the integrity of an encrypted file, without writing its plaintext")]
+    Verify {
+        #[clap(help = "File to check")]
+        input: PathBuf,
+        #[clap(
+            long,
+            help = "Private key to decrypt the data (or the name of a key of the key store)"
+        )]
+        key: PathBuf,
+        #[clap(
+            long,
+            help = "Accept the legacy files without end marker (a truncation can't be detected)"
+        )]
+        allow_legacy: bool,
+    },
+    Recover {
+        #[clap(help = "File to decrypt")]
+        input: PathBuf,
+        #[clap(
+            long = "share",
+            required = true,
+            help = "Share of the AES key (repeat for each share)"
+        )]
+        shares: Vec<PathBuf>,
+        #[clap(
+            help = "File to save the decrypted data (default: stdout)",
+            default_value = "-"
+        )]
+        output: String,
+    },
+    Rekey {
+        #[clap(help = "File to re-encrypt")]
+        input: PathBuf,
+        #[clap(
+            long,
+            help = "Private key to decrypt the data (or the name of a key of the key store)"
+        )]
+        old_key: PathBuf,
+        #[clap(
+            long,
+            help = "Public key of the new recipient (or the name of a trusted key)"
+        )]
+        new_recipient: PathBuf,
+        #[clap(help = "File to save the re-encrypted data (always written in the latest format)")]
+        output: PathBuf,
+    },
+    Seal {
+        #[clap(help = "File to sign and encrypt")]
+        input: PathBuf,
+        #[clap(
+            long,
+            help = "Public key of the recipient (or the name of a trusted key)"
+        )]
+        to: PathBuf,
+        #[clap(
+            long,
+            help = "Private key to sign the data (or the name of a key of the key store)"
+        )]
+        sign_with: PathBuf,
+        #[clap(help = "File to save the sealed data (default: <data>.sealed)")]
+        output: Option<PathBuf>,
+    },
+    Open {
+        #[clap(help = "File to decrypt and verify")]
+        input: PathBuf,
+        #[clap(help = "Private key to decrypt the data (or the name of a key of the key store)")]
+        key: PathBuf,
+        #[clap(
+            help = "File to save the verified data (default: stdout)",
+            default_value = "-"
+        )]
+        output: String,
+    },
+    Archive {
+        #[clap(
+            long,
+            help = "Public key of the recipient (or the name of a trusted key)"
+        )]
+        to: PathBuf,
+        #[clap(help = "File to save the archive")]
+        output: PathBuf,
+        #[clap(
+            required = true,
+            help = "Files to add to the archive (directories are added recursively)"
+        )]
+        inputs: Vec<PathBuf>,
+    },
+    Inspect {
+        #[clap(help = "Archive to list")]
+        input: PathBuf,
+        #[clap(
+            help = "Private key to decrypt the archive (or the name of a key of the key store)"
+        )]
+        key: PathBuf,
+        #[clap(long, help = "Decrypt every member and check it against its SHA-256")]
+        verify: bool,
+    },
+    Extract {
+        #[clap(help = "Archive to extract from")]
+        input: PathBuf,
+        #[clap(
+            help = "Private key to decrypt the archive (or the name of a key of the key store)"
+        )]
         key: PathBuf,
+        #[clap(long, help = "Name of the file to extract (as listed by `inspect`)")]
+        member: String,
         #[clap(
-            help = "File to save the decrypted data (default: <data>.dec)",
+            help = "File to save the extracted file (default: stdout)",
             default_value = "-"
         )]
         output: String,
     },
+    Fingerprint {
+        #[clap(help = "Public key, private key or key bundle")]
+        key: PathBuf,
+    },
+    Trust {
+        #[clap(subcommand)]
+        command: TrustCommand,
+    },
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCommand,
+    },
+    #[clap(
+        about = "Check the build and the machine: RNG health, known-answer vectors and a round-trip with a temporary key (exits with 1 on failure)"
+    )]
+    Selftest,
+    #[clap(about = "Print the completion script of a shell")]
+    Completions {
+        #[clap(help = "Shell of the completion script")]
+        shell: clap_complete::Shell,
+    },
+    #[clap(about = "Print the man page (roff)")]
+    Man,
+}
+
+/// Manage the trust store. (See `trust::store_dir`)
+#[derive(Subcommand)]
+enum TrustCommand {
+    Add {
+        #[clap(help = "Public key to trust")]
+        key: PathBuf,
+        #[clap(
+            long,
+            help = "Name of the key (default: the file name without extension)"
+        )]
+        name: Option<String>,
+    },
+    List,
+    Remove {
+        #[clap(help = "Name of the key to remove")]
+        name: String,
+    },
+}
+
+/// Manage the key store. (`$CRYPTO_KEYSTORE`, or `~/.crypto/keys`)
+#[derive(Subcommand)]
+enum KeysCommand {
+    Create {
+        #[clap(help = "Name of the key")]
+        name: String,
+        #[clap(
+            long,
+            value_parser = parse_duration,
+            help = "Validity period of the key (e.g. 90d, 52w, 1y)"
+        )]
+        expires: Option<u64>,
+    },
+    List,
+    Import {
+        #[clap(help = "Name of the key")]
+        name: String,
+        #[clap(help = "Private or public key to import (with its .bundle sidecar, if any)")]
+        key: PathBuf,
+    },
+    Export {
+        #[clap(help = "Name or fingerprint of the key")]
+        name: String,
+        #[clap(
+            help = "File to save the private key (the public key is saved with a .pub extension)"
+        )]
+        output: PathBuf,
+    },
+    Delete {
+        #[clap(help = "Name of the key")]
+        name: String,
+    },
+    #[clap(about = "Set the default key, used by `encrypt --self` (print it without a name)")]
+    Default {
+        #[clap(help = "Name or fingerprint of the key")]
+        name: Option<String>,
+    },
 }
 
 enum Operation {
     Keygen,
     Encrypt,
     Decrypt,
+    Verify,
+    Recover,
+    Rekey,
+    Seal,
+    Open,
+    Archive,
+    Inspect,
+    Extract,
+    Fingerprint,
+    Trust,
+    Keys,
+    Selftest,
+    Completions,
+    Man,
 }
 
 fn main() {
     let start = std::time::Instant::now();
     let args: Args = Args::parse();
+    output::init(args.quiet, args.verbose);
+    passphrase::set_file(args.passphrase_file);
 
     let mut footer_print = true;
 
     let op = match args.subcommand {
-        Subcommands::Keygen { output } => {
-            generate_keys(output);
+        Subcommands::Keygen {
+            output,
+            owner,
+            expires,
+            bits,
+            passphrase,
+            force,
+        } => {
+            generate_keys(output, owner, expires, bits, passphrase, force);
             Operation::Keygen
         }
         Subcommands::Encrypt {
             key: public_key,
             input: data,
             output,
+            to,
+            self_,
+            shares,
+            threshold,
+            split_size,
+            force,
         } => {
-            encrypt(public_key, data, output);
+            // With `--to` or `--self`, the second positional argument is the output
+            let (key, output) = match (to, self_) {
+                (Some(to), _) => (resolve_recipient(&to), public_key.or(output)),
+                (None, true) => (default_recipient(), public_key.or(output)),
+                (None, false) => (
+                    resolve_recipient(&public_key.expect("required by clap")),
+                    output,
+                ),
+            };
+            encrypt(key, data, output, shares.zip(threshold), split_size, force);
             Operation::Encrypt
         }
         Subcommands::Decrypt {
             key: private_key,
             input: data,
             output,
+            no_restore_name,
+            force,
         } => {
-            if &output == "-" {
+            if decrypt(private_key, data, output, !no_restore_name, force) == "-" {
                 footer_print = false;
             }
-            decrypt(private_key, data, output);
             Operation::Decrypt
         }
+        Subcommands::Verify {
+            input,
+            key,
+            allow_legacy,
+        } => {
+            verify(key, input, allow_legacy);
+            Operation::Verify
+        }
+        Subcommands::Recover {
+            input,
+            shares,
+            output,
+        } => {
+            if &output == "-" {
+                footer_print = false;
+            }
+            recover(shares, input, output);
+            Operation::Recover
+        }
+        Subcommands::Rekey {
+            input,
+            old_key,
+            new_recipient,
+            output,
+        } => {
+            rekey(old_key, new_recipient, input, output);
+            Operation::Rekey
+        }
+        Subcommands::Seal {
+            input,
+            to,
+            sign_with,
+            output,
+        } => {
+            seal(to, sign_with, input, output);
+            Operation::Seal
+        }
+        Subcommands::Open { input, key, output } => {
+            if &output == "-" {
+                footer_print = false;
+            }
+            open(key, input, output);
+            Operation::Open
+        }
+        Subcommands::Archive { to, output, inputs } => {
+            archive(to, inputs, output);
+            Operation::Archive
+        }
+        Subcommands::Inspect { input, key, verify } => {
+            footer_print = verify;
+            inspect(key, input, verify);
+            Operation::Inspect
+        }
+        Subcommands::Extract {
+            input,
+            key,
+            member,
+            output,
+        } => {
+            if &output == "-" {
+                footer_print = false;
+            }
+            extract(key, input, member, output);
+            Operation::Extract
+        }
+        Subcommands::Fingerprint { key } => {
+            footer_print = false;
+            fingerprint(key);
+            Operation::Fingerprint
+        }
+        Subcommands::Trust { command } => {
+            footer_print = false;
+            manage_trust(command);
+            Operation::Trust
+        }
+        Subcommands::Keys { command } => {
+            footer_print = false;
+            manage_keys(command);
+            Operation::Keys
+        }
+        Subcommands::Selftest => {
+            if !selftest::run() {
+                fail(Failure::Other, "Self-test failed");
+            }
+            Operation::Selftest
+        }
+        Subcommands::Completions { shell } => {
+            footer_print = false;
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+            Operation::Completions
+        }
+        Subcommands::Man => {
+            footer_print = false;
+            clap_mangen::Man::new(Args::command())
+                .render(&mut std::io::stdout())
+                .or_fail(Failure::Io, "Failed to write the man page");
+            Operation::Man
+        }
     };
 
-    let elapsed = start.elapsed();
-    if footer_print {
-        match op {
-            Operation::Keygen => println!("Key generation took {:?}", elapsed),
-            Operation::Encrypt => println!("Encryption took {:?}", elapsed),
-            Operation::Decrypt => println!("Decryption took {:?}", elapsed),
-        }
+    let operation = match op {
+        Operation::Keygen => "Key generation",
+        Operation::Encrypt => "Encryption",
+        Operation::Decrypt => "Decryption",
+        Operation::Verify => "Verification",
+        Operation::Recover => "Recovery",
+        Operation::Rekey => "Re-encryption",
+        Operation::Seal => "Sealing",
+        Operation::Open => "Opening",
+        Operation::Archive => "Archiving",
+        Operation::Inspect => "Verification",
+        Operation::Extract => "Extraction",
+        Operation::Selftest => "Self-test",
+        Operation::Fingerprint
+        | Operation::Trust
+        | Operation::Keys
+        | Operation::Completions
+        | Operation::Man => return,
+    };
+    if footer_print && output::verbose() {
+        let footer = format!("{} took {:?}", operation, start.elapsed());
+        println!("{}", output::paint(footer, Style::Dim, Stream::Stdout));
     }
 }
 
-fn generate_keys(output: PathBuf) {
-    let keys = crypto::RsaKeys::generate().expect("failed to generate keys");
-    let private_key = keys
-        .private_key_to_pem()
-        .expect("failed to convert private key to PEM");
-    let public_key = keys
-        .public_key_to_pem()
-        .expect("failed to convert public key to PEM");
+fn generate_keys(
+    output: PathBuf,
+    owner: Option<String>,
+    expires: Option<u64>,
+    bits: usize,
+    passphrase: Option<Option<String>>,
+    force: bool,
+) {
+    let passphrase = passphrase.map(|passphrase| {
+        passphrase.unwrap_or_else(|| {
+            passphrase::read_new().or_fail(Failure::BadKey, "Failed to read the passphrase")
+        })
+    });
+    let options = ops::KeygenOptions {
+        bits,
+        owner,
+        expires,
+        passphrase,
+        overwrite: force,
+    };
+    let generated = with_overwrite(options.overwrite, |overwrite| {
+        // A dot every few prime candidates, as a 2048 bits key can take a few seconds
+        let progress = output::verbose();
+        if progress {
+            eprint!("Generating keys");
+        }
+        let generated = ops::keygen_with(
+            &output,
+            &ops::KeygenOptions {
+                overwrite,
+                ..options.clone()
+            },
+            |attempt| {
+                if progress && attempt.total.is_multiple_of(32) {
+                    eprint!(".");
+                }
+                std::ops::ControlFlow::Continue(())
+            },
+        );
+        if progress {
+            eprintln!();
+        }
+        generated
+    })
+    .or_fail(Failure::Other, "Failed to generate keys");
 
-    std::fs::write(&output, private_key).expect("failed to write private key");
-    std::fs::write(output.with_extension("pub"), public_key).expect("failed to write public key");
+    status!(
+        "Keys saved to {} and {} (metadata in {})",
+        generated.private_key.display(),
+        generated.public_key.display(),
+        generated.bundle.display()
+    );
+}
 
-    println!(
-        "Keys saved to {} and {}",
-        output.display(),
-        output.with_extension("pub").display()
+/// Run an operation without overwriting the existing files, then again with `overwrite` if it
+/// refused to overwrite a file and the user confirms it. (See `confirm_overwrite`)
+fn with_overwrite<T>(
+    force: bool,
+    mut operation: impl FnMut(bool) -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let result = operation(force);
+    let existing = result.as_ref().err().and_then(|e| {
+        let file = ops::FileError::from_io(e)?;
+        (e.kind() == std::io::ErrorKind::AlreadyExists && file.output)
+            .then_some(file.path.as_ref()?)
+    });
+    match existing {
+        Some(path) if !force => {
+            confirm_overwrite(path);
+            operation(true)
+        }
+        _ => result,
+    }
+}
+
+/// Ask the user to confirm the overwrite of an existing output file. (Only asked on a terminal)
+/// Exits otherwise.
+fn confirm_overwrite(path: &Path) {
+    if std::io::stdin().is_terminal() {
+        eprint!("{} already exists, overwrite? [y/N] ", path.display());
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).is_ok()
+            && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+        {
+            return;
+        }
+    }
+    fail(
+        Failure::Other,
+        format_args!(
+            "{} already exists (use --force to overwrite it)",
+            path.display()
+        ),
     );
 }
 
-fn encrypt(public_key: PathBuf, input: PathBuf, output: Option<PathBuf>) {
-    let key = RsaKeys::from_public_key_pem(
-        &std::fs::read_to_string(public_key).expect("failed to read public key"),
-    )
-    .expect("failed to parse public key")
-    .public_key
-    .unwrap();
+fn encrypt(
+    key: rsa::RsaPublicKey,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    shares: Option<(u8, u8)>,
+    split_size: Option<u64>,
+    force: bool,
+) {
+    warn_software_aes(&input);
 
     let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.enc", input.display())));
-    let file = std::fs::File::create(&output).expect("failed to open file");
-    let mut writer = CryptoWriter::<_, 16>::new(file, key).expect("failed to create CryptoWriter");
-    let data = std::fs::read(&input).expect("failed to read data");
-    writer.write_all(&data).expect("failed to write data");
-    println!("Encrypted data saved to {}", output.display());
+    let encrypted = with_overwrite(force, |overwrite| {
+        let options = ops::EncryptOptions {
+            shares,
+            split_size,
+            overwrite,
+            ..Default::default()
+        };
+        ops::encrypt_path::<16>(&input, &output, key.clone(), &options)
+    })
+    .or_fail(Failure::Io, "Encryption failed");
+
+    for share in &encrypted.shares {
+        status!("Share saved to {}", share.display());
+    }
+    match encrypted.outputs.as_slice() {
+        [output] => status!("Encrypted data saved to {}", output.display()),
+        [first, .., last] => status!(
+            "Encrypted data saved to {} ... {}",
+            first.display(),
+            last.display()
+        ),
+        [] => {}
+    }
 }
 
-pub fn decrypt(private_key: PathBuf, input: PathBuf, output: String) {
-    let key = RsaKeys::from_private_key_pem(
-        &std::fs::read_to_string(private_key).expect("failed to read private key"),
+/// The input size from which the software AES implementation is noticeably slow.
+const LARGE_JOB: u64 = 64 * 1024 * 1024;
+
+/// Warn if a large input is processed without hardware AES acceleration. (See
+/// `crypto::capabilities`)
+fn warn_software_aes(input: &Path) {
+    let large = std::fs::metadata(input).is_ok_and(|metadata| metadata.len() >= LARGE_JOB);
+    if large && !crypto::capabilities().is_accelerated() {
+        warning!(
+            "no hardware AES acceleration, using the software implementation (several times slower)"
+        );
+    }
+}
+
+/// Parse a duration such as `12h`, `90d`, `52w` or `1y`, in seconds. (A year is 365 days)
+fn parse_duration(duration: &str) -> Result<u64, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration: {}", duration))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "h" => 3_600,
+        "" | "d" => 86_400,
+        "w" => 7 * 86_400,
+        "y" => 365 * 86_400,
+        _ => return Err(format!("invalid duration unit: {}", unit)),
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("duration is too long: {}", duration))
+}
+
+/// Format a Unix time as a UTC date. (e.g. `2024-09-17 14:03:00 UTC`)
+fn format_time(time: u64) -> String {
+    let (days, seconds) = (time / 86_400, time % 86_400);
+    // Civil date from the days since 1970-01-01, in 400-year eras starting on March 1st
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
     )
-    .expect("failed to parse private key")
-    .private_key
-    .unwrap();
+}
+
+/// Parse a size such as `4096`, `100MB` or `1GiB`.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size: {}", size))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(format!("invalid size unit: {}", unit)),
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size is too large: {}", size))
+}
+
+/// Decrypt the input, by default to the original name of the file. (See `ops::restored_path`)
+///
+/// # Returns
+/// The output the data was written to. (`-` for stdout)
+pub fn decrypt(
+    private_key: PathBuf,
+    input: PathBuf,
+    output: Option<String>,
+    restore_name: bool,
+    force: bool,
+) -> String {
+    let key = load_private_key(&private_key);
+    warn_software_aes(&input);
+
+    let decrypted = with_overwrite(force, |overwrite| {
+        let mut stdout = std::io::stdout();
+        let output = match &output {
+            Some(output) if output == "-" => ops::Output::Writer(&mut stdout),
+            Some(output) => ops::Output::Path(Path::new(output)),
+            None if restore_name => ops::Output::Restore(&mut stdout),
+            None => ops::Output::Writer(&mut stdout),
+        };
+        let options = ops::DecryptOptions { overwrite };
+        ops::decrypt_path::<16>(&input, output, key.clone(), &options)
+    })
+    .or_fail(Failure::BadInput, "Decryption failed");
+    match decrypted.path {
+        Some(path) => {
+            status!("Decrypted data saved to {}", path.display());
+            path.display().to_string()
+        }
+        None => "-".to_string(),
+    }
+}
+
+/// Check the input, and print `OK` with its chunk count and plaintext size, or exit with `FAILED`.
+/// A file without end marker fails, unless `allow_legacy` is set.
+fn verify(private_key: PathBuf, input: PathBuf, allow_legacy: bool) {
+    let key = load_private_key(&private_key);
+    let options = ops::VerifyOptions { allow_legacy };
+    let info = ops::verify_path::<16>(&input, key, &options).unwrap_or_else(|e| {
+        fail(
+            failure::classify(&e, Failure::BadInput),
+            format_args!("{}: FAILED ({})", input.display(), e),
+        )
+    });
+    println!(
+        "{}: {} ({} chunks, {} bytes)",
+        input.display(),
+        output::paint("OK", Style::Success, Stream::Stdout),
+        info.chunks,
+        info.plaintext_len
+    );
+    if !info.end_marker {
+        warning!(
+            "{} has no end marker: a truncation on a chunk boundary can't be detected",
+            input.display()
+        );
+    }
+}
 
-    let file = std::fs::File::open(&input).expect("Failed to open input file");
+fn recover(shares: Vec<PathBuf>, input: PathBuf, output: String) {
+    let shares = shares
+        .iter()
+        .map(|path| {
+            let share = std::fs::read(path).or_fail(
+                Failure::BadKey,
+                &format!("Failed to read {}", path.display()),
+            );
+            split::Share::from_bytes(&share).or_fail(
+                Failure::BadKey,
+                &format!("Failed to parse {}", path.display()),
+            )
+        })
+        .collect::<Vec<_>>();
 
-    let mut reader = CryptoReader::<_, 16>::new(file, key).expect("failed to create CryptoReader");
+    let file = ops::open_input(&input).or_fail(Failure::BadInput, "Failed to open the input");
+
+    let reader = split::new_reader_with_shares::<_, 16>(file, &shares)
+        .or_fail(Failure::BadKey, "Failed to recover the AES key");
+    write_decrypted(reader, output);
+}
+
+fn write_decrypted(mut reader: impl Read, output: String) {
     if output == "-" {
         let mut buffer = [0u8; 16];
         loop {
-            if let Ok(n) = reader.read(&mut buffer) {
-                if n == 0 {
-                    break;
-                }
-                std::io::stdout()
-                    .write_all(&buffer[..n])
-                    .expect("failed to write decrypted data");
-            } else {
-                _ = std::io::stdout().flush();
-                eprintln!("\n\nDecryption failed");
+            let n = reader
+                .read(&mut buffer)
+                .or_fail(Failure::BadInput, "\n\nDecryption failed");
+            if n == 0 {
                 break;
-            };
+            }
+            std::io::stdout()
+                .write_all(&buffer[..n])
+                .or_fail(Failure::Io, "Failed to write the decrypted data");
         }
     } else {
-        let mut file = std::fs::File::create(&output).expect("failed to open output file");
-        std::io::copy(&mut reader, &mut file).expect("failed to write decrypted data");
-        println!("Decrypted data saved to {}", output);
+        let mut file = std::fs::File::create(&output)
+            .or_fail(Failure::Io, &format!("Failed to create {}", output));
+        copy_decrypted(&mut reader, &mut file, Path::new(&output));
+        status!("Decrypted data saved to {}", output);
+    };
+}
+
+/// Copy the decrypted data to the output file. On failure, the partial output is removed.
+fn copy_decrypted(reader: &mut impl Read, file: &mut std::fs::File, path: &Path) {
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) => {
+                _ = std::fs::remove_file(path);
+                let failure = failure::classify(&e, Failure::BadInput);
+                fail(failure, format_args!("Decryption failed: {}", e));
+            }
+        };
+        if n == 0 {
+            return;
+        }
+        file.write_all(&buffer[..n])
+            .or_fail(Failure::Io, &format!("Failed to write {}", path.display()));
+    }
+}
+
+fn rekey(private_key: PathBuf, public_key: PathBuf, input: PathBuf, output: PathBuf) {
+    let old_key = load_private_key(&private_key);
+    let new_key = resolve_recipient(&public_key);
+
+    let options = ops::RekeyOptions {
+        overwrite: true,
+        ..Default::default()
     };
+    ops::rekey::<16>(&input, &output, old_key, new_key, &options)
+        .or_fail(Failure::BadInput, "Re-encryption failed");
+    status!("Re-encrypted data saved to {}", output.display());
+}
+
+fn seal(public_key: PathBuf, private_key: PathBuf, input: PathBuf, output: Option<PathBuf>) {
+    let recipient = resolve_recipient(&public_key);
+    let signer = load_private_key(&private_key);
+
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.sealed", input.display())));
+    let data = std::fs::read(&input).or_fail(
+        Failure::BadInput,
+        &format!("Failed to read {}", input.display()),
+    );
+    let file = std::fs::File::create(&output).or_fail(
+        Failure::Io,
+        &format!("Failed to create {}", output.display()),
+    );
+    seal::seal::<_, 16>(file, recipient, &signer, &data)
+        .or_fail(Failure::Io, "Failed to seal the data");
+    status!(
+        "Sealed data saved to {} (signed by {})",
+        output.display(),
+        seal::fingerprint(&signer.to_public_key())
+    );
+}
+
+fn open(private_key: PathBuf, input: PathBuf, output: String) {
+    let key = load_private_key(&private_key);
+
+    let opened = seal::open::<_, 16>(
+        ops::open_input(&input).or_fail(Failure::BadInput, "Failed to open the input"),
+        key,
+    )
+    .or_fail(Failure::BadInput, "Failed to open sealed data");
+    // On stdout, the data must not be mixed with the report
+    match trust::find(&opened.signer) {
+        Ok(Some(trusted)) => notice!("Good signature, signed by {} (trusted)", trusted.name),
+        Ok(None) => notice!(
+            "Good signature, signed by {} (untrusted, see `trust add`)",
+            seal::fingerprint(&opened.signer)
+        ),
+        Err(e) => notice!(
+            "Good signature, signed by {} (failed to read the trust store: {})",
+            seal::fingerprint(&opened.signer),
+            e
+        ),
+    }
+    write_decrypted(opened.payload.as_slice(), output);
+}
+
+fn archive(public_key: PathBuf, inputs: Vec<PathBuf>, output: PathBuf) {
+    let key = resolve_recipient(&public_key);
+
+    let mut files = Vec::new();
+    for input in inputs {
+        collect_files(input, &mut files);
+    }
+    let file = std::fs::File::create(&output).or_fail(
+        Failure::Io,
+        &format!("Failed to create {}", output.display()),
+    );
+    let mut writer = ArchiveWriter::<_, 16>::new(std::io::BufWriter::new(file), key)
+        .or_fail(Failure::BadKey, "Failed to encrypt the AES key");
+    for path in files {
+        let name = member_name(&path);
+        let file = std::fs::File::open(&path).or_fail(
+            Failure::BadInput,
+            &format!("Failed to open {}", path.display()),
+        );
+        writer
+            .add(&name, file)
+            .or_fail(Failure::Io, &format!("Failed to add {}", path.display()));
+        status!("Added {}", name);
+    }
+    let manifest = writer
+        .finish()
+        .or_fail(Failure::Io, "Failed to write the manifest");
+    status!(
+        "Archive of {} files ({} bytes) saved to {}",
+        manifest.entries.len(),
+        manifest.total_size(),
+        output.display()
+    );
+}
+
+/// Collect the files of `path`, walking directories recursively. (In name order)
+fn collect_files(path: PathBuf, files: &mut Vec<PathBuf>) {
+    if !path.is_dir() {
+        files.push(path);
+        return;
+    }
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(&path)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .or_fail(
+            Failure::BadInput,
+            &format!("Failed to read {}", path.display()),
+        );
+    entries.sort();
+    for entry in entries {
+        collect_files(entry, files);
+    }
+}
+
+/// The name of a file in an archive: its relative path, with `/` separators. (e.g. `/tmp/./a`
+/// is stored as `tmp/a`)
+fn member_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Open an archive, and decrypt its manifest.
+fn open_archive(
+    private_key: &Path,
+    input: &Path,
+) -> ArchiveReader<std::io::BufReader<std::fs::File>, 16> {
+    let key = load_private_key(private_key);
+
+    let file = std::fs::File::open(input).or_fail(
+        Failure::BadInput,
+        &format!("Failed to open {}", input.display()),
+    );
+    ArchiveReader::new(std::io::BufReader::new(file), key)
+        .or_fail(Failure::BadInput, "Failed to open the archive")
+}
+
+fn inspect(private_key: PathBuf, input: PathBuf, verify: bool) {
+    let mut reader = open_archive(&private_key, &input);
+    let manifest = reader.manifest();
+    for entry in &manifest.entries {
+        let sha256 = entry
+            .sha256
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        println!("{}  {:>12}  {}", sha256, entry.size, entry.name);
+    }
+    println!(
+        "{} files, {} bytes",
+        manifest.entries.len(),
+        manifest.total_size()
+    );
+    if verify {
+        reader
+            .verify()
+            .or_fail(Failure::Authentication, "Verification failed");
+        status!("All files match their SHA-256");
+    }
+}
+
+fn extract(private_key: PathBuf, input: PathBuf, member: String, output: String) {
+    let mut reader = open_archive(&private_key, &input);
+
+    // Only the chunks of the member are decrypted
+    let result = if output == "-" {
+        reader.extract(&member, &mut std::io::stdout().lock())
+    } else {
+        let mut file = std::fs::File::create(&output)
+            .or_fail(Failure::Io, &format!("Failed to create {}", output));
+        let result = reader.extract(&member, &mut file);
+        if result.is_err() {
+            drop(file);
+            _ = std::fs::remove_file(&output);
+        }
+        result
+    };
+    match result {
+        Ok(_) if output == "-" => {}
+        Ok(size) => status!("Extracted {} ({} bytes) to {}", member, size, output),
+        Err(e) => fail(
+            failure::classify(&e, Failure::BadInput),
+            format_args!("Failed to extract {}: {}", member, e),
+        ),
+    }
+}
+
+/// Load the public key of a recipient, given as a path or the name of a trusted key.
+fn resolve_recipient(recipient: &Path) -> rsa::RsaPublicKey {
+    trust::resolve(recipient)
+        .or_else(|e| {
+            // One of our own keys (e.g. to encrypt a backup for ourselves)
+            let entry = recipient
+                .to_str()
+                .and_then(|name| KeyStore::open_default().ok()?.resolve(name).ok())
+                .ok_or(e)?;
+            if let Some(bundle) = &entry.bundle {
+                bundle
+                    .check_valid()
+                    .map_err(|e| format!("{}: {}", entry.name, e))?;
+            }
+            Ok::<_, String>(entry.public_key)
+        })
+        .or_fail(Failure::BadKey, "Failed to load the recipient key")
+}
+
+/// Load the public key of the default key: `$CRYPTO_DEFAULT_KEY` (a name or fingerprint), else the
+/// default key of the key store. (See `KeyStore::default_key`)
+fn default_recipient() -> rsa::RsaPublicKey {
+    let entry =
+        KeyStore::open_default().and_then(|store| match std::env::var("CRYPTO_DEFAULT_KEY") {
+            Ok(name) => store.resolve(&name),
+            Err(_) => store.default_key(),
+        });
+    let entry = entry.or_fail(Failure::BadKey, "Failed to load your default key");
+    if let Some(bundle) = &entry.bundle {
+        bundle.check_valid().or_fail(
+            Failure::BadKey,
+            &format!("Failed to load your default key {}", entry.name),
+        );
+    }
+    entry.public_key
+}
+
+/// Load a private key, given as a path or the name (or fingerprint) of a key of the key store.
+fn load_private_key(path: &Path) -> rsa::RsaPrivateKey {
+    if !path.exists() {
+        let keys = KeyStore::open_default()
+            .and_then(|store| store.export(&store.resolve(&path.to_string_lossy())?.name))
+            .or_fail(
+                Failure::BadKey,
+                &format!("Failed to load the private key {}", path.display()),
+            );
+        return keys.split().0.or_fail(
+            Failure::BadKey,
+            &format!(
+                "The key store only holds the public key of {}",
+                path.display()
+            ),
+        );
+    }
+    let pem = std::fs::read_to_string(path).or_fail(
+        Failure::BadKey,
+        &format!("Failed to read {}", path.display()),
+    );
+    let keys = if pem.contains("ENCRYPTED PRIVATE KEY") {
+        // Written by `keygen --passphrase`
+        read_encrypted_key(path, &pem)
+    } else {
+        RsaKeys::from_private_key_pem(&pem).map_err(|e| e.to_string())
+    };
+    keys.or_fail(
+        Failure::BadKey,
+        &format!("Failed to read the private key {}", path.display()),
+    )
+    .split()
+    .0
+    .or_fail(
+        Failure::BadKey,
+        &format!("{} is not a private key", path.display()),
+    )
+}
+
+/// Decrypt a passphrase-protected private key. (See `passphrase`) A typed passphrase is asked
+/// again when wrong.
+fn read_encrypted_key(path: &Path, pem: &str) -> Result<RsaKeys, String> {
+    for _ in 0..passphrase::PROMPT_ATTEMPTS {
+        let (passphrase, source) = passphrase::read(path)?;
+        match RsaKeys::from_encrypted_key_pem(pem, passphrase.as_bytes()) {
+            Ok(keys) => return Ok(keys),
+            Err(_) if source == passphrase::Source::Prompt => eprintln!("Wrong passphrase"),
+            // The usual cause (a corrupted key fails the same way)
+            Err(_) => return Err(format!("wrong passphrase ({})", source)),
+        }
+    }
+    Err("wrong passphrase".to_string())
+}
+
+fn manage_keys(command: KeysCommand) {
+    let store = KeyStore::open_default().or_fail(Failure::Other, "Key store error");
+    let result = match command {
+        KeysCommand::Create { name, expires } => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .or_fail(Failure::Other, "The clock is before 1970")
+                .as_secs();
+            store
+                .create(&name, expires.map(|expires| now + expires))
+                .map(|entry| status!("Created {} ({})", entry.name, entry.fingerprint))
+        }
+        KeysCommand::List => store.list().map(|entries| {
+            let default = store.default_key().ok().map(|entry| entry.name);
+            if entries.is_empty() {
+                println!("No key in {}", store.root().display());
+            }
+            for entry in entries {
+                let kind = if entry.has_private_key {
+                    "key pair"
+                } else {
+                    "public key"
+                };
+                let expiry = match entry.bundle.as_ref().and_then(|bundle| bundle.expires) {
+                    Some(expires) if entry.bundle.as_ref().unwrap().is_expired() => {
+                        format!(", expired {}", format_time(expires))
+                    }
+                    Some(expires) => format!(", expires {}", format_time(expires)),
+                    None => String::new(),
+                };
+                let default = if default.as_ref() == Some(&entry.name) {
+                    ", default"
+                } else {
+                    ""
+                };
+                println!(
+                    "{}  {} ({}{}{})",
+                    entry.fingerprint, entry.name, kind, default, expiry
+                );
+            }
+        }),
+        KeysCommand::Import { name, key } => {
+            let (keys, public_key) = read_any_key(&key);
+            let bundle = trust::read_sidecar(&key, &public_key)
+                .or_fail(Failure::BadKey, "Failed to read the key bundle");
+            store
+                .import(&name, &keys, bundle.as_ref())
+                .map(|entry| status!("Imported {} ({})", entry.name, entry.fingerprint))
+        }
+        KeysCommand::Export { name, output } => store.resolve(&name).and_then(|entry| {
+            let keys = store.export(&entry.name)?;
+            if let Ok(pem) = keys.private_key_to_pem() {
+                std::fs::write(&output, pem)?;
+            }
+            let pem = keys
+                .public_key_to_pem()
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            std::fs::write(output.with_extension("pub"), pem)?;
+            if let Some(bundle) = entry.bundle {
+                std::fs::write(trust::sidecar(&output), bundle.to_pem()?)?;
+            }
+            status!("Exported {} to {}", entry.name, output.display());
+            Ok(())
+        }),
+        KeysCommand::Delete { name } => store
+            .delete(&name)
+            .map(|()| status!("Deleted {} from the key store", name)),
+        KeysCommand::Default { name: Some(name) } => store.resolve(&name).and_then(|entry| {
+            store.set_default(&entry.name)?;
+            status!("{} is the default key", entry.name);
+            Ok(())
+        }),
+        KeysCommand::Default { name: None } => store
+            .default_key()
+            .map(|entry| println!("{}  {}", entry.fingerprint, entry.name)),
+    };
+    result.or_fail(Failure::Other, "Key store error");
+}
+
+fn manage_trust(command: TrustCommand) {
+    let result = match command {
+        TrustCommand::Add { key, name } => {
+            let name = name.unwrap_or_else(|| {
+                key.file_stem()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            trust::add(&key, &name).map(|trusted| {
+                status!("Trusted {} ({})", trusted.name, trusted.fingerprint());
+            })
+        }
+        TrustCommand::List => trust::list().map(|keys| {
+            if keys.is_empty() {
+                println!("No trusted key in {}", trust::store_dir().display());
+            }
+            for trusted in keys {
+                println!("{}  {}", trusted.fingerprint(), trusted.name);
+            }
+        }),
+        TrustCommand::Remove { name } => {
+            trust::remove(&name).map(|()| status!("Removed {} from the trust store", name))
+        }
+    };
+    result.or_fail(Failure::Other, "Trust store error");
+}
+
+fn fingerprint(path: PathBuf) {
+    let pem = std::fs::read_to_string(&path).or_fail(
+        Failure::BadKey,
+        &format!("Failed to read {}", path.display()),
+    );
+    let (key, bundle) = if let Ok(bundle) = KeyBundle::from_pem(&pem) {
+        (bundle.public_key.clone(), Some(bundle))
+    } else {
+        let (_, key) = read_any_key(&path);
+        let bundle = trust::read_sidecar(&path, &key)
+            .or_fail(Failure::BadKey, "Failed to read the key bundle");
+        (key, bundle)
+    };
+
+    println!("Fingerprint: {}", seal::fingerprint(&key));
+    let Some(bundle) = bundle else {
+        return;
+    };
+    println!("Owner:       {}", bundle.owner);
+    println!("Created:     {}", format_time(bundle.created));
+    match bundle.expires {
+        Some(expires) if bundle.is_expired() => {
+            println!("Expires:     {} (expired)", format_time(expires))
+        }
+        Some(expires) => println!("Expires:     {}", format_time(expires)),
+        None => println!("Expires:     never"),
+    }
+    bundle
+        .verify()
+        .or_fail(Failure::Authentication, "Invalid key bundle");
+}
+
+/// Read a private or public key file.
+///
+/// # Returns
+/// The keys, and the public key.
+fn read_any_key(path: &Path) -> (RsaKeys, rsa::RsaPublicKey) {
+    let pem = std::fs::read_to_string(path).or_fail(
+        Failure::BadKey,
+        &format!("Failed to read {}", path.display()),
+    );
+    let keys = RsaKeys::from_key_pem(&pem)
+        .or_else(|_| RsaKeys::from_public_key_pem(&pem))
+        .or_fail(
+            Failure::BadKey,
+            &format!("Failed to parse {}", path.display()),
+        );
+    let public_key = keys.public_key().cloned().or_fail(
+        Failure::BadKey,
+        &format!("{} has no public key", path.display()),
+    );
+    (keys, public_key)
 }
@@ -0,0 +1,154 @@
+//! The terminal output of the CLI: the verbosity, and the colors.
+//!
+//! | Flag  | Output                                                       |
+//! |-------|--------------------------------------------------------------|
+//! | `-q`  | The results (e.g. listings, decrypted data) and the errors   |
+//! | none  | The status messages and the warnings too                     |
+//! | `-v`  | The debug events of the library too (see `crypto` `tracing`) |
+//! | `-vv` | The trace events of the library too (e.g. every chunk)       |
+//!
+//! The status messages, warnings and errors are colored when written to a terminal, unless
+//! `$NO_COLOR` is set. (See <https://no-color.org>)
+use std::{
+    fmt::Display,
+    io::IsTerminal as _,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+/// The verbosity of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Debug,
+    Trace,
+}
+
+/// The verbosity, set once by `init`. (As a `Verbosity`)
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Set the verbosity from the `-q` and `-v` flags, and route the events of the library to stderr
+/// from `-v`.
+pub fn init(quiet: bool, verbose: u8) {
+    let verbosity = match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, 0) => Verbosity::Normal,
+        (false, 1) => Verbosity::Debug,
+        (false, _) => Verbosity::Trace,
+    };
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    let level = match verbosity {
+        Verbosity::Quiet | Verbosity::Normal => return,
+        Verbosity::Debug => tracing_subscriber::filter::LevelFilter::DEBUG,
+        Verbosity::Trace => tracing_subscriber::filter::LevelFilter::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(color(Stream::Stderr))
+        .without_time()
+        .with_target(false)
+        .init();
+}
+
+/// Whether the status messages and the warnings are shown. (Not with `-q`)
+pub fn verbose() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) > Verbosity::Quiet as u8
+}
+
+/// An output stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Whether the output to `stream` is colored: it is a terminal, and `$NO_COLOR` is not set.
+pub fn color(stream: Stream) -> bool {
+    static NO_COLOR: OnceLock<bool> = OnceLock::new();
+    let no_color = *NO_COLOR
+        .get_or_init(|| std::env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty()));
+    !no_color
+        && match stream {
+            Stream::Stdout => std::io::stdout().is_terminal(),
+            Stream::Stderr => std::io::stderr().is_terminal(),
+        }
+}
+
+/// The style of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// A completed step. (Green)
+    Success,
+    /// A warning. (Yellow)
+    Warning,
+    /// An error. (Red)
+    Error,
+    /// A secondary message, e.g. a timing. (Dimmed)
+    Dim,
+}
+
+/// The text, in the style, if the output to `stream` is colored.
+pub fn paint(text: impl Display, style: Style, stream: Stream) -> String {
+    if !color(stream) {
+        return text.to_string();
+    }
+    let code = match style {
+        Style::Success => "32",
+        Style::Warning => "33",
+        Style::Error => "31",
+        Style::Dim => "2",
+    };
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Print a status message on stdout, unless `-q`. (e.g. `Encrypted data saved to data.enc`)
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::verbose() {
+            println!(
+                "{}",
+                $crate::output::paint(
+                    format_args!($($arg)*),
+                    $crate::output::Style::Success,
+                    $crate::output::Stream::Stdout
+                )
+            );
+        }
+    };
+}
+
+/// Print a status message on stderr, unless `-q`. (When stdout holds the data)
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        if $crate::output::verbose() {
+            eprintln!(
+                "{}",
+                $crate::output::paint(
+                    format_args!($($arg)*),
+                    $crate::output::Style::Success,
+                    $crate::output::Stream::Stderr
+                )
+            );
+        }
+    };
+}
+
+/// Print a warning on stderr, unless `-q`.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::output::verbose() {
+            eprintln!(
+                "{}",
+                $crate::output::paint(
+                    format_args!("Warning: {}", format_args!($($arg)*)),
+                    $crate::output::Style::Warning,
+                    $crate::output::Stream::Stderr
+                )
+            );
+        }
+    };
+}
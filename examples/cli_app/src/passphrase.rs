@@ -0,0 +1,107 @@
+//! The passphrase of the protected private keys. (See `keygen --passphrase`)
+//!
+//! It is read from the first of:
+//! - The file given with `--passphrase-file`. (The first line, e.g. a mounted CI secret)
+//! - `$CRYPTO_PASSPHRASE`.
+//! - An interactive prompt, when stdin is a terminal. (Not echoed)
+use std::{
+    fmt,
+    io::IsTerminal as _,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The environment variable holding the passphrase.
+pub const PASSPHRASE_ENV: &str = "CRYPTO_PASSPHRASE";
+/// The number of prompts for the passphrase of a key before giving up.
+pub const PROMPT_ATTEMPTS: usize = 3;
+
+/// The `--passphrase-file` argument, if any. (Set once by `main`)
+static PASSPHRASE_FILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Set the `--passphrase-file` argument.
+pub fn set_file(path: Option<PathBuf>) {
+    _ = PASSPHRASE_FILE.set(path);
+}
+
+/// Where a passphrase was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env,
+    Prompt,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "from {}", path.display()),
+            Source::Env => write!(f, "from ${}", PASSPHRASE_ENV),
+            Source::Prompt => write!(f, "typed"),
+        }
+    }
+}
+
+/// Read the passphrase of the protected key `key`.
+///
+/// # Errors
+/// If the passphrase file can't be read, or there is no source at all.
+pub fn read(key: &Path) -> Result<(String, Source), String> {
+    if let Some(source) = non_interactive()? {
+        return Ok(source);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(format!(
+            "{} is protected by a passphrase: use --passphrase-file or set ${}",
+            key.display(),
+            PASSPHRASE_ENV
+        ));
+    }
+    let prompt = format!("Passphrase of {}: ", key.display());
+    let passphrase = rpassword::prompt_password(prompt).map_err(|e| e.to_string())?;
+    Ok((passphrase, Source::Prompt))
+}
+
+/// Read the passphrase of a new key. A typed passphrase is asked twice.
+///
+/// # Errors
+/// If the passphrase is empty, the typed passphrases differ, or there is no source at all.
+pub fn read_new() -> Result<String, String> {
+    let passphrase = match non_interactive()? {
+        Some((passphrase, _)) => passphrase,
+        None if std::io::stdin().is_terminal() => {
+            let passphrase =
+                rpassword::prompt_password("New passphrase: ").map_err(|e| e.to_string())?;
+            let confirmation = rpassword::prompt_password("Confirm the passphrase: ")
+                .map_err(|e| e.to_string())?;
+            if passphrase != confirmation {
+                return Err("the passphrases differ".to_string());
+            }
+            passphrase
+        }
+        None => {
+            return Err(format!(
+                "no passphrase: use --passphrase-file or set ${}",
+                PASSPHRASE_ENV
+            ))
+        }
+    };
+    if passphrase.is_empty() {
+        return Err("the passphrase is empty".to_string());
+    }
+    Ok(passphrase)
+}
+
+/// The passphrase from the file, or the environment, if any.
+fn non_interactive() -> Result<Option<(String, Source)>, String> {
+    if let Some(path) = PASSPHRASE_FILE.get().cloned().flatten() {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        // Only the first line: the files usually end with a newline
+        let passphrase = content.lines().next().unwrap_or_default().to_string();
+        return Ok(Some((passphrase, Source::File(path))));
+    }
+    Ok(std::env::var(PASSPHRASE_ENV)
+        .ok()
+        .map(|passphrase| (passphrase, Source::Env)))
+}
@@ -0,0 +1,153 @@
+//! The self-test: a quick check of the build and of the machine, e.g. as a deploy-time smoke test
+//! in a container.
+//!
+//! Each check prints its result. The self-test fails if any check fails.
+use crate::output::{paint, Stream, Style};
+use crypto::{CryptoReader, CryptoWriter, RsaKeys};
+use rsa::rand_core::{OsRng, RngCore};
+use std::{
+    io::{Read, Write},
+    ops::ControlFlow,
+};
+
+/// The private key of the known-answer vectors. (A test key, checked in with the library)
+const KAT_KEY: &str = include_str!("../../../crypto/tests/test");
+
+/// The known-answer vectors: the name, the chunk size, the stream and the expected plaintext.
+const KAT_VECTORS: &[(&str, usize, &[u8], &[u8])] = &[
+    (
+        "short",
+        16,
+        include_bytes!("../../../crypto/tests/kat/short.bin"),
+        b"The quick brown fox jumps over the lazy dog",
+    ),
+    (
+        "lorem_ipsum",
+        1024,
+        include_bytes!("../../../crypto/tests/kat/lorem_ipsum.bin"),
+        include_bytes!("../../../crypto/tests/lorem_ipsum.txt"),
+    ),
+];
+
+type Check = fn() -> Result<(), String>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("RNG health", rng_health),
+    ("Known-answer vectors", known_answers),
+    ("Round-trip", round_trip),
+];
+
+/// Run every check.
+///
+/// # Returns
+/// Whether every check passed.
+pub fn run() -> bool {
+    let mut passed = true;
+    for (name, check) in CHECKS {
+        match check() {
+            Ok(()) => println!("{name}: {}", paint("ok", Style::Success, Stream::Stdout)),
+            Err(e) => {
+                let failed = paint(format_args!("FAILED ({e})"), Style::Error, Stream::Stdout);
+                println!("{name}: {failed}");
+                passed = false;
+            }
+        }
+    }
+    let capabilities = crypto::capabilities();
+    println!(
+        "Hardware acceleration: AES {}, GHASH {}",
+        if capabilities.aes { "yes" } else { "no" },
+        if capabilities.clmul { "yes" } else { "no" }
+    );
+    passed
+}
+
+/// Check that the system RNG returns distinct, balanced output. (A broken or stuck entropy
+/// source, not a statistical test suite)
+fn rng_health() -> Result<(), String> {
+    let mut first = [0u8; 1024];
+    let mut second = [0u8; 1024];
+    OsRng
+        .try_fill_bytes(&mut first)
+        .and_then(|()| OsRng.try_fill_bytes(&mut second))
+        .map_err(|e| format!("the RNG failed: {e}"))?;
+    if first == second {
+        return Err("the RNG repeated its output".to_string());
+    }
+    if first.iter().all(|&byte| byte == first[0]) {
+        return Err("the RNG output is constant".to_string());
+    }
+    // 8192 bits: 4096 ones expected, with a standard deviation of about 45
+    let ones: u32 = first.iter().map(|byte| byte.count_ones()).sum();
+    if !(3800..=4392).contains(&ones) {
+        return Err(format!(
+            "the RNG output is biased ({ones} of 8192 bits set)"
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the checked-in vectors decrypt to their plaintext.
+fn known_answers() -> Result<(), String> {
+    let keys = RsaKeys::from_key_pem(KAT_KEY).map_err(|e| format!("invalid test key: {e}"))?;
+    let private_key = keys.split().0.ok_or("the test key has no private key")?;
+    for (name, chunk_size, stream, plaintext) in KAT_VECTORS {
+        let decrypted = match chunk_size {
+            16 => decrypt::<16>(stream, private_key.clone()),
+            1024 => decrypt::<1024>(stream, private_key.clone()),
+            _ => unreachable!("no vector has {chunk_size} bytes chunks"),
+        }
+        .map_err(|e| format!("{name}: {e}"))?;
+        if decrypted != *plaintext {
+            return Err(format!("{name}: wrong plaintext"));
+        }
+    }
+    Ok(())
+}
+
+/// Check an encryption and decryption with a temporary key, and that a modified stream is
+/// rejected.
+fn round_trip() -> Result<(), String> {
+    // A small key: the check is about the build, not the key strength
+    let keys = RsaKeys::generate_with(1024, |_| ControlFlow::Continue(()))
+        .map_err(|e| format!("key generation failed: {e}"))?;
+    let (private_key, public_key) = keys.split();
+    let (public_key, private_key) = public_key
+        .zip(private_key)
+        .ok_or("the generated key is incomplete")?;
+
+    let mut plaintext = vec![0u8; 10_000];
+    OsRng.fill_bytes(&mut plaintext);
+    let mut encrypted = Vec::new();
+    let mut writer = CryptoWriter::<_, 1024>::new(&mut encrypted, public_key)
+        .map_err(|e| format!("encryption failed: {e}"))?;
+    writer
+        .write_all(&plaintext)
+        .and_then(|()| writer.flush())
+        .map_err(|e| format!("encryption failed: {e}"))?;
+    drop(writer);
+
+    let decrypted = decrypt::<1024>(&encrypted, private_key.clone())
+        .map_err(|e| format!("decryption failed: {e}"))?;
+    if decrypted != plaintext {
+        return Err("the decrypted data doesn't match".to_string());
+    }
+
+    let last = encrypted.len() - 1;
+    encrypted[last] ^= 1;
+    if decrypt::<1024>(&encrypted, private_key).is_ok() {
+        return Err("a modified stream was accepted".to_string());
+    }
+    Ok(())
+}
+
+fn decrypt<const BUFFER_SIZE: usize>(
+    stream: &[u8],
+    private_key: rsa::RsaPrivateKey,
+) -> std::io::Result<Vec<u8>> {
+    let mut reader = CryptoReader::<_, BUFFER_SIZE>::new(stream, private_key)?;
+    reader.set_strict(true);
+    let mut decrypted = Vec::new();
+    reader.read_to_end(&mut decrypted)?;
+    Ok(decrypted)
+}
@@ -0,0 +1,201 @@
+//! The trust store: a local directory of named public keys.
+//!
+//! Each key is saved as `<name>.pub` (PEM) in the store directory, which is `$CRYPTO_TRUST_STORE`
+//! if set, else `crypto-files/trust` in the configuration directory of the user.
+//! (`$XDG_CONFIG_HOME`, or `~/.config`)
+//!
+//! The `KeyBundle` sidecar of a key (`<name>.bundle`, see `sidecar`) is stored next to it, so the
+//! expired keys are refused.
+use crypto::{seal, KeyBundle, RsaKeys};
+use rsa::RsaPublicKey;
+use std::path::{Path, PathBuf};
+
+/// A trusted public key.
+pub struct TrustedKey {
+    pub name: String,
+    pub key: RsaPublicKey,
+}
+
+impl TrustedKey {
+    pub fn fingerprint(&self) -> String {
+        seal::fingerprint(&self.key)
+    }
+}
+
+/// The directory of the trust store.
+pub fn store_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("CRYPTO_TRUST_STORE") {
+        return PathBuf::from(dir);
+    }
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    config.join("crypto-files").join("trust")
+}
+
+/// Check that a name can be used as a file name in the store.
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid name: {:?} (use letters, digits, '-', '_', '.' and '@')",
+            name
+        ))
+    }
+}
+
+fn key_path(name: &str) -> PathBuf {
+    store_dir().join(format!("{}.pub", name))
+}
+
+/// The path of the `KeyBundle` sidecar of a key file, written by `keygen`. (`alice.pub` and
+/// `alice` share `alice.bundle`)
+pub fn sidecar(path: &Path) -> PathBuf {
+    crypto::ops::sidecar(path)
+}
+
+/// Read the `KeyBundle` sidecar of the key file at `path`, if any.
+///
+/// Fails if the sidecar is invalid, or is the bundle of another key.
+pub fn read_sidecar(path: &Path, key: &RsaPublicKey) -> Result<Option<KeyBundle>, String> {
+    let sidecar = sidecar(path);
+    let pem = match std::fs::read_to_string(&sidecar) {
+        Ok(pem) => pem,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to read {}: {}", sidecar.display(), e)),
+    };
+    let bundle = KeyBundle::from_pem(&pem)
+        .map_err(|e| format!("failed to parse {}: {}", sidecar.display(), e))?;
+    if &bundle.public_key != key {
+        return Err(format!(
+            "{} is the bundle of another key",
+            sidecar.display()
+        ));
+    }
+    Ok(Some(bundle))
+}
+
+pub fn read_public_key(path: &Path) -> Result<RsaPublicKey, String> {
+    let pem = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    RsaKeys::from_public_key_pem(&pem)
+        .ok()
+        .and_then(|keys| keys.split().1)
+        .ok_or_else(|| format!("failed to parse public key {}", path.display()))
+}
+
+/// The keys of the store, sorted by name.
+pub fn list() -> Result<Vec<TrustedKey>, String> {
+    let dir = store_dir();
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("failed to read {}: {}", dir.display(), e)),
+    };
+    let mut keys = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("failed to read {}: {}", dir.display(), e))?
+            .path();
+        if path.extension().is_none_or(|extension| extension != "pub") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        keys.push(TrustedKey {
+            name: name.to_owned(),
+            key: read_public_key(&path)?,
+        });
+    }
+    keys.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(keys)
+}
+
+/// Add the public key at `path` to the store, as `name`.
+///
+/// Fails if the name is taken, or the key is already trusted under another name.
+pub fn add(path: &Path, name: &str) -> Result<TrustedKey, String> {
+    check_name(name)?;
+    let key = TrustedKey {
+        name: name.to_owned(),
+        key: read_public_key(path)?,
+    };
+    let fingerprint = key.fingerprint();
+    for trusted in list()? {
+        if trusted.name == name {
+            return Err(format!("{} is already in the trust store", name));
+        }
+        if trusted.fingerprint() == fingerprint {
+            return Err(format!("this key is already trusted as {}", trusted.name));
+        }
+    }
+
+    let pem = RsaKeys::from_public_key(key.key.clone())
+        .public_key_to_pem()
+        .map_err(|e| format!("failed to encode public key: {}", e))?;
+    std::fs::create_dir_all(store_dir())
+        .map_err(|e| format!("failed to create {}: {}", store_dir().display(), e))?;
+    std::fs::write(key_path(name), pem).map_err(|e| format!("failed to write key: {}", e))?;
+    if let Some(bundle) = read_sidecar(path, &key.key)? {
+        let pem = bundle
+            .to_pem()
+            .map_err(|e| format!("failed to encode key bundle: {}", e))?;
+        std::fs::write(sidecar(&key_path(name)), pem)
+            .map_err(|e| format!("failed to write key bundle: {}", e))?;
+    }
+    Ok(key)
+}
+
+/// Remove `name` from the store.
+pub fn remove(name: &str) -> Result<(), String> {
+    check_name(name)?;
+    _ = std::fs::remove_file(sidecar(&key_path(name)));
+    match std::fs::remove_file(key_path(name)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(format!("{} is not in the trust store", name))
+        }
+        Err(e) => Err(format!("failed to remove {}: {}", name, e)),
+    }
+}
+
+/// The trusted key with the given fingerprint, if any.
+pub fn find(key: &RsaPublicKey) -> Result<Option<TrustedKey>, String> {
+    let fingerprint = seal::fingerprint(key);
+    Ok(list()?
+        .into_iter()
+        .find(|trusted| trusted.fingerprint() == fingerprint))
+}
+
+/// Resolve a recipient: the name of a trusted key, or a path to a public key.
+///
+/// The trusted names come first, so `alice` is the trusted key even if a file `alice` exists in
+/// the current directory. (e.g. the private key of alice) If the key has a `KeyBundle` sidecar,
+/// the key is refused once expired.
+pub fn resolve(recipient: &Path) -> Result<RsaPublicKey, String> {
+    let path = match recipient.to_str() {
+        Some(name) if check_name(name).is_ok() && key_path(name).exists() => key_path(name),
+        _ if recipient.exists() => recipient.to_path_buf(),
+        _ => {
+            return Err(format!(
+                "{} is neither a trusted name (see `trust list`) nor a public key file",
+                recipient.display()
+            ))
+        }
+    };
+    let key = read_public_key(&path)?;
+    if let Some(bundle) = read_sidecar(&path, &key)? {
+        bundle
+            .check_valid()
+            .map_err(|e| format!("{}: {}", recipient.display(), e))?;
+    }
+    Ok(key)
+}
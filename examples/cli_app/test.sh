@@ -38,6 +38,31 @@ function test {
         exit 1
     fi
     echo "Files are the same"
+    # Re-encrypt the file for a new key
+    $1 keygen $TARGET_DIR/new_key
+    $1 rekey $TARGET_DIR/hello.enc --old-key $TARGET_DIR/key --new-recipient $TARGET_DIR/new_key.pub $TARGET_DIR/hello.rekey.enc
+    if [ $? -ne 0 ]; then
+        echo "Failed to re-encrypt file"
+        exit 1
+    fi
+    $1 decrypt $TARGET_DIR/hello.rekey.enc $TARGET_DIR/new_key $TARGET_DIR/hello.rekey.dec
+    diff ./tests/hello.txt $TARGET_DIR/hello.rekey.dec
+    if [ $? -ne 0 ]; then
+        echo "Re-encrypted files are not the same"
+        exit 1
+    fi
+    # Split the encrypted file into parts
+    $1 encrypt ./tests/hello.txt $TARGET_DIR/key.pub $TARGET_DIR/hello.split.enc --split-size 64
+    if [ $? -ne 0 ]; then
+        echo "Failed to encrypt file into parts"
+        exit 1
+    fi
+    $1 decrypt $TARGET_DIR/hello.split.enc $TARGET_DIR/key $TARGET_DIR/hello.split.dec
+    diff ./tests/hello.txt $TARGET_DIR/hello.split.dec
+    if [ $? -ne 0 ]; then
+        echo "Joined files are not the same"
+        exit 1
+    fi
     echo "Time taken: $((end-start)) seconds"
     rm -rf $TARGET_DIR
 }